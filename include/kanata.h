@@ -0,0 +1,91 @@
+#ifndef KANATA_H
+#define KANATA_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Define KANATA_WIDE_IDS when the library is built with `wide-ids`.
+
+typedef struct KanataParser KanataParser;
+
+typedef struct KanataWriter KanataWriter;
+
+#if !defined(KANATA_WIDE_IDS)
+typedef uint32_t kanata_id_t;
+#endif
+
+#if defined(KANATA_WIDE_IDS)
+typedef uint64_t kanata_id_t;
+#endif
+
+typedef struct KanataCommand {
+  uint8_t tag;
+  uint8_t flag;
+  kanata_id_t a;
+  kanata_id_t b;
+  uint32_t c;
+  int32_t value;
+  const uint8_t *text;
+  uintptr_t text_len;
+} KanataCommand;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * # Safety
+ * `data` must point to `len` readable bytes. The bytes are copied.
+ */
+struct KanataParser *kanata_parser_new(const uint8_t *data, uintptr_t len);
+
+/**
+ * # Safety
+ * `p` must come from `kanata_parser_new` and `out` must be writable.
+ * Returns 1 when a command was stored, 0 at end of input and -1 on error.
+ */
+int kanata_parser_next(struct KanataParser *p, struct KanataCommand *out);
+
+/**
+ * # Safety
+ * `p` must come from `kanata_parser_new`. Returns the byte offset of the
+ * last error and stores its kind (as `ParseErrorKind` discriminant) in
+ * `kind`, or returns -1 when no error occurred.
+ */
+int64_t kanata_parser_error(const struct KanataParser *p, int *kind);
+
+/**
+ * # Safety
+ * `p` must come from `kanata_parser_new` and not be used afterwards.
+ */
+void kanata_parser_free(struct KanataParser *p);
+
+/**
+ * # Safety
+ * `path` must be a NUL-terminated string.
+ */
+struct KanataWriter *kanata_writer_new(const char *path);
+
+/**
+ * # Safety
+ * `w` must come from `kanata_writer_new`, `cmd` must be readable and, for
+ * `L`/`S`/`E`/`W`/`P`, `cmd.text` must be null or point to `cmd.text_len`
+ * readable bytes.
+ * Returns 0 on success and -1 on error.
+ */
+int kanata_writer_write(struct KanataWriter *w, const struct KanataCommand *cmd);
+
+/**
+ * # Safety
+ * `w` must come from `kanata_writer_new` and not be used afterwards.
+ * Returns 0 if the final flush succeeded.
+ */
+int kanata_writer_free(struct KanataWriter *w);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* KANATA_H */
@@ -0,0 +1,220 @@
+use crate::{Clock, Command, Commands, CycleWriter, Id, InstructionRecord, LogKind, Trace};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AnnotationTarget {
+    Instruction(Id),
+    Cycles { start: i64, end: i64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Annotation {
+    pub target: AnnotationTarget,
+    pub note: String,
+    pub tags: Vec<String>,
+}
+
+impl Annotation {
+    pub fn instruction(id: Id, note: impl Into<String>) -> Self {
+        Self {
+            target: AnnotationTarget::Instruction(id),
+            note: note.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn cycles(cycles: Range<i64>, note: impl Into<String>) -> Self {
+        Self {
+            target: AnnotationTarget::Cycles {
+                start: cycles.start,
+                end: cycles.end,
+            },
+            note: note.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    // Whether it's about `rec`: its own, or a range of cycles `rec` spends
+    // some of in flight.
+    pub fn applies_to(&self, rec: &InstructionRecord) -> bool {
+        match self.target {
+            AnnotationTarget::Instruction(id) => rec.id == id,
+            AnnotationTarget::Cycles { start, end } => {
+                rec.start < end && rec.end.unwrap_or(rec.start) >= start
+            }
+        }
+    }
+
+    // As the text of an inlined `L` record: `note: text #tag`, with the
+    // range after `note@` for cycles. Line breaks in the note become spaces.
+    fn log_text(&self) -> String {
+        let mut s = match self.target {
+            AnnotationTarget::Instruction(_) => "note: ".to_string(),
+            AnnotationTarget::Cycles { start, end } => format!("note@{}..{}: ", start, end),
+        };
+        s.extend(
+            self.note
+                .chars()
+                .map(|c| if matches!(c, '\n' | '\r') { ' ' } else { c }),
+        );
+        for tag in &self.tags {
+            s.push_str(" #");
+            s.push_str(tag);
+        }
+        s
+    }
+
+    fn from_log_text(id: Id, text: &str) -> Option<Self> {
+        let rest = text.strip_prefix("note")?;
+        let (target, rest) = match rest.strip_prefix(": ") {
+            Some(rest) => (AnnotationTarget::Instruction(id), rest),
+            None => {
+                let (range, rest) = rest.strip_prefix('@')?.split_once(": ")?;
+                let (start, end) = range.split_once("..")?;
+                let target = AnnotationTarget::Cycles {
+                    start: start.parse().ok()?,
+                    end: end.parse().ok()?,
+                };
+                (target, rest)
+            }
+        };
+        // tags are the `#words` at the end
+        let mut words: Vec<&str> = rest.split(' ').collect();
+        let mut tags = Vec::new();
+        while words.len() > 1
+            && words
+                .last()
+                .is_some_and(|w| w.len() > 1 && w.starts_with('#'))
+        {
+            tags.push(words.pop().unwrap()[1..].to_string());
+        }
+        tags.reverse();
+        Some(Self {
+            target,
+            note: words.join(" "),
+            tags,
+        })
+    }
+}
+
+// Notes about instructions and bursts of cycles, kept apart from the trace in
+// a sidecar file or written into a copy of it as kind 2 `L` records, which
+// Konata keeps without showing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Annotations {
+    pub annotations: Vec<Annotation>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, annotation: Annotation) {
+        self.annotations.push(annotation);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Annotation> {
+        self.annotations.iter()
+    }
+
+    pub fn tagged<'s>(&'s self, tag: &'s str) -> impl Iterator<Item = &'s Annotation> {
+        self.iter().filter(move |a| a.tags.iter().any(|t| t == tag))
+    }
+
+    pub fn for_instruction<'s>(
+        &'s self,
+        rec: &'s InstructionRecord,
+    ) -> impl Iterator<Item = &'s Annotation> {
+        self.iter().filter(move |a| a.applies_to(rec))
+    }
+
+    // The annotations a trace written by `write_annotated` carries. One
+    // about cycles is read back once, however many instructions it was on.
+    pub fn from_trace(trace: &Trace) -> Self {
+        let mut found = Self::new();
+        for rec in trace.instructions() {
+            for log in rec.logs.iter().filter(|l| l.kind == LogKind::Other) {
+                let text = String::from_utf8_lossy(trace.text(log.text));
+                if let Some(a) = Annotation::from_log_text(rec.id, &text)
+                    && !found.annotations.contains(&a)
+                {
+                    found.add(a);
+                }
+            }
+        }
+        found
+    }
+
+    #[cfg(feature = "json")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        crate::from_json(&std::fs::read(path)?)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        std::fs::write(path, crate::to_json(self)?)
+    }
+}
+
+// Copies a trace with the annotations inlined: each instruction's just after
+// its `I`, and one about cycles on the first instruction started in them.
+// A range no instruction starts in isn't written.
+pub fn write_annotated<W: Write>(input: &[u8], annotations: &Annotations, out: W) -> io::Result<W> {
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    let mut own: HashMap<Id, Vec<&Annotation>> = HashMap::new();
+    for a in annotations.iter() {
+        if let AnnotationTarget::Instruction(id) = a.target {
+            own.entry(id).or_default().push(a);
+        }
+    }
+    let mut ranges: Vec<(&Annotation, bool)> = annotations
+        .iter()
+        .filter(|a| matches!(a.target, AnnotationTarget::Cycles { .. }))
+        .map(|a| (a, false))
+        .collect();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if matches!(cmd, Command::Kanata { .. } | Command::Cycle { .. }) {
+            continue;
+        }
+        let cycle = clock.cycle();
+        w.write(cycle, &cmd.map_text(|s| s.get(input)))?;
+        let Command::Instruction { id_in_file, .. } = cmd else {
+            continue;
+        };
+        let own = own.get(&id_in_file).into_iter().flatten().copied();
+        let placed = ranges.iter_mut().filter_map(|(a, done)| match a.target {
+            AnnotationTarget::Cycles { start, end } if !*done && (start..end).contains(&cycle) => {
+                *done = true;
+                Some(*a)
+            }
+            _ => None,
+        });
+        for a in own.chain(placed) {
+            let text = a.log_text();
+            w.write(
+                cycle,
+                &Command::Log {
+                    id: id_in_file,
+                    kind: LogKind::Other,
+                    text: text.as_bytes(),
+                },
+            )?;
+        }
+    }
+    w.finish()
+}
@@ -0,0 +1,1068 @@
+use clap::{Parser as _, Subcommand, ValueEnum};
+use kanata::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+#[derive(clap::Parser)]
+#[command(
+    name = "kanata-tool",
+    version,
+    about = "Inspect and transform Kanata pipeline traces"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Check that a trace parses and reconstructs cleanly
+    Validate {
+        input: PathBuf,
+        /// Treat lanes as issue slots that only one instruction can hold
+        #[arg(long)]
+        exclusive_lanes: bool,
+        /// How many cycles a relative cycle record may step back unflagged
+        #[arg(long, default_value_t = 0)]
+        max_back: u64,
+    },
+    /// Print retirement, IPC and per-stage latency figures
+    Stats {
+        input: PathBuf,
+        /// Also write the CSV report tables into this directory
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Also write the summary metrics in Prometheus text format to this file
+        #[arg(long)]
+        prometheus: Option<PathBuf>,
+        /// Also write the wakeup latency heatmap to this file, as JSON if it
+        /// ends in .json and as CSV otherwise
+        #[arg(long)]
+        wakeups: Option<PathBuf>,
+        /// Window size in cycles for the report's IPC and occupancy tables
+        #[arg(long, default_value_t = 1000)]
+        window: u64,
+    },
+    /// Convert a trace to another format, inferred from the output extension
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, value_enum)]
+        to: Option<Target>,
+        /// Also write the output's index and stage table next to it, as
+        /// `<output>.kidx`, for Kanata and binary output
+        #[arg(long)]
+        index: bool,
+    },
+    /// Keep only the instructions matching every given criterion
+    Filter {
+        input: PathBuf,
+        output: PathBuf,
+        /// Instructions alive at some point in LO..HI
+        #[arg(long)]
+        cycles: Option<Span>,
+        /// Instruction ids in LO..HI
+        #[arg(long)]
+        ids: Option<Span>,
+        #[arg(long)]
+        thread: Option<u32>,
+        #[arg(long, conflicts_with = "flushed")]
+        retired: bool,
+        #[arg(long)]
+        flushed: bool,
+        /// A filter expression, e.g. 'thread == 1 && stage("X") > 20'
+        #[arg(long = "where", value_parser = FilterExpr::parse)]
+        expr: Option<FilterExpr>,
+    },
+    /// Extract the given instructions into a small trace of their own
+    Slice {
+        input: PathBuf,
+        output: PathBuf,
+        /// Instruction ids, comma separated
+        #[arg(long, value_delimiter = ',', required_unless_present_any = ["producers_of", "consumers_of"])]
+        ids: Vec<Id>,
+        /// Also take this instruction and everything it waited on, transitively
+        #[arg(long)]
+        producers_of: Option<Id>,
+        /// Also take this instruction and everything that waited on it
+        #[arg(long)]
+        consumers_of: Option<Id>,
+        /// How many dependency edges to follow from either of those
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Only instructions starting at most this many cycles from it
+        #[arg(long)]
+        cycles: Option<u64>,
+    },
+    /// Split a trace into files of at most N instructions each
+    Split {
+        input: PathBuf,
+        out_dir: PathBuf,
+        #[arg(long, default_value_t = 100_000)]
+        instructions: usize,
+    },
+    /// Keep every Nth instruction, or a seeded random fraction of them
+    Sample {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, required_unless_present = "rate", conflicts_with = "rate")]
+        every: Option<u64>,
+        #[arg(long)]
+        rate: Option<f64>,
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Cut a trace short, dropping instructions still in flight at the cut
+    Truncate {
+        input: PathBuf,
+        output: PathBuf,
+        /// Stop once this many instructions have retired
+        #[arg(long, required_unless_present = "cycles", conflicts_with = "cycles")]
+        retired: Option<u64>,
+        /// Stop this many cycles after the first instruction
+        #[arg(long)]
+        cycles: Option<u64>,
+    },
+    /// Merge every K cycles into one for an overview of a long trace
+    Coarsen {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long)]
+        factor: u64,
+    },
+    /// Interleave several traces by cycle, renumbering instructions
+    Merge {
+        output: PathBuf,
+        #[arg(num_args = 2.., required = true)]
+        inputs: Vec<PathBuf>,
+        /// Give each input its own thread id
+        #[arg(long)]
+        threads: bool,
+    },
+    /// Shift a trace so its first instruction starts at the given cycle
+    Rebase {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        epoch: i64,
+    },
+    /// Put the commands of a trace whose cycles go briefly backwards in order
+    Sort {
+        input: PathBuf,
+        output: PathBuf,
+        /// How many cycles a command may lag behind and still be put in place
+        #[arg(long, default_value_t = 64)]
+        window: u64,
+    },
+    /// Drop records that change nothing, like repeated logs and dependencies
+    Optimize { input: PathBuf, output: PathBuf },
+    /// Drop a half-written last line and flush what's still in flight
+    Repair {
+        input: PathBuf,
+        output: PathBuf,
+        /// Also hold cycle records that go back at the cycle reached, moving
+        /// everything after them along
+        #[arg(long)]
+        reanchor: bool,
+        /// With --reanchor, how many cycles a relative record may step back
+        #[arg(long, default_value_t = 0)]
+        max_back: u64,
+    },
+    /// Keep only the left pane labels, for a much smaller trace
+    Compact {
+        input: PathBuf,
+        output: PathBuf,
+        /// Cut each instruction's label to this many bytes
+        #[arg(long)]
+        label_budget: Option<usize>,
+    },
+    /// Append traces end to end into one continuous timeline
+    Concat {
+        output: PathBuf,
+        #[arg(num_args = 2.., required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Compare two traces; exits with status 1 when they differ
+    Diff {
+        a: PathBuf,
+        b: PathBuf,
+        /// How many differing instructions to print
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Print a unified diff of the normalized traces instead
+        #[arg(long)]
+        text: bool,
+    },
+    /// Find instructions by the text of their panes, best matches first
+    Search {
+        input: PathBuf,
+        query: String,
+        /// How many hits to print
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Keep the trace's label index in this cache directory, and search
+        /// through it
+        #[cfg(feature = "cache")]
+        #[arg(long)]
+        cache: Option<PathBuf>,
+    },
+    /// Write a synthetic trace from a seeded pipeline model
+    Generate {
+        output: PathBuf,
+        #[arg(long, default_value_t = 10_000)]
+        instructions: u64,
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+        #[arg(long, default_value_t = 8)]
+        depth: usize,
+        #[arg(long, default_value_t = 4)]
+        width: usize,
+        #[arg(long, default_value_t = 0.05)]
+        mispredict_rate: f64,
+        #[arg(long, default_value_t = 0.05)]
+        miss_rate: f64,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Target {
+    Kanata,
+    Kanatab,
+    Chrome,
+    O3,
+    Vcd,
+    Speedscope,
+    Collapsed,
+    Graphml,
+    Dot,
+    Csv,
+    Jsonl,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl Target {
+    fn infer(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        Some(match name.rsplit('.').next()? {
+            "log" | "kanata" => Target::Kanata,
+            "kanatab" => Target::Kanatab,
+            "json" if name.ends_with(".speedscope.json") => Target::Speedscope,
+            "json" => Target::Chrome,
+            "o3" | "pipeview" => Target::O3,
+            "vcd" => Target::Vcd,
+            "folded" | "collapsed" => Target::Collapsed,
+            "graphml" => Target::Graphml,
+            "dot" | "gv" => Target::Dot,
+            "csv" => Target::Csv,
+            "jsonl" => Target::Jsonl,
+            #[cfg(feature = "sqlite")]
+            "db" | "sqlite" => Target::Sqlite,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Span {
+    lo: i64,
+    hi: i64,
+}
+
+impl FromStr for Span {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (lo, hi) = s.split_once("..").ok_or("expected LO..HI")?;
+        let num = |v: &str| v.trim().parse::<i64>().map_err(|e| format!("{}: {}", v, e));
+        Ok(Span {
+            lo: if lo.is_empty() { i64::MIN } else { num(lo)? },
+            hi: if hi.is_empty() { i64::MAX } else { num(hi)? },
+        })
+    }
+}
+
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+fn validate(
+    input: &Path,
+    config: &OverlapConfig,
+    cycle_check: &CycleCheck,
+) -> io::Result<ExitCode> {
+    let data = read_any(input)?;
+    match Trace::new(&data) {
+        Ok(trace) => {
+            let cycles = DepGraph::new(&trace).cycles();
+            let lines = (!data.starts_with(BINARY_MAGIC)).then(|| LineIndex::new(&data));
+            let overlaps = match lines {
+                Some(_) => {
+                    let mut v = cycle_diagnostics(&data, cycle_check);
+                    v.extend(overlap_diagnostics(&data, config));
+                    v.sort_by_key(|d| d.span.start);
+                    v
+                }
+                None => Vec::new(),
+            };
+            if cycles.is_empty() && overlaps.is_empty() {
+                println!(
+                    "{}: ok, {} instructions over {} cycles",
+                    input.display(),
+                    trace.instructions().len(),
+                    trace.end_cycle() - trace.start_cycle()
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            for d in overlaps {
+                let first = d
+                    .related
+                    .first()
+                    .and_then(|r| lines.as_ref().map(|l| l.line(r.start)));
+                println!(
+                    "{}:{}:{}: {}: {}{}",
+                    input.display(),
+                    d.start.line + 1,
+                    d.start.character + 1,
+                    d.code,
+                    d.message,
+                    first.map_or_else(String::new, |l| format!(" (see line {})", l + 1))
+                );
+            }
+            for cycle in cycles {
+                println!("{}: dependency cycle:", input.display());
+                for m in cycle.members {
+                    let at = match &lines {
+                        Some(l) => format!("line {}", l.line(m.offset) + 1),
+                        None => format!("offset {}", m.offset),
+                    };
+                    println!("  id {} at {}: {}", m.id, at, m.label);
+                }
+            }
+            Ok(ExitCode::FAILURE)
+        }
+        Err(e) => {
+            // report everything wrong with a text trace, not just the first
+            let lines = (!data.starts_with(BINARY_MAGIC)).then(|| LineIndex::new(&data));
+            let mut errors = match lines {
+                Some(_) => check(&data),
+                None => Vec::new(),
+            };
+            if errors.is_empty() {
+                errors.push(e);
+            }
+            for e in errors {
+                match lines.as_ref().map(|l| l.line_col(e.offset)) {
+                    Some((line, col)) => {
+                        println!("{}:{}:{}: {:?}", input.display(), line + 1, col + 1, e.kind)
+                    }
+                    None => println!("{}: {}", input.display(), e),
+                }
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn quantile(v: Option<u64>) -> String {
+    v.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+fn stats(
+    input: &Path,
+    report: Option<&Path>,
+    prometheus: Option<&Path>,
+    wakeups: Option<&Path>,
+    window: u64,
+) -> io::Result<()> {
+    let data = read_any(input)?;
+    let trace = Trace::new(&data)?;
+    let stats = Stats::from_trace(&trace);
+    let meta = RunMetadata::for_trace(input)?;
+
+    if let Some(run) = meta.as_ref().and_then(RunMetadata::describe) {
+        println!("{}\n", run);
+    }
+    println!("{:<14}{:>12}", "instructions", stats.instructions());
+    println!("{:<14}{:>12}", "retired", stats.retired());
+    println!("{:<14}{:>12}", "flushed", stats.flushed());
+    println!("{:<14}{:>12}", "cycles", stats.cycles());
+    println!("{:<14}{:>12.3}", "ipc", stats.ipc());
+    let bw = trace.retire_bandwidth(None);
+    println!("{:<14}{:>12.3}", "commit util", bw.utilization());
+    println!("{:<14}{:>12}", "idle commits", bw.zero_cycles());
+    if let Some(&(stage, n)) = bw.stalls.first() {
+        let name = trace.stages().name(stage);
+        println!("{:<14}{:>12}", format!("  in {}", name), n);
+    }
+    if trace.threads().len() > 1 {
+        println!("{:<14}{:>12}", "threads", trace.threads().len());
+        println!(
+            "{:<14}{:>12.3}",
+            "switch rate",
+            trace.interleaving().switch_rate()
+        );
+    }
+    println!();
+    println!(
+        "{:<14}{:>10}{:>10}{:>8}{:>8}{:>8}",
+        "stage", "count", "mean", "p50", "p99", "max"
+    );
+    let row = |name: &str, s: Summary, p50: Option<u64>, p99: Option<u64>| {
+        println!(
+            "{:<14}{:>10}{:>10.2}{:>8}{:>8}{:>8}",
+            name,
+            s.count,
+            s.mean(),
+            quantile(p50),
+            quantile(p99),
+            s.max
+        );
+    };
+    for (id, name, s) in stats.iter() {
+        row(
+            name,
+            s,
+            stats.stage_quantile(id, 0.5),
+            stats.stage_quantile(id, 0.99),
+        );
+    }
+    row(
+        "(total)",
+        stats.latency(),
+        stats.latency_quantile(0.5),
+        stats.latency_quantile(0.99),
+    );
+
+    let full = || {
+        let report = Report::from_trace(&trace, ReportConfig { window });
+        match &meta {
+            Some(m) => report.with_metadata(m.clone()),
+            None => report,
+        }
+    };
+    if let Some(dir) = report {
+        std::fs::create_dir_all(dir)?;
+        full().write_csv(dir)?;
+    }
+    if let Some(path) = prometheus {
+        let name = input.file_name().unwrap_or_default().to_string_lossy();
+        let text = full().to_prometheus_text(&[("trace", &name)]);
+        std::fs::write(path, text)?;
+    }
+    if let Some(path) = wakeups {
+        let heatmap = trace.wakeup_heatmap();
+        if path.extension().is_some_and(|e| e == "json") {
+            heatmap.write_json(create(path)?)?;
+        } else {
+            heatmap.write_csv(create(path)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn as_text(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(BINARY_MAGIC) {
+        return Ok(data);
+    }
+    let mut out = Vec::new();
+    migrate(&data, &mut out)?;
+    Ok(out)
+}
+
+fn convert(input: &Path, output: &Path, to: Option<Target>, index: bool) -> io::Result<()> {
+    let target = to.or_else(|| Target::infer(output)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot infer a format for {}; pass --to", output.display()),
+        )
+    })?;
+    if !index {
+        return write_converted(input, output, target);
+    }
+    if !matches!(target, Target::Kanata | Target::Kanatab) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--index needs Kanata or binary output",
+        ));
+    }
+    write_converted(input, output, target)?;
+    let written = std::fs::read(output)?;
+    TraceIndex::build(&written, DEFAULT_INDEX_INTERVAL)?.save(output)
+}
+
+fn write_converted(input: &Path, output: &Path, target: Target) -> io::Result<()> {
+    let data = read_any(input)?;
+    match target {
+        Target::Kanata => migrate(&data, create(output)?),
+        Target::Kanatab => {
+            let mut w = BinaryWriter::new(create(output)?);
+            for (_, cmd) in Commands::new(&data)? {
+                w.write_ref(&cmd?, &data)?;
+            }
+            w.finish().map(drop)
+        }
+        Target::Csv => write_events_csv(&as_text(data)?, create(output)?),
+        Target::Jsonl => write_events_jsonl(&as_text(data)?, create(output)?),
+        _ => {
+            let trace = Trace::new(&data)?;
+            match target {
+                Target::Chrome => {
+                    write_chrome_trace(&trace, &ChromeConfig::default(), create(output)?)
+                }
+                Target::O3 => write_o3pipeview(&trace, &O3Config::default(), create(output)?),
+                Target::Vcd => write_vcd(&trace, &VcdConfig::default(), create(output)?),
+                Target::Speedscope => {
+                    write_speedscope(&trace, SpeedscopeGroup::Pc, create(output)?)
+                }
+                Target::Collapsed => write_collapsed(&trace, create(output)?),
+                Target::Graphml => write_graphml(&trace, create(output)?),
+                Target::Dot => write_dot(&trace, None, create(output)?),
+                #[cfg(feature = "sqlite")]
+                Target::Sqlite => write_sqlite(&trace, output).map_err(io::Error::other),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+fn filter(input: &Path, output: &Path, sel: Filter) -> io::Result<()> {
+    let data = read_any(input)?;
+    let (_, report) = filter_input(&data, &sel, create(output)?)?;
+    eprintln!(
+        "kept {} of {} instructions",
+        report.kept, report.instructions
+    );
+    Ok(())
+}
+
+fn split(input: &Path, out_dir: &Path, per_file: usize) -> io::Result<()> {
+    let data = read_any(input)?;
+    let trace = Trace::new(&data)?;
+    let chunk: HashMap<Id, usize> = trace
+        .instructions()
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.id, i / per_file.max(1)))
+        .collect();
+
+    std::fs::create_dir_all(out_dir)?;
+    let mut sinks = HashMap::new();
+    let mut clock = Clock::new();
+    for (_, cmd) in Commands::new(&data)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        let Some(&c) = cmd.id().and_then(|id| chunk.get(&id)) else {
+            continue;
+        };
+        // a dependency on an instruction in another file has nothing to point at
+        if let Command::Dep { producer_id, .. } = cmd
+            && chunk.get(&producer_id) != Some(&c)
+        {
+            continue;
+        }
+        let sink = match sinks.entry(c) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let path = out_dir.join(format!("part-{:04}.log", c));
+                e.insert(CycleWriter::new(create(&path)?)?)
+            }
+        };
+        sink.write(clock.cycle(), &cmd.map_text(|s| s.get(&data)))?;
+    }
+    let files = sinks.len();
+    for (_, sink) in sinks {
+        sink.finish()?;
+    }
+    eprintln!("wrote {} files to {}", files, out_dir.display());
+    Ok(())
+}
+
+struct Source<'a> {
+    data: &'a [u8],
+    commands: Commands<'a>,
+    clock: Clock,
+    next: Option<Command>,
+    ids: HashMap<Id, Id>,
+}
+
+impl<'a> Source<'a> {
+    fn advance(&mut self) -> io::Result<()> {
+        self.next = None;
+        for (_, cmd) in &mut self.commands {
+            let cmd = cmd?;
+            self.clock.apply(&cmd);
+            if !matches!(cmd, Command::Kanata { .. } | Command::Cycle { .. }) {
+                self.next = Some(cmd);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn merge(output: &Path, inputs: &[PathBuf], threads: bool) -> io::Result<()> {
+    let data = inputs
+        .iter()
+        .map(read_any)
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut sources = Vec::new();
+    for d in &data {
+        let mut s = Source {
+            data: d,
+            commands: Commands::new(d)?,
+            clock: Clock::new(),
+            next: None,
+            ids: HashMap::new(),
+        };
+        s.advance()?;
+        sources.push(s);
+    }
+
+    let mut sink = CycleWriter::new(create(output)?)?;
+    let mut next_id = 0;
+    let mut next_retire = 0;
+    while let Some((i, _)) = sources
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.next.is_some())
+        .min_by_key(|&(i, s)| (s.clock.cycle(), i))
+    {
+        let s = &mut sources[i];
+        let cmd = s.next.take().unwrap();
+        let id = |id: Id| s.ids.get(&id).copied();
+        let mapped = match cmd {
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => {
+                s.ids.insert(id_in_file, next_id);
+                next_id += 1;
+                Some(Command::Instruction {
+                    id_in_file: next_id - 1,
+                    id_in_sim,
+                    thread_id: if threads { i as u32 } else { thread_id },
+                })
+            }
+            Command::Log { id: v, kind, text } => id(v).map(|id| Command::Log { id, kind, text }),
+            Command::Pipeline {
+                start,
+                id: v,
+                lane_id,
+                name,
+            } => id(v).map(|id| Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            }),
+            // as `slice` numbers them: only retirements take a retire id
+            Command::Retire { id: v, kind, .. } => id(v).map(|id| {
+                let retire = next_retire;
+                if kind == RetireKind::Retire {
+                    next_retire += 1;
+                }
+                Command::Retire { id, retire, kind }
+            }),
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => id(consumer_id)
+                .zip(id(producer_id))
+                .map(|(c, p)| Command::Dep {
+                    consumer_id: c,
+                    producer_id: p,
+                    kind,
+                    label,
+                }),
+            Command::StageColor { .. } => Some(cmd),
+            Command::Kanata { .. } | Command::Cycle { .. } => None,
+        };
+        if let Some(cmd) = mapped {
+            let data = s.data;
+            sink.write(s.clock.cycle(), &cmd.map_text(|t| t.get(data)))?;
+        }
+        s.advance()?;
+    }
+    sink.finish()?;
+    eprintln!("merged {} instructions", next_id);
+    Ok(())
+}
+
+fn shape(trace: &Trace, rec: &InstructionRecord) -> String {
+    let mut s = match rec.latency() {
+        Some(l) => format!("{:>5}", l),
+        None => format!("{:>5}", "-"),
+    };
+    if rec.is_flushed() {
+        s.push_str(" flush");
+    }
+    for span in &rec.stages {
+        s.push_str(&format!(
+            " {}@{}+{}",
+            trace.stages().name(span.stage),
+            span.start - rec.start,
+            span.cycles()
+        ));
+    }
+    s
+}
+
+fn diff(a: &Path, b: &Path, limit: usize, text: bool) -> io::Result<ExitCode> {
+    let (da, db) = (read_any(a)?, read_any(b)?);
+    if text {
+        let out = textual_diff(&da, &db)?;
+        print!("{}", out);
+        return Ok(if out.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+    let mut ws = Workspace::new();
+    for (name, data) in [("a", da), ("b", db)] {
+        ws.add(name, Trace::from_vec(data)?)
+            .map_err(|kind| io::Error::new(io::ErrorKind::InvalidData, kind.message()))?;
+    }
+    let (ta, tb) = (ws.get("a").unwrap(), ws.get("b").unwrap());
+    let cmp = ws.compare("a", "b").unwrap();
+    let (sa, sb) = (&cmp.a, &cmp.b);
+    let (ma, mb) = (RunMetadata::for_trace(a)?, RunMetadata::for_trace(b)?);
+    if ma.is_some() || mb.is_some() {
+        // the runs by name where their metadata has one
+        let name = |m: &Option<RunMetadata>, path: &Path| {
+            m.as_ref()
+                .and_then(RunMetadata::describe)
+                .unwrap_or_else(|| path.display().to_string())
+        };
+        println!("a  {}", name(&ma, a));
+        println!("b  {}\n", name(&mb, b));
+    }
+
+    println!("{:<14}{:>12}{:>12}{:>12}", "", "a", "b", "delta");
+    let row = |name: &str, a: f64, b: f64| {
+        println!("{:<14}{:>12.3}{:>12.3}{:>+12.3}", name, a, b, b - a);
+    };
+    row(
+        "instructions",
+        sa.instructions() as f64,
+        sb.instructions() as f64,
+    );
+    row("retired", sa.retired() as f64, sb.retired() as f64);
+    row("flushed", sa.flushed() as f64, sb.flushed() as f64);
+    row("cycles", sa.cycles() as f64, sb.cycles() as f64);
+    row("ipc", sa.ipc(), sb.ipc());
+    row("latency", sa.latency().mean(), sb.latency().mean());
+    for (_, name, a, b) in cmp.stage_means() {
+        row(name, a, b);
+    }
+
+    let mut differ = 0;
+    let mut only_a = 0;
+    for ra in ta.instructions() {
+        let Some(rb) = tb.get(ra.id) else {
+            only_a += 1;
+            continue;
+        };
+        let (x, y) = (shape(ta, ra), shape(tb, rb));
+        if x != y {
+            if differ < limit {
+                println!("\nid {} {}", ra.id, String::from_utf8_lossy(&ta.label(ra)));
+                println!("  a {}", x);
+                println!("  b {}", y);
+            }
+            differ += 1;
+        }
+    }
+    let only_b = tb
+        .instructions()
+        .iter()
+        .filter(|r| ta.get(r.id).is_none())
+        .count();
+    println!(
+        "\n{} instructions differ, {} only in a, {} only in b",
+        differ, only_a, only_b
+    );
+    Ok(if differ + only_a + only_b == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn run(args: Args) -> io::Result<ExitCode> {
+    match args.command {
+        Cmd::Validate {
+            input,
+            exclusive_lanes,
+            max_back,
+        } => {
+            return validate(
+                &input,
+                &OverlapConfig { exclusive_lanes },
+                &CycleCheck { max_back },
+            );
+        }
+        Cmd::Stats {
+            input,
+            report,
+            prometheus,
+            wakeups,
+            window,
+        } => stats(
+            &input,
+            report.as_deref(),
+            prometheus.as_deref(),
+            wakeups.as_deref(),
+            window,
+        )?,
+        Cmd::Convert {
+            input,
+            output,
+            to,
+            index,
+        } => convert(&input, &output, to, index)?,
+        Cmd::Filter {
+            input,
+            output,
+            cycles,
+            ids,
+            thread,
+            retired,
+            flushed,
+            expr,
+        } => {
+            let clamp = |v: i64| Id::try_from(v.max(0)).unwrap_or(Id::MAX);
+            let mut sel = Filter::all();
+            if let Some(c) = cycles {
+                sel = sel.and(Filter::cycle_range(c.lo..c.hi));
+            }
+            if let Some(s) = ids {
+                sel = sel.and(Filter::id_range(clamp(s.lo)..clamp(s.hi)));
+            }
+            if let Some(t) = thread {
+                sel = sel.and(Filter::thread(t));
+            }
+            if retired {
+                sel = sel.and(Filter::retired());
+            }
+            if flushed {
+                sel = sel.and(Filter::flushed());
+            }
+            if let Some(e) = expr {
+                sel = sel.and(e.into());
+            }
+            filter(&input, &output, sel)?
+        }
+        Cmd::Sample {
+            input,
+            output,
+            every,
+            rate,
+            seed,
+        } => {
+            let sampling = match every {
+                Some(n) => Sampling::EveryNth(n),
+                None => Sampling::Random {
+                    rate: rate.unwrap_or(1.0),
+                    seed,
+                },
+            };
+            write_sampled(&read_any(&input)?, sampling, create(&output)?)?;
+        }
+        Cmd::Truncate {
+            input,
+            output,
+            retired,
+            cycles,
+        } => {
+            let until = match retired {
+                Some(n) => Until::RetiredInstructions(n),
+                None => Until::Cycles(cycles.unwrap_or(0)),
+            };
+            truncate(&read_any(&input)?, until, create(&output)?)?;
+        }
+        Cmd::Coarsen {
+            input,
+            output,
+            factor,
+        } => {
+            coarsen(&read_any(&input)?, factor, create(&output)?)?;
+        }
+        Cmd::Rebase {
+            input,
+            output,
+            epoch,
+        } => {
+            rebase(&read_any(&input)?, epoch, create(&output)?)?;
+        }
+        Cmd::Sort {
+            input,
+            output,
+            window,
+        } => {
+            let data = read_any(&input)?;
+            let (_, report) = sort_by_cycle(&data, window, create(&output)?)?;
+            eprintln!(
+                "reordered {} of {} commands, by up to {} cycles; {} clamped",
+                report.reordered, report.commands, report.max_lag, report.clamped
+            );
+        }
+        Cmd::Optimize { input, output } => {
+            let data = read_any(&input)?;
+            let (_, report) = optimize(&data, create(&output)?)?;
+            eprintln!(
+                "removed {} records: {} cycles, {} stages, {} logs, {} dependencies",
+                report.removed(),
+                report.cycles,
+                report.stages,
+                report.logs,
+                report.deps
+            );
+        }
+        Cmd::Repair {
+            input,
+            output,
+            reanchor,
+            max_back,
+        } => {
+            let mut data = read_any(&input)?;
+            // what's cut short is a text line; a binary trace has none
+            if data.starts_with(BINARY_MAGIC) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: repair takes a text trace", input.display()),
+                ));
+            }
+            if reanchor {
+                let check = CycleCheck { max_back };
+                let (fixed, report) = reanchor_cycles(&data, &check, Vec::new())?;
+                eprintln!(
+                    "reanchored {} cycle records, shifting the end by {} cycles",
+                    report.reanchored, report.shift
+                );
+                data = fixed;
+            }
+            let (_, report) = repair(&data, create(&output)?)?;
+            eprintln!(
+                "dropped {} bytes; ended {} stages and flushed {} instructions",
+                report.dropped,
+                report.ended,
+                report.flushed.len()
+            );
+        }
+        Cmd::Compact {
+            input,
+            output,
+            label_budget,
+        } => {
+            let config = CompactConfig { label_budget };
+            compact(&read_any(&input)?, &config, create(&output)?)?;
+        }
+        Cmd::Concat { output, inputs } => {
+            let data = inputs
+                .iter()
+                .map(read_any)
+                .collect::<io::Result<Vec<_>>>()?;
+            let inputs: Vec<&[u8]> = data.iter().map(Vec::as_slice).collect();
+            concat(&inputs, create(&output)?)?;
+        }
+        Cmd::Slice {
+            input,
+            output,
+            ids,
+            producers_of,
+            consumers_of,
+            depth,
+            cycles,
+        } => {
+            let data = read_any(&input)?;
+            let trace = Trace::new(&data)?;
+            let mut ids: IdSet = ids.into_iter().collect();
+            let graph = DepGraph::new(&trace);
+            let bound = SliceBound { depth, cycles };
+            if let Some(target) = producers_of {
+                ids.extend(graph.backward_slice(target, bound));
+            }
+            if let Some(source) = consumers_of {
+                ids.extend(graph.forward_slice(source, bound));
+            }
+            slice(&trace, &ids, create(&output)?)?;
+            let found = ids.iter().filter(|&&id| trace.get(id).is_some()).count();
+            eprintln!("kept {} of {} instructions asked for", found, ids.len());
+        }
+        Cmd::Split {
+            input,
+            out_dir,
+            instructions,
+        } => split(&input, &out_dir, instructions)?,
+        Cmd::Merge {
+            output,
+            inputs,
+            threads,
+        } => merge(&output, &inputs, threads)?,
+        Cmd::Diff { a, b, limit, text } => return diff(&a, &b, limit, text),
+        Cmd::Search {
+            input,
+            query,
+            limit,
+            #[cfg(feature = "cache")]
+            cache,
+        } => {
+            let data = read_any(&input)?;
+            let trace = Trace::new(&data)?;
+            #[cfg(feature = "cache")]
+            let hits = match cache {
+                Some(dir) => trace.search_indexed(&TraceCache::new(dir).labels(&data)?, &query),
+                None => trace.search(&query),
+            };
+            #[cfg(not(feature = "cache"))]
+            let hits = trace.search(&query);
+            for h in hits.iter().take(limit) {
+                println!("{}\t{}\t{}\t{}", h.id, h.cycle, h.pane.name(), h.snippet);
+            }
+            if hits.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Cmd::Generate {
+            output,
+            instructions,
+            seed,
+            depth,
+            width,
+            mispredict_rate,
+            miss_rate,
+        } => {
+            let config = GenConfig {
+                seed,
+                instructions,
+                depth,
+                width,
+                mispredict_rate,
+                miss_rate,
+                ..GenConfig::default()
+            };
+            generate(&config, create(&output)?)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("kanata-tool: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,583 @@
+use crate::{
+    Checkpoint, Clock, Command, DEFAULT_INDEX_INTERVAL, DepKind, Id, Index, LogKind, ParseError,
+    ParseErrorKind, Parser, RetireKind, StrRef, Trace,
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::Range;
+
+pub const BINARY_MAGIC: &[u8; 8] = b"KANATAB\0";
+pub const BINARY_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = BINARY_MAGIC.len() + 1;
+const TRAILER_LEN: usize = 8;
+
+const TAG_HEADER: u8 = 0;
+const TAG_CYCLE: u8 = 1;
+const TAG_CYCLE_ABS: u8 = 2;
+const TAG_INSTRUCTION: u8 = 3;
+const TAG_LOG: u8 = 4;
+const TAG_START: u8 = 5;
+const TAG_END: u8 = 6;
+const TAG_RETIRE: u8 = 7;
+const TAG_DEP: u8 = 8;
+const TAG_STAGE: u8 = 9;
+const TAG_SYNC: u8 = 10;
+const TAG_DEP_LABEL: u8 = 11;
+const TAG_STAGE_COLOR: u8 = 12;
+
+pub(crate) fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub(crate) fn unzigzag(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+pub(crate) fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub(crate) fn put_checkpoint(buf: &mut Vec<u8>, cp: &Checkpoint) {
+    put_varint(buf, cp.offset as u64);
+    put_varint(buf, zigzag(cp.cycle));
+    put_varint(buf, cp.commands);
+    put_varint(buf, cp.instructions);
+}
+
+// Ids are stored relative to the most recent `I`, which keeps them to a byte
+// or two for any reasonably sized in-flight window.
+#[derive(Default)]
+struct State {
+    last_id: Id,
+    stages: HashMap<Vec<u8>, u64>,
+}
+
+impl State {
+    fn id_delta(&self, id: Id) -> u64 {
+        zigzag((id as i64).wrapping_sub(self.last_id as i64))
+    }
+}
+
+pub struct BinaryWriter<W: Write> {
+    out: W,
+    buf: Vec<u8>,
+    pos: usize,
+    interval: usize,
+    next: usize,
+    clock: Clock,
+    at: Checkpoint,
+    checkpoints: Vec<Checkpoint>,
+    state: State,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            buf: Vec::new(),
+            pos: 0,
+            interval: DEFAULT_INDEX_INTERVAL,
+            next: 0,
+            clock: Clock::new(),
+            at: Checkpoint::default(),
+            checkpoints: Vec::new(),
+            state: State::default(),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: usize) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        if self.pos == 0 {
+            self.out.write_all(BINARY_MAGIC)?;
+            self.out.write_all(&[BINARY_VERSION])?;
+            self.pos = HEADER_LEN;
+            self.next = HEADER_LEN;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self) -> io::Result<()> {
+        self.out.write_all(&self.buf)?;
+        self.pos += self.buf.len();
+        self.buf.clear();
+        Ok(())
+    }
+
+    pub fn write<T: AsRef<[u8]>>(&mut self, cmd: &Command<T>) -> io::Result<()> {
+        self.header()?;
+        if self.pos >= self.next {
+            self.checkpoints.push(Checkpoint {
+                offset: self.pos,
+                cycle: self.clock.cycle(),
+                ..self.at
+            });
+            self.next = self.pos + self.interval.max(1);
+            self.state = State::default();
+            self.buf.push(TAG_SYNC);
+        }
+
+        let buf = &mut self.buf;
+        let state = &mut self.state;
+        match cmd {
+            Command::Kanata { version } => {
+                buf.push(TAG_HEADER);
+                put_varint(buf, *version as u64);
+            }
+            Command::Cycle { abs, value } => {
+                buf.push(if *abs { TAG_CYCLE_ABS } else { TAG_CYCLE });
+                put_varint(buf, zigzag(*value as i64));
+            }
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => {
+                buf.push(TAG_INSTRUCTION);
+                put_varint(buf, state.id_delta(*id_in_file));
+                put_varint(
+                    buf,
+                    zigzag((*id_in_sim as i64).wrapping_sub(*id_in_file as i64)),
+                );
+                put_varint(buf, *thread_id as u64);
+                state.last_id = *id_in_file;
+            }
+            Command::Log { id, kind, text } => {
+                let text = text.as_ref();
+                if u16::try_from(text.len()).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "log text longer than 65535 bytes",
+                    ));
+                }
+                buf.push(TAG_LOG);
+                put_varint(buf, state.id_delta(*id));
+                buf.push(*kind as u8);
+                put_varint(buf, text.len() as u64);
+                buf.extend_from_slice(text);
+            }
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                let name = name.as_ref();
+                let stage = match state.stages.get(name) {
+                    Some(&i) => i,
+                    None => {
+                        if u16::try_from(name.len()).is_err() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "stage name longer than 65535 bytes",
+                            ));
+                        }
+                        let i = state.stages.len() as u64;
+                        buf.push(TAG_STAGE);
+                        put_varint(buf, name.len() as u64);
+                        buf.extend_from_slice(name);
+                        state.stages.insert(name.to_vec(), i);
+                        i
+                    }
+                };
+                buf.push(if *start { TAG_START } else { TAG_END });
+                put_varint(buf, state.id_delta(*id));
+                put_varint(buf, *lane_id as u64);
+                put_varint(buf, stage);
+            }
+            Command::Retire { id, retire, kind } => {
+                buf.push(TAG_RETIRE);
+                put_varint(buf, state.id_delta(*id));
+                #[allow(clippy::unnecessary_cast)] // already u64 with wide ids
+                put_varint(buf, *retire as u64);
+                buf.push(*kind as u8);
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => {
+                let label = label.as_ref().map(|l| l.as_ref());
+                if label.is_some_and(|l| u16::try_from(l.len()).is_err()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "dependency label longer than 65535 bytes",
+                    ));
+                }
+                buf.push(if label.is_some() {
+                    TAG_DEP_LABEL
+                } else {
+                    TAG_DEP
+                });
+                put_varint(buf, state.id_delta(*consumer_id));
+                put_varint(buf, state.id_delta(*producer_id));
+                buf.push(*kind as u8);
+                if let Some(label) = label {
+                    put_varint(buf, label.len() as u64);
+                    buf.extend_from_slice(label);
+                }
+            }
+            Command::StageColor { name, color } => {
+                let name = name.as_ref();
+                if u16::try_from(name.len()).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "stage name longer than 65535 bytes",
+                    ));
+                }
+                buf.push(TAG_STAGE_COLOR);
+                put_varint(buf, *color as u64);
+                put_varint(buf, name.len() as u64);
+                buf.extend_from_slice(name);
+            }
+        }
+        self.emit()?;
+
+        self.clock.apply(cmd);
+        self.at.commands += 1;
+        self.at.instructions += matches!(cmd, Command::Instruction { .. }) as u64;
+        Ok(())
+    }
+
+    pub fn write_ref(&mut self, cmd: &Command<StrRef>, input: &[u8]) -> io::Result<()> {
+        self.write(&cmd.map_text(|s| s.get(input)))
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.header()?;
+        let index = self.pos as u64;
+        let end = Checkpoint {
+            offset: self.pos,
+            cycle: self.clock.cycle(),
+            ..self.at
+        };
+        let buf = &mut self.buf;
+        put_varint(buf, self.interval as u64);
+        put_varint(buf, self.checkpoints.len() as u64);
+        for cp in self.checkpoints.iter().chain([&end]) {
+            put_checkpoint(buf, cp);
+        }
+        buf.extend_from_slice(&index.to_le_bytes());
+        self.emit()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub fn convert_to_binary<W: Write>(input: &[u8], out: W) -> io::Result<W> {
+    let mut w = BinaryWriter::new(out);
+    for (_, cmd) in Parser::new(input).extensions() {
+        w.write_ref(&cmd?, input)?;
+    }
+    w.finish()
+}
+
+pub(crate) struct Decoder<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            kind,
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| self.error(ParseErrorKind::UnexpectedEof))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, ParseError> {
+        let start = self.pos;
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            // the tenth byte has room for one bit
+            if shift == 63 && b & 0x7e != 0 {
+                break;
+            }
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(ParseError {
+            offset: start,
+            kind: ParseErrorKind::ValueTooBig,
+        })
+    }
+
+    pub(crate) fn checkpoint(&mut self) -> Result<Checkpoint, ParseError> {
+        Ok(Checkpoint {
+            offset: self.varint()? as usize,
+            cycle: unzigzag(self.varint()?),
+            commands: self.varint()?,
+            instructions: self.varint()?,
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        let v = self.varint()?;
+        u32::try_from(v).map_err(|_| self.error(ParseErrorKind::ValueTooBig))
+    }
+
+    fn id(&mut self, last: Id) -> Result<Id, ParseError> {
+        let v = (last as i64).wrapping_add(unzigzag(self.varint()?));
+        Id::try_from(v).map_err(|_| self.error(ParseErrorKind::ValueTooBig))
+    }
+
+    fn retire_id(&mut self) -> Result<Id, ParseError> {
+        let v = self.varint()?;
+        Id::try_from(v).map_err(|_| self.error(ParseErrorKind::ValueTooBig))
+    }
+
+    fn text(&mut self) -> Result<StrRef, ParseError> {
+        let len =
+            u16::try_from(self.varint()?).map_err(|_| self.error(ParseErrorKind::TextTooLong))?;
+        if self.data.len() - self.pos < len as usize {
+            return Err(self.error(ParseErrorKind::UnexpectedEof));
+        }
+        let s = StrRef::new(self.pos as u64, len);
+        self.pos += len as usize;
+        Ok(s)
+    }
+}
+
+pub struct BinaryTrace<'a> {
+    data: &'a [u8],
+    index: Index,
+}
+
+impl<'a> BinaryTrace<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, ParseError> {
+        let invalid = ParseError {
+            offset: 0,
+            kind: ParseErrorKind::InvalidHeader,
+        };
+        if data.len() < HEADER_LEN + TRAILER_LEN
+            || !data.starts_with(BINARY_MAGIC)
+            || data[BINARY_MAGIC.len()] != BINARY_VERSION
+        {
+            return Err(invalid);
+        }
+        let (body, trailer) = data.split_at(data.len() - TRAILER_LEN);
+        let start = u64::from_le_bytes(trailer.try_into().unwrap()) as usize;
+        if !(HEADER_LEN..=body.len()).contains(&start) {
+            return Err(invalid);
+        }
+
+        let mut d = Decoder {
+            data: body,
+            pos: start,
+        };
+        let interval = d.varint()? as usize;
+        let n = d.varint()? as usize;
+        let mut checkpoints = Vec::with_capacity(n.min(body.len()));
+        for _ in 0..=n {
+            checkpoints.push(d.checkpoint()?);
+        }
+        let end = checkpoints.pop().unwrap();
+        if end.offset != start || checkpoints.iter().any(|c| c.offset >= start) {
+            return Err(invalid);
+        }
+        Ok(Self {
+            data,
+            index: Index::from_parts(interval, checkpoints, end),
+        })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn commands(&self) -> BinaryReader<'a> {
+        self.commands_at(Checkpoint {
+            offset: HEADER_LEN,
+            ..Checkpoint::default()
+        })
+    }
+
+    pub fn commands_at(&self, cp: Checkpoint) -> BinaryReader<'a> {
+        BinaryReader {
+            d: Decoder {
+                data: &self.data[..self.index.end().offset],
+                pos: cp.offset.max(HEADER_LEN),
+            },
+            state: State::default(),
+            stages: Vec::new(),
+            clock: Clock::at(cp.cycle),
+        }
+    }
+
+    pub fn trace(&self) -> Result<Trace<'a>, ParseError> {
+        Trace::from_source(self.commands())
+    }
+
+    // Same caveat as `Index::window`: instructions created before the
+    // checkpoint preceding `cycles.start` are not part of the window.
+    pub fn window(&self, cycles: Range<i64>) -> Result<Trace<'a>, ParseError> {
+        let cp = self.index.seek_cycle(cycles.start);
+        Trace::from_source_at(self.commands_at(cp), cp.cycle, Some(cycles.end))
+    }
+}
+
+pub struct BinaryReader<'a> {
+    d: Decoder<'a>,
+    state: State,
+    stages: Vec<StrRef>,
+    clock: Clock,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, ParseError> {
+        Ok(BinaryTrace::new(data)?.commands())
+    }
+
+    // What the commands' texts point into.
+    pub fn input(&self) -> &'a [u8] {
+        self.d.data
+    }
+
+    // The cycle as of the last command read.
+    // Not `cycle`, which would be shadowed by `Iterator::cycle` on `&mut`.
+    pub fn current_cycle(&self) -> i64 {
+        self.clock.cycle()
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        loop {
+            let d = &mut self.d;
+            let last = self.state.last_id;
+            let cmd = match d.byte()? {
+                TAG_SYNC => {
+                    self.state = State::default();
+                    self.stages.clear();
+                    continue;
+                }
+                TAG_STAGE => {
+                    let name = d.text()?;
+                    self.stages.push(name);
+                    continue;
+                }
+                TAG_HEADER => Command::Kanata { version: d.u32()? },
+                tag @ (TAG_CYCLE | TAG_CYCLE_ABS) => {
+                    let value = i32::try_from(unzigzag(d.varint()?))
+                        .map_err(|_| d.error(ParseErrorKind::ValueTooBig))?;
+                    Command::Cycle {
+                        abs: tag == TAG_CYCLE_ABS,
+                        value,
+                    }
+                }
+                TAG_INSTRUCTION => {
+                    let id_in_file = d.id(last)?;
+                    let id_in_sim = d.id(id_in_file)?;
+                    self.state.last_id = id_in_file;
+                    Command::Instruction {
+                        id_in_file,
+                        id_in_sim,
+                        thread_id: d.u32()?,
+                    }
+                }
+                TAG_LOG => {
+                    let id = d.id(last)?;
+                    let kind = LogKind::try_from(d.byte()?).map_err(|e| d.error(e))?;
+                    Command::Log {
+                        id,
+                        kind,
+                        text: d.text()?,
+                    }
+                }
+                tag @ (TAG_START | TAG_END) => {
+                    let id = d.id(last)?;
+                    let lane_id = d.u32()?;
+                    let stage = d.varint()?;
+                    let name = *usize::try_from(stage)
+                        .ok()
+                        .and_then(|i| self.stages.get(i))
+                        .ok_or_else(|| d.error(ParseErrorKind::UnexpectedCharacter))?;
+                    Command::Pipeline {
+                        start: tag == TAG_START,
+                        id,
+                        lane_id,
+                        name,
+                    }
+                }
+                TAG_RETIRE => {
+                    let id = d.id(last)?;
+                    let retire = d.retire_id()?;
+                    let kind = RetireKind::try_from(d.byte()?).map_err(|e| d.error(e))?;
+                    Command::Retire { id, retire, kind }
+                }
+                tag @ (TAG_DEP | TAG_DEP_LABEL) => {
+                    let consumer_id = d.id(last)?;
+                    let producer_id = d.id(last)?;
+                    let kind = DepKind::try_from(d.byte()?).map_err(|e| d.error(e))?;
+                    let label = match tag {
+                        TAG_DEP_LABEL => Some(d.text()?),
+                        _ => None,
+                    };
+                    Command::Dep {
+                        consumer_id,
+                        producer_id,
+                        kind,
+                        label,
+                    }
+                }
+                TAG_STAGE_COLOR => {
+                    let color = d.u32()?;
+                    Command::StageColor {
+                        name: d.text()?,
+                        color,
+                    }
+                }
+                _ => {
+                    return Err(ParseError {
+                        offset: d.pos - 1,
+                        kind: ParseErrorKind::UnexpectedCharacter,
+                    });
+                }
+            };
+            return Ok(cmd);
+        }
+    }
+}
+
+impl<'a> Iterator for BinaryReader<'a> {
+    type Item = (usize, Result<Command, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.d.pos >= self.d.data.len() {
+            return None;
+        }
+        let offset = self.d.pos;
+        let res = self.command();
+        if let Ok(cmd) = &res {
+            self.clock.apply(cmd);
+        }
+        if res.is_err() {
+            // a corrupt record leaves no way to find the next one
+            self.d.pos = self.d.data.len();
+        }
+        Some((offset, res))
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{Checkpoint, Clock, Command, Cursor, Id, Index, ParseError, Parser};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bookmark {
+    Cycle(i64),
+    // the cycle the instruction was created in
+    Instruction(Id),
+}
+
+// Named positions in a trace, for pointing someone at `tlb-storm` rather
+// than at cycle 5812400. The sidecar file has a line per bookmark: the
+// name, `cycle` or `instruction`, and the number, separated by tabs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bookmarks {
+    marks: BTreeMap<String, Bookmark>,
+}
+
+fn invalid(line: usize, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("bookmarks line {}: {}", line + 1, what),
+    )
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces one of the same name. Names can't hold tabs or line breaks.
+    pub fn set(&mut self, name: impl Into<String>, mark: Bookmark) -> io::Result<()> {
+        let name = name.into();
+        if name.is_empty() || name.contains(['\t', '\n', '\r']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bookmark names must be non-empty, without tabs or line breaks",
+            ));
+        }
+        self.marks.insert(name, mark);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Bookmark> {
+        self.marks.get(name).copied()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Bookmark> {
+        self.marks.remove(name)
+    }
+
+    // By name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Bookmark)> {
+        self.marks.iter().map(|(k, &v)| (k.as_str(), v))
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut marks = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let (Some(name), Some(kind), Some(n), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid(i, "expected name, kind and number"));
+            };
+            let mark = match kind {
+                "cycle" => Bookmark::Cycle(n.parse().map_err(|_| invalid(i, "bad cycle"))?),
+                "instruction" => {
+                    Bookmark::Instruction(n.parse().map_err(|_| invalid(i, "bad id"))?)
+                }
+                _ => return Err(invalid(i, "unknown kind")),
+            };
+            marks.set(name, mark).map_err(|_| invalid(i, "bad name"))?;
+        }
+        Ok(marks)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (name, mark) in self.iter() {
+            let line = match mark {
+                Bookmark::Cycle(c) => format!("{}\tcycle\t{}\n", name, c),
+                Bookmark::Instruction(id) => format!("{}\tinstruction\t{}\n", name, id),
+            };
+            out.push_str(&line);
+        }
+        out
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    // The cycle a bookmark points at in an indexed text trace, or `None`
+    // for an unknown name or an instruction the trace doesn't have.
+    pub fn resolve(
+        &self,
+        name: &str,
+        input: &[u8],
+        index: &Index,
+    ) -> Result<Option<i64>, ParseError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(Bookmark::Cycle(c)) => Ok(Some(c)),
+            Some(Bookmark::Instruction(id)) => instruction_cycle(input, index, id),
+        }
+    }
+}
+
+// Looked for from the checkpoint that would hold it were ids numbered in
+// order, then from the start.
+#[allow(clippy::unnecessary_cast)] // already u64 with wide ids
+fn instruction_cycle(input: &[u8], index: &Index, id: Id) -> Result<Option<i64>, ParseError> {
+    let scan = |cp: Checkpoint| -> Result<Option<i64>, ParseError> {
+        let mut clock = Clock::at(cp.cycle);
+        for (_, cmd) in Parser::resume(input, cp.offset).extensions() {
+            let cmd = cmd?;
+            clock.apply(&cmd);
+            if let Command::Instruction { id_in_file, .. } = cmd
+                && id_in_file == id
+            {
+                return Ok(Some(clock.cycle()));
+            }
+        }
+        Ok(None)
+    };
+    let cp = index.seek_instruction(id as u64);
+    match scan(cp)? {
+        None if cp.offset > 0 => scan(Checkpoint::default()),
+        found => Ok(found),
+    }
+}
+
+impl Cursor<'_> {
+    // Moves to a bookmark's cycle. False, without moving, for a name that
+    // isn't there or an instruction that isn't in the trace.
+    pub fn seek_bookmark(&mut self, marks: &Bookmarks, name: &str) -> Result<bool, ParseError> {
+        let Some(cycle) = marks.resolve(name, self.input(), self.index())? else {
+            return Ok(false);
+        };
+        self.seek(cycle)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    Command, CommandSource, DepKind, Id, ParseError, RetireKind, SpanEvent, Spans, StageTable,
+    Stamped,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstructionEvent {
+    pub offset: usize,
+    pub cycle: i64,
+    pub id: Id,
+    pub id_in_sim: Id,
+    pub thread_id: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetireEvent {
+    pub offset: usize,
+    pub cycle: i64,
+    pub id: Id,
+    pub retire: Id,
+    pub kind: RetireKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DepEvent<'a> {
+    pub offset: usize,
+    pub cycle: i64,
+    pub consumer_id: Id,
+    pub producer_id: Id,
+    pub kind: DepKind,
+    pub label: Option<&'a [u8]>,
+}
+
+type Handlers<'f, T> = Vec<Box<dyn FnMut(&T) + 'f>>;
+type DepHandler<'f> = Box<dyn for<'a> FnMut(&DepEvent<'a>) + 'f>;
+type SpanHandler<'f> = Box<dyn FnMut(&SpanEvent, &StageTable) + 'f>;
+
+// Callbacks for the events of a trace, all fed from one pass over it, so
+// separate analyses can share the parse. Callbacks of a kind run in the
+// order they were added; stage spans arrive as `Spans` yields them, just
+// after the command that closed them.
+#[derive(Default)]
+pub struct EventBus<'f> {
+    instructions: Handlers<'f, InstructionEvent>,
+    retires: Handlers<'f, RetireEvent>,
+    deps: Vec<DepHandler<'f>>,
+    spans: Vec<SpanHandler<'f>>,
+}
+
+impl<'f> EventBus<'f> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_instruction(mut self, f: impl FnMut(&InstructionEvent) + 'f) -> Self {
+        self.instructions.push(Box::new(f));
+        self
+    }
+
+    pub fn on_retire(mut self, f: impl FnMut(&RetireEvent) + 'f) -> Self {
+        self.retires.push(Box::new(f));
+        self
+    }
+
+    pub fn on_dep(mut self, f: impl for<'a> FnMut(&DepEvent<'a>) + 'f) -> Self {
+        self.deps.push(Box::new(f));
+        self
+    }
+
+    pub fn on_stage_span(mut self, f: impl FnMut(&SpanEvent, &StageTable) + 'f) -> Self {
+        self.spans.push(Box::new(f));
+        self
+    }
+
+    // Stops at the first error, after the callbacks for what came before it.
+    pub fn run<'a, S: CommandSource<'a>>(&mut self, source: S) -> Result<(), ParseError> {
+        let Self {
+            instructions,
+            retires,
+            deps,
+            spans,
+        } = self;
+        let input = source.input();
+        let dispatch = |cmd: &Stamped| {
+            let (offset, cycle) = (cmd.offset, cmd.cycle);
+            match cmd.command {
+                Command::Instruction {
+                    id_in_file,
+                    id_in_sim,
+                    thread_id,
+                } => {
+                    let e = InstructionEvent {
+                        offset,
+                        cycle,
+                        id: id_in_file,
+                        id_in_sim,
+                        thread_id,
+                    };
+                    instructions.iter_mut().for_each(|f| f(&e));
+                }
+                Command::Retire { id, retire, kind } => {
+                    let e = RetireEvent {
+                        offset,
+                        cycle,
+                        id,
+                        retire,
+                        kind,
+                    };
+                    retires.iter_mut().for_each(|f| f(&e));
+                }
+                Command::Dep {
+                    consumer_id,
+                    producer_id,
+                    kind,
+                    label,
+                } => {
+                    let e = DepEvent {
+                        offset,
+                        cycle,
+                        consumer_id,
+                        producer_id,
+                        kind,
+                        label: label.map(|l| l.get(input)),
+                    };
+                    deps.iter_mut().for_each(|f| f(&e));
+                }
+                _ => {}
+            }
+        };
+        let mut events = Spans::new(Tap { source, dispatch });
+        while let Some(event) = events.next() {
+            let event = event?;
+            for f in spans.iter_mut() {
+                f(&event, events.stages());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Passes each command to `dispatch` on its way through.
+struct Tap<S, F> {
+    source: S,
+    dispatch: F,
+}
+
+impl<'a, S: CommandSource<'a>, F: FnMut(&Stamped)> CommandSource<'a> for Tap<S, F> {
+    fn input(&self) -> &'a [u8] {
+        self.source.input()
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        let cmd = self.source.next_command()?;
+        if let Ok(cmd) = &cmd {
+            (self.dispatch)(cmd);
+        }
+        Some(cmd)
+    }
+}
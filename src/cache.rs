@@ -0,0 +1,157 @@
+use crate::{
+    BINARY_MAGIC, BinaryTrace, Index, LabelIndex, Report, ReportConfig, StageTable, Trace,
+    from_bincode, read_any, to_bincode,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use xxhash_rust::xxh3::xxh3_64;
+
+// bumped whenever what's stored changes shape
+const CACHE_VERSION: u32 = 2;
+
+// The cache `Trace::open` and `Trace::open_any` note traces in, if any.
+static DEFAULT: RwLock<Option<TraceCache>> = RwLock::new(None);
+
+// Indexes, stage tables and reports kept on disk between runs, so tools that
+// keep reopening the same traces build each only once. Stage tables and
+// reports are keyed by `Trace::fingerprint`, so a trace reformatted or
+// converted to binary still hits; a map from the hash of a file's bytes to
+// its fingerprint lets a hit skip parsing altogether. Unreadable entries are
+// rebuilt. Once set as the default, every trace opened with `Trace::open`
+// is noted in it too.
+#[derive(Clone, Debug)]
+pub struct TraceCache {
+    dir: PathBuf,
+}
+
+impl TraceCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into().join(format!("v{}", CACHE_VERSION)),
+        }
+    }
+
+    // Makes `cache` the one `Trace::open` goes through, or stops it using
+    // one.
+    pub fn set_default(cache: Option<TraceCache>) {
+        *DEFAULT.write().unwrap_or_else(|e| e.into_inner()) = cache;
+    }
+
+    pub fn default_cache() -> Option<TraceCache> {
+        DEFAULT.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn load<T: DeserializeOwned>(&self, path: &PathBuf) -> Option<T> {
+        from_bincode(&std::fs::read(path).ok()?).ok()
+    }
+
+    // Written to a temporary file first, so a reader never sees half an entry.
+    fn store<T: Serialize>(&self, path: &PathBuf, value: &T) -> io::Result<()> {
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.dir))?;
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp, to_bincode(value)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    fn get_or_build<T: Serialize + DeserializeOwned>(
+        &self,
+        path: PathBuf,
+        build: impl FnOnce() -> io::Result<T>,
+    ) -> io::Result<T> {
+        if let Some(value) = self.load(&path) {
+            return Ok(value);
+        }
+        let value = build()?;
+        self.store(&path, &value)?;
+        Ok(value)
+    }
+
+    fn file_key(&self, input: &[u8]) -> PathBuf {
+        self.dir
+            .join("files")
+            .join(format!("{:016x}", xxh3_64(input)))
+    }
+
+    // The fingerprint of the trace in `input`, parsing it only the first
+    // time these bytes are seen.
+    pub fn fingerprint(&self, input: &[u8]) -> io::Result<u64> {
+        self.get_or_build(
+            self.file_key(input),
+            || Ok(Trace::new(input)?.fingerprint()),
+        )
+    }
+
+    // `Trace::open_any` that also notes the file's fingerprint, so asking
+    // for its index or report afterwards doesn't parse it again.
+    pub fn open(&self, path: impl AsRef<Path>) -> io::Result<Trace<'static>> {
+        let trace = Trace::from_vec(read_any(path)?)?;
+        self.note(&trace)?;
+        Ok(trace)
+    }
+
+    pub(crate) fn note(&self, trace: &Trace) -> io::Result<()> {
+        let key = self.file_key(trace.input());
+        if self.load::<u64>(&key).is_none() {
+            self.store(&key, &trace.fingerprint())?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, input: &[u8], name: String) -> io::Result<PathBuf> {
+        let fingerprint = self.fingerprint(input)?;
+        Ok(self.dir.join(format!("{:016x}", fingerprint)).join(name))
+    }
+
+    // Checkpoints hold byte offsets, so unlike the rest this is kept per
+    // file rather than per fingerprint.
+    pub fn index(&self, input: &[u8], interval: usize) -> io::Result<Index> {
+        let mut path = self.file_key(input);
+        path.set_extension(format!("index-{}", interval));
+        self.get_or_build(path, || {
+            if input.starts_with(BINARY_MAGIC) {
+                // binary traces carry their own, at the interval they were written with
+                return Ok(BinaryTrace::new(input)?.index().clone());
+            }
+            Ok(Index::build(input, interval)?)
+        })
+    }
+
+    // Kept per file next to the index, as it counts instructions by where
+    // they come in the file.
+    pub fn labels(&self, input: &[u8]) -> io::Result<LabelIndex> {
+        self.labels_with(input, || Ok(LabelIndex::build(&Trace::new(input)?)))
+    }
+
+    // `labels`, building a missing one with `build`, for a caller that has
+    // the trace parsed already.
+    pub(crate) fn labels_with(
+        &self,
+        input: &[u8],
+        build: impl FnOnce() -> io::Result<LabelIndex>,
+    ) -> io::Result<LabelIndex> {
+        let mut path = self.file_key(input);
+        path.set_extension("labels");
+        self.get_or_build(path, build)
+    }
+
+    pub fn stages(&self, input: &[u8]) -> io::Result<StageTable> {
+        let path = self.entry(input, "stages".to_string())?;
+        self.get_or_build(path, || Ok(Trace::new(input)?.stages().clone()))
+    }
+
+    pub fn report(&self, input: &[u8], config: ReportConfig) -> io::Result<Report> {
+        let path = self.entry(input, format!("report-{}", config.window))?;
+        self.get_or_build(path, || Ok(Report::from_trace(&Trace::new(input)?, config)))
+    }
+
+    // Drops everything cached.
+    pub fn clear(&self) -> io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        }
+    }
+}
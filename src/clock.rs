@@ -0,0 +1,30 @@
+use crate::Command;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Clock {
+    cycle: i64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at(cycle: i64) -> Self {
+        Self { cycle }
+    }
+
+    pub fn cycle(&self) -> i64 {
+        self.cycle
+    }
+
+    pub fn apply<T>(&mut self, cmd: &Command<T>) {
+        if let Command::Cycle { abs, value } = *cmd {
+            if abs {
+                self.cycle = value as i64;
+            } else {
+                self.cycle += value as i64;
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+
+fn invalid(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(feature = "msgpack")]
+pub fn to_msgpack<T: Serialize + ?Sized>(value: &T) -> io::Result<Vec<u8>> {
+    rmp_serde::to_vec(value).map_err(invalid)
+}
+
+#[cfg(feature = "msgpack")]
+pub fn from_msgpack<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    rmp_serde::from_slice(data).map_err(invalid)
+}
+
+#[cfg(feature = "bincode")]
+pub fn to_bincode<T: Serialize + ?Sized>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(invalid)
+}
+
+#[cfg(feature = "bincode")]
+pub fn from_bincode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    let (value, len) =
+        bincode::serde::decode_from_slice(data, bincode::config::standard()).map_err(invalid)?;
+    if len != data.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "trailing bytes after bincode value",
+        ));
+    }
+    Ok(value)
+}
+
+#[cfg(feature = "json")]
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> io::Result<Vec<u8>> {
+    serde_json::to_vec_pretty(value).map_err(invalid)
+}
+
+#[cfg(feature = "json")]
+pub fn from_json<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    serde_json::from_slice(data).map_err(invalid)
+}
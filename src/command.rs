@@ -1,13 +1,25 @@
 use crate::parser::ParseErrorKind;
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum LogKind {
     LeftPane = b'0',
     MouseOver = b'1',
     Other = b'2',
 }
 
+impl LogKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogKind::LeftPane => "left",
+            LogKind::MouseOver => "hover",
+            LogKind::Other => "other",
+        }
+    }
+}
+
 impl TryFrom<u8> for LogKind {
     type Error = ParseErrorKind;
 
@@ -22,12 +34,23 @@ impl TryFrom<u8> for LogKind {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum RetireKind {
     Retire = b'0',
     Flush = b'1',
 }
 
+impl RetireKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            RetireKind::Retire => "retire",
+            RetireKind::Flush => "flush",
+        }
+    }
+}
+
 impl TryFrom<u8> for RetireKind {
     type Error = ParseErrorKind;
 
@@ -41,11 +64,21 @@ impl TryFrom<u8> for RetireKind {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum DepKind {
     WakeUp = b'0',
 }
 
+impl DepKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DepKind::WakeUp => "wakeup",
+        }
+    }
+}
+
 impl TryFrom<u8> for DepKind {
     type Error = ParseErrorKind;
 
@@ -57,7 +90,15 @@ impl TryFrom<u8> for DepKind {
     }
 }
 
-#[derive(Copy, Clone)]
+// Instruction and retire ids. Simulations long enough to run past u32 can
+// turn on `wide-ids`.
+#[cfg(not(feature = "wide-ids"))]
+pub type Id = u32;
+#[cfg(feature = "wide-ids")]
+pub type Id = u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StrRef(u64);
 
 impl StrRef {
@@ -73,9 +114,16 @@ impl StrRef {
     pub fn len(self) -> u16 {
         self.0 as u16
     }
+
+    pub fn get(self, input: &[u8]) -> &[u8] {
+        let off = self.offset() as usize;
+        &input[off..off + self.len() as usize]
+    }
 }
 
-pub enum Command {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command<T = StrRef> {
     Kanata {
         version: u32,
     },
@@ -84,29 +132,122 @@ pub enum Command {
         value: i32,
     },
     Instruction {
-        id_in_file: u32,
-        id_in_sim: u32,
+        id_in_file: Id,
+        id_in_sim: Id,
         thread_id: u32,
     },
     Log {
-        id: u32,
+        id: Id,
         kind: LogKind,
-        text: StrRef,
+        text: T,
     },
     Pipeline {
         start: bool,
-        id: u32,
+        id: Id,
         lane_id: u32,
-        name: StrRef,
+        name: T,
     },
     Retire {
-        id: u32,
-        retire: u32,
+        id: Id,
+        retire: Id,
         kind: RetireKind,
     },
     Dep {
-        consumer_id: u32,
-        producer_id: u32,
+        consumer_id: Id,
+        producer_id: Id,
         kind: DepKind,
+        // an extra column some simulators add, naming what the edge carries
+        label: Option<T>,
     },
+    // An extension record giving a stage a display color, 0xRRGGBB. Only
+    // parsed when extensions are enabled; Konata ignores it.
+    StageColor {
+        name: T,
+        color: u32,
+    },
+}
+
+pub type OwnedCommand = Command<Vec<u8>>;
+
+impl<T> Command<T> {
+    pub fn map_text<U>(self, f: impl FnOnce(T) -> U) -> Command<U> {
+        match self {
+            Command::Kanata { version } => Command::Kanata { version },
+            Command::Cycle { abs, value } => Command::Cycle { abs, value },
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            },
+            Command::Log { id, kind, text } => Command::Log {
+                id,
+                kind,
+                text: f(text),
+            },
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name: f(name),
+            },
+            Command::Retire { id, retire, kind } => Command::Retire { id, retire, kind },
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label: label.map(f),
+            },
+            Command::StageColor { name, color } => Command::StageColor {
+                name: f(name),
+                color,
+            },
+        }
+    }
+
+    pub fn id(&self) -> Option<Id> {
+        match *self {
+            Command::Instruction { id_in_file: id, .. }
+            | Command::Log { id, .. }
+            | Command::Pipeline { id, .. }
+            | Command::Retire { id, .. }
+            | Command::Dep {
+                consumer_id: id, ..
+            } => Some(id),
+            Command::Kanata { .. } | Command::Cycle { .. } | Command::StageColor { .. } => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&T> {
+        match self {
+            Command::Log { text, .. } => Some(text),
+            Command::Pipeline { name, .. } | Command::StageColor { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+pub type BorrowedCommand<'a> = Command<&'a [u8]>;
+
+impl Command {
+    pub fn into_owned(self, input: &[u8]) -> OwnedCommand {
+        self.map_text(|s| s.get(input).to_vec())
+    }
+
+    pub fn resolve(self, input: &[u8]) -> BorrowedCommand<'_> {
+        self.map_text(|s| s.get(input))
+    }
 }
@@ -0,0 +1,267 @@
+use crate::{
+    BINARY_MAGIC, BinaryTrace, Checkpoint, Command, Decoder, Id, Index, ParseError, ParseErrorKind,
+    StageTable, Trace, put_checkpoint, put_varint,
+};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+
+pub const INDEX_MAGIC: &[u8; 8] = b"KANATAX\0";
+pub const INDEX_VERSION: u8 = 1;
+
+// bytes hashed at either end of a trace to tell whether an index is its own
+const CHECK_BYTES: usize = 1 << 16;
+
+// A trace's length and a hash of its first and last bytes: cheap enough to
+// take on every open, and changed by anything that appends to or rewrites
+// the trace short of the same length with the same ends.
+fn check(input: &[u8]) -> u64 {
+    let mut h = Xxh3::new();
+    h.update(&(input.len() as u64).to_le_bytes());
+    h.update(&input[..input.len().min(CHECK_BYTES)]);
+    h.update(&input[input.len().saturating_sub(CHECK_BYTES)..]);
+    h.digest()
+}
+
+// What opening a trace would otherwise have to scan all of it for: the
+// checkpoints to seek by, and the stage table with the stages in the order
+// the whole trace would number them, colors and all. Written next to the
+// trace as `<trace>.kidx`, so a tool reopening a big trace can go straight
+// to a window, and windows all number stages the same way. It records the
+// trace it was built from, so one that no longer fits is ignored rather
+// than trusted; a binary trace carries its own checkpoints, but keeps them
+// here too.
+//
+// The file is `INDEX_MAGIC`, `INDEX_VERSION`, the trace's length and check
+// as eight little-endian bytes each, then varints: the interval, the
+// checkpoints and the end as the binary trailer has them, the stage names,
+// and the colors by name, each text as its length and bytes.
+#[derive(Clone, Debug, Default)]
+pub struct TraceIndex {
+    index: Index,
+    stages: StageTable,
+    len: u64,
+    check: u64,
+}
+
+fn invalid(offset: usize) -> ParseError {
+    ParseError {
+        offset,
+        kind: ParseErrorKind::InvalidHeader,
+    }
+}
+
+// Interns the stage names `Trace` would, taking only those of instructions
+// in flight.
+#[derive(Default)]
+struct StageScan {
+    in_flight: HashSet<Id>,
+    stages: StageTable,
+}
+
+impl StageScan {
+    fn feed(&mut self, cmd: &Command, input: &[u8]) -> Result<(), ParseErrorKind> {
+        match cmd {
+            Command::Instruction { id_in_file, .. } => {
+                self.in_flight.insert(*id_in_file);
+            }
+            Command::Retire { id, .. } => {
+                self.in_flight.remove(id);
+            }
+            Command::Pipeline { id, name, .. } if self.in_flight.contains(id) => {
+                self.stages.intern(name.get(input))?;
+            }
+            Command::StageColor { name, color } => self.stages.set_color(name.get(input), *color),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl TraceIndex {
+    // One pass over a text trace, or over a binary trace's commands for its
+    // stages.
+    pub fn build(input: &[u8], interval: usize) -> Result<Self, ParseError> {
+        let mut scan = StageScan::default();
+        let index = if input.starts_with(BINARY_MAGIC) {
+            let file = BinaryTrace::new(input)?;
+            let mut commands = file.commands();
+            let text = commands.input();
+            for (offset, cmd) in &mut commands {
+                scan.feed(&cmd?, text)
+                    .map_err(|kind| ParseError { offset, kind })?;
+            }
+            file.index().clone()
+        } else {
+            Index::build_with(input, interval, |cmd, text| scan.feed(cmd, text))?
+        };
+        Ok(Self {
+            index,
+            stages: scan.stages,
+            len: input.len() as u64,
+            check: check(input),
+        })
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub fn into_index(self) -> Index {
+        self.index
+    }
+
+    // Whether it was built from `input`, as far as the length and the ends
+    // tell.
+    pub fn fits(&self, input: &[u8]) -> bool {
+        self.len == input.len() as u64 && self.check == check(input)
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<W> {
+        let mut buf = INDEX_MAGIC.to_vec();
+        buf.push(INDEX_VERSION);
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.check.to_le_bytes());
+        let text = |buf: &mut Vec<u8>, s: &str| {
+            put_varint(buf, s.len() as u64);
+            buf.extend_from_slice(s.as_bytes());
+        };
+        put_varint(&mut buf, self.index.interval() as u64);
+        let checkpoints = self.index.checkpoints();
+        put_varint(&mut buf, checkpoints.len() as u64);
+        for cp in checkpoints.iter().chain([&self.index.end()]) {
+            put_checkpoint(&mut buf, cp);
+        }
+        put_varint(&mut buf, self.stages.len() as u64);
+        for (_, name) in self.stages.iter() {
+            text(&mut buf, name);
+        }
+        let mut colors: Vec<(&str, u32)> = self.stages.colors().collect();
+        colors.sort_unstable();
+        put_varint(&mut buf, colors.len() as u64);
+        for (name, color) in colors {
+            text(&mut buf, name);
+            put_varint(&mut buf, color as u64);
+        }
+        out.write_all(&buf)?;
+        out.flush()?;
+        Ok(out)
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, ParseError> {
+        let head = INDEX_MAGIC.len() + 1 + 16;
+        if data.len() < head || !data.starts_with(INDEX_MAGIC) {
+            return Err(invalid(0));
+        }
+        if data[INDEX_MAGIC.len()] != INDEX_VERSION {
+            return Err(invalid(INDEX_MAGIC.len()));
+        }
+        let word = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+        let (len, check) = (word(head - 16), word(head - 8));
+        let mut d = Decoder { data, pos: head };
+        let text = |d: &mut Decoder| -> Result<String, ParseError> {
+            let n = d.varint()? as usize;
+            let at = d.pos;
+            let s = data
+                .get(at..at.saturating_add(n))
+                .ok_or_else(|| invalid(at))?;
+            d.pos += n;
+            String::from_utf8(s.to_vec()).map_err(|_| invalid(at))
+        };
+        let interval = d.varint()? as usize;
+        let n = d.varint()? as usize;
+        let mut checkpoints: Vec<Checkpoint> = Vec::with_capacity(n.min(data.len()));
+        for _ in 0..=n {
+            checkpoints.push(d.checkpoint()?);
+        }
+        let end = checkpoints.pop().unwrap();
+        if checkpoints.iter().any(|c| c.offset > end.offset) || end.offset as u64 > len {
+            return Err(invalid(d.pos));
+        }
+        let mut stages = StageTable::new();
+        for _ in 0..d.varint()? {
+            let at = d.pos;
+            let name = text(&mut d)?;
+            // names come interned, so each one is new
+            if stages.get(name.trim()).is_some() {
+                return Err(invalid(at));
+            }
+            stages.intern(name.as_bytes()).map_err(|_| invalid(at))?;
+        }
+        for _ in 0..d.varint()? {
+            let name = text(&mut d)?;
+            let color = u32::try_from(d.varint()?).map_err(|_| invalid(d.pos))?;
+            stages.set_color(name.as_bytes(), color);
+        }
+        if d.pos != data.len() {
+            return Err(invalid(d.pos));
+        }
+        Ok(Self {
+            index: Index::from_parts(interval, checkpoints, end),
+            stages,
+            len,
+            check,
+        })
+    }
+
+    // Where the index for the trace at `trace` lives.
+    pub fn sidecar_path(trace: impl AsRef<Path>) -> PathBuf {
+        let mut path = trace.as_ref().as_os_str().to_owned();
+        path.push(".kidx");
+        path.into()
+    }
+
+    // Written to a temporary file first, so a reader never sees half of one.
+    pub fn save(&self, trace: impl AsRef<Path>) -> io::Result<()> {
+        let path = Self::sidecar_path(trace);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(format!(".tmp{}", std::process::id()));
+        self.write(io::BufWriter::new(std::fs::File::create(&tmp)?))?;
+        std::fs::rename(tmp, path)
+    }
+
+    // The index next to the trace at `trace`, read as `input`, if it has one
+    // that fits. One that is damaged, of another version or left over from
+    // an earlier trace is as good as none.
+    pub fn for_trace(trace: impl AsRef<Path>, input: &[u8]) -> io::Result<Option<Self>> {
+        let data = match std::fs::read(Self::sidecar_path(trace)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Self::read(&data).ok().filter(|t| t.fits(input)))
+    }
+
+    // `for_trace`, or else built from `input`.
+    pub fn open(trace: impl AsRef<Path>, input: &[u8], interval: usize) -> io::Result<Self> {
+        match Self::for_trace(trace, input)? {
+            Some(t) => Ok(t),
+            None => Ok(Self::build(input, interval)?),
+        }
+    }
+
+    // `Index::window`, or `BinaryTrace::window`, with the stages numbered
+    // as in the whole trace.
+    pub fn window<'a>(&self, input: &'a [u8], cycles: Range<i64>) -> Result<Trace<'a>, ParseError> {
+        let cp = self.index.seek_cycle(cycles.start);
+        let mut trace = if input.starts_with(BINARY_MAGIC) {
+            let file = BinaryTrace::new(input)?;
+            Trace::from_source_at(file.commands_at(cp), cp.cycle, Some(cycles.end))?
+        } else {
+            self.index.window(input, cycles)?
+        };
+        let mut shared = self.stages.clone();
+        let map = shared.absorb(trace.stages()).map_err(|kind| ParseError {
+            offset: cp.offset,
+            kind,
+        })?;
+        trace.restage(&shared, &map);
+        Ok(trace)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{Clock, Index, InstructionRecord, ParseError, Parser, Reconstructor, StageTable, Step};
+
+// Replays an indexed text trace to any cycle, forwards or back. The model
+// state is snapshotted at every index checkpoint the first time the cursor
+// passes it, so moving around costs at most one index interval of replay.
+pub struct Cursor<'a> {
+    input: &'a [u8],
+    index: &'a Index,
+    snapshots: Vec<Option<Reconstructor<'a>>>,
+    rec: Reconstructor<'a>,
+    pos: usize,
+    cycle: i64,
+    retired: Vec<InstructionRecord>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(input: &'a [u8], index: &'a Index) -> Result<Self, ParseError> {
+        let mut cursor = Self {
+            input,
+            index,
+            snapshots: (0..index.checkpoints().len()).map(|_| None).collect(),
+            rec: Reconstructor::new(input),
+            pos: 0,
+            cycle: i64::MIN,
+            retired: Vec::new(),
+        };
+        cursor.seek(index.first_cycle())?;
+        Ok(cursor)
+    }
+
+    pub fn cycle(&self) -> i64 {
+        self.cycle
+    }
+
+    pub fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    pub fn index(&self) -> &'a Index {
+        self.index
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        self.rec.stages()
+    }
+
+    // Instructions in flight at the cursor's cycle, oldest first, with
+    // stages they are still in ending at the cursor.
+    pub fn in_flight(&self) -> Vec<InstructionRecord> {
+        let mut out: Vec<_> = self.rec.in_flight_records().cloned().collect();
+        for rec in &mut out {
+            rec.close_all(self.cycle);
+        }
+        out.sort_by_key(|r| r.offset);
+        out
+    }
+
+    // Instructions that retired or were flushed at the cursor's cycle.
+    pub fn retired(&self) -> &[InstructionRecord] {
+        &self.retired
+    }
+
+    pub fn step_forward(&mut self, cycles: u64) -> Result<(), ParseError> {
+        self.seek(self.cycle.saturating_add_unsigned(cycles))
+    }
+
+    pub fn step_back(&mut self, cycles: u64) -> Result<(), ParseError> {
+        self.seek(self.cycle.saturating_sub_unsigned(cycles))
+    }
+
+    pub fn seek(&mut self, cycle: i64) -> Result<(), ParseError> {
+        // the latest snapshot from before `cycle`, if it beats replaying on
+        // from where the cursor is; not one from during it, or what retired
+        // earlier in the cycle would be missed
+        let checkpoints = self.index.checkpoints();
+        let i = checkpoints.partition_point(|c| c.cycle < cycle);
+        let best = (0..i).rev().find(|&i| self.snapshots[i].is_some());
+        let behind = cycle < self.cycle;
+        if let Some(i) = best
+            && (behind || checkpoints[i].offset > self.pos)
+        {
+            self.rec = self.snapshots[i].as_ref().unwrap().fork();
+            self.pos = checkpoints[i].offset;
+            self.retired.clear();
+        } else if behind {
+            self.rec = Reconstructor::new(self.input);
+            self.pos = 0;
+            self.retired.clear();
+        }
+        self.cycle = cycle;
+
+        let mut next_cp = checkpoints.partition_point(|c| c.offset < self.pos);
+        let mut parser = Parser::with_offset(self.input, self.pos).extensions();
+        if let Some(v) = self.rec.version() {
+            parser = parser.with_version(v);
+        }
+        while let Some((offset, cmd)) = parser.next() {
+            let cmd = cmd?;
+            if let Some(cp) = checkpoints.get(next_cp)
+                && cp.offset == offset
+            {
+                if self.snapshots[next_cp].is_none() {
+                    self.snapshots[next_cp] = Some(self.rec.fork());
+                }
+                next_cp += 1;
+            }
+            let before = self.rec.cycle();
+            let mut probe = Clock::at(before);
+            probe.apply(&cmd);
+            if probe.cycle() > cycle {
+                break;
+            }
+            if probe.cycle() != before {
+                self.retired.clear();
+            }
+            self.pos = parser.get_offset();
+            if let Step::Retired(r) = self.rec.feed(offset, cmd)? {
+                self.retired.push(r);
+            }
+        }
+        if self.rec.cycle() != cycle {
+            // nothing happened at `cycle` itself
+            self.retired.clear();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{InstructionMap, InstructionRecord, StageId, Trace, Workspace};
+
+// The change in one stage's latency from `a` to `b`, over the matched pairs
+// of instructions that both went through it. The interval is a paired t
+// interval around `delta`; with so few pairs that it's uninformative it is
+// left infinite.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StageDelta {
+    pub stage: StageId,
+    pub pairs: u64,
+    pub mean_a: f64,
+    pub mean_b: f64,
+    pub delta: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl StageDelta {
+    // Whether the interval leaves out no change at all.
+    pub fn is_significant(&self) -> bool {
+        self.low > 0.0 || self.high < 0.0
+    }
+
+    // Significantly slower in `b`.
+    pub fn is_regression(&self) -> bool {
+        self.low > 0.0
+    }
+}
+
+// The standard normal quantile, by Acklam's rational approximation, good to
+// about 1e-9.
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    let tail = |q: f64| {
+        let q = (-2.0 * q.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail(p)
+    } else if p > 1.0 - 0.02425 {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+// Student's t quantile from the normal one by a Cornish-Fisher expansion,
+// close enough past a handful of degrees of freedom.
+fn t_quantile(p: f64, df: f64) -> f64 {
+    let z = normal_quantile(p);
+    let z3 = z * z * z;
+    let z5 = z3 * z * z;
+    let z7 = z5 * z * z;
+    z + (z3 + z) / (4.0 * df)
+        + (5.0 * z5 + 16.0 * z3 + 3.0 * z) / (96.0 * df * df)
+        + (3.0 * z7 + 19.0 * z5 + 17.0 * z3 - 15.0 * z) / (384.0 * df * df * df)
+}
+
+fn visits(rec: &InstructionRecord, stage: StageId) -> bool {
+    rec.stages.iter().any(|s| s.stage == stage)
+}
+
+// Each of `a`'s stages that `b` has too, by name, with how its latency
+// changed over the instructions `map` pairs up. `confidence` is the two
+// sided level of the intervals, such as 0.95. Pairing takes out most of
+// the noise that separate means carry from one run's mix to another's.
+pub fn stage_deltas(
+    a: &Trace,
+    b: &Trace,
+    map: &InstructionMap,
+    confidence: f64,
+) -> Vec<StageDelta> {
+    let p = 1.0 - (1.0 - confidence.clamp(0.0, 1.0)) / 2.0;
+    let mut out = Vec::new();
+    for (stage, name) in a.stages().iter() {
+        let Some(other) = b.stages().get(name) else {
+            continue;
+        };
+        let (mut n, mut sum_a, mut sum_b) = (0u64, 0.0, 0.0);
+        let (mut mean, mut m2) = (0.0, 0.0);
+        for &(x, y) in map.pairs() {
+            let (Some(ra), Some(rb)) = (a.get(x), b.get(y)) else {
+                continue;
+            };
+            if !visits(ra, stage) || !visits(rb, other) {
+                continue;
+            }
+            let (la, lb) = (
+                ra.stage_latency(stage) as f64,
+                rb.stage_latency(other) as f64,
+            );
+            n += 1;
+            sum_a += la;
+            sum_b += lb;
+            // Welford's, for the variance of the differences
+            let d = lb - la;
+            let step = d - mean;
+            mean += step / n as f64;
+            m2 += step * (d - mean);
+        }
+        if n == 0 {
+            continue;
+        }
+        let half = if n < 3 {
+            f64::INFINITY
+        } else {
+            let df = (n - 1) as f64;
+            t_quantile(p, df) * (m2 / df / n as f64).sqrt()
+        };
+        out.push(StageDelta {
+            stage,
+            pairs: n,
+            mean_a: sum_a / n as f64,
+            mean_b: sum_b / n as f64,
+            delta: mean,
+            low: mean - half,
+            high: mean + half,
+        });
+    }
+    out
+}
+
+impl Workspace {
+    pub fn stage_deltas(&self, a: &str, b: &str, confidence: f64) -> Option<Vec<StageDelta>> {
+        let map = self.match_instructions(a, b)?;
+        Some(stage_deltas(self.get(a)?, self.get(b)?, &map, confidence))
+    }
+}
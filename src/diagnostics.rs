@@ -0,0 +1,488 @@
+use crate::document::parse_line;
+use crate::{Command, Id, KANATA_VERSION, LineIndex, ParseError, ParseErrorKind, Tolerance};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+// Numbered as in the Language Server Protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[repr(u8)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+// Zero-based, with `character` counted in UTF-16 code units as LSP expects.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+// Serialize only, as `code` borrows from the lint table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Diagnostic {
+    pub span: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    // the other commands the finding is about, such as the one it clashes with
+    pub related: Vec<Range<usize>>,
+}
+
+fn end_of_line(lines: &LineIndex, offset: usize) -> usize {
+    let span = lines.line_span(lines.line(offset));
+    let text = &lines.input()[offset.min(span.end)..span.end];
+    offset + text.trim_ascii_end().len()
+}
+
+fn position(lines: &LineIndex, offset: usize) -> Position {
+    let offset = offset.min(lines.input().len());
+    let (line, col) = lines.line_col(offset);
+    let text = &lines.input()[offset - col..offset];
+    Position {
+        line: line as u32,
+        character: String::from_utf8_lossy(text).encode_utf16().count() as u32,
+    }
+}
+
+struct Lint<'a> {
+    lines: LineIndex<'a>,
+    out: Vec<Diagnostic>,
+}
+
+impl Lint<'_> {
+    fn push(
+        &mut self,
+        span: Range<usize>,
+        severity: Severity,
+        code: &'static str,
+        message: String,
+    ) {
+        let (start, end) = (
+            position(&self.lines, span.start),
+            position(&self.lines, span.end),
+        );
+        self.out.push(Diagnostic {
+            span,
+            start,
+            end,
+            severity,
+            code,
+            message,
+            related: Vec::new(),
+        });
+    }
+
+    fn push_related(
+        &mut self,
+        span: Range<usize>,
+        related: Range<usize>,
+        code: &'static str,
+        message: String,
+    ) {
+        self.push(span, Severity::Warning, code, message);
+        self.out.last_mut().unwrap().related.push(related);
+    }
+
+    // the whole line, for findings about a command rather than one field
+    fn line(&self, offset: usize) -> Range<usize> {
+        offset..end_of_line(&self.lines, offset)
+    }
+
+    fn error(&mut self, e: ParseError) {
+        let end = match end_of_line(&self.lines, e.offset) {
+            // just the number, which the error starts at
+            end if e.kind == ParseErrorKind::ValueTooBig && end > e.offset => {
+                let field = &self.lines.input()[e.offset..end];
+                let sign = field.first().is_some_and(|&c| c == b'-' || c == b'+') as usize;
+                let digits = field[sign..].iter().take_while(|c| c.is_ascii_digit());
+                (e.offset + sign + digits.count()).max(e.offset + 1)
+            }
+            end if end > e.offset => end,
+            _ => (e.offset + 1).min(self.lines.input().len()),
+        };
+        self.push(
+            e.offset..end,
+            Severity::Error,
+            e.kind.code(),
+            e.kind.message().to_string(),
+        );
+    }
+}
+
+pub fn diagnostics(input: &[u8]) -> Vec<Diagnostic> {
+    let mut lint = Lint {
+        lines: LineIndex::new(input),
+        out: Vec::new(),
+    };
+    let mut version = None;
+    let mut live: HashMap<Id, (usize, HashMap<u32, &[u8]>)> = HashMap::new();
+    let mut seen = HashSet::new();
+
+    let mut pos = 0;
+    while pos < input.len() {
+        let Some((offset, cmd, next)) = parse_line(input, pos, version, Tolerance::Lenient) else {
+            break;
+        };
+        pos = next;
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                lint.error(e);
+                continue;
+            }
+        };
+        if offset == 0 && !matches!(cmd, Command::Kanata { .. }) {
+            lint.push(
+                lint.line(0),
+                Severity::Warning,
+                "missing-header",
+                "trace does not start with a `Kanata` header".to_string(),
+            );
+        }
+
+        match cmd {
+            Command::Kanata { version: v } => {
+                if version.is_some() {
+                    lint.push(
+                        lint.line(offset),
+                        Severity::Warning,
+                        "repeated-header",
+                        "only the first `Kanata` header is used".to_string(),
+                    );
+                    continue;
+                }
+                version = Some(v);
+                if v < KANATA_VERSION {
+                    lint.push(
+                        lint.line(offset),
+                        Severity::Information,
+                        "old-version",
+                        format!("Kanata {:04} trace; `migrate` can upgrade it", v),
+                    );
+                }
+            }
+            Command::Cycle { .. } => {}
+            Command::Instruction { id_in_file: id, .. } => {
+                if let Entry::Vacant(e) = live.entry(id) {
+                    e.insert((offset, HashMap::new()));
+                    seen.insert(id);
+                } else {
+                    lint.error(ParseError {
+                        offset,
+                        kind: ParseErrorKind::DuplicateInstruction,
+                    });
+                }
+            }
+            _ => {
+                let id = cmd.id().unwrap();
+                let Some((_, open)) = live.get_mut(&id) else {
+                    // logs and dependencies may still arrive after retirement
+                    let late = matches!(cmd, Command::Log { .. } | Command::Dep { .. });
+                    if !seen.contains(&id) {
+                        lint.push(
+                            lint.line(offset),
+                            Severity::Warning,
+                            "unknown-instruction",
+                            format!("instruction {} was never declared", id),
+                        );
+                    } else if !late {
+                        lint.push(
+                            lint.line(offset),
+                            Severity::Warning,
+                            "retired-instruction",
+                            format!("instruction {} has already retired", id),
+                        );
+                    }
+                    continue;
+                };
+                match cmd {
+                    Command::Pipeline {
+                        start,
+                        lane_id,
+                        name,
+                        ..
+                    } => {
+                        let name = name.get(input).trim_ascii();
+                        if start {
+                            open.insert(lane_id, name);
+                        } else if open.get(&lane_id) == Some(&name) {
+                            open.remove(&lane_id);
+                        } else {
+                            let stage = String::from_utf8_lossy(name);
+                            lint.push(
+                                lint.line(offset),
+                                Severity::Warning,
+                                "unmatched-end",
+                                format!("stage {} is not open on lane {}", stage, lane_id),
+                            );
+                        }
+                    }
+                    Command::Retire { .. } => {
+                        live.remove(&id);
+                    }
+                    Command::Dep { producer_id, .. } if !seen.contains(&producer_id) => {
+                        lint.push(
+                            lint.line(offset),
+                            Severity::Warning,
+                            "unknown-producer",
+                            format!("producer {} was never declared", producer_id),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut pending: Vec<_> = live.into_iter().collect();
+    pending.sort_by_key(|&(_, (offset, _))| offset);
+    for (id, (offset, _)) in pending {
+        lint.push(
+            lint.line(offset),
+            Severity::Hint,
+            "not-retired",
+            format!("instruction {} never retires", id),
+        );
+    }
+
+    lint.out.sort_by_key(|d| (d.span.start, d.severity));
+    lint.out
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OverlapConfig {
+    // Lanes are issue slots, so that two instructions in the same stage on
+    // the same lane in one cycle clash. In most traces lanes only layer the
+    // stages of one instruction, and every instruction shares lane 0.
+    pub exclusive_lanes: bool,
+}
+
+type Slot<'a> = (&'a [u8], u32);
+
+#[derive(Default)]
+struct Overlaps<'a> {
+    // each instruction's open stages, with the offset of their `S`
+    open: HashMap<Id, Vec<(Slot<'a>, usize)>>,
+    // who is in each stage on each lane, in the order they went in
+    holders: HashMap<Slot<'a>, Vec<(usize, Id)>>,
+    touched: HashSet<Slot<'a>>,
+    reported: HashSet<usize>,
+}
+
+impl<'a> Overlaps<'a> {
+    fn release(&mut self, slot: Slot<'a>, at: usize) {
+        if let Some(v) = self.holders.get_mut(&slot) {
+            v.retain(|&(offset, _)| offset != at);
+        }
+    }
+
+    fn end(&mut self, id: Id, slot: Slot<'a>) {
+        let spans = self.open.entry(id).or_default();
+        if let Some(i) = spans.iter().position(|&(s, _)| s == slot) {
+            let (_, at) = spans.remove(i);
+            self.release(slot, at);
+        }
+    }
+
+    // Starting a stage on a lane ends whatever was open there. The `S` of
+    // the same stage still open, if there is one.
+    fn start(&mut self, id: Id, slot: Slot<'a>, offset: usize, exclusive: bool) -> Option<usize> {
+        let spans = self.open.entry(id).or_default();
+        let again = spans
+            .iter()
+            .find(|&&((n, _), _)| n == slot.0)
+            .map(|&(_, at)| at);
+        let (ended, kept) = std::mem::take(spans)
+            .into_iter()
+            .partition(|&((_, l), _)| l == slot.1);
+        *spans = kept;
+        spans.push((slot, offset));
+        for (s, at) in ended as Vec<_> {
+            self.release(s, at);
+        }
+        if exclusive {
+            self.holders.entry(slot).or_default().push((offset, id));
+            self.touched.insert(slot);
+        }
+        again
+    }
+
+    fn retire(&mut self, id: Id) {
+        for (slot, at) in self.open.remove(&id).unwrap_or_default() {
+            self.release(slot, at);
+        }
+    }
+
+    // Who holds a lane once the cycle's records are all in, so that one
+    // instruction leaving as another arrives is no clash.
+    fn settle(&mut self, lint: &mut Lint) {
+        let mut slots: Vec<_> = self.touched.drain().collect();
+        slots.sort_unstable();
+        for slot in slots {
+            let Some(v) = self.holders.get(&slot).filter(|v| v.len() > 1) else {
+                continue;
+            };
+            let (first, first_id) = v[0];
+            for &(offset, id) in &v[1..] {
+                if self.reported.insert(offset) {
+                    lint.push_related(
+                        lint.line(offset),
+                        lint.line(first),
+                        "lane-conflict",
+                        format!(
+                            "instruction {} enters stage {} on lane {} while {} is there",
+                            id,
+                            String::from_utf8_lossy(slot.0),
+                            slot.1,
+                            first_id
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Stage records that Konata can't draw sensibly: an instruction starting a
+// stage it is still in, and with `exclusive_lanes`, two instructions on the
+// same lane of a stage at once. Each points at the later `S` and relates it
+// to the one it clashes with. Parse errors are left to `diagnostics`.
+pub fn overlap_diagnostics(input: &[u8], config: &OverlapConfig) -> Vec<Diagnostic> {
+    let mut lint = Lint {
+        lines: LineIndex::new(input),
+        out: Vec::new(),
+    };
+    let mut version = None;
+    let mut state = Overlaps::default();
+
+    let mut pos = 0;
+    while pos < input.len() {
+        let Some((offset, cmd, next)) = parse_line(input, pos, version, Tolerance::Lenient) else {
+            break;
+        };
+        pos = next;
+        let Ok(cmd) = cmd else {
+            continue;
+        };
+        match cmd {
+            Command::Kanata { version: v } => {
+                version.get_or_insert(v);
+            }
+            Command::Cycle { .. } => state.settle(&mut lint),
+            Command::Pipeline {
+                start: false,
+                id,
+                lane_id,
+                name,
+            } => state.end(id, (name.get(input).trim_ascii(), lane_id)),
+            Command::Pipeline {
+                start: true,
+                id,
+                lane_id,
+                name,
+            } => {
+                let slot = (name.get(input).trim_ascii(), lane_id);
+                if let Some(at) = state.start(id, slot, offset, config.exclusive_lanes) {
+                    lint.push_related(
+                        lint.line(offset),
+                        lint.line(at),
+                        "reentered-stage",
+                        format!(
+                            "instruction {} starts stage {} again without leaving it",
+                            id,
+                            String::from_utf8_lossy(slot.0)
+                        ),
+                    );
+                }
+            }
+            Command::Retire { id, .. } => state.retire(id),
+            _ => {}
+        }
+    }
+    state.settle(&mut lint);
+
+    lint.out.sort_by_key(|d| (d.span.start, d.severity));
+    lint.out
+}
+
+// How far a relative `C` may step back before it's flagged. Zero, the
+// default, flags any negative delta.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CycleCheck {
+    pub max_back: u64,
+}
+
+impl CycleCheck {
+    // Whether the `C` or `C=` taking the clock from `before` to `value`
+    // breaks the check. The first `C=` sets time wherever it likes.
+    pub(crate) fn breaks(&self, abs: bool, value: i32, before: Option<i64>) -> bool {
+        match (abs, before) {
+            (true, Some(before)) => (value as i64) < before,
+            (true, None) => false,
+            (false, _) => (value as i64) < -(self.max_back.min(i32::MAX as u64) as i64),
+        }
+    }
+}
+
+// Cycle records a mix of absolute and relative emitters tends to get wrong,
+// leaving the rest of the trace shifted: a relative `C` further back than
+// `check` allows, and a `C=` before the cycle already reached, related to
+// the record that got the clock there. `reanchor_cycles` fixes both.
+pub fn cycle_diagnostics(input: &[u8], check: &CycleCheck) -> Vec<Diagnostic> {
+    let mut lint = Lint {
+        lines: LineIndex::new(input),
+        out: Vec::new(),
+    };
+    let mut version = None;
+    // the cycle reached, and the record that set it
+    let mut clock: Option<(i64, usize)> = None;
+
+    let mut pos = 0;
+    while pos < input.len() {
+        let Some((offset, cmd, next)) = parse_line(input, pos, version, Tolerance::Lenient) else {
+            break;
+        };
+        pos = next;
+        match cmd {
+            Ok(Command::Kanata { version: v }) => {
+                version.get_or_insert(v);
+            }
+            Ok(Command::Cycle { abs, value }) => {
+                let before = clock.map(|(c, _)| c);
+                if check.breaks(abs, value, before) {
+                    let span = lint.line(offset);
+                    match (abs, clock) {
+                        (true, Some((c, at))) => lint.push_related(
+                            span,
+                            lint.line(at),
+                            "cycle-went-back",
+                            format!("C= {} is before cycle {}, already reached", value, c),
+                        ),
+                        _ => lint.push(
+                            span,
+                            Severity::Warning,
+                            "negative-delta",
+                            format!("C {} steps back more than {} cycles", value, check.max_back),
+                        ),
+                    }
+                }
+                let c = before.unwrap_or(0);
+                clock = Some((if abs { value as i64 } else { c + value as i64 }, offset));
+            }
+            _ => {}
+        }
+    }
+    lint.out
+}
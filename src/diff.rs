@@ -0,0 +1,196 @@
+use crate::{Clock, Command, Commands, Id, ParseError, Writer};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+const CONTEXT: usize = 3;
+// past this many line pairs a changed cycle is shown as replaced outright
+const MAX_CELLS: usize = 1 << 22;
+
+type Lines = Vec<String>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Op {
+    Same,
+    Del,
+    Add,
+}
+
+// Where a command goes among the others of its cycle.
+fn rank<T>(cmd: &Command<T>) -> u8 {
+    match cmd {
+        Command::StageColor { .. } => 0,
+        Command::Instruction { .. } => 1,
+        Command::Log { .. } => 2,
+        Command::Pipeline { .. } => 3,
+        Command::Dep { .. } => 4,
+        Command::Retire { .. } => 5,
+        Command::Kanata { .. } | Command::Cycle { .. } => 6,
+    }
+}
+
+// The header, then each cycle as a `C=` line followed by its commands as
+// the writer prints them, ordered by kind and then instruction with the
+// file order kept between equals.
+fn normalize(input: &[u8]) -> Result<(Lines, BTreeMap<i64, Lines>), ParseError> {
+    let mut header = Vec::new();
+    let mut cycles: BTreeMap<i64, Vec<(u8, Id, String)>> = BTreeMap::new();
+    let mut clock = Clock::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        let mut w = Writer::new(Vec::new());
+        w.write_ref(&cmd, input)
+            .expect("writing to a Vec cannot fail");
+        let line = String::from_utf8_lossy(w.get_ref().trim_ascii_end()).into_owned();
+        match cmd {
+            Command::Kanata { .. } if header.is_empty() => header.push(line),
+            Command::Kanata { .. } | Command::Cycle { .. } => {}
+            _ => cycles.entry(clock.cycle()).or_default().push((
+                rank(&cmd),
+                cmd.id().unwrap_or(0),
+                line,
+            )),
+        }
+    }
+    let cycles = cycles
+        .into_iter()
+        .map(|(c, mut v)| {
+            v.sort_by_key(|&(rank, id, _)| (rank, id));
+            let lines = std::iter::once(format!("C=\t{}", c));
+            (c, lines.chain(v.into_iter().map(|(_, _, l)| l)).collect())
+        })
+        .collect();
+    Ok((header, cycles))
+}
+
+// A shortest edit script from `a` to `b`, by dynamic programming over what's
+// left once the common ends are trimmed.
+fn diff_lines<'a>(a: &'a [String], b: &'a [String], ops: &mut Vec<(Op, &'a str)>) {
+    let pre = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suf = a[pre..]
+        .iter()
+        .rev()
+        .zip(b[pre..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    ops.extend(a[..pre].iter().map(|l| (Op::Same, l.as_str())));
+    let (x, y) = (&a[pre..a.len() - suf], &b[pre..b.len() - suf]);
+    if x.is_empty() || y.is_empty() || x.len().saturating_mul(y.len()) > MAX_CELLS {
+        ops.extend(x.iter().map(|l| (Op::Del, l.as_str())));
+        ops.extend(y.iter().map(|l| (Op::Add, l.as_str())));
+    } else {
+        // the longest common subsequence of x[i..] and y[j..]
+        let w = y.len() + 1;
+        let mut lcs = vec![0u32; (x.len() + 1) * w];
+        for i in (0..x.len()).rev() {
+            for j in (0..y.len()).rev() {
+                lcs[i * w + j] = if x[i] == y[j] {
+                    lcs[(i + 1) * w + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < x.len() || j < y.len() {
+            if i < x.len() && j < y.len() && x[i] == y[j] {
+                ops.push((Op::Same, &x[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == y.len() || (i < x.len() && lcs[(i + 1) * w + j] >= lcs[i * w + j + 1]) {
+                ops.push((Op::Del, &x[i]));
+                i += 1;
+            } else {
+                ops.push((Op::Add, &y[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(a[a.len() - suf..].iter().map(|l| (Op::Same, l.as_str())));
+}
+
+// A unified diff of two traces after normalizing both, so that formatting
+// and the order of records within a cycle don't count as differences. Each
+// hunk is headed by the cycle its first change is in. Empty when they
+// match.
+pub fn textual_diff(a: &[u8], b: &[u8]) -> Result<String, ParseError> {
+    let (ha, ca) = normalize(a)?;
+    let (hb, cb) = normalize(b)?;
+    let mut ops = Vec::new();
+    diff_lines(&ha, &hb, &mut ops);
+    let (mut ia, mut ib) = (ca.iter().peekable(), cb.iter().peekable());
+    loop {
+        match (ia.peek(), ib.peek()) {
+            (Some((x, la)), Some((y, lb))) if x == y => {
+                diff_lines(la, lb, &mut ops);
+                ia.next();
+                ib.next();
+            }
+            (Some((x, la)), Some((y, _))) if x < y => {
+                diff_lines(la, &[], &mut ops);
+                ia.next();
+            }
+            (Some((_, la)), None) => {
+                diff_lines(la, &[], &mut ops);
+                ia.next();
+            }
+            (_, Some((_, lb))) => {
+                diff_lines(&[], lb, &mut ops);
+                ib.next();
+            }
+            (None, None) => break,
+        }
+    }
+
+    // the line each op is at on either side, counting from 1
+    let mut at = Vec::with_capacity(ops.len());
+    let (mut na, mut nb) = (1, 1);
+    for &(op, _) in &ops {
+        at.push((na, nb));
+        na += (op != Op::Add) as usize;
+        nb += (op != Op::Del) as usize;
+    }
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i].0 != Op::Same).collect();
+    let mut out = String::new();
+    if changes.is_empty() {
+        return Ok(out);
+    }
+    out.push_str("--- a\n+++ b\n");
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(CONTEXT);
+        // the cycle the first change is in
+        let cycle = ops[..changes[k]]
+            .iter()
+            .rev()
+            .find(|(_, l)| l.starts_with("C=\t"))
+            .map_or("", |(_, l)| l);
+        let mut end = changes[k] + 1;
+        while k < changes.len() && changes[k] <= end + 2 * CONTEXT {
+            end = changes[k] + 1;
+            k += 1;
+        }
+        let end = (end + CONTEXT).min(ops.len());
+        let hunk = &ops[start..end];
+        let del = hunk.iter().filter(|(op, _)| *op != Op::Add).count();
+        let add = hunk.iter().filter(|(op, _)| *op != Op::Del).count();
+        let (sa, sb) = at[start];
+        let head = format!(
+            "@@ -{},{} +{},{} @@ {}",
+            if del == 0 { sa - 1 } else { sa },
+            del,
+            if add == 0 { sb - 1 } else { sb },
+            add,
+            cycle
+        );
+        let _ = writeln!(out, "{}", head.trim_end());
+        for (op, line) in hunk {
+            let c = match op {
+                Op::Same => ' ',
+                Op::Del => '-',
+                Op::Add => '+',
+            };
+            let _ = writeln!(out, "{}{}", c, line);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,314 @@
+use crate::{
+    Checkpoint, Clock, Clocked, Command, CommandSource, Index, ParseError, Parser, StrRef,
+    Tolerance, Trace,
+};
+use memchr::memchr;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub offset: usize,
+    pub cycle: i64,
+    pub command: Result<Command, ParseError>,
+}
+
+// The bytes of a line that aren't part of any field, as offsets into the
+// document text, for formatters that want to keep or normalize them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trivia {
+    // the whitespace before each field after the command name
+    pub separators: Vec<Range<usize>>,
+    // whitespace after the last field; a trailing text field keeps its own
+    pub trailing: Range<usize>,
+    // the line break, along with any blank lines after it
+    pub line_end: Range<usize>,
+}
+
+// The field that runs to the end of the line, if any, and whether it may be
+// preceded by more whitespace than one tab.
+fn text_field(cmd: &Command) -> Option<(usize, bool)> {
+    match cmd {
+        Command::Log { .. } | Command::Pipeline { .. } => Some((2, false)),
+        Command::StageColor { .. } => Some((1, false)),
+        Command::Dep { label: Some(_), .. } => Some((3, true)),
+        _ => None,
+    }
+}
+
+fn trivia(text: &[u8], line: Range<usize>, cmd: Option<&Command>) -> Trivia {
+    let blank = |b: u8| b == b' ' || b == b'\t';
+    let mut end = line.end;
+    while end > line.start && matches!(text[end - 1], b'\r' | b'\n') {
+        end -= 1;
+    }
+    let run = |from: usize, f: &dyn Fn(u8) -> bool| {
+        from + text[from..end].iter().take_while(|&&b| f(b)).count()
+    };
+    let mut t = Trivia {
+        separators: Vec::new(),
+        trailing: end..end,
+        line_end: end..line.end,
+    };
+    let mut pos = run(line.start, &|b| !blank(b));
+    let field = cmd.and_then(text_field);
+    for k in 0.. {
+        let sep = match field {
+            Some((i, false)) if i == k && pos < end && text[pos] == b'\t' => pos + 1,
+            _ => run(pos, &blank),
+        };
+        if sep == end {
+            t.trailing = pos..end;
+            break;
+        }
+        t.separators.push(pos..sep);
+        if field.is_some_and(|(i, _)| i == k) {
+            break;
+        }
+        pos = run(sep, &|b| !blank(b));
+    }
+    t
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    text: Vec<u8>,
+    lines: Vec<Line>,
+    version: Option<u32>,
+}
+
+// Unlike the plain parser, a document keeps going after an error by skipping
+// to the next line, so one typo doesn't hide the rest of the file.
+pub(crate) fn parse_line(
+    text: &[u8],
+    pos: usize,
+    version: Option<u32>,
+    tolerance: Tolerance,
+) -> Option<(usize, Result<Command, ParseError>, usize)> {
+    let mut parser = Parser::with_offset(text, pos)
+        .extensions()
+        .tolerance(tolerance);
+    if let Some(v) = version {
+        parser = parser.with_version(v);
+    }
+    // blank lines are skipped, so the command may start after `pos`
+    let (offset, cmd) = parser.next()?;
+    match cmd {
+        Ok(cmd) => Some((offset, Ok(cmd), parser.get_offset())),
+        Err(e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                offset = e.offset,
+                code = e.kind.code(),
+                "resyncing at next line"
+            );
+            let next = memchr(b'\n', &text[offset..]).map_or(text.len(), |i| offset + i + 1);
+            Some((offset, Err(e), next))
+        }
+    }
+}
+
+fn header_version(lines: &[Line]) -> Option<u32> {
+    lines.iter().find_map(|l| match l.command {
+        Ok(Command::Kanata { version }) => Some(version),
+        _ => None,
+    })
+}
+
+fn shift(n: usize, delta: isize) -> usize {
+    n.wrapping_add_signed(delta)
+}
+
+impl Document {
+    pub fn new(text: Vec<u8>) -> Self {
+        let mut doc = Self {
+            text,
+            lines: Vec::new(),
+            version: None,
+        };
+        let mut pos = 0;
+        while pos < doc.text.len() {
+            let Some((offset, command, next)) =
+                parse_line(&doc.text, pos, doc.version, Tolerance::Lenient)
+            else {
+                break;
+            };
+            if let Ok(Command::Kanata { version }) = command {
+                doc.version.get_or_insert(version);
+            }
+            doc.lines.push(Line {
+                offset,
+                cycle: 0,
+                command,
+            });
+            pos = next;
+        }
+        let n = doc.lines.len();
+        doc.recycle(0, n);
+        doc
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = ParseError> + '_ {
+        self.lines.iter().filter_map(|l| l.command.err())
+    }
+
+    // Lines that didn't parse are split at every run of whitespace.
+    pub fn trivia(&self, line: usize) -> Trivia {
+        let l = &self.lines[line];
+        let end = self
+            .lines
+            .get(line + 1)
+            .map_or(self.text.len(), |n| n.offset);
+        trivia(&self.text, l.offset..end, l.command.as_ref().ok())
+    }
+
+    pub fn line_at(&self, offset: usize) -> usize {
+        self.lines
+            .partition_point(|l| l.offset <= offset)
+            .saturating_sub(1)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = (usize, Result<Command, ParseError>)> + '_ {
+        self.lines.iter().map(|l| (l.offset, l.command))
+    }
+
+    pub fn source(&self) -> impl CommandSource<'_> {
+        Clocked::new(&self.text, self.commands())
+    }
+
+    pub fn trace(&self) -> Result<Trace<'_>, ParseError> {
+        Trace::from_source(self.source())
+    }
+
+    pub fn index(&self, interval: usize) -> Index {
+        let mut checkpoints = Vec::new();
+        let mut at = Checkpoint::default();
+        let mut next = 0;
+        for l in &self.lines {
+            let Ok(cmd) = l.command else { continue };
+            if l.offset >= next {
+                checkpoints.push(Checkpoint {
+                    offset: l.offset,
+                    cycle: l.cycle,
+                    ..at
+                });
+                next = l.offset + interval.max(1);
+            }
+            at.commands += 1;
+            at.instructions += matches!(cmd, Command::Instruction { .. }) as u64;
+        }
+        let mut clock = Clock::at(self.lines.last().map_or(0, |l| l.cycle));
+        if let Some(Line {
+            command: Ok(cmd), ..
+        }) = self.lines.last()
+        {
+            clock.apply(cmd);
+        }
+        let end = Checkpoint {
+            offset: self.text.len(),
+            cycle: clock.cycle(),
+            ..at
+        };
+        Index::from_parts(interval, checkpoints, end)
+    }
+
+    // Replaces `range` of the text and reparses only the lines it touched.
+    // Returns the indices in `lines()` that were reparsed.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &[u8]) -> Range<usize> {
+        assert!(
+            range.start <= range.end && range.end <= self.text.len(),
+            "edit range {:?} out of bounds for length {}",
+            range,
+            self.text.len()
+        );
+        let delta = replacement.len() as isize - range.len() as isize;
+        let edit_end = range.start + replacement.len();
+
+        // back up one line so a line whose terminator was edited is reparsed
+        let first = self.line_at(range.start).saturating_sub(1);
+        let mut old = self.lines.partition_point(|l| l.offset <= range.end);
+        self.text.splice(range, replacement.iter().copied());
+
+        let mut pos = self.lines.get(first).map_or(0, |l| l.offset);
+        let mut fresh = Vec::new();
+        loop {
+            while old < self.lines.len() && shift(self.lines[old].offset, delta) < pos {
+                old += 1;
+            }
+            let resynced = old < self.lines.len() && shift(self.lines[old].offset, delta) == pos;
+            if (resynced && pos >= edit_end) || pos >= self.text.len() {
+                break;
+            }
+            let Some((offset, command, next)) =
+                parse_line(&self.text, pos, self.version, Tolerance::Lenient)
+            else {
+                pos = self.text.len();
+                break;
+            };
+            fresh.push(Line {
+                offset,
+                cycle: 0,
+                command,
+            });
+            pos = next;
+        }
+        if pos >= self.text.len() {
+            old = self.lines.len();
+        }
+
+        let reparsed = first..first + fresh.len();
+        self.lines.splice(first..old, fresh);
+        if header_version(&self.lines) != self.version {
+            // the version changes how every later line parses
+            *self = Document::new(std::mem::take(&mut self.text));
+            return 0..self.lines.len();
+        }
+        for l in &mut self.lines[reparsed.end..] {
+            l.offset = shift(l.offset, delta);
+            l.command = match l.command {
+                Ok(cmd) => Ok(cmd
+                    .map_text(|s| StrRef::new(shift(s.offset() as usize, delta) as u64, s.len()))),
+                Err(e) => Err(ParseError {
+                    offset: shift(e.offset, delta),
+                    ..e
+                }),
+            };
+        }
+        self.recycle(reparsed.start, reparsed.end);
+        reparsed
+    }
+
+    // Recomputes the cycle of each line from `from` on. Lines from `stable`
+    // on still hold their old cycles, so once one agrees the rest do too.
+    fn recycle(&mut self, from: usize, stable: usize) {
+        let mut clock = match from.checked_sub(1).map(|i| self.lines[i]) {
+            Some(Line {
+                cycle,
+                command: Ok(cmd),
+                ..
+            }) => {
+                let mut c = Clock::at(cycle);
+                c.apply(&cmd);
+                c
+            }
+            Some(l) => Clock::at(l.cycle),
+            None => Clock::new(),
+        };
+        for (i, l) in self.lines.iter_mut().enumerate().skip(from) {
+            if i >= stable && l.cycle == clock.cycle() {
+                break;
+            }
+            l.cycle = clock.cycle();
+            if let Ok(cmd) = &l.command {
+                clock.apply(cmd);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use super::json::write_str;
+use crate::Trace;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChromeConfig {
+    pub us_per_cycle: f64,
+}
+
+impl Default for ChromeConfig {
+    fn default() -> Self {
+        Self { us_per_cycle: 1.0 }
+    }
+}
+
+pub fn write_chrome_trace<W: Write>(
+    trace: &Trace,
+    config: &ChromeConfig,
+    out: W,
+) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    let ts = |c: i64| c as f64 * config.us_per_cycle;
+    let mut first = true;
+    let mut sep = |out: &mut io::BufWriter<W>| -> io::Result<()> {
+        if !std::mem::take(&mut first) {
+            out.write_all(b",\n")?;
+        }
+        Ok(())
+    };
+
+    out.write_all(b"{\"traceEvents\":[\n")?;
+    let threads: BTreeSet<u32> = trace.instructions().iter().map(|r| r.thread_id).collect();
+    for t in threads {
+        sep(&mut out)?;
+        write!(
+            out,
+            "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{},\"args\":{{\"name\":\"thread {}\"}}}}",
+            t, t
+        )?;
+    }
+
+    for rec in trace.instructions() {
+        let end = rec.end.unwrap_or(trace.end_cycle());
+        sep(&mut out)?;
+        write!(
+            out,
+            "{{\"ph\":\"b\",\"cat\":\"instr\",\"id\":{},\"pid\":{},\"tid\":0,\"ts\":{},\"name\":",
+            rec.id,
+            rec.thread_id,
+            ts(rec.start)
+        )?;
+        write_str(&mut out, &trace.label(rec))?;
+        write!(
+            out,
+            ",\"args\":{{\"sim_id\":{},\"flushed\":{}}}}}",
+            rec.sim_id,
+            rec.is_flushed()
+        )?;
+
+        for span in &rec.stages {
+            for (ph, at) in [("b", span.start), ("e", span.end)] {
+                sep(&mut out)?;
+                write!(
+                    out,
+                    "{{\"ph\":\"{}\",\"cat\":\"instr\",\"id\":{},\"pid\":{},\"tid\":{},\"ts\":{},\"name\":",
+                    ph,
+                    rec.id,
+                    rec.thread_id,
+                    span.lane,
+                    ts(at)
+                )?;
+                write_str(&mut out, trace.stages().name(span.stage).as_bytes())?;
+                write!(out, ",\"args\":{{\"lane\":{}}}}}", span.lane)?;
+            }
+        }
+
+        sep(&mut out)?;
+        write!(
+            out,
+            "{{\"ph\":\"e\",\"cat\":\"instr\",\"id\":{},\"pid\":{},\"tid\":0,\"ts\":{},\"name\":",
+            rec.id,
+            rec.thread_id,
+            ts(end)
+        )?;
+        write_str(&mut out, &trace.label(rec))?;
+        out.write_all(b"}")?;
+    }
+    out.write_all(b"\n],\"displayTimeUnit\":\"ns\"}\n")?;
+    out.flush()
+}
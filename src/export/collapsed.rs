@@ -0,0 +1,32 @@
+use crate::Trace;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+pub fn write_collapsed<W: Write>(trace: &Trace, out: W) -> io::Result<()> {
+    let mut stacks: BTreeMap<(String, usize), u64> = BTreeMap::new();
+    for rec in trace.instructions() {
+        let root = match trace.pc(rec) {
+            Some(pc) => format!("0x{:x}", pc),
+            None if trace.label(rec).is_empty() => "[unknown]".to_string(),
+            None => String::from_utf8_lossy(&trace.label(rec)).replace(';', ":"),
+        };
+        for span in &rec.stages {
+            *stacks
+                .entry((root.clone(), span.stage.index()))
+                .or_default() += span.cycles();
+        }
+    }
+
+    let names: Vec<String> = trace
+        .stages()
+        .iter()
+        .map(|(_, n)| n.replace(';', ":"))
+        .collect();
+    let mut out = io::BufWriter::new(out);
+    for ((root, stage), cycles) in stacks {
+        if cycles > 0 {
+            writeln!(out, "{};{} {}", root, names[stage], cycles)?;
+        }
+    }
+    out.flush()
+}
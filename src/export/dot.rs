@@ -0,0 +1,72 @@
+use crate::{IdSet, Trace};
+use std::io::{self, Write};
+
+// For inside a DOT string: line breaks and other control characters become
+// spaces.
+fn escape(s: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The dependency graph in graphviz's DOT, for small graphs such as a slice:
+// a node for each instruction, labelled with its id, label and stage path
+// and dashed when flushed, and an edge from each producer to its consumer
+// labelled with the dependency's kind and label. With `only`, just those
+// instructions and the edges among them. Edges from producers the trace has
+// no record of are left out.
+pub fn write_dot<W: Write>(trace: &Trace, only: Option<&IdSet>, out: W) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    let wanted = |id| only.is_none_or(|ids| ids.contains(&id));
+    writeln!(out, "digraph deps {{")?;
+    writeln!(out, "  node [shape=box];")?;
+    for rec in trace.instructions().iter().filter(|r| wanted(r.id)) {
+        let path: Vec<&str> = rec
+            .stages
+            .iter()
+            .map(|s| trace.stages().name(s.stage))
+            .collect();
+        let style = if rec.is_flushed() {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  n{} [label=\"{}: {}\\n{}\"{}];",
+            rec.id,
+            rec.id,
+            escape(&trace.label(rec)),
+            escape(path.join(">").as_bytes()),
+            style
+        )?;
+    }
+    for rec in trace.instructions().iter().filter(|r| wanted(r.id)) {
+        for d in &rec.producers {
+            let Some(p) = trace.get(d.producer_id).filter(|p| wanted(p.id)) else {
+                continue;
+            };
+            let mut label = d.kind.name().as_bytes().to_vec();
+            if let Some(text) = d.label {
+                label.push(b' ');
+                label.extend_from_slice(trace.text(text));
+            }
+            writeln!(
+                out,
+                "  n{} -> n{} [label=\"{}\"];",
+                p.id,
+                rec.id,
+                escape(&label)
+            )?;
+        }
+    }
+    writeln!(out, "}}")?;
+    out.flush()
+}
@@ -0,0 +1,159 @@
+use super::json::write_str;
+use crate::{Clock, Command, Parser, StrRef};
+use std::io::{self, Write};
+
+pub(crate) fn cmd_name<T>(cmd: &Command<T>) -> &'static str {
+    match cmd {
+        Command::Kanata { .. } => "Kanata",
+        Command::Cycle { abs: true, .. } => "C=",
+        Command::Cycle { abs: false, .. } => "C",
+        Command::Instruction { .. } => "I",
+        Command::Log { .. } => "L",
+        Command::Pipeline { start: true, .. } => "S",
+        Command::Pipeline { start: false, .. } => "E",
+        Command::Retire { .. } => "R",
+        Command::Dep { .. } => "W",
+        Command::StageColor { .. } => "P",
+    }
+}
+
+fn csv_text<W: Write>(out: &mut W, s: &[u8]) -> io::Result<()> {
+    if s.iter().any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r')) {
+        out.write_all(b"\"")?;
+        for (i, part) in s.split(|&b| b == b'"').enumerate() {
+            if i > 0 {
+                out.write_all(b"\"\"")?;
+            }
+            out.write_all(part)?;
+        }
+        out.write_all(b"\"")
+    } else {
+        out.write_all(s)
+    }
+}
+
+pub fn write_events_csv<W: Write>(input: &[u8], out: W) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    let mut clock = Clock::new();
+    let text = |s: StrRef| s.get(input);
+    writeln!(
+        out,
+        "offset,cycle,cmd,id,sim_id,thread_id,lane,value,kind,text"
+    )?;
+    for (offset, cmd) in Parser::new(input).extensions() {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        write!(out, "{},{},{},", offset, clock.cycle(), cmd_name(&cmd))?;
+        match cmd {
+            Command::Kanata { version } => write!(out, ",,,,{},,", version)?,
+            Command::Cycle { value, .. } => write!(out, ",,,,{},,", value)?,
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => write!(out, "{},{},{},,,,", id_in_file, id_in_sim, thread_id)?,
+            Command::Log { id, kind, text: t } => {
+                write!(out, "{},,,,,{},", id, kind.name())?;
+                csv_text(&mut out, text(t))?;
+            }
+            Command::Pipeline {
+                id, lane_id, name, ..
+            } => {
+                write!(out, "{},,,{},,,", id, lane_id)?;
+                csv_text(&mut out, text(name).trim_ascii())?;
+            }
+            Command::Retire { id, retire, kind } => {
+                write!(out, "{},,,,{},{},", id, retire, kind.name())?
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => {
+                write!(out, "{},,,,{},{},", consumer_id, producer_id, kind.name())?;
+                if let Some(label) = label {
+                    csv_text(&mut out, text(label))?;
+                }
+            }
+            Command::StageColor { name, color } => {
+                write!(out, ",,,,#{:06x},,", color)?;
+                csv_text(&mut out, text(name).trim_ascii())?;
+            }
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+pub fn write_events_jsonl<W: Write>(input: &[u8], out: W) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    let mut clock = Clock::new();
+    let text = |s: StrRef| s.get(input);
+    for (offset, cmd) in Parser::new(input).extensions() {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        write!(
+            out,
+            "{{\"offset\":{},\"cycle\":{},\"cmd\":\"{}\"",
+            offset,
+            clock.cycle(),
+            cmd_name(&cmd)
+        )?;
+        match cmd {
+            Command::Kanata { version } => write!(out, ",\"version\":{}", version)?,
+            Command::Cycle { value, .. } => write!(out, ",\"value\":{}", value)?,
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => write!(
+                out,
+                ",\"id\":{},\"sim_id\":{},\"thread_id\":{}",
+                id_in_file, id_in_sim, thread_id
+            )?,
+            Command::Log { id, kind, text: t } => {
+                write!(out, ",\"id\":{},\"kind\":\"{}\",\"text\":", id, kind.name())?;
+                write_str(&mut out, text(t))?;
+            }
+            Command::Pipeline {
+                id, lane_id, name, ..
+            } => {
+                write!(out, ",\"id\":{},\"lane\":{},\"stage\":", id, lane_id)?;
+                write_str(&mut out, text(name).trim_ascii())?;
+            }
+            Command::Retire { id, retire, kind } => write!(
+                out,
+                ",\"id\":{},\"retire_id\":{},\"kind\":\"{}\"",
+                id,
+                retire,
+                kind.name()
+            )?,
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => {
+                write!(
+                    out,
+                    ",\"consumer_id\":{},\"producer_id\":{},\"kind\":\"{}\"",
+                    consumer_id,
+                    producer_id,
+                    kind.name()
+                )?;
+                if let Some(label) = label {
+                    write!(out, ",\"label\":")?;
+                    write_str(&mut out, text(label))?;
+                }
+            }
+            Command::StageColor { name, color } => {
+                write!(out, ",\"stage\":")?;
+                write_str(&mut out, text(name).trim_ascii())?;
+                write!(out, ",\"color\":\"#{:06x}\"", color)?;
+            }
+        }
+        writeln!(out, "}}")?;
+    }
+    out.flush()
+}
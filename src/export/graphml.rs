@@ -0,0 +1,111 @@
+use crate::{InstructionRecord, Trace};
+use std::io::{self, Write};
+
+// (id, for, name, type) of each attribute written
+const KEYS: [(&str, &str, &str, &str); 11] = [
+    ("label", "node", "label", "string"),
+    ("thread", "node", "thread", "int"),
+    ("start", "node", "start", "long"),
+    ("end", "node", "end", "long"),
+    ("latency", "node", "latency", "long"),
+    ("stages", "node", "stages", "string"),
+    ("status", "node", "status", "string"),
+    ("kind", "edge", "kind", "string"),
+    ("cycle", "edge", "cycle", "long"),
+    ("wait", "edge", "wait", "long"),
+    ("dep_label", "edge", "label", "string"),
+];
+
+fn escape(s: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c if c.is_control() => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn status(rec: &InstructionRecord) -> &'static str {
+    if rec.is_retired() {
+        "retired"
+    } else if rec.is_flushed() {
+        "flushed"
+    } else {
+        "unfinished"
+    }
+}
+
+// The dependency graph as GraphML, for tools like Gephi and yEd that cope
+// with graphs of a whole trace: a node for each instruction with its label,
+// cycles, stage path (`F>Dc>X`) and status, and an edge from each producer to
+// its consumer with the dependency's kind, cycle and wait in cycles from the
+// producer starting. Edges from producers the trace has no record of are
+// left out.
+pub fn write_graphml<W: Write>(trace: &Trace, out: W) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for (id, on, name, ty) in KEYS {
+        writeln!(
+            out,
+            r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+            id, on, name, ty
+        )?;
+    }
+    writeln!(out, r#"  <graph id="deps" edgedefault="directed">"#)?;
+    for rec in trace.instructions() {
+        writeln!(out, r#"    <node id="n{}">"#, rec.id)?;
+        let data = |out: &mut io::BufWriter<W>, key: &str, v: &dyn std::fmt::Display| {
+            writeln!(out, r#"      <data key="{}">{}</data>"#, key, v)
+        };
+        data(&mut out, "label", &escape(&trace.label(rec)))?;
+        data(&mut out, "thread", &rec.thread_id)?;
+        data(&mut out, "start", &rec.start)?;
+        if let Some(end) = rec.end {
+            data(&mut out, "end", &end)?;
+        }
+        if let Some(latency) = rec.latency() {
+            data(&mut out, "latency", &latency)?;
+        }
+        let path: Vec<&str> = rec
+            .stages
+            .iter()
+            .map(|s| trace.stages().name(s.stage))
+            .collect();
+        data(&mut out, "stages", &escape(path.join(">").as_bytes()))?;
+        data(&mut out, "status", &status(rec))?;
+        writeln!(out, "    </node>")?;
+    }
+    for rec in trace.instructions() {
+        for d in &rec.producers {
+            let Some(p) = trace.get(d.producer_id) else {
+                continue;
+            };
+            writeln!(out, r#"    <edge source="n{}" target="n{}">"#, p.id, rec.id)?;
+            writeln!(out, r#"      <data key="kind">{}</data>"#, d.kind.name())?;
+            writeln!(out, r#"      <data key="cycle">{}</data>"#, d.cycle)?;
+            writeln!(
+                out,
+                r#"      <data key="wait">{}</data>"#,
+                d.cycle - p.start
+            )?;
+            if let Some(label) = d.label {
+                let label = escape(trace.text(label));
+                writeln!(out, r#"      <data key="dep_label">{}</data>"#, label)?;
+            }
+            writeln!(out, "    </edge>")?;
+        }
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    out.flush()
+}
@@ -0,0 +1,22 @@
+mod chrome;
+mod collapsed;
+mod dot;
+mod events;
+mod graphml;
+mod json;
+mod o3;
+mod speedscope;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod vcd;
+pub use chrome::*;
+pub use collapsed::*;
+pub use dot::*;
+pub use events::*;
+pub use graphml::*;
+pub(crate) use json::write_str;
+pub use o3::*;
+pub use speedscope::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+pub use vcd::*;
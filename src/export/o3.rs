@@ -0,0 +1,86 @@
+use crate::{InstructionRecord, StageId, Trace, split_pc};
+use std::io::{self, Write};
+
+#[derive(Clone, Debug)]
+pub struct O3Config {
+    pub ticks_per_cycle: u64,
+    pub fetch: Vec<String>,
+    pub decode: Vec<String>,
+    pub rename: Vec<String>,
+    pub dispatch: Vec<String>,
+    pub issue: Vec<String>,
+    pub complete: Vec<String>,
+}
+
+impl Default for O3Config {
+    fn default() -> Self {
+        let names = |n: &[&str]| n.iter().map(|s| s.to_string()).collect();
+        Self {
+            ticks_per_cycle: 1000,
+            fetch: names(&["F", "IF", "Fetch"]),
+            decode: names(&["Dc", "D", "ID", "Decode"]),
+            rename: names(&["Rn", "Rename"]),
+            dispatch: names(&["Ds", "Dispatch"]),
+            issue: names(&["Is", "Issue"]),
+            complete: names(&["Cm", "Complete", "WB"]),
+        }
+    }
+}
+
+pub fn write_o3pipeview<W: Write>(trace: &Trace, config: &O3Config, out: W) -> io::Result<()> {
+    let mut out = io::BufWriter::new(out);
+    // the format writes 0 for a stage never entered, so nothing that was
+    // lands on tick 0
+    let origin = trace.start_cycle().min(1) - 1;
+    let tick = |c: i64| (c - origin) as u64 * config.ticks_per_cycle;
+    let resolve = |names: &[String]| -> Vec<StageId> {
+        names.iter().filter_map(|n| trace.stages().get(n)).collect()
+    };
+    let [fetch, decode, rename, dispatch, issue, complete] = [
+        &config.fetch,
+        &config.decode,
+        &config.rename,
+        &config.dispatch,
+        &config.issue,
+        &config.complete,
+    ]
+    .map(|n| resolve(n));
+    let first = |rec: &InstructionRecord, ids: &[StageId]| {
+        rec.stages
+            .iter()
+            .find(|s| ids.contains(&s.stage))
+            .map(|s| tick(s.start))
+    };
+    let or_none = |t: Option<u64>| t.unwrap_or(0);
+
+    for rec in trace.instructions() {
+        let label = trace.label(rec);
+        let (pc, disasm) = split_pc(&label);
+        let fetched = first(rec, &fetch).unwrap_or(tick(rec.start));
+        write!(
+            out,
+            "O3PipeView:fetch:{}:0x{:08x}:0:{}:",
+            fetched,
+            pc.unwrap_or(0),
+            rec.sim_id
+        )?;
+        out.write_all(disasm)?;
+        writeln!(out)?;
+        writeln!(out, "O3PipeView:decode:{}", or_none(first(rec, &decode)))?;
+        writeln!(out, "O3PipeView:rename:{}", or_none(first(rec, &rename)))?;
+        writeln!(
+            out,
+            "O3PipeView:dispatch:{}",
+            or_none(first(rec, &dispatch))
+        )?;
+        writeln!(out, "O3PipeView:issue:{}", or_none(first(rec, &issue)))?;
+        writeln!(
+            out,
+            "O3PipeView:complete:{}",
+            or_none(first(rec, &complete))
+        )?;
+        let retire = rec.end.filter(|_| rec.is_retired()).map(tick);
+        writeln!(out, "O3PipeView:retire:{}:store:0", or_none(retire))?;
+    }
+    out.flush()
+}
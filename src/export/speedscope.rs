@@ -0,0 +1,99 @@
+use super::json::write_str;
+use crate::Trace;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpeedscopeGroup {
+    Pc,
+    StagePath,
+}
+
+#[derive(Default)]
+struct Frames {
+    names: Vec<Vec<u8>>,
+    ids: HashMap<Vec<u8>, usize>,
+}
+
+impl Frames {
+    fn id(&mut self, name: &[u8]) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        self.names.push(name.to_vec());
+        self.ids.insert(name.to_vec(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+pub fn write_speedscope<W: Write>(trace: &Trace, group: SpeedscopeGroup, out: W) -> io::Result<()> {
+    let mut frames = Frames::default();
+    let mut stacks: BTreeMap<Vec<usize>, u64> = BTreeMap::new();
+
+    for rec in trace.instructions() {
+        match group {
+            SpeedscopeGroup::Pc => {
+                let root = match trace.pc(rec) {
+                    Some(pc) => frames.id(format!("0x{:x}", pc).as_bytes()),
+                    None => frames.id(&trace.label(rec)),
+                };
+                for span in &rec.stages {
+                    let stage = frames.id(trace.stages().name(span.stage).as_bytes());
+                    *stacks.entry(vec![root, stage]).or_default() += span.cycles();
+                }
+            }
+            SpeedscopeGroup::StagePath => {
+                let mut path = Vec::with_capacity(rec.stages.len());
+                for span in &rec.stages {
+                    path.push(frames.id(trace.stages().name(span.stage).as_bytes()));
+                    *stacks.entry(path.clone()).or_default() += span.cycles();
+                }
+            }
+        }
+    }
+    stacks.retain(|_, w| *w > 0);
+    let total: u64 = stacks.values().sum();
+
+    let mut out = io::BufWriter::new(out);
+    out.write_all(b"{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",")?;
+    out.write_all(b"\"exporter\":\"kanata\",\"shared\":{\"frames\":[")?;
+    for (i, name) in frames.names.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(b"{\"name\":")?;
+        write_str(&mut out, name)?;
+        out.write_all(b"}")?;
+    }
+    let name = match group {
+        SpeedscopeGroup::Pc => "stage cycles by pc",
+        SpeedscopeGroup::StagePath => "stage cycles by stage path",
+    };
+    write!(
+        out,
+        "]}},\"profiles\":[{{\"type\":\"sampled\",\"name\":\"{}\",\"unit\":\"none\",\"startValue\":0,\"endValue\":{},\"samples\":[",
+        name, total
+    )?;
+    for (i, stack) in stacks.keys().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(b"[")?;
+        for (j, f) in stack.iter().enumerate() {
+            if j > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{}", f)?;
+        }
+        out.write_all(b"]")?;
+    }
+    out.write_all(b"],\"weights\":[")?;
+    for (i, w) in stacks.values().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(out, "{}", w)?;
+    }
+    out.write_all(b"]}]}\n")?;
+    out.flush()
+}
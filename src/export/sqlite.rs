@@ -0,0 +1,117 @@
+use crate::Trace;
+use rusqlite::{Connection, params};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE stages (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+CREATE TABLE instructions (
+    id INTEGER NOT NULL,
+    sim_id INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+    offset INTEGER NOT NULL,
+    start_cycle INTEGER NOT NULL,
+    end_cycle INTEGER,
+    retire_id INTEGER,
+    retire_kind TEXT,
+    pc INTEGER,
+    label TEXT
+);
+CREATE TABLE stage_spans (
+    instr_id INTEGER NOT NULL,
+    stage_id INTEGER NOT NULL REFERENCES stages(id),
+    lane INTEGER NOT NULL,
+    start_cycle INTEGER NOT NULL,
+    end_cycle INTEGER NOT NULL
+);
+CREATE TABLE deps (
+    consumer_id INTEGER NOT NULL,
+    producer_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    cycle INTEGER NOT NULL,
+    label TEXT
+);
+CREATE TABLE logs (
+    instr_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    text TEXT NOT NULL
+);
+";
+
+const INDICES: &str = "
+CREATE INDEX instructions_id ON instructions(id);
+CREATE INDEX instructions_sim_id ON instructions(sim_id);
+CREATE INDEX instructions_start ON instructions(start_cycle);
+CREATE INDEX instructions_pc ON instructions(pc);
+CREATE INDEX stage_spans_instr ON stage_spans(instr_id);
+CREATE INDEX stage_spans_start ON stage_spans(start_cycle);
+CREATE INDEX deps_consumer ON deps(consumer_id);
+CREATE INDEX deps_producer ON deps(producer_id);
+CREATE INDEX logs_instr ON logs(instr_id);
+";
+
+// Ids, offsets and pcs as SQLite integers. With `wide-ids` an id may not
+// fit, which fails the export rather than wrapping to a negative row.
+fn int<T: TryInto<i64>>(v: T) -> rusqlite::Result<i64>
+where
+    T::Error: std::error::Error + Send + Sync + 'static,
+{
+    v.try_into()
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+pub fn write_sqlite(trace: &Trace, path: impl AsRef<Path>) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(path)?;
+    // the tables too, so a failed export leaves none behind
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    {
+        let mut stage = tx.prepare("INSERT INTO stages VALUES (?1, ?2)")?;
+        for (id, name) in trace.stages().iter() {
+            stage.execute(params![id.index() as i64, name])?;
+        }
+
+        let mut instr = tx
+            .prepare("INSERT INTO instructions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+        let mut span = tx.prepare("INSERT INTO stage_spans VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        let mut dep = tx.prepare("INSERT INTO deps VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        let mut log = tx.prepare("INSERT INTO logs VALUES (?1, ?2, ?3)")?;
+        for rec in trace.instructions() {
+            instr.execute(params![
+                int(rec.id)?,
+                int(rec.sim_id)?,
+                rec.thread_id,
+                int(rec.offset)?,
+                rec.start,
+                rec.end,
+                rec.retire_id.map(int).transpose()?,
+                rec.retire_kind.map(|k| k.name()),
+                trace.pc(rec).map(int).transpose()?,
+                String::from_utf8_lossy(&trace.label(rec)),
+            ])?;
+            for s in &rec.stages {
+                span.execute(params![
+                    int(rec.id)?,
+                    s.stage.index() as i64,
+                    s.lane,
+                    s.start,
+                    s.end
+                ])?;
+            }
+            for d in &rec.producers {
+                dep.execute(params![
+                    int(rec.id)?,
+                    int(d.producer_id)?,
+                    d.kind.name(),
+                    d.cycle,
+                    d.label.map(|l| String::from_utf8_lossy(trace.text(l)))
+                ])?;
+            }
+            for l in &rec.logs {
+                let text = String::from_utf8_lossy(trace.text(l.text));
+                log.execute(params![int(rec.id)?, l.kind.name(), text])?;
+            }
+        }
+    }
+    tx.execute_batch(INDICES)?;
+    tx.commit()
+}
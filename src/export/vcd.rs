@@ -0,0 +1,153 @@
+use crate::{Id, Trace};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdConfig {
+    pub timescale: String,
+    pub lane_ids: bool,
+}
+
+impl Default for VcdConfig {
+    fn default() -> Self {
+        Self {
+            timescale: "1ns".to_string(),
+            lane_ids: false,
+        }
+    }
+}
+
+fn ident(mut n: usize) -> String {
+    let mut s = String::new();
+    loop {
+        s.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return s;
+        }
+        n -= 1;
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    End { lane: u32, stage: usize, id: Id },
+    Start { lane: u32, stage: usize, id: Id },
+}
+
+pub fn write_vcd<W: Write>(trace: &Trace, config: &VcdConfig, out: W) -> io::Result<()> {
+    let origin = trace.start_cycle().min(0);
+    let mut changes: BTreeMap<i64, Vec<Change>> = BTreeMap::new();
+    let mut lanes = BTreeSet::new();
+    for rec in trace.instructions() {
+        // zero-length spans are never visible and would end before they start
+        for s in rec.stages.iter().filter(|s| s.end > s.start) {
+            let (lane, stage, id) = (s.lane, s.stage.index(), rec.id);
+            lanes.insert(lane);
+            changes
+                .entry(s.start)
+                .or_default()
+                .push(Change::Start { lane, stage, id });
+            changes
+                .entry(s.end)
+                .or_default()
+                .push(Change::End { lane, stage, id });
+        }
+    }
+    let lanes: Vec<u32> = lanes.into_iter().collect();
+    let lane_ix = |lane: u32| lanes.binary_search(&lane).unwrap();
+
+    let stages = trace.stages().len();
+    let stage_sig = |s: usize| ident(s);
+    let lane_sig = |l: usize| ident(stages + l);
+    let id_sig = |l: usize| ident(stages + lanes.len() + l);
+
+    let mut out = io::BufWriter::new(out);
+    writeln!(out, "$timescale {} $end", config.timescale)?;
+    writeln!(out, "$scope module kanata $end")?;
+    writeln!(out, "$scope module stage $end")?;
+    for (id, name) in trace.stages().iter() {
+        let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        writeln!(out, "$var wire 32 {} {} $end", stage_sig(id.index()), name)?;
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$scope module lane $end")?;
+    for (i, lane) in lanes.iter().enumerate() {
+        writeln!(out, "$var wire 32 {} lane{} $end", lane_sig(i), lane)?;
+        if config.lane_ids {
+            // as wide as an id, which is 64 bits with `wide-ids`
+            writeln!(
+                out,
+                "$var wire {} {} lane{}_id $end",
+                Id::BITS,
+                id_sig(i),
+                lane
+            )?;
+        }
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+
+    let mut stage_occ = vec![0u32; stages];
+    let mut lane_occ = vec![0u32; lanes.len()];
+    let mut lane_active: Vec<Vec<Id>> = vec![Vec::new(); lanes.len()];
+    let mut lane_cur: Vec<Option<Id>> = vec![None; lanes.len()];
+
+    writeln!(out, "#0")?;
+    writeln!(out, "$dumpvars")?;
+    for s in 0..stages {
+        writeln!(out, "b0 {}", stage_sig(s))?;
+    }
+    for l in 0..lanes.len() {
+        writeln!(out, "b0 {}", lane_sig(l))?;
+        if config.lane_ids {
+            writeln!(out, "bx {}", id_sig(l))?;
+        }
+    }
+    writeln!(out, "$end")?;
+
+    for (cycle, mut list) in changes {
+        list.sort();
+        let before_stage = stage_occ.clone();
+        let before_lane = lane_occ.clone();
+        for c in list {
+            match c {
+                Change::End { lane, stage, id } => {
+                    let l = lane_ix(lane);
+                    stage_occ[stage] -= 1;
+                    lane_occ[l] -= 1;
+                    if let Some(p) = lane_active[l].iter().rposition(|&a| a == id) {
+                        lane_active[l].remove(p);
+                    }
+                }
+                Change::Start { lane, stage, id } => {
+                    let l = lane_ix(lane);
+                    stage_occ[stage] += 1;
+                    lane_occ[l] += 1;
+                    lane_active[l].push(id);
+                }
+            }
+        }
+        writeln!(out, "#{}", cycle - origin)?;
+        for s in 0..stages {
+            if stage_occ[s] != before_stage[s] {
+                writeln!(out, "b{:b} {}", stage_occ[s], stage_sig(s))?;
+            }
+        }
+        for l in 0..lanes.len() {
+            if lane_occ[l] != before_lane[l] {
+                writeln!(out, "b{:b} {}", lane_occ[l], lane_sig(l))?;
+            }
+            let cur = lane_active[l].last().copied();
+            if config.lane_ids && cur != lane_cur[l] {
+                match cur {
+                    Some(id) => writeln!(out, "b{:b} {}", id, id_sig(l))?,
+                    None => writeln!(out, "bx {}", id_sig(l))?,
+                }
+                lane_cur[l] = cur;
+            }
+        }
+    }
+    out.flush()
+}
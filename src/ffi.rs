@@ -0,0 +1,330 @@
+// C ABI over the parser and writer. Every function is null-tolerant and
+// returns a negative value (or null) on misuse instead of unwinding. The
+// crate builds as a plain Rust library; for C, build it as one with
+//
+//     cargo rustc --lib --release --features ffi --crate-type staticlib
+//
+// (or `cdylib`) and include `include/kanata.h`.
+use crate::{Command, DepKind, Id, LogKind, ParseError, Parser, RetireKind, Writer};
+use std::ffi::{CStr, c_char, c_int};
+use std::fs::File;
+use std::io::BufWriter;
+use std::{ptr, slice};
+
+// One parser for the handle's life, so the version, clock and line endings
+// carry from one command to the next. It reads `input`, which is leaked
+// from a box and only taken back once the parser is gone.
+pub struct KanataParser {
+    parser: Parser<'static>,
+    input: *mut [u8],
+    error: Option<ParseError>,
+}
+
+pub struct KanataWriter {
+    writer: Writer<BufWriter<File>>,
+}
+
+// Field use per tag:
+//   'K' a=version
+//   'C' flag=1 if absolute, value=cycle
+//   'I' a=id_in_file b=id_in_sim c=thread_id
+//   'L' a=id flag=kind digit, text
+//   'S'/'E' a=id b=lane_id, text=stage name
+//   'R' a=id b=retire id flag=kind digit
+//   'W' a=consumer b=producer flag=kind digit, text=label if any
+//   'P' a=color as 0xRRGGBB, text=stage name
+// `text` points into the parser's buffer and lives as long as the parser.
+// `a` and `b` are 64 bits wide when the library is built with `wide-ids`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct KanataCommand {
+    pub tag: u8,
+    pub flag: u8,
+    pub a: Id,
+    pub b: Id,
+    pub c: u32,
+    pub value: i32,
+    pub text: *const u8,
+    pub text_len: usize,
+}
+
+impl Default for KanataCommand {
+    fn default() -> Self {
+        Self {
+            tag: 0,
+            flag: 0,
+            a: 0,
+            b: 0,
+            c: 0,
+            value: 0,
+            text: ptr::null(),
+            text_len: 0,
+        }
+    }
+}
+
+fn to_ffi(cmd: Command, input: &[u8]) -> KanataCommand {
+    let d = KanataCommand::default();
+    match cmd {
+        Command::Kanata { version } => KanataCommand {
+            tag: b'K',
+            a: version as Id,
+            ..d
+        },
+        Command::Cycle { abs, value } => KanataCommand {
+            tag: b'C',
+            flag: abs as u8,
+            value,
+            ..d
+        },
+        Command::Instruction {
+            id_in_file,
+            id_in_sim,
+            thread_id,
+        } => KanataCommand {
+            tag: b'I',
+            a: id_in_file,
+            b: id_in_sim,
+            c: thread_id,
+            ..d
+        },
+        Command::Log { id, kind, text } => {
+            let text = text.get(input);
+            KanataCommand {
+                tag: b'L',
+                flag: kind as u8,
+                a: id,
+                text: text.as_ptr(),
+                text_len: text.len(),
+                ..d
+            }
+        }
+        Command::Pipeline {
+            start,
+            id,
+            lane_id,
+            name,
+        } => {
+            let name = name.get(input);
+            KanataCommand {
+                tag: if start { b'S' } else { b'E' },
+                a: id,
+                b: lane_id as Id,
+                text: name.as_ptr(),
+                text_len: name.len(),
+                ..d
+            }
+        }
+        Command::Retire { id, retire, kind } => KanataCommand {
+            tag: b'R',
+            flag: kind as u8,
+            a: id,
+            b: retire,
+            ..d
+        },
+        Command::Dep {
+            consumer_id,
+            producer_id,
+            kind,
+            label,
+        } => {
+            let label = label.map(|l| l.get(input));
+            KanataCommand {
+                tag: b'W',
+                flag: kind as u8,
+                a: consumer_id,
+                b: producer_id,
+                text: label.map_or(ptr::null(), |l| l.as_ptr()),
+                text_len: label.map_or(0, |l| l.len()),
+                ..d
+            }
+        }
+        Command::StageColor { name, color } => {
+            let name = name.get(input);
+            KanataCommand {
+                tag: b'P',
+                a: color as Id,
+                text: name.as_ptr(),
+                text_len: name.len(),
+                ..d
+            }
+        }
+    }
+}
+
+// The fields that aren't ids stay u32 whatever the id width.
+#[allow(clippy::useless_conversion)]
+fn narrow(v: Id) -> Option<u32> {
+    u32::try_from(v).ok()
+}
+
+fn from_ffi<'a>(cmd: &KanataCommand, text: &'a [u8]) -> Option<Command<&'a [u8]>> {
+    Some(match cmd.tag {
+        b'K' => Command::Kanata {
+            version: narrow(cmd.a)?,
+        },
+        b'C' => Command::Cycle {
+            abs: cmd.flag != 0,
+            value: cmd.value,
+        },
+        b'I' => Command::Instruction {
+            id_in_file: cmd.a,
+            id_in_sim: cmd.b,
+            thread_id: cmd.c,
+        },
+        b'L' => Command::Log {
+            id: cmd.a,
+            kind: LogKind::try_from(cmd.flag).ok()?,
+            text,
+        },
+        b'S' | b'E' => Command::Pipeline {
+            start: cmd.tag == b'S',
+            id: cmd.a,
+            lane_id: narrow(cmd.b)?,
+            name: text,
+        },
+        b'R' => Command::Retire {
+            id: cmd.a,
+            retire: cmd.b,
+            kind: RetireKind::try_from(cmd.flag).ok()?,
+        },
+        b'W' => Command::Dep {
+            consumer_id: cmd.a,
+            producer_id: cmd.b,
+            kind: DepKind::try_from(cmd.flag).ok()?,
+            label: (!text.is_empty()).then_some(text),
+        },
+        b'P' => Command::StageColor {
+            name: text,
+            color: narrow(cmd.a & 0xff_ffff)?,
+        },
+        _ => return None,
+    })
+}
+
+/// # Safety
+/// `data` must point to `len` readable bytes. The bytes are copied.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_parser_new(data: *const u8, len: usize) -> *mut KanataParser {
+    let input: Box<[u8]> = if data.is_null() {
+        Box::new([])
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }.into()
+    };
+    let input = Box::into_raw(input);
+    Box::into_raw(Box::new(KanataParser {
+        parser: Parser::new(unsafe { &*input }).extensions(),
+        input,
+        error: None,
+    }))
+}
+
+/// # Safety
+/// `p` must come from `kanata_parser_new` and `out` must be writable.
+/// Returns 1 when a command was stored, 0 at end of input and -1 on error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_parser_next(
+    p: *mut KanataParser,
+    out: *mut KanataCommand,
+) -> c_int {
+    let (Some(p), Some(out)) = (unsafe { p.as_mut() }, unsafe { out.as_mut() }) else {
+        return -1;
+    };
+    if p.error.is_some() {
+        return -1;
+    }
+    match p.parser.next() {
+        None => 0,
+        Some((_, Ok(cmd))) => {
+            *out = to_ffi(cmd, p.parser.input());
+            1
+        }
+        Some((_, Err(e))) => {
+            p.error = Some(e);
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// `p` must come from `kanata_parser_new`. Returns the byte offset of the
+/// last error and stores its kind (as `ParseErrorKind` discriminant) in
+/// `kind`, or returns -1 when no error occurred.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_parser_error(p: *const KanataParser, kind: *mut c_int) -> i64 {
+    let Some(e) = unsafe { p.as_ref() }.and_then(|p| p.error) else {
+        return -1;
+    };
+    if let Some(kind) = unsafe { kind.as_mut() } {
+        *kind = e.kind as c_int;
+    }
+    e.offset as i64
+}
+
+/// # Safety
+/// `p` must come from `kanata_parser_new` and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_parser_free(p: *mut KanataParser) {
+    if !p.is_null() {
+        let KanataParser { parser, input, .. } = *unsafe { Box::from_raw(p) };
+        drop(parser);
+        drop(unsafe { Box::from_raw(input) });
+    }
+}
+
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_writer_new(path: *const c_char) -> *mut KanataWriter {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return ptr::null_mut();
+    };
+    match File::create(path) {
+        Ok(f) => Box::into_raw(Box::new(KanataWriter {
+            writer: Writer::new(BufWriter::new(f)),
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `w` must come from `kanata_writer_new`, `cmd` must be readable and, for
+/// `L`/`S`/`E`/`W`/`P`, `cmd.text` must be null or point to `cmd.text_len`
+/// readable bytes.
+/// Returns 0 on success and -1 on error.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_writer_write(
+    w: *mut KanataWriter,
+    cmd: *const KanataCommand,
+) -> c_int {
+    let (Some(w), Some(cmd)) = (unsafe { w.as_mut() }, unsafe { cmd.as_ref() }) else {
+        return -1;
+    };
+    let text = if cmd.text.is_null() {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(cmd.text, cmd.text_len) }
+    };
+    match from_ffi(cmd, text) {
+        Some(c) if w.writer.write(&c).is_ok() => 0,
+        _ => -1,
+    }
+}
+
+/// # Safety
+/// `w` must come from `kanata_writer_new` and not be used afterwards.
+/// Returns 0 if the final flush succeeded.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kanata_writer_free(w: *mut KanataWriter) -> c_int {
+    if w.is_null() {
+        return -1;
+    }
+    let mut w = unsafe { Box::from_raw(w) };
+    match w.writer.flush() {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
@@ -0,0 +1,609 @@
+use crate::{Id, InstructionRecord, Trace};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+// Filter expressions over instructions, e.g.
+//
+//     thread == 1 && stage("LSU") > 20 && label =~ "vadd"
+//
+// `=~` and `!~` test for a substring. Comparisons against a missing value,
+// like the `end` of an instruction still in flight, are false.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterExpr {
+    root: Node,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterErrorKind {
+    UnexpectedCharacter,
+    UnterminatedString,
+    NumberTooBig,
+    ExpectedValue,
+    ExpectedParen,
+    UnknownField,
+    TypeMismatch,
+    TrailingInput,
+    TooDeep,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FilterError {
+    pub offset: usize,
+    pub kind: FilterErrorKind,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Str,
+    Bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Field {
+    Id,
+    SimId,
+    Thread,
+    Start,
+    End,
+    Latency,
+    RetireId,
+    Pc,
+    Wakeup,
+    Retired,
+    Flushed,
+    Label,
+    Stage(String),
+}
+
+impl Field {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "id" => Field::Id,
+            "sim_id" => Field::SimId,
+            "thread" => Field::Thread,
+            "start" => Field::Start,
+            "end" => Field::End,
+            "latency" => Field::Latency,
+            "retire_id" => Field::RetireId,
+            "pc" => Field::Pc,
+            "wakeup" => Field::Wakeup,
+            "retired" => Field::Retired,
+            "flushed" => Field::Flushed,
+            "label" => Field::Label,
+            _ => return None,
+        })
+    }
+
+    fn ty(&self) -> Ty {
+        match self {
+            Field::Retired | Field::Flushed => Ty::Bool,
+            Field::Label => Ty::Str,
+            _ => Ty::Int,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+    Field(Field),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Cmp(Cmp, Box<Node>, Box<Node>),
+}
+
+enum Value<'t> {
+    Int(Option<i64>),
+    Str(Cow<'t, str>),
+    Bool(bool),
+}
+
+impl FilterExpr {
+    pub fn parse(src: &str) -> Result<Self, FilterError> {
+        let mut p = ExprParser {
+            src,
+            pos: 0,
+            depth: 0,
+        };
+        let (root, ty) = p.or()?;
+        p.skip_spaces();
+        if p.pos < src.len() {
+            return Err(p.error(FilterErrorKind::TrailingInput));
+        }
+        if ty != Ty::Bool {
+            return Err(FilterError {
+                offset: 0,
+                kind: FilterErrorKind::TypeMismatch,
+            });
+        }
+        Ok(Self { root })
+    }
+
+    pub fn matches(&self, trace: &Trace, rec: &InstructionRecord) -> bool {
+        matches!(eval(&self.root, trace, rec), Value::Bool(true))
+    }
+}
+
+// The same selection built in code rather than parsed, e.g.
+//
+//     Filter::thread(1).and(Filter::cycle_range(100..200)).and(!Filter::flushed())
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    rule: Rule,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Rule {
+    All,
+    Thread(u32),
+    Cycles(Range<i64>),
+    Ids(Range<Id>),
+    Label(String),
+    Retired,
+    Flushed,
+    StageAtLeast(String, u64),
+    Expr(FilterExpr),
+    Not(Box<Rule>),
+    And(Box<Rule>, Box<Rule>),
+    Or(Box<Rule>, Box<Rule>),
+}
+
+impl Filter {
+    fn new(rule: Rule) -> Self {
+        Self { rule }
+    }
+
+    pub fn all() -> Self {
+        Self::new(Rule::All)
+    }
+
+    pub fn thread(thread: u32) -> Self {
+        Self::new(Rule::Thread(thread))
+    }
+
+    // instructions alive at some point in the range; those still in flight
+    // count as alive until the end of the trace
+    pub fn cycle_range(cycles: Range<i64>) -> Self {
+        Self::new(Rule::Cycles(cycles))
+    }
+
+    pub fn id_range(ids: Range<Id>) -> Self {
+        Self::new(Rule::Ids(ids))
+    }
+
+    pub fn label_contains(text: impl Into<String>) -> Self {
+        Self::new(Rule::Label(text.into()))
+    }
+
+    pub fn retired() -> Self {
+        Self::new(Rule::Retired)
+    }
+
+    pub fn flushed() -> Self {
+        Self::new(Rule::Flushed)
+    }
+
+    pub fn stage_at_least(stage: impl Into<String>, cycles: u64) -> Self {
+        Self::new(Rule::StageAtLeast(stage.into(), cycles))
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match (self.rule, other.rule) {
+            (Rule::All, rule) | (rule, Rule::All) => Self::new(rule),
+            (a, b) => Self::new(Rule::And(Box::new(a), Box::new(b))),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        Self::new(Rule::Or(Box::new(self.rule), Box::new(other.rule)))
+    }
+
+    pub fn matches(&self, trace: &Trace, rec: &InstructionRecord) -> bool {
+        rule_matches(&self.rule, trace, rec)
+    }
+
+    // Text that the label of every instruction the filter keeps contains,
+    // if it has to have some: from `label_contains` or `label =~ "text"`,
+    // alone or and-ed with other rules.
+    pub fn label_literal(&self) -> Option<&str> {
+        rule_literal(&self.rule).filter(|t| !t.is_empty())
+    }
+}
+
+fn rule_literal(rule: &Rule) -> Option<&str> {
+    match rule {
+        Rule::Label(text) => Some(text),
+        Rule::Expr(e) => node_literal(&e.root),
+        Rule::And(a, b) => rule_literal(a).or_else(|| rule_literal(b)),
+        _ => None,
+    }
+}
+
+fn node_literal(node: &Node) -> Option<&str> {
+    match node {
+        Node::Cmp(Cmp::Contains, a, b) => match (&**a, &**b) {
+            (Node::Field(Field::Label), Node::Str(text)) => Some(text),
+            _ => None,
+        },
+        Node::And(a, b) => node_literal(a).or_else(|| node_literal(b)),
+        _ => None,
+    }
+}
+
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Self::new(Rule::Not(Box::new(self.rule)))
+    }
+}
+
+impl From<FilterExpr> for Filter {
+    fn from(expr: FilterExpr) -> Self {
+        Self::new(Rule::Expr(expr))
+    }
+}
+
+fn rule_matches(rule: &Rule, trace: &Trace, rec: &InstructionRecord) -> bool {
+    match rule {
+        Rule::All => true,
+        Rule::Thread(t) => rec.thread_id == *t,
+        Rule::Cycles(c) => rec.start < c.end && rec.end.unwrap_or(trace.end_cycle()) >= c.start,
+        Rule::Ids(ids) => ids.contains(&rec.id),
+        Rule::Label(text) => memchr::memmem::find(&trace.label(rec), text.as_bytes()).is_some(),
+        Rule::Retired => rec.is_retired(),
+        Rule::Flushed => rec.is_flushed(),
+        Rule::StageAtLeast(name, cycles) => trace
+            .stages()
+            .get(name)
+            .is_some_and(|s| rec.stage_latency(s) >= *cycles),
+        Rule::Expr(e) => e.matches(trace, rec),
+        Rule::Not(a) => !rule_matches(a, trace, rec),
+        Rule::And(a, b) => rule_matches(a, trace, rec) && rule_matches(b, trace, rec),
+        Rule::Or(a, b) => rule_matches(a, trace, rec) || rule_matches(b, trace, rec),
+    }
+}
+
+impl Trace<'_> {
+    pub fn select<'t>(&'t self, filter: &'t Filter) -> impl Iterator<Item = &'t InstructionRecord> {
+        self.instructions()
+            .iter()
+            .filter(move |r| filter.matches(self, r))
+    }
+}
+
+fn field<'t>(f: &Field, trace: &'t Trace, rec: &InstructionRecord) -> Value<'t> {
+    let int = |v: i64| Value::Int(Some(v));
+    match f {
+        Field::Id => int(rec.id as i64),
+        Field::SimId => int(rec.sim_id as i64),
+        Field::Thread => int(rec.thread_id as i64),
+        Field::Start => int(rec.start),
+        Field::End => Value::Int(rec.end),
+        Field::Latency => Value::Int(rec.latency().map(|v| v as i64)),
+        Field::RetireId => Value::Int(rec.retire_id.map(|v| v as i64)),
+        Field::Pc => Value::Int(trace.pc(rec).map(|v| v as i64)),
+        Field::Wakeup => Value::Int(rec.wakeup_delay().map(|v| v as i64)),
+        Field::Retired => Value::Bool(rec.is_retired()),
+        Field::Flushed => Value::Bool(rec.is_flushed()),
+        Field::Label => Value::Str(match trace.label(rec) {
+            Cow::Borrowed(s) => String::from_utf8_lossy(s),
+            Cow::Owned(s) => Cow::Owned(String::from_utf8_lossy(&s).into_owned()),
+        }),
+        Field::Stage(name) => int(trace
+            .stages()
+            .get(name)
+            .map_or(0, |s| rec.stage_latency(s) as i64)),
+    }
+}
+
+fn eval<'t>(node: &'t Node, trace: &'t Trace, rec: &InstructionRecord) -> Value<'t> {
+    let truth = |n: &'t Node| matches!(eval(n, trace, rec), Value::Bool(true));
+    match node {
+        Node::Int(v) => Value::Int(Some(*v)),
+        Node::Str(s) => Value::Str(Cow::Borrowed(s)),
+        Node::Bool(b) => Value::Bool(*b),
+        Node::Field(f) => field(f, trace, rec),
+        Node::Not(a) => Value::Bool(!truth(a)),
+        Node::And(a, b) => Value::Bool(truth(a) && truth(b)),
+        Node::Or(a, b) => Value::Bool(truth(a) || truth(b)),
+        Node::Cmp(op, a, b) => {
+            let r = match (eval(a, trace, rec), eval(b, trace, rec)) {
+                (Value::Int(Some(a)), Value::Int(Some(b))) => compare(*op, a.cmp(&b)),
+                (Value::Int(_), Value::Int(_)) => false,
+                (Value::Str(a), Value::Str(b)) => match op {
+                    Cmp::Contains => a.contains(b.as_ref()),
+                    Cmp::NotContains => !a.contains(b.as_ref()),
+                    _ => compare(*op, a.cmp(&b)),
+                },
+                (Value::Bool(a), Value::Bool(b)) => compare(*op, a.cmp(&b)),
+                _ => false,
+            };
+            Value::Bool(r)
+        }
+    }
+}
+
+fn compare(op: Cmp, ord: std::cmp::Ordering) -> bool {
+    match op {
+        Cmp::Eq => ord.is_eq(),
+        Cmp::Ne => ord.is_ne(),
+        Cmp::Lt => ord.is_lt(),
+        Cmp::Le => ord.is_le(),
+        Cmp::Gt => ord.is_gt(),
+        Cmp::Ge => ord.is_ge(),
+        Cmp::Contains | Cmp::NotContains => false,
+    }
+}
+
+// How deep parentheses, `!` and chains of `&&` or `||` may nest, so the
+// recursive parser and `eval` stay well within the stack.
+const MAX_DEPTH: usize = 256;
+
+struct ExprParser<'s> {
+    src: &'s str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'s> ExprParser<'s> {
+    fn error(&self, kind: FilterErrorKind) -> FilterError {
+        FilterError {
+            offset: self.pos,
+            kind,
+        }
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // One level deeper, until `MAX_DEPTH`; the caller steps back out.
+    fn nest(&mut self) -> Result<(), FilterError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error(FilterErrorKind::TooDeep));
+        }
+        Ok(())
+    }
+
+    fn expect_bool(&self, ty: Ty, at: usize) -> Result<(), FilterError> {
+        if ty == Ty::Bool {
+            Ok(())
+        } else {
+            Err(FilterError {
+                offset: at,
+                kind: FilterErrorKind::TypeMismatch,
+            })
+        }
+    }
+
+    fn or(&mut self) -> Result<(Node, Ty), FilterError> {
+        let at = self.pos;
+        let (mut node, ty) = self.and()?;
+        let depth = self.depth;
+        while self.eat("||") {
+            self.expect_bool(ty, at)?;
+            self.nest()?;
+            let at = self.pos;
+            let (rhs, ty) = self.and()?;
+            self.expect_bool(ty, at)?;
+            node = Node::Or(Box::new(node), Box::new(rhs));
+        }
+        self.depth = depth;
+        Ok((node, ty))
+    }
+
+    fn and(&mut self) -> Result<(Node, Ty), FilterError> {
+        let at = self.pos;
+        let (mut node, ty) = self.unary()?;
+        let depth = self.depth;
+        while self.eat("&&") {
+            self.expect_bool(ty, at)?;
+            self.nest()?;
+            let at = self.pos;
+            let (rhs, ty) = self.unary()?;
+            self.expect_bool(ty, at)?;
+            node = Node::And(Box::new(node), Box::new(rhs));
+        }
+        self.depth = depth;
+        Ok((node, ty))
+    }
+
+    fn unary(&mut self) -> Result<(Node, Ty), FilterError> {
+        self.skip_spaces();
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.pos += 1;
+            self.nest()?;
+            let at = self.pos;
+            let (node, ty) = self.unary()?;
+            self.depth -= 1;
+            self.expect_bool(ty, at)?;
+            return Ok((Node::Not(Box::new(node)), Ty::Bool));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<(Node, Ty), FilterError> {
+        let (lhs, lty) = self.primary()?;
+        const OPS: [(&str, Cmp); 8] = [
+            ("==", Cmp::Eq),
+            ("!=", Cmp::Ne),
+            ("<=", Cmp::Le),
+            (">=", Cmp::Ge),
+            ("=~", Cmp::Contains),
+            ("!~", Cmp::NotContains),
+            ("<", Cmp::Lt),
+            (">", Cmp::Gt),
+        ];
+        let Some(&(_, op)) = OPS.iter().find(|(tok, _)| self.eat(tok)) else {
+            return Ok((lhs, lty));
+        };
+        let at = self.pos;
+        let (rhs, rty) = self.primary()?;
+        let text_only = matches!(op, Cmp::Contains | Cmp::NotContains);
+        if lty != rty || (text_only && lty != Ty::Str) {
+            return Err(FilterError {
+                offset: at,
+                kind: FilterErrorKind::TypeMismatch,
+            });
+        }
+        Ok((Node::Cmp(op, Box::new(lhs), Box::new(rhs)), Ty::Bool))
+    }
+
+    fn primary(&mut self) -> Result<(Node, Ty), FilterError> {
+        self.skip_spaces();
+        let rest = self.rest();
+        let Some(c) = rest.chars().next() else {
+            return Err(self.error(FilterErrorKind::ExpectedValue));
+        };
+        if c == '(' {
+            self.pos += 1;
+            self.nest()?;
+            let inner = self.or()?;
+            self.depth -= 1;
+            if !self.eat(")") {
+                return Err(self.error(FilterErrorKind::ExpectedParen));
+            }
+            return Ok(inner);
+        }
+        if c == '"' {
+            return Ok((Node::Str(self.string()?), Ty::Str));
+        }
+        if c.is_ascii_digit() || c == '-' {
+            return Ok((Node::Int(self.number()?), Ty::Int));
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = self.pos;
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            self.pos += len;
+            let name = &self.src[start..self.pos];
+            let node = match name {
+                "true" => Node::Bool(true),
+                "false" => Node::Bool(false),
+                "stage" => {
+                    if !self.eat("(") {
+                        return Err(self.error(FilterErrorKind::ExpectedParen));
+                    }
+                    self.skip_spaces();
+                    let stage = self.string()?;
+                    if !self.eat(")") {
+                        return Err(self.error(FilterErrorKind::ExpectedParen));
+                    }
+                    Node::Field(Field::Stage(stage))
+                }
+                _ => match Field::named(name) {
+                    Some(f) => Node::Field(f),
+                    None => {
+                        return Err(FilterError {
+                            offset: start,
+                            kind: FilterErrorKind::UnknownField,
+                        });
+                    }
+                },
+            };
+            let ty = match &node {
+                Node::Field(f) => f.ty(),
+                _ => Ty::Bool,
+            };
+            return Ok((node, ty));
+        }
+        Err(self.error(FilterErrorKind::UnexpectedCharacter))
+    }
+
+    fn string(&mut self) -> Result<String, FilterError> {
+        let start = self.pos;
+        if !self.rest().starts_with('"') {
+            return Err(self.error(FilterErrorKind::ExpectedValue));
+        }
+        let mut out = String::new();
+        let mut chars = self.rest()[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 2;
+                    return Ok(out);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, c)) => out.push(c),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        Err(FilterError {
+            offset: start,
+            kind: FilterErrorKind::UnterminatedString,
+        })
+    }
+
+    fn number(&mut self) -> Result<i64, FilterError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let (neg, digits) = match rest.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, rest),
+        };
+        let (radix, digits, skip) = match digits.strip_prefix("0x") {
+            Some(hex) => (16, hex, 2),
+            None => (10, digits, 0),
+        };
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if len == 0 {
+            return Err(self.error(FilterErrorKind::ExpectedValue));
+        }
+        self.pos += neg as usize + skip + len;
+        let err = FilterError {
+            offset: start,
+            kind: FilterErrorKind::NumberTooBig,
+        };
+        let v = i64::from_str_radix(&digits[..len], radix).map_err(|_| err)?;
+        Ok(if neg { -v } else { v })
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    BINARY_MAGIC, BinaryReader, Command, ParseError, Parser, RunMetadata, Tolerance, Trace,
+};
+use std::io;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Kanata,
+    Binary,
+    O3PipeView,
+    Gzip,
+    Zstd,
+    Unknown,
+}
+
+const SNIFF_LINES: usize = 16;
+
+fn is_kanata_line(line: &[u8]) -> bool {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    matches!(
+        line,
+        [b'C' | b'I' | b'L' | b'S' | b'E' | b'R' | b'W', b'\t', ..] | [b'C', b'=', b'\t', ..]
+    )
+}
+
+pub fn detect_format(input: &[u8]) -> Format {
+    if input.starts_with(&[0x1f, 0x8b]) {
+        return Format::Gzip;
+    }
+    if input.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        return Format::Zstd;
+    }
+    if input.starts_with(BINARY_MAGIC) {
+        return Format::Binary;
+    }
+    if input.starts_with(b"Kanata\t") {
+        return Format::Kanata;
+    }
+
+    let mut lines = input
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.trim_ascii().is_empty())
+        .take(SNIFF_LINES)
+        .peekable();
+    if lines.peek().is_none() {
+        return Format::Unknown;
+    }
+    let mut kanata = true;
+    for line in lines {
+        if line.trim_ascii_start().starts_with(b"O3PipeView:") {
+            return Format::O3PipeView;
+        }
+        kanata &= is_kanata_line(line);
+    }
+    if kanata {
+        Format::Kanata
+    } else {
+        Format::Unknown
+    }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, what.to_string())
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    io::Read::read_to_end(&mut flate2::read::MultiGzDecoder::new(input), &mut out)?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unsupported("gzip input needs the gzip feature"))
+}
+
+#[cfg(feature = "zstd")]
+fn unzstd(input: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::decode_all(input)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unzstd(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unsupported("zstd input needs the zstd feature"))
+}
+
+pub fn read_any(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let mut input = std::fs::read(path)?;
+    // containers may nest (e.g. a gzip of a zstd file), so keep unwrapping
+    loop {
+        match detect_format(&input) {
+            Format::Kanata | Format::Binary => return Ok(input),
+            Format::Gzip => input = gunzip(&input)?,
+            Format::Zstd => input = unzstd(&input)?,
+            Format::O3PipeView => {
+                return Err(unsupported("O3PipeView input cannot be read as a trace"));
+            }
+            Format::Unknown => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unrecognized trace format",
+                ));
+            }
+        }
+    }
+}
+
+pub enum Commands<'a> {
+    Text(Parser<'a>),
+    Binary(BinaryReader<'a>),
+}
+
+impl<'a> Commands<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, ParseError> {
+        if data.starts_with(BINARY_MAGIC) {
+            Ok(Commands::Binary(BinaryReader::new(data)?))
+        } else {
+            Ok(Commands::Text(Parser::new(data).extensions()))
+        }
+    }
+
+    // Binary input has no syntax to be tolerant of, so this only changes
+    // how text is parsed.
+    pub fn with_tolerance(data: &'a [u8], tolerance: Tolerance) -> Result<Self, ParseError> {
+        Ok(match Self::new(data)? {
+            Commands::Text(p) => Commands::Text(p.tolerance(tolerance)),
+            binary => binary,
+        })
+    }
+}
+
+impl<'a> Iterator for Commands<'a> {
+    type Item = (usize, Result<Command, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Commands::Text(p) => p.next(),
+            Commands::Binary(r) => r.next(),
+        }
+    }
+}
+
+impl Trace<'static> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::noted(Trace::from_vec(std::fs::read(path)?)?))
+    }
+
+    pub fn open_any(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::noted(Trace::from_vec(read_any(path)?)?))
+    }
+
+    // Notes the trace in the default `TraceCache`, if one is set. A cache
+    // that can't be written to doesn't stop the trace opening.
+    fn noted(trace: Self) -> Self {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = crate::TraceCache::default_cache() {
+            let _ = cache.note(&trace);
+        }
+        trace
+    }
+
+    // With the metadata in the trace's sidecar file, if it has one.
+    pub fn open_with_metadata(path: impl AsRef<Path>) -> io::Result<(Self, Option<RunMetadata>)> {
+        let path = path.as_ref();
+        Ok((Self::open_any(path)?, RunMetadata::for_trace(path)?))
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{Command, DepKind, Id, KANATA_VERSION, LogKind, RetireKind, Writer};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenConfig {
+    pub seed: u64,
+    // retired instructions; flushed wrong-path ones come on top
+    pub instructions: u64,
+    pub depth: usize,
+    pub width: usize,
+    pub branch_rate: f64,
+    pub mispredict_rate: f64,
+    pub load_rate: f64,
+    pub miss_rate: f64,
+    // (extra cycles, weight) pairs a missing load draws its latency from
+    pub miss_latency: Vec<(u32, f64)>,
+    pub dep_rate: f64,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            instructions: 10_000,
+            depth: 8,
+            width: 4,
+            branch_rate: 0.15,
+            mispredict_rate: 0.05,
+            load_rate: 0.25,
+            miss_rate: 0.05,
+            miss_latency: vec![(12, 0.7), (40, 0.2), (200, 0.1)],
+            dep_rate: 0.3,
+        }
+    }
+}
+
+// xorshift64*, so traces are reproducible everywhere from just the seed
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        self.unit() < p
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    fn weighted(&mut self, choices: &[(u32, f64)]) -> u32 {
+        let total: f64 = choices.iter().map(|&(_, w)| w).sum();
+        let mut x = self.unit() * total;
+        for &(v, w) in choices {
+            if x < w {
+                return v;
+            }
+            x -= w;
+        }
+        choices.last().map_or(0, |&(v, _)| v)
+    }
+}
+
+const FRONT: [&str; 5] = ["F", "Dc", "Rn", "Ds", "Is"];
+
+// the front end, then execute, writeback and commit
+fn stage_names(depth: usize) -> Vec<Vec<u8>> {
+    let front = depth.max(4) - 3;
+    let mut names: Vec<Vec<u8>> = (0..front)
+        .map(|i| match FRONT.get(i) {
+            Some(s) if front <= FRONT.len() => s.as_bytes().to_vec(),
+            _ => format!("F{}", i).into_bytes(),
+        })
+        .collect();
+    names.extend([b"X".to_vec(), b"Wb".to_vec(), b"Cm".to_vec()]);
+    names
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Op {
+    Alu,
+    Load,
+    Branch,
+}
+
+struct Inst {
+    id: Id,
+    stage: usize,
+    left: u32,
+    op: Op,
+    pc: u64,
+    mispredict: bool,
+    producer: Option<Id>,
+}
+
+struct Gen<W: Write> {
+    out: Writer<W>,
+    idle: i32,
+}
+
+impl<W: Write> Gen<W> {
+    fn emit(&mut self, cmd: Command<&[u8]>) -> io::Result<()> {
+        if self.idle > 0 {
+            self.out.write(&Command::<&[u8]>::Cycle {
+                abs: false,
+                value: self.idle,
+            })?;
+            self.idle = 0;
+        }
+        self.out.write(&cmd)
+    }
+}
+
+// A small out-of-order core: in-order fetch and commit, stages of one cycle
+// each except for missing loads, consumers waiting in issue for their
+// producer, and mispredicted branches flushing everything younger once they
+// execute.
+pub fn generate<W: Write>(config: &GenConfig, out: W) -> io::Result<W> {
+    let mut rng = Rng::new(config.seed);
+    let names = stage_names(config.depth);
+    let commit = names.len() - 1;
+    let exec = commit - 2;
+    let issue = exec - 1;
+    let width = config.width.max(1);
+    let window = width * names.len() * 4;
+
+    let mut g = Gen {
+        out: Writer::new(out),
+        idle: 0,
+    };
+    g.emit(Command::Kanata {
+        version: KANATA_VERSION,
+    })?;
+    g.emit(Command::Cycle {
+        abs: true,
+        value: 0,
+    })?;
+
+    let mut inflight: VecDeque<Inst> = VecDeque::new();
+    let (mut next_id, mut retired, mut pc): (Id, u64, u64) = (0, 0, 0x1000);
+    while retired < config.instructions || !inflight.is_empty() {
+        // commit
+        let mut n = 0;
+        while n < width {
+            match inflight.front() {
+                Some(i) if i.stage == commit && i.left == 0 => {}
+                _ => break,
+            }
+            let i = inflight.pop_front().unwrap();
+            g.emit(Command::Pipeline {
+                start: false,
+                id: i.id,
+                lane_id: 0,
+                name: &names[commit],
+            })?;
+            g.emit(Command::Retire {
+                id: i.id,
+                retire: retired as Id,
+                kind: RetireKind::Retire,
+            })?;
+            retired += 1;
+            n += 1;
+        }
+
+        // advance, oldest first
+        let mut flush_after = None;
+        for k in 0..inflight.len() {
+            let i = &inflight[k];
+            if i.left > 0 {
+                inflight[k].left -= 1;
+                continue;
+            }
+            if i.stage == commit {
+                continue;
+            }
+            if i.stage == issue {
+                let waiting = i.producer.is_some_and(|p| {
+                    inflight
+                        .iter()
+                        .take(k)
+                        .any(|o| o.id == p && o.stage <= exec)
+                });
+                if waiting {
+                    continue;
+                }
+            }
+            if i.stage == exec && i.mispredict {
+                flush_after = Some(k);
+            }
+            let i = &mut inflight[k];
+            i.stage += 1;
+            i.left = match i.op {
+                Op::Load if i.stage == exec && rng.chance(config.miss_rate) => {
+                    rng.weighted(&config.miss_latency)
+                }
+                _ => 0,
+            };
+            let (id, stage) = (i.id, i.stage);
+            g.emit(Command::Pipeline {
+                start: true,
+                id,
+                lane_id: 0,
+                name: &names[stage],
+            })?;
+            if flush_after == Some(k) {
+                break;
+            }
+        }
+        if let Some(k) = flush_after {
+            for i in inflight.drain(k + 1..) {
+                g.emit(Command::Pipeline {
+                    start: false,
+                    id: i.id,
+                    lane_id: 0,
+                    name: &names[i.stage],
+                })?;
+                g.emit(Command::Retire {
+                    id: i.id,
+                    retire: retired as Id,
+                    kind: RetireKind::Flush,
+                })?;
+            }
+            inflight[k].mispredict = false;
+            // refetch down the right path
+            pc = inflight[k].pc + 4;
+        }
+
+        // fetch; past a mispredicted branch this is the wrong path
+        let mut n = 0;
+        while n < width && inflight.len() < window {
+            let fetched = retired + inflight.len() as u64;
+            if fetched >= config.instructions {
+                break;
+            }
+            let op = if rng.chance(config.branch_rate) {
+                Op::Branch
+            } else if rng.chance(config.load_rate) {
+                Op::Load
+            } else {
+                Op::Alu
+            };
+            let producer = match inflight.len() {
+                0 => None,
+                len if rng.chance(config.dep_rate) => {
+                    Some(inflight[len - 1 - rng.below(len.min(8))].id)
+                }
+                _ => None,
+            };
+            let mispredict = op == Op::Branch && rng.chance(config.mispredict_rate);
+            let id = next_id;
+            next_id += 1;
+            g.emit(Command::Instruction {
+                id_in_file: id,
+                id_in_sim: id,
+                thread_id: 0,
+            })?;
+            let text = match op {
+                Op::Alu => format!("{:x}: add r{}, r{}", pc, rng.below(32), rng.below(32)),
+                Op::Load => format!("{:x}: ld r{}, [r{}]", pc, rng.below(32), rng.below(32)),
+                Op::Branch => format!("{:x}: bne {:x}", pc, pc + 4 * rng.below(64) as u64),
+            };
+            g.emit(Command::Log {
+                id,
+                kind: LogKind::LeftPane,
+                text: text.as_bytes(),
+            })?;
+            if let Some(p) = producer {
+                g.emit(Command::Dep {
+                    consumer_id: id,
+                    producer_id: p,
+                    kind: DepKind::WakeUp,
+                    label: None,
+                })?;
+            }
+            g.emit(Command::Pipeline {
+                start: true,
+                id,
+                lane_id: 0,
+                name: &names[0],
+            })?;
+            inflight.push_back(Inst {
+                id,
+                stage: 0,
+                left: 0,
+                op,
+                pc,
+                mispredict,
+                producer,
+            });
+            pc += 4;
+            n += 1;
+        }
+        g.idle += 1;
+    }
+    g.out.flush()?;
+    Ok(g.out.into_inner())
+}
@@ -0,0 +1,104 @@
+use crate::{DepKind, LogKind, RetireKind};
+use memchr::memchr;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Command,
+    Id,
+    Number,
+    Kind,
+    Text,
+    Error,
+}
+
+#[derive(Copy, Clone)]
+enum Field {
+    Id,
+    Number,
+    Signed,
+    Kind(fn(u8) -> bool),
+    Text,
+}
+
+fn schema(command: &[u8]) -> Option<&'static [Field]> {
+    use Field::*;
+    Some(match command {
+        b"Kanata" => &[Number],
+        b"C" | b"C=" => &[Signed],
+        b"I" => &[Id, Id, Number],
+        b"L" => &[Id, Kind(|b| LogKind::try_from(b).is_ok()), Text],
+        b"S" | b"E" => &[Id, Number, Text],
+        b"R" => &[Id, Number, Kind(|b| RetireKind::try_from(b).is_ok())],
+        b"W" => &[Id, Id, Kind(|b| DepKind::try_from(b).is_ok())],
+        _ => return None,
+    })
+}
+
+fn is_number(s: &[u8]) -> bool {
+    !s.is_empty() && s.iter().all(u8::is_ascii_digit)
+}
+
+fn classify(field: Field, s: &[u8]) -> TokenKind {
+    let ok = match field {
+        Field::Id | Field::Number => is_number(s),
+        Field::Signed => is_number(s.strip_prefix(b"-").or(s.strip_prefix(b"+")).unwrap_or(s)),
+        Field::Kind(valid) => matches!(s, [b] if valid(*b)),
+        Field::Text => true,
+    };
+    match (ok, field) {
+        (false, _) => TokenKind::Error,
+        (true, Field::Id) => TokenKind::Id,
+        (true, Field::Number | Field::Signed) => TokenKind::Number,
+        (true, Field::Kind(_)) => TokenKind::Kind,
+        (true, Field::Text) => TokenKind::Text,
+    }
+}
+
+fn highlight_line(line: &[u8], base: usize, out: &mut Vec<(Range<usize>, TokenKind)>) {
+    let end = line.trim_ascii_end().len();
+    if end == 0 {
+        return;
+    }
+    let mut fields = Vec::new();
+    let mut start = 0;
+    while start < end {
+        let stop = memchr(b'\t', &line[start..end]).map_or(end, |i| start + i);
+        fields.push(start..stop);
+        start = stop + 1;
+    }
+
+    let Some(schema) = schema(&line[fields[0].clone()]) else {
+        out.push((base..base + end, TokenKind::Error));
+        return;
+    };
+    out.push((
+        base + fields[0].start..base + fields[0].end,
+        TokenKind::Command,
+    ));
+    let mut rest = fields[1..].iter().filter(|r| !r.is_empty());
+    for &field in schema {
+        let Some(r) = rest.next() else { return };
+        if let Field::Text = field {
+            // text runs to the end of the line, tabs included
+            out.push((base + r.start..base + end, TokenKind::Text));
+            return;
+        }
+        let s = line[r.clone()].trim_ascii();
+        out.push((base + r.start..base + r.end, classify(field, s)));
+    }
+    for r in rest {
+        out.push((base + r.start..base + r.end, TokenKind::Error));
+    }
+}
+
+pub fn highlight(input: &[u8]) -> Vec<(Range<usize>, TokenKind)> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let next = memchr(b'\n', &input[pos..]).map_or(input.len(), |i| pos + i + 1);
+        highlight_line(&input[pos..next], pos, &mut out);
+        pos = next;
+    }
+    out
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImportErrorKind {
+    InvalidConfig,
+    MissingHeader,
+    MissingColumn,
+    InvalidNumber,
+    NonMonotonicCycle,
+    RowLength,
+    ValueTooBig,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImportError {
+    pub line: usize,
+    pub kind: ImportErrorKind,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} on line {}", self.kind, self.line)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<ImportError> for std::io::Error {
+    fn from(e: ImportError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+mod rtl;
+pub use rtl::*;
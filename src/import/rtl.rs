@@ -0,0 +1,424 @@
+use super::{ImportError, ImportErrorKind};
+use crate::{Command, CommandBuffer, Id, LogKind, RetireKind, Tolerance, Writer};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Delimiter {
+    Comma,
+    Whitespace,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    fn parse(s: &str) -> Self {
+        match s.parse() {
+            Ok(i) => Column::Index(i),
+            Err(_) => Column::Name(s.to_string()),
+        }
+    }
+
+    fn resolve(&self, header: Option<&[&str]>) -> Result<usize, ImportErrorKind> {
+        match self {
+            Column::Index(i) => Ok(*i),
+            Column::Name(n) => header
+                .ok_or(ImportErrorKind::MissingHeader)?
+                .iter()
+                .position(|h| h == n)
+                .ok_or(ImportErrorKind::MissingColumn),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageSignal {
+    pub name: String,
+    pub valid: Column,
+    pub pc: Option<Column>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtlConfig {
+    pub delimiter: Delimiter,
+    pub header: bool,
+    pub cycle: Column,
+    pub hart: Option<Column>,
+    pub pc: Option<Column>,
+    pub stages: Vec<StageSignal>,
+    // Strict also fails on rows with more or fewer fields than the header;
+    // Recover skips rows that would fail.
+    pub tolerance: Tolerance,
+}
+
+impl Default for RtlConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: Delimiter::Whitespace,
+            header: true,
+            cycle: Column::Name("cycle".to_string()),
+            hart: None,
+            pc: None,
+            stages: Vec::new(),
+            tolerance: Tolerance::default(),
+        }
+    }
+}
+
+impl RtlConfig {
+    // One `key = value` per line, `#` starts a comment:
+    //   delimiter = comma | whitespace
+    //   header = true | false
+    //   cycle = <column>
+    //   hart = <column>
+    //   pc = <column>
+    //   stage = <name> <valid column> [<pc column>]
+    //   tolerance = strict | lenient | recover
+    // A column is a zero-based index or a header name.
+    pub fn parse(text: &str) -> Result<Self, ImportError> {
+        let mut config = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let err = ImportError {
+                line: i + 1,
+                kind: ImportErrorKind::InvalidConfig,
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(err)?;
+            let value = value.trim();
+            match key.trim() {
+                "delimiter" => {
+                    config.delimiter = match value {
+                        "comma" => Delimiter::Comma,
+                        "whitespace" => Delimiter::Whitespace,
+                        _ => return Err(err),
+                    }
+                }
+                "header" => config.header = value.parse().map_err(|_| err)?,
+                "cycle" => config.cycle = Column::parse(value),
+                "hart" => config.hart = Some(Column::parse(value)),
+                "pc" => config.pc = Some(Column::parse(value)),
+                "tolerance" => {
+                    config.tolerance = match value {
+                        "strict" => Tolerance::Strict,
+                        "lenient" => Tolerance::Lenient,
+                        "recover" => Tolerance::Recover,
+                        _ => return Err(err),
+                    }
+                }
+                "stage" => {
+                    let mut parts = value.split_whitespace();
+                    let (Some(name), Some(valid)) = (parts.next(), parts.next()) else {
+                        return Err(err);
+                    };
+                    config.stages.push(StageSignal {
+                        name: name.to_string(),
+                        valid: Column::parse(valid),
+                        pc: parts.next().map(Column::parse),
+                    });
+                }
+                _ => return Err(err),
+            }
+        }
+        if config.stages.is_empty() {
+            return Err(ImportError {
+                line: 0,
+                kind: ImportErrorKind::InvalidConfig,
+            });
+        }
+        Ok(config)
+    }
+}
+
+struct Columns {
+    cycle: usize,
+    hart: Option<usize>,
+    valid: Vec<usize>,
+    pc: Vec<Option<usize>>,
+}
+
+fn split(delimiter: Delimiter, line: &str) -> Vec<&str> {
+    match delimiter {
+        Delimiter::Comma => line.split(',').map(str::trim).collect(),
+        Delimiter::Whitespace => line.split_whitespace().collect(),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn is_valid(s: &str) -> bool {
+    !matches!(s, "" | "0" | "x" | "X" | "z" | "Z" | "false")
+}
+
+type Slots = Vec<Option<(Id, u64)>>;
+
+// Where the importer's commands go.
+trait Sink {
+    fn emit(&mut self, cmd: &Command<&[u8]>) -> io::Result<()>;
+}
+
+impl<W: Write> Sink for Writer<W> {
+    fn emit(&mut self, cmd: &Command<&[u8]>) -> io::Result<()> {
+        self.write(cmd)
+    }
+}
+
+impl Sink for CommandBuffer {
+    fn emit(&mut self, cmd: &Command<&[u8]>) -> io::Result<()> {
+        self.push(*cmd);
+        Ok(())
+    }
+}
+
+struct Importer<'c, S: Sink> {
+    w: S,
+    config: &'c RtlConfig,
+    cycle: Option<i64>,
+    next_id: Id,
+    next_retire: Id,
+    harts: BTreeMap<u32, Slots>,
+}
+
+impl<S: Sink> Importer<'_, S> {
+    // Moves to the cycle of the row on `line`, in as many `C` records as
+    // it takes when that's further than one can go.
+    fn advance(&mut self, line: usize, cycle: i64) -> io::Result<()> {
+        let error = |kind| ImportError { line, kind };
+        let from = match self.cycle {
+            None => {
+                let first = i32::try_from(cycle.min(i32::MAX as i64))
+                    .map_err(|_| error(ImportErrorKind::ValueTooBig))?;
+                self.w.emit(&Command::<&[u8]>::Cycle {
+                    abs: true,
+                    value: first,
+                })?;
+                first as i64
+            }
+            Some(c) if cycle < c => return Err(error(ImportErrorKind::NonMonotonicCycle).into()),
+            Some(c) => c,
+        };
+        let mut left = cycle - from;
+        while left > 0 {
+            let step = left.min(i32::MAX as i64);
+            self.w.emit(&Command::<&[u8]>::Cycle {
+                abs: false,
+                value: step as i32,
+            })?;
+            left -= step;
+        }
+        self.cycle = Some(cycle);
+        Ok(())
+    }
+
+    // Instructions still in the pipeline at the end of the dump leave it
+    // flushed, from the last stage back, as no row saw them complete.
+    fn finish(&mut self) -> io::Result<()> {
+        for slots in std::mem::take(&mut self.harts).into_values() {
+            for (k, slot) in slots.iter().enumerate().rev() {
+                let Some((id, _)) = *slot else { continue };
+                self.pipeline(false, id, k)?;
+                self.w.emit(&Command::<&[u8]>::Retire {
+                    id,
+                    retire: self.next_retire,
+                    kind: RetireKind::Flush,
+                })?;
+                self.next_retire += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn sample(&mut self, hart: u32, new: &[Option<u64>]) -> io::Result<()> {
+        let n = new.len();
+        let old = self.harts.remove(&hart).unwrap_or_else(|| vec![None; n]);
+        let mut taken = vec![false; n];
+        let mut next: Slots = vec![None; n];
+
+        for k in (0..n).rev() {
+            let Some(pc) = new[k] else { continue };
+            let same = |k: usize| !taken[k] && old[k].is_some_and(|(_, p)| p == pc);
+            if k > 0 && same(k - 1) {
+                let (id, _) = old[k - 1].unwrap();
+                taken[k - 1] = true;
+                self.pipeline(false, id, k - 1)?;
+                self.pipeline(true, id, k)?;
+                next[k] = Some((id, pc));
+            } else if same(k) {
+                taken[k] = true;
+                next[k] = old[k];
+            } else {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.w.emit(&Command::<&[u8]>::Instruction {
+                    id_in_file: id,
+                    id_in_sim: id,
+                    thread_id: hart,
+                })?;
+                self.w.emit(&Command::Log {
+                    id,
+                    kind: LogKind::LeftPane,
+                    text: format!("{:x}", pc).as_bytes(),
+                })?;
+                self.pipeline(true, id, k)?;
+                next[k] = Some((id, pc));
+            }
+        }
+
+        for k in 0..n {
+            if let Some((id, _)) = old[k]
+                && !taken[k]
+            {
+                self.pipeline(false, id, k)?;
+                let kind = if k == n - 1 {
+                    RetireKind::Retire
+                } else {
+                    RetireKind::Flush
+                };
+                self.w.emit(&Command::<&[u8]>::Retire {
+                    id,
+                    retire: self.next_retire,
+                    kind,
+                })?;
+                self.next_retire += 1;
+            }
+        }
+        self.harts.insert(hart, next);
+        Ok(())
+    }
+
+    fn pipeline(&mut self, start: bool, id: Id, k: usize) -> io::Result<()> {
+        let config = self.config;
+        self.w.emit(&Command::Pipeline {
+            start,
+            id,
+            lane_id: 0,
+            name: config.stages[k].name.as_bytes(),
+        })
+    }
+}
+
+pub fn import_rtl<W: Write>(input: &[u8], config: &RtlConfig, out: W) -> io::Result<()> {
+    import(input, config, Writer::new(io::BufWriter::new(out)))?.flush()
+}
+
+// Like `import_rtl`, keeping the commands in memory to build a trace from.
+pub fn import_rtl_commands(input: &[u8], config: &RtlConfig) -> io::Result<CommandBuffer> {
+    import(input, config, CommandBuffer::new())
+}
+
+fn import<S: Sink>(input: &[u8], config: &RtlConfig, sink: S) -> io::Result<S> {
+    let text = String::from_utf8_lossy(input);
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'));
+
+    let header = if config.header {
+        let (line, h) = lines.next().ok_or(ImportError {
+            line: 0,
+            kind: ImportErrorKind::MissingHeader,
+        })?;
+        Some((line, split(config.delimiter, h)))
+    } else {
+        None
+    };
+    let resolve = |c: &Column| {
+        c.resolve(header.as_ref().map(|(_, h)| &h[..]))
+            .map_err(|kind| ImportError {
+                line: header.as_ref().map_or(0, |(l, _)| *l),
+                kind,
+            })
+    };
+    let cols = Columns {
+        cycle: resolve(&config.cycle)?,
+        hart: config.hart.as_ref().map(resolve).transpose()?,
+        valid: config
+            .stages
+            .iter()
+            .map(|s| resolve(&s.valid))
+            .collect::<Result<_, _>>()?,
+        pc: config
+            .stages
+            .iter()
+            .map(|s| {
+                s.pc.as_ref()
+                    .or(config.pc.as_ref())
+                    .map(resolve)
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut imp = Importer {
+        w: sink,
+        config,
+        cycle: None,
+        next_id: 0,
+        next_retire: 0,
+        harts: BTreeMap::new(),
+    };
+    imp.w.emit(&Command::<&[u8]>::Kanata { version: 4 })?;
+
+    let width = header.as_ref().map(|(_, h)| h.len());
+    for (line, row) in lines {
+        let fields = split(config.delimiter, row);
+        let row = match read_row(&cols, &fields, width, config.tolerance) {
+            Ok(row) => row,
+            Err(_) if config.tolerance == Tolerance::Recover => continue,
+            Err(kind) => return Err(ImportError { line, kind }.into()),
+        };
+        let (cycle, hart, sample) = row;
+        if imp.cycle.is_some_and(|c| cycle < c) && config.tolerance == Tolerance::Recover {
+            continue;
+        }
+        imp.advance(line, cycle)?;
+        imp.sample(hart, &sample)?;
+    }
+    imp.finish()?;
+    Ok(imp.w)
+}
+
+// One row's cycle, hart and sampled PC for each stage.
+fn read_row(
+    cols: &Columns,
+    fields: &[&str],
+    width: Option<usize>,
+    tolerance: Tolerance,
+) -> Result<(i64, u32, Vec<Option<u64>>), ImportErrorKind> {
+    if tolerance == Tolerance::Strict && width.is_some_and(|w| w != fields.len()) {
+        return Err(ImportErrorKind::RowLength);
+    }
+    let field = |i: usize| fields.get(i).copied().ok_or(ImportErrorKind::MissingColumn);
+    let bad = |_: std::num::ParseIntError| ImportErrorKind::InvalidNumber;
+    let cycle: i64 = field(cols.cycle)?.parse().map_err(bad)?;
+    let hart: u32 = match cols.hart {
+        Some(c) => field(c)?.parse().map_err(bad)?,
+        None => 0,
+    };
+    let mut sample = Vec::with_capacity(cols.valid.len());
+    for (k, &v) in cols.valid.iter().enumerate() {
+        if !is_valid(field(v)?) {
+            sample.push(None);
+            continue;
+        }
+        let pc = match cols.pc[k] {
+            Some(c) => parse_hex(field(c)?).ok_or(ImportErrorKind::InvalidNumber)?,
+            None => 0,
+        };
+        sample.push(Some(pc));
+    }
+    Ok((cycle, hart, sample))
+}
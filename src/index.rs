@@ -0,0 +1,260 @@
+use crate::{Clock, Command, ParseError, ParseErrorKind, Parser, Trace};
+use std::ops::Range;
+
+pub const DEFAULT_INDEX_INTERVAL: usize = 1 << 20;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint {
+    pub offset: usize,
+    pub cycle: i64,
+    pub commands: u64,
+    pub instructions: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Index {
+    interval: usize,
+    checkpoints: Vec<Checkpoint>,
+    end: Checkpoint,
+}
+
+impl Index {
+    pub fn build(input: &[u8], interval: usize) -> Result<Self, ParseError> {
+        Self::build_with(input, interval, |_, _| Ok(()))
+    }
+
+    // `build`, showing `each` every command on the way, with the input its
+    // texts point into. An error from `each` stops it at that command.
+    pub(crate) fn build_with(
+        input: &[u8],
+        interval: usize,
+        mut each: impl FnMut(&Command, &[u8]) -> Result<(), ParseErrorKind>,
+    ) -> Result<Self, ParseError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("index", bytes = input.len(), interval).entered();
+        let mut clock = Clock::new();
+        let mut at = Checkpoint::default();
+        let mut checkpoints = Vec::new();
+        let mut next = 0;
+        for (offset, cmd) in Parser::new(input).extensions() {
+            let cmd = cmd?;
+            if offset >= next {
+                checkpoints.push(Checkpoint {
+                    offset,
+                    cycle: clock.cycle(),
+                    ..at
+                });
+                next = offset + interval.max(1);
+            }
+            clock.apply(&cmd);
+            each(&cmd, input).map_err(|kind| ParseError { offset, kind })?;
+            at.commands += 1;
+            at.instructions += matches!(cmd, Command::Instruction { .. }) as u64;
+        }
+        let end = Checkpoint {
+            offset: input.len(),
+            cycle: clock.cycle(),
+            ..at
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(checkpoints = checkpoints.len(), "index built");
+        Ok(Self {
+            interval,
+            checkpoints,
+            end,
+        })
+    }
+
+    // `build`, scanning stretches of at least `interval` bytes on all cores
+    // and stitching them together: for a big file mapped into memory, where
+    // one pass would be the whole cost of opening it. Each stretch starts
+    // with a checkpoint at its first line, so the checkpoints only match
+    // `build`'s when there is a single stretch, but every one of them is as
+    // good. A file with no `\n` line breaks is a single stretch.
+    #[cfg(feature = "parallel")]
+    pub fn build_parallel(input: &[u8], interval: usize) -> Result<Self, ParseError> {
+        use rayon::prelude::*;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("parallel index", bytes = input.len(), interval).entered();
+        let step = interval.max(1);
+        let stretch = (input.len() / (rayon::current_num_threads() * 4))
+            .next_multiple_of(step)
+            .max(step);
+        let mut starts = vec![0];
+        while let Some(&last) = starts.last() {
+            let from = last + stretch;
+            let Some(nl) = input.get(from..).and_then(|r| memchr::memchr(b'\n', r)) else {
+                break;
+            };
+            starts.push(from + nl + 1);
+        }
+        starts.retain(|&s| s < input.len() || s == 0);
+        let version = crate::parser::header_version(input);
+        let ends: Vec<usize> = starts
+            .iter()
+            .skip(1)
+            .copied()
+            .chain([input.len()])
+            .collect();
+        let stretches: Vec<Result<Stretch, ParseError>> = starts
+            .par_iter()
+            .zip(ends.par_iter())
+            .map(|(&from, &to)| Stretch::scan(input, from..to, step, version))
+            .collect();
+
+        let mut clock = Clock::new();
+        let mut at = Checkpoint::default();
+        let mut checkpoints = Vec::new();
+        for stretch in stretches {
+            let stretch = stretch?;
+            let base = clock.cycle();
+            for (cp, anchored) in stretch.checkpoints {
+                checkpoints.push(Checkpoint {
+                    offset: cp.offset,
+                    cycle: if anchored { cp.cycle } else { base + cp.cycle },
+                    commands: at.commands + cp.commands,
+                    instructions: at.instructions + cp.instructions,
+                });
+            }
+            let (end, anchored) = stretch.end;
+            clock = Clock::at(if anchored {
+                end.cycle
+            } else {
+                base + end.cycle
+            });
+            at.commands += end.commands;
+            at.instructions += end.instructions;
+        }
+        let end = Checkpoint {
+            offset: input.len(),
+            cycle: clock.cycle(),
+            ..at
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(checkpoints = checkpoints.len(), "index built");
+        Ok(Self {
+            interval,
+            checkpoints,
+            end,
+        })
+    }
+
+    // For opening a trace: in parallel when that's built in.
+    #[cfg(any(feature = "server", feature = "tui"))]
+    pub(crate) fn build_for_open(input: &[u8], interval: usize) -> Result<Self, ParseError> {
+        #[cfg(feature = "parallel")]
+        {
+            Self::build_parallel(input, interval)
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            Self::build(input, interval)
+        }
+    }
+
+    pub(crate) fn from_parts(
+        interval: usize,
+        checkpoints: Vec<Checkpoint>,
+        end: Checkpoint,
+    ) -> Self {
+        Self {
+            interval,
+            checkpoints,
+            end,
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    pub fn end(&self) -> Checkpoint {
+        self.end
+    }
+
+    pub fn first_cycle(&self) -> i64 {
+        self.checkpoints.first().map_or(0, |c| c.cycle)
+    }
+
+    pub fn seek_cycle(&self, cycle: i64) -> Checkpoint {
+        let i = self.checkpoints.partition_point(|c| c.cycle <= cycle);
+        self.checkpoints
+            .get(i.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn seek_instruction(&self, n: u64) -> Checkpoint {
+        let i = self.checkpoints.partition_point(|c| c.instructions <= n);
+        self.checkpoints
+            .get(i.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Instructions created before the checkpoint preceding `cycles.start`
+    // are not part of the window.
+    pub fn window<'a>(&self, input: &'a [u8], cycles: Range<i64>) -> Result<Trace<'a>, ParseError> {
+        let cp = self.seek_cycle(cycles.start);
+        Trace::window(input, cp.offset, cp.cycle, cycles.end)
+    }
+}
+
+// What one stretch of a parallel build found, with cycles and counts from
+// its start, and whether a `C=` had fixed the cycle by each point.
+#[cfg(feature = "parallel")]
+struct Stretch {
+    checkpoints: Vec<(Checkpoint, bool)>,
+    end: (Checkpoint, bool),
+}
+
+#[cfg(feature = "parallel")]
+impl Stretch {
+    fn scan(
+        input: &[u8],
+        range: Range<usize>,
+        interval: usize,
+        version: Option<u32>,
+    ) -> Result<Self, ParseError> {
+        let mut parser = Parser::with_offset(&input[..range.end], range.start).extensions();
+        if let Some(v) = version.filter(|_| range.start > 0) {
+            parser = parser.with_version(v);
+        }
+        let mut clock = Clock::new();
+        let mut anchored = false;
+        let mut at = Checkpoint::default();
+        let mut checkpoints = Vec::new();
+        let mut next = range.start;
+        for (offset, cmd) in parser {
+            let cmd = cmd?;
+            if offset >= next {
+                let cp = Checkpoint {
+                    offset,
+                    cycle: clock.cycle(),
+                    ..at
+                };
+                checkpoints.push((cp, anchored));
+                next = offset + interval;
+            }
+            clock.apply(&cmd);
+            anchored |= matches!(cmd, Command::Cycle { abs: true, .. });
+            at.commands += 1;
+            at.instructions += matches!(cmd, Command::Instruction { .. }) as u64;
+        }
+        let end = Checkpoint {
+            offset: range.end,
+            cycle: clock.cycle(),
+            ..at
+        };
+        Ok(Self {
+            checkpoints,
+            end: (end, anchored),
+        })
+    }
+}
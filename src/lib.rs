@@ -1,8 +1,157 @@
+mod annotate;
+pub use annotate::*;
+
+mod binary;
+pub use binary::*;
+
+mod bookmark;
+pub use bookmark::*;
+
+mod bus;
+pub use bus::*;
+
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "cache")]
+pub use cache::*;
+
+mod clock;
+pub use clock::*;
+
+#[cfg(any(feature = "msgpack", feature = "bincode", feature = "json"))]
+mod codec;
+#[cfg(any(feature = "msgpack", feature = "bincode", feature = "json"))]
+pub use codec::*;
+
 mod command;
 pub use command::*;
 
+mod container;
+pub use container::*;
+
+mod cursor;
+pub use cursor::*;
+
+mod delta;
+pub use delta::*;
+
+mod diagnostics;
+pub use diagnostics::*;
+
+mod diff;
+pub use diff::*;
+
+mod document;
+pub use document::*;
+
+mod export;
+pub use export::*;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+mod filter;
+pub use filter::*;
+
+mod format;
+pub use format::*;
+
+mod generate;
+pub use generate::*;
+
+mod highlight;
+pub use highlight::*;
+
+mod import;
+pub use import::*;
+
+mod index;
+pub use index::*;
+
+#[cfg(feature = "listen")]
+mod live;
+#[cfg(feature = "listen")]
+pub use live::*;
+
+mod lines;
+pub use lines::*;
+
+mod matching;
+pub use matching::*;
+
+mod metadata;
+pub use metadata::*;
+
+mod migrate;
+pub use migrate::*;
+
+mod model;
+pub use model::*;
+
+mod packed;
+pub use packed::*;
+
 mod parser;
 pub use parser::*;
 
+mod pipeline;
+pub use pipeline::*;
+
+#[cfg(feature = "python")]
+mod python;
+
+mod render;
+pub use render::*;
+
+mod report;
+pub use report::*;
+
+mod rewrite;
+pub use rewrite::*;
+
+#[cfg(feature = "schema")]
+mod schema;
+#[cfg(feature = "schema")]
+pub use schema::*;
+
+mod search;
+pub use search::*;
+
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "server")]
+pub use server::*;
+
+mod sketch;
+pub use sketch::*;
+
+mod source;
+pub use source::*;
+
+mod spans;
+pub use spans::*;
+
+mod stats;
+pub use stats::*;
+
+mod testing;
+pub use testing::*;
+
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "tui")]
+pub use tui::*;
+
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "watch")]
+pub use watch::*;
+
+mod workspace;
+pub use workspace::*;
+
+mod writer;
+pub use writer::*;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,63 @@
+use memchr::memchr2_iter;
+use std::ops::Range;
+
+// Where each line of a text starts, found once, for turning byte offsets
+// into lines and back. A line ends at `\n`, `\r\n` or a lone `\r`, as with
+// the parser's default newline policy.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    input: &'a [u8],
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        let mut starts = vec![0];
+        for i in memchr2_iter(b'\r', b'\n', input) {
+            if input[i] == b'\r' && input.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            starts.push(i + 1);
+        }
+        Self { input, starts }
+    }
+
+    pub fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    // The number of lines, counting the one after a final line break.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    // Zero-based; offsets past the end count as the end.
+    pub fn line(&self, offset: usize) -> usize {
+        let offset = offset.min(self.input.len());
+        self.starts.partition_point(|&s| s <= offset) - 1
+    }
+
+    // Zero-based line and byte column.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        (line, offset.min(self.input.len()) - self.starts[line])
+    }
+
+    // The bytes of a line without its line break.
+    pub fn line_span(&self, line: usize) -> Range<usize> {
+        let start = self.starts[line];
+        let mut end = self.starts.get(line + 1).map_or(self.input.len(), |&s| s);
+        if end > start && self.input[end - 1] == b'\n' {
+            end -= 1;
+        }
+        if end > start && self.input[end - 1] == b'\r' {
+            end -= 1;
+        }
+        start..end
+    }
+
+    pub fn line_text(&self, line: usize) -> &'a [u8] {
+        &self.input[self.line_span(line)]
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{
+    Collector, Command, DEFAULT_MAX_IN_FLIGHT, Eviction, ParseError, ParseMetrics, Parser,
+    PushParser, Reconstructor, Stats, Step,
+};
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Statistics over a stream of Kanata text, kept up to date as bytes arrive.
+// Only what the statistics need is kept: logs and dependency labels are
+// dropped as they're parsed, and with more instructions in flight than the
+// default cap the oldest are counted unfinished.
+pub struct LiveStats {
+    parser: PushParser,
+    rec: Reconstructor<'static>,
+    stats: Stats,
+}
+
+impl Default for LiveStats {
+    fn default() -> Self {
+        Self {
+            parser: PushParser::new(Parser::new(b"").extensions()),
+            rec: Reconstructor::new(b"")
+                .with_max_in_flight(DEFAULT_MAX_IN_FLIGHT)
+                .with_eviction(Eviction::EvictOldest),
+            stats: Stats::new(),
+        }
+    }
+}
+
+fn apply(
+    rec: &mut Reconstructor<'static>,
+    stats: &mut Stats,
+    lines: &[u8],
+    offset: usize,
+    cmd: Command,
+) -> Result<(), ParseError> {
+    // nothing may keep a text past the buffer it came in
+    let cmd = match cmd {
+        Command::Log { .. } => return Ok(()),
+        Command::Dep {
+            consumer_id,
+            producer_id,
+            kind,
+            ..
+        } => Command::Dep {
+            consumer_id,
+            producer_id,
+            kind,
+            label: None,
+        },
+        cmd => cmd,
+    };
+    let mut here = std::mem::replace(rec, Reconstructor::new(b"")).continue_on(lines);
+    let step = here.feed(offset, cmd);
+    if let Ok(Step::Retired(r) | Step::Evicted(r)) = &step {
+        stats.record(lines, here.stages(), r);
+    }
+    *rec = here.continue_on(b"");
+    step.map(drop)
+}
+
+impl LiveStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Instructions are counted as they retire.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn metrics(&self) -> ParseMetrics {
+        self.parser.metrics()
+    }
+
+    pub fn current_cycle(&self) -> i64 {
+        self.parser.current_cycle()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.rec.in_flight()
+    }
+
+    // Don't feed it more after an error.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), ParseError> {
+        let Self { parser, rec, stats } = self;
+        parser.feed_refs(chunk, |lines, offset, cmd| {
+            apply(rec, stats, lines, offset, cmd)
+        })
+    }
+
+    // Ends the stream: parses a last unterminated line and counts the
+    // instructions still in flight, unfinished.
+    pub fn finish(&mut self) -> Result<(), ParseError> {
+        let Self { parser, rec, stats } = self;
+        parser.finish_refs(|lines, offset, cmd| apply(rec, stats, lines, offset, cmd))?;
+        let rec = std::mem::replace(rec, Reconstructor::new(b""));
+        let (stages, rest) = rec.finish()?;
+        for r in &rest {
+            stats.record(b"", &stages, r);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    pub peer: String,
+    pub stats: Stats,
+    pub metrics: ParseMetrics,
+    pub cycle: i64,
+    pub in_flight: usize,
+    // what stopped the stream being parsed, if it was bad
+    pub error: Option<ParseError>,
+    pub closed: bool,
+}
+
+impl ConnectionStats {
+    fn update(&mut self, live: &LiveStats) {
+        self.stats.clone_from(live.stats());
+        self.metrics = live.metrics();
+        self.cycle = live.current_cycle();
+        self.in_flight = live.in_flight();
+    }
+}
+
+type Connections = Arc<Mutex<Vec<Arc<Mutex<ConnectionStats>>>>>;
+
+enum Bound {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// Accepts simulators streaming Kanata text over TCP or a Unix socket and
+// keeps `LiveStats` for each connection, each read on a thread of its own.
+// Dropping the listener stops it accepting; connections already open run
+// until their peers close them.
+pub struct LiveListener {
+    bound: Bound,
+    connections: Connections,
+    stop: Arc<AtomicBool>,
+}
+
+// The stream is parsed on this thread; what's shared is a copy of the
+// figures, refreshed after each read.
+fn read_stream(mut stream: impl Read, shared: &Mutex<ConnectionStats>) {
+    let mut live = LiveStats::new();
+    let mut buf = vec![0; 64 << 10];
+    let error = loop {
+        // a connection that fails ends as if closed
+        let n = match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break live.finish().err(),
+            Ok(n) => n,
+        };
+        if let Err(e) = live.feed(&buf[..n]) {
+            break Some(e);
+        }
+        shared.lock().unwrap().update(&live);
+    };
+    let mut c = shared.lock().unwrap();
+    c.update(&live);
+    c.error = error;
+    c.closed = true;
+}
+
+fn serve<S: Read + Send + 'static>(
+    mut accept: impl FnMut() -> io::Result<(S, String)> + Send + 'static,
+    connections: Connections,
+    stop: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        loop {
+            let next = accept();
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let Ok((stream, peer)) = next else {
+                continue;
+            };
+            let conn = Arc::new(Mutex::new(ConnectionStats {
+                peer,
+                stats: Stats::new(),
+                metrics: ParseMetrics::default(),
+                cycle: 0,
+                in_flight: 0,
+                error: None,
+                closed: false,
+            }));
+            connections.lock().unwrap().push(conn.clone());
+            std::thread::spawn(move || read_stream(stream, &conn));
+        }
+    });
+}
+
+impl LiveListener {
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        let this = Self::new(Bound::Tcp(local));
+        serve(
+            move || {
+                let (stream, peer) = listener.accept()?;
+                Ok((stream, peer.to_string()))
+            },
+            this.connections.clone(),
+            this.stop.clone(),
+        );
+        Ok(this)
+    }
+
+    // The socket file is removed when the listener is dropped.
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let this = Self::new(Bound::Unix(path));
+        let mut n = 0;
+        serve(
+            move || {
+                let (stream, _) = listener.accept()?;
+                // Unix peers are usually unnamed, so they're numbered
+                n += 1;
+                Ok((stream, format!("unix:{}", n)))
+            },
+            this.connections.clone(),
+            this.stop.clone(),
+        );
+        Ok(this)
+    }
+
+    fn new(bound: Bound) -> Self {
+        Self {
+            bound,
+            connections: Arc::default(),
+            stop: Arc::default(),
+        }
+    }
+
+    // Where a TCP listener ended up, for one bound to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.bound {
+            Bound::Tcp(addr) => Some(*addr),
+            #[cfg(unix)]
+            Bound::Unix(_) => None,
+        }
+    }
+
+    // A snapshot of every connection so far, in the order they came.
+    pub fn connections(&self) -> Vec<ConnectionStats> {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .iter()
+            .map(|c| c.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+impl Drop for LiveListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // wake the accepting thread so it sees the stop
+        match &self.bound {
+            Bound::Tcp(addr) => drop(TcpStream::connect(addr)),
+            #[cfg(unix)]
+            Bound::Unix(path) => {
+                drop(UnixStream::connect(path));
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
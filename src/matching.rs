@@ -0,0 +1,135 @@
+use crate::{Id, Trace, Workspace};
+use std::collections::HashMap;
+
+// How far ahead in either trace a lost alignment looks for the two to agree
+// again before giving a pair up as unmatched.
+const RESYNC: usize = 64;
+
+// The instructions of one trace paired with their counterparts in another,
+// by program position rather than by id, so that runs numbering their
+// instructions differently can still be compared one by one.
+#[derive(Clone, Debug, Default)]
+pub struct InstructionMap {
+    pairs: Vec<(Id, Id)>,
+    ab: HashMap<Id, Id>,
+    ba: HashMap<Id, Id>,
+}
+
+impl InstructionMap {
+    pub fn get(&self, a: Id) -> Option<Id> {
+        self.ab.get(&a).copied()
+    }
+
+    pub fn reverse(&self, b: Id) -> Option<Id> {
+        self.ba.get(&b).copied()
+    }
+
+    // In the order the instructions were created in `a`.
+    pub fn pairs(&self) -> &[(Id, Id)] {
+        &self.pairs
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Each matched pair with how many more cycles it took in `b`, for those
+    // that finished in both.
+    pub fn latency_deltas<'s>(
+        &'s self,
+        a: &'s Trace,
+        b: &'s Trace,
+    ) -> impl Iterator<Item = (Id, Id, i64)> + 's {
+        self.pairs.iter().filter_map(|&(x, y)| {
+            let (la, lb) = (a.get(x)?.latency()?, b.get(y)?.latency()?);
+            Some((x, y, lb as i64 - la as i64))
+        })
+    }
+}
+
+// What an instruction is matched on: its PC where the label starts with
+// one, and otherwise the whole label.
+fn key(trace: &Trace, i: usize) -> (Option<u64>, u64) {
+    let rec = &trace.instructions()[i];
+    match trace.pc(rec) {
+        Some(pc) => (Some(pc), 0),
+        None => (None, xxhash_rust::xxh3::xxh3_64(&trace.label(rec))),
+    }
+}
+
+// Pairs up equal keys in order. Where they stop agreeing, both sides skip
+// ahead to the nearest point, fewest skipped first, where they agree again.
+fn align<K: PartialEq>(a: &[K], b: &[K], out: &mut Vec<(usize, usize)>) {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            out.push((i, j));
+            (i, j) = (i + 1, j + 1);
+            continue;
+        }
+        let resync = (1..=2 * RESYNC).find_map(|d| {
+            (d.saturating_sub(RESYNC)..=d.min(RESYNC))
+                .map(|di| (i + di, j + d - di))
+                .find(|&(x, y)| x < a.len() && y < b.len() && a[x] == b[y])
+        });
+        (i, j) = resync.unwrap_or((i + 1, j + 1));
+    }
+}
+
+// Matches the instructions that retired first: both runs commit the same
+// program, so those line up but for the odd divergence. Instructions that
+// didn't retire, such as ones down a mispredicted path, are then matched
+// among themselves between the same pair of retired neighbours, so that
+// wrong paths differing between the runs don't pull the rest out of step.
+pub fn match_instructions(a: &Trace, b: &Trace) -> InstructionMap {
+    let keys = |t: &Trace, v: &[usize]| v.iter().map(|&i| key(t, i)).collect::<Vec<_>>();
+    let split = |t: &Trace| -> (Vec<usize>, Vec<usize>) {
+        (0..t.instructions().len()).partition(|&i| t.instructions()[i].is_retired())
+    };
+    let ((ra, fa), (rb, fb)) = (split(a), split(b));
+    let mut anchors = Vec::new();
+    align(&keys(a, &ra), &keys(b, &rb), &mut anchors);
+    let mut pairs: Vec<(usize, usize)> = anchors.iter().map(|&(x, y)| (ra[x], rb[y])).collect();
+
+    // the gaps before each anchor, and after the last
+    let (mut x, mut y) = (0, 0);
+    let retired = pairs.len();
+    for k in 0..=retired {
+        let (ea, eb) = if k < retired {
+            pairs[k]
+        } else {
+            (usize::MAX, usize::MAX)
+        };
+        let (sx, sy) = (x, y);
+        while x < fa.len() && fa[x] < ea {
+            x += 1;
+        }
+        while y < fb.len() && fb[y] < eb {
+            y += 1;
+        }
+        let (ga, gb) = (&fa[sx..x], &fb[sy..y]);
+        let mut gap = Vec::new();
+        align(&keys(a, ga), &keys(b, gb), &mut gap);
+        pairs.extend(gap.into_iter().map(|(p, q)| (ga[p], gb[q])));
+    }
+    pairs.sort_unstable();
+
+    let mut map = InstructionMap::default();
+    for (p, q) in pairs {
+        let (x, y) = (a.instructions()[p].id, b.instructions()[q].id);
+        map.pairs.push((x, y));
+        map.ab.insert(x, y);
+        map.ba.insert(y, x);
+    }
+    map
+}
+
+impl Workspace {
+    pub fn match_instructions(&self, a: &str, b: &str) -> Option<InstructionMap> {
+        Some(match_instructions(self.get(a)?, self.get(b)?))
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// What run a trace came from, kept next to it in `<trace>.meta.toml`: flat
+// `key = value` lines of TOML, with strings quoted and the clock a bare
+// integer. Keys other than the known ones are kept, as strings, in `extra`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RunMetadata {
+    pub simulator: Option<String>,
+    pub benchmark: Option<String>,
+    pub config_hash: Option<String>,
+    pub clock_hz: Option<u64>,
+    pub extra: BTreeMap<String, String>,
+}
+
+fn invalid(line: usize, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("metadata line {}: {}", line + 1, what),
+    )
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn unquote(s: &str) -> Option<String> {
+    let mut chars = s.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut out = String::new();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+fn bare_key(k: &str) -> bool {
+    !k.is_empty()
+        && k.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+impl RunMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Where the metadata for the trace at `trace` lives.
+    pub fn sidecar_path(trace: impl AsRef<Path>) -> PathBuf {
+        let mut path = trace.as_ref().as_os_str().to_owned();
+        path.push(".meta.toml");
+        path.into()
+    }
+
+    // The metadata next to the trace at `trace`, if there is any.
+    pub fn for_trace(trace: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match Self::load(Self::sidecar_path(trace)) {
+            Ok(m) => Ok(Some(m)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut m = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(i, "expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+            if !bare_key(key) {
+                return Err(invalid(i, "bad key"));
+            }
+            if key == "clock_hz" {
+                m.clock_hz = Some(value.parse().map_err(|_| invalid(i, "bad clock_hz"))?);
+                continue;
+            }
+            let value = match unquote(value) {
+                Some(s) => s,
+                None if value.parse::<i64>().is_ok() => value.to_string(),
+                None => return Err(invalid(i, "expected a string or an integer")),
+            };
+            match key {
+                "simulator" => m.simulator = Some(value),
+                "benchmark" => m.benchmark = Some(value),
+                "config_hash" => m.config_hash = Some(value),
+                _ => {
+                    m.extra.insert(key.to_string(), value);
+                }
+            }
+        }
+        Ok(m)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in self.fields() {
+            out.push_str(&format!("{} = {}\n", key, quote(value)));
+        }
+        if let Some(hz) = self.clock_hz {
+            out.push_str(&format!("clock_hz = {}\n", hz));
+        }
+        for (key, value) in &self.extra {
+            if bare_key(key) {
+                out.push_str(&format!("{} = {}\n", key, quote(value)));
+            }
+        }
+        out
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_toml())
+    }
+
+    // The known string fields that are set.
+    fn fields(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("simulator", &self.simulator),
+            ("benchmark", &self.benchmark),
+            ("config_hash", &self.config_hash),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| Some((k, v.as_deref()?)))
+    }
+
+    // Labels for a metrics exporter: the known string fields that are set.
+    pub fn labels(&self) -> Vec<(&'static str, &str)> {
+        self.fields().collect()
+    }
+
+    // A short name for the run, such as `mcf (gem5 23.1, cfg 9f2c)`, for
+    // showing in place of a path. `None` when there's nothing to go on.
+    pub fn describe(&self) -> Option<String> {
+        let mut detail = Vec::new();
+        if let Some(sim) = &self.simulator {
+            detail.push(sim.clone());
+        }
+        if let Some(hash) = &self.config_hash {
+            detail.push(format!("cfg {}", hash));
+        }
+        if let Some(hz) = self.clock_hz {
+            detail.push(format!("{} MHz", hz as f64 / 1e6));
+        }
+        match (&self.benchmark, detail.is_empty()) {
+            (Some(b), true) => Some(b.clone()),
+            (Some(b), false) => Some(format!("{} ({})", b, detail.join(", "))),
+            (None, false) => Some(detail.join(", ")),
+            (None, true) => None,
+        }
+    }
+}
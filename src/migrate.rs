@@ -0,0 +1,17 @@
+use crate::{Command, Commands, KANATA_VERSION, Writer};
+use std::io::{self, Write};
+
+pub fn migrate<W: Write>(input: &[u8], out: W) -> io::Result<()> {
+    let mut w = Writer::new(out);
+    w.write(&Command::<&[u8]>::Kanata {
+        version: KANATA_VERSION,
+    })?;
+    for (_, cmd) in Commands::new(input)? {
+        match cmd? {
+            // normalized fields come out of the parser; only the header changes
+            Command::Kanata { .. } => {}
+            cmd => w.write_ref(&cmd, input)?,
+        }
+    }
+    w.flush()
+}
@@ -0,0 +1,95 @@
+use super::{StageId, Trace};
+use crate::Id;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetireBandwidth {
+    pub width: usize,
+    pub cycles: u64,
+    pub retired: u64,
+    // cycles by how many instructions retired in them, 0 through width
+    pub slots: Vec<u64>,
+    // cycles without a retirement by the stage the next instruction to
+    // retire was in, most first
+    pub stalls: Vec<(StageId, u64)>,
+    // ones where it hadn't started yet, or there was none
+    pub unattributed: u64,
+}
+
+impl RetireBandwidth {
+    pub fn utilization(&self) -> f64 {
+        match self.cycles * self.width as u64 {
+            0 => 0.0,
+            slots => self.retired as f64 / slots as f64,
+        }
+    }
+
+    pub fn zero_cycles(&self) -> u64 {
+        self.slots.first().copied().unwrap_or(0)
+    }
+}
+
+impl Trace<'_> {
+    // Commit bandwidth over the cycles from the first instruction's start to
+    // the end of the trace. Without a width, the most instructions seen
+    // retiring in one cycle is taken as the width.
+    pub fn retire_bandwidth(&self, width: Option<usize>) -> RetireBandwidth {
+        let mut retired: Vec<(i64, Id, usize)> = self
+            .instructions()
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_retired())
+            .filter_map(|(i, r)| Some((r.end?, r.retire_id.unwrap_or(0), i)))
+            .collect();
+        retired.sort_unstable();
+        let groups: Vec<&[(i64, Id, usize)]> = retired.chunk_by(|a, b| a.0 == b.0).collect();
+        let width = width.unwrap_or_else(|| groups.iter().map(|g| g.len()).max().unwrap_or(1));
+        let width = width.max(1);
+
+        let (start, end) = (self.start_cycle(), self.end_cycle());
+        let mut m = RetireBandwidth {
+            width,
+            cycles: if self.instructions().is_empty() {
+                0
+            } else {
+                (end - start + 1).max(0) as u64
+            },
+            retired: retired.len() as u64,
+            slots: vec![0; width + 1],
+            ..RetireBandwidth::default()
+        };
+        let mut stalls = vec![0; self.stages().len()];
+        let mut at = start;
+        for group in groups {
+            let (cycle, _, i) = group[0];
+            // the cycles since the last retirement were spent waiting on
+            // the first instruction to retire next
+            if cycle > at {
+                let rec = &self.instructions()[i];
+                let mut covered = 0;
+                for s in &rec.stages {
+                    let n = (s.end.min(cycle) - s.start.max(at)).max(0) as u64;
+                    stalls[s.stage.index()] += n;
+                    covered += n;
+                }
+                m.unattributed += cycle.abs_diff(at).saturating_sub(covered);
+                m.slots[0] += cycle.abs_diff(at);
+            }
+            if (start..=end).contains(&cycle) {
+                m.slots[group.len().min(width)] += 1;
+            }
+            at = at.max(cycle + 1);
+        }
+        if m.cycles > 0 && end >= at {
+            m.unattributed += end.abs_diff(at) + 1;
+            m.slots[0] += end.abs_diff(at) + 1;
+        }
+        m.stalls = self
+            .stages()
+            .iter()
+            .map(|(id, _)| (id, stalls[id.index()]))
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        m.stalls.sort_by_key(|&(id, n)| (std::cmp::Reverse(n), id));
+        m
+    }
+}
@@ -0,0 +1,197 @@
+use super::{StageId, Trace};
+use crate::{Bookmark, Bookmarks, Id, Summary};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+// A run of cycles in which nothing was in a lane of a stage while an
+// instruction further up the pipeline was stalled. `blocker` is the stalled
+// instruction nearest the lane when the bubble began, and `cause` the stage
+// it was stuck in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bubble {
+    pub stage: StageId,
+    pub lane: u32,
+    pub start: i64,
+    // exclusive
+    pub end: i64,
+    pub cause: StageId,
+    pub blocker: Id,
+}
+
+impl Bubble {
+    pub fn cycles(&self) -> u64 {
+        self.start.abs_diff(self.end)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LaneBubbles {
+    pub stage: StageId,
+    pub lane: u32,
+    pub lengths: Summary,
+    // bubble cycles by the stage the blocker was in, most first
+    pub causes: Vec<(StageId, u64)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BubbleReport {
+    // by start
+    pub bubbles: Vec<Bubble>,
+    // by stage, then lane; only those that had a bubble
+    pub lanes: Vec<LaneBubbles>,
+}
+
+impl BubbleReport {
+    // Longest first, earliest first among equals.
+    pub fn worst(&self, n: usize) -> Vec<Bubble> {
+        let mut v = self.bubbles.clone();
+        v.sort_by_key(|b| (std::cmp::Reverse(b.cycles()), b.start));
+        v.truncate(n);
+        v
+    }
+
+    // Bookmarks the start of each of the `n` worst bubbles, as
+    // `bubble-<rank>-<stage>-<lane>`.
+    pub fn bookmark_worst(&self, trace: &Trace, n: usize, marks: &mut Bookmarks) -> io::Result<()> {
+        for (i, b) in self.worst(n).iter().enumerate() {
+            let name = format!(
+                "bubble-{}-{}-{}",
+                i + 1,
+                trace.stages().name(b.stage),
+                b.lane
+            );
+            marks.set(name, Bookmark::Cycle(b.start))?;
+        }
+        Ok(())
+    }
+}
+
+enum Event {
+    Enter(usize),
+    Leave(usize),
+    Stall(usize, (i64, Id)),
+    Unstall(usize, (i64, Id)),
+}
+
+impl Trace<'_> {
+    // Pipeline bubbles in each lane of each stage, taking stages to run in
+    // the order they first appear. An instruction counts as stalled once it
+    // has been in a stage longer than the least time any spent there.
+    pub fn bubbles(&self) -> BubbleReport {
+        let stages = self.stages().len();
+        let mut least = vec![u64::MAX; stages];
+        for rec in self.instructions() {
+            for s in rec.stages.iter().filter(|s| s.end > s.start) {
+                let l = &mut least[s.stage.index()];
+                *l = (*l).min(s.cycles());
+            }
+        }
+
+        let mut lanes: Vec<(StageId, u32)> = Vec::new();
+        let mut lane_ix = BTreeMap::new();
+        let mut events: Vec<(i64, Event)> = Vec::new();
+        for rec in self.instructions() {
+            for s in rec.stages.iter().filter(|s| s.end > s.start) {
+                let i = *lane_ix.entry((s.stage, s.lane)).or_insert_with(|| {
+                    lanes.push((s.stage, s.lane));
+                    lanes.len() - 1
+                });
+                events.push((s.start, Event::Enter(i)));
+                events.push((s.end, Event::Leave(i)));
+                let from = s.start + least[s.stage.index()] as i64;
+                if from < s.end {
+                    let key = (s.start, rec.id);
+                    events.push((from, Event::Stall(s.stage.index(), key)));
+                    events.push((s.end, Event::Unstall(s.stage.index(), key)));
+                }
+            }
+        }
+        events.sort_by_key(|&(cycle, _)| cycle);
+
+        let mut occupied = vec![0u32; lanes.len()];
+        let mut stalled: Vec<BTreeSet<(i64, Id)>> = vec![BTreeSet::new(); stages];
+        let mut open: Vec<Option<Bubble>> = vec![None; lanes.len()];
+        let mut causes: Vec<BTreeMap<StageId, u64>> = vec![BTreeMap::new(); lanes.len()];
+        let mut bubbles = Vec::new();
+        let mut i = 0;
+        while i < events.len() {
+            let at = events[i].0;
+            while i < events.len() && events[i].0 == at {
+                match events[i].1 {
+                    Event::Enter(l) => occupied[l] += 1,
+                    Event::Leave(l) => occupied[l] -= 1,
+                    Event::Stall(s, key) => {
+                        stalled[s].insert(key);
+                    }
+                    Event::Unstall(s, key) => {
+                        stalled[s].remove(&key);
+                    }
+                }
+                i += 1;
+            }
+            let Some(&(next, _)) = events.get(i) else {
+                break;
+            };
+            for (l, &(stage, lane)) in lanes.iter().enumerate() {
+                // the nearest stage upstream with a stalled instruction
+                let blocked = (occupied[l] == 0)
+                    .then(|| {
+                        (0..stage.index())
+                            .rev()
+                            .find_map(|s| Some((s, *stalled[s].first()?)))
+                    })
+                    .flatten();
+                let Some((s, (_, blocker))) = blocked else {
+                    bubbles.extend(open[l].take());
+                    continue;
+                };
+                let cause = StageId::from_raw(s as u16);
+                *causes[l].entry(cause).or_default() += at.abs_diff(next);
+                match &mut open[l] {
+                    Some(b) => b.end = next,
+                    None => {
+                        open[l] = Some(Bubble {
+                            stage,
+                            lane,
+                            start: at,
+                            end: next,
+                            cause,
+                            blocker,
+                        })
+                    }
+                }
+            }
+        }
+        bubbles.extend(open.into_iter().flatten());
+        bubbles.sort_by_key(|b| (b.start, b.stage, b.lane));
+
+        let mut report = BubbleReport {
+            bubbles,
+            lanes: Vec::new(),
+        };
+        for (l, &(stage, lane)) in lanes.iter().enumerate() {
+            let mut lengths = Summary::default();
+            let mine = report
+                .bubbles
+                .iter()
+                .filter(|b| (b.stage, b.lane) == (stage, lane));
+            for b in mine {
+                lengths.add(b.cycles());
+            }
+            if lengths.count == 0 {
+                continue;
+            }
+            let mut by_cause: Vec<(StageId, u64)> =
+                std::mem::take(&mut causes[l]).into_iter().collect();
+            by_cause.sort_by_key(|&(id, n)| (std::cmp::Reverse(n), id));
+            report.lanes.push(LaneBubbles {
+                stage,
+                lane,
+                lengths,
+                causes: by_cause,
+            });
+        }
+        report.lanes.sort_by_key(|l| (l.stage, l.lane));
+        report
+    }
+}
@@ -0,0 +1,273 @@
+use super::{DepRecord, Trace};
+use crate::{Id, IdSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+// How far a slice reaches: how many dependency edges from the instruction
+// it starts at, and how many cycles apart that one's start and another's
+// may be. Unbounded by default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SliceBound {
+    pub depth: Option<usize>,
+    pub cycles: Option<u64>,
+}
+
+// A chain of dependencies from a producer to an instruction that waited on
+// it, through the ids in between. `cycles` adds up, for each edge, the wait
+// from its producer starting to the dependency being met.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepPath {
+    pub ids: Vec<Id>,
+    pub cycles: u64,
+}
+
+impl DepPath {
+    pub fn edges(&self) -> usize {
+        self.ids.len() - 1
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleMember {
+    pub id: Id,
+    // of the instruction's `I` record
+    pub offset: usize,
+    pub label: String,
+}
+
+// Instructions that each wait, through the others, on themselves. No real
+// pipeline can do that, so one of these means the simulator logged the
+// dependencies wrong, reusing ids or writing an edge the wrong way round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepCycle {
+    // in the order they were created
+    pub members: Vec<CycleMember>,
+}
+
+// The dependency edges of a trace both ways: the producers each record
+// lists, and the consumers of each instruction, found once up front.
+pub struct DepGraph<'t> {
+    trace: &'t Trace<'t>,
+    consumers: HashMap<Id, Vec<Id>>,
+}
+
+impl<'t> DepGraph<'t> {
+    pub fn new(trace: &'t Trace<'t>) -> Self {
+        let mut consumers: HashMap<Id, Vec<Id>> = HashMap::new();
+        for rec in trace.instructions() {
+            for d in &rec.producers {
+                consumers.entry(d.producer_id).or_default().push(rec.id);
+            }
+        }
+        Self { trace, consumers }
+    }
+
+    pub fn producers(&self, id: Id) -> &'t [DepRecord] {
+        self.trace.get(id).map_or(&[], |r| &r.producers)
+    }
+
+    pub fn consumers(&self, id: Id) -> &[Id] {
+        self.consumers.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    // The target and everything it waited on, transitively, within `bound`:
+    // the instructions to look at for why it was late. Producers the trace
+    // has no record of are left out. Empty for a target it doesn't have.
+    pub fn backward_slice(&self, target: Id, bound: SliceBound) -> IdSet {
+        self.walk(target, bound, |id| {
+            self.producers(id).iter().map(|d| d.producer_id).collect()
+        })
+    }
+
+    // The source and everything that waited on it, transitively, within
+    // `bound`: what a slow instruction, such as a missing load, held up.
+    pub fn forward_slice(&self, source: Id, bound: SliceBound) -> IdSet {
+        self.walk(source, bound, |id| self.consumers(id).to_vec())
+    }
+
+    fn walk(&self, from: Id, bound: SliceBound, next: impl Fn(Id) -> Vec<Id>) -> IdSet {
+        let mut found = IdSet::new();
+        let Some(rec) = self.trace.get(from) else {
+            return found;
+        };
+        found.insert(from);
+        // breadth first, so each is reached by its shortest path
+        let mut queue = VecDeque::from([(from, 0)]);
+        while let Some((id, depth)) = queue.pop_front() {
+            if bound.depth.is_some_and(|d| depth >= d) {
+                continue;
+            }
+            for n in next(id) {
+                let Some(r) = self.trace.get(n) else {
+                    continue;
+                };
+                if bound
+                    .cycles
+                    .is_some_and(|c| r.start.abs_diff(rec.start) > c)
+                    || !found.insert(n)
+                {
+                    continue;
+                }
+                queue.push_back((n, depth + 1));
+            }
+        }
+        found
+    }
+
+    // The cycles the edge from producer `p` to consumer `c` stands for; the
+    // least of them if the trace gives it more than once.
+    fn edge_cycles(&self, p: Id, c: Id) -> u64 {
+        let start = self.trace.get(p).map_or(0, |r| r.start);
+        self.producers(c)
+            .iter()
+            .filter(|d| d.producer_id == p)
+            .map(|d| (d.cycle - start).max(0) as u64)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn path(&self, mut to: Id, from: Id, prev: &HashMap<Id, Id>) -> DepPath {
+        let mut ids = vec![to];
+        while to != from {
+            to = prev[&to];
+            ids.push(to);
+        }
+        ids.reverse();
+        let cycles = ids.windows(2).map(|w| self.edge_cycles(w[0], w[1])).sum();
+        DepPath { ids, cycles }
+    }
+
+    // Whether `b` waited on `a`, directly or through others. An instruction
+    // reaches itself.
+    pub fn reachable(&self, a: Id, b: Id) -> bool {
+        self.shortest_path(a, b).is_some()
+    }
+
+    // The chain from `a` to `b` with the fewest edges.
+    pub fn shortest_path(&self, a: Id, b: Id) -> Option<DepPath> {
+        self.trace.get(a)?;
+        let mut prev = HashMap::new();
+        let mut queue = VecDeque::from([a]);
+        while let Some(id) = queue.pop_front() {
+            if id == b {
+                return Some(self.path(b, a, &prev));
+            }
+            for &c in self.consumers(id) {
+                if c != a && !prev.contains_key(&c) {
+                    prev.insert(c, id);
+                    queue.push_back(c);
+                }
+            }
+        }
+        None
+    }
+
+    // The chain from `a` to `b` that waits the fewest cycles in all.
+    pub fn shortest_path_by_cycles(&self, a: Id, b: Id) -> Option<DepPath> {
+        self.trace.get(a)?;
+        let mut prev = HashMap::new();
+        let mut best = HashMap::from([(a, 0u64)]);
+        let mut heap = BinaryHeap::from([Reverse((0u64, a))]);
+        while let Some(Reverse((cost, id))) = heap.pop() {
+            if id == b {
+                return Some(self.path(b, a, &prev));
+            }
+            if best.get(&id).is_some_and(|&c| c < cost) {
+                continue;
+            }
+            for &c in self.consumers(id) {
+                let next = cost + self.edge_cycles(id, c);
+                if best.get(&c).is_none_or(|&old| next < old) {
+                    best.insert(c, next);
+                    prev.insert(c, id);
+                    heap.push(Reverse((next, c)));
+                }
+            }
+        }
+        None
+    }
+
+    // Each set of instructions caught in a dependency cycle, as the strongly
+    // connected components of the graph with more than one member, or with
+    // one that waits on itself. Ordered by their first member.
+    pub fn cycles(&self) -> Vec<DepCycle> {
+        let instructions = self.trace.instructions();
+        let node: HashMap<Id, usize> = instructions
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.id, i))
+            .collect();
+        let next: Vec<Vec<usize>> = instructions
+            .iter()
+            .map(|r| {
+                let out = self.consumers(r.id).iter();
+                out.filter_map(|c| node.get(c).copied()).collect()
+            })
+            .collect();
+
+        // Tarjan's, with an explicit stack of (node, next edge to follow)
+        const NONE: usize = usize::MAX;
+        let n = instructions.len();
+        let (mut index, mut low) = (vec![NONE; n], vec![0; n]);
+        let mut on_stack = vec![false; n];
+        let (mut stack, mut sccs, mut counter) = (Vec::new(), Vec::new(), 0);
+        for root in 0..n {
+            if index[root] != NONE {
+                continue;
+            }
+            let mut work = vec![(root, 0)];
+            while let Some(&mut (v, ref mut edge)) = work.last_mut() {
+                if *edge == 0 {
+                    index[v] = counter;
+                    low[v] = counter;
+                    counter += 1;
+                    stack.push(v);
+                    on_stack[v] = true;
+                }
+                if let Some(&w) = next[v].get(*edge) {
+                    *edge += 1;
+                    if index[w] == NONE {
+                        work.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                work.pop();
+                if let Some(&(parent, _)) = work.last() {
+                    low[parent] = low[parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut scc = Vec::new();
+                    while let Some(w) = stack.pop() {
+                        on_stack[w] = false;
+                        scc.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    if scc.len() > 1 || next[v].contains(&v) {
+                        scc.sort_unstable();
+                        sccs.push(scc);
+                    }
+                }
+            }
+        }
+        sccs.sort_unstable();
+        sccs.into_iter()
+            .map(|scc| DepCycle {
+                members: scc
+                    .into_iter()
+                    .map(|i| {
+                        let r = &instructions[i];
+                        CycleMember {
+                            id: r.id,
+                            offset: r.offset,
+                            label: String::from_utf8_lossy(&self.trace.label(r)).into_owned(),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,59 @@
+use super::Trace;
+use crate::Id;
+use xxhash_rust::xxh3::Xxh3;
+
+#[allow(clippy::unnecessary_cast)] // already u64 with wide ids
+fn wide(id: Id) -> u64 {
+    id as u64
+}
+
+impl Trace<'_> {
+    // A stable 64-bit hash of what the trace says, for cache keys and for
+    // spotting the same trace uploaded twice. It covers the instructions
+    // with their stages, logs and dependencies, and not how the file said
+    // it: text and binary, line endings, padding around stage names and how
+    // cycles were split up all hash the same. Ids hash the same with or
+    // without `wide-ids`.
+    pub fn fingerprint(&self) -> u64 {
+        let mut h = Xxh3::new();
+        let num = |h: &mut Xxh3, v: u64| h.update(&v.to_le_bytes());
+        let text = |h: &mut Xxh3, s: &[u8]| {
+            h.update(&(s.len() as u64).to_le_bytes());
+            h.update(s);
+        };
+        num(&mut h, self.version.map_or(0, |v| v as u64 + 1));
+        for r in &self.instructions {
+            num(&mut h, wide(r.id));
+            num(&mut h, wide(r.sim_id));
+            num(&mut h, r.thread_id as u64);
+            num(&mut h, r.start as u64);
+            num(&mut h, r.end.map_or(u64::MAX, |c| c as u64));
+            num(&mut h, r.retire_id.map_or(u64::MAX, wide));
+            num(&mut h, r.retire_kind.map_or(0, |k| k as u64));
+            num(&mut h, r.stages.len() as u64);
+            for s in &r.stages {
+                text(&mut h, self.stages.name(s.stage).as_bytes());
+                num(&mut h, s.lane as u64);
+                num(&mut h, s.start as u64);
+                num(&mut h, s.end as u64);
+            }
+            num(&mut h, r.logs.len() as u64);
+            for l in &r.logs {
+                num(&mut h, l.kind as u64);
+                text(&mut h, self.text(l.text));
+            }
+            num(&mut h, r.producers.len() as u64);
+            for d in &r.producers {
+                num(&mut h, wide(d.producer_id));
+                num(&mut h, d.kind as u64);
+                num(&mut h, d.cycle as u64);
+                match d.label {
+                    Some(label) => text(&mut h, self.text(label)),
+                    None => num(&mut h, u64::MAX),
+                }
+            }
+        }
+        num(&mut h, self.end_cycle as u64);
+        h.digest()
+    }
+}
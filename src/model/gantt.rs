@@ -0,0 +1,112 @@
+use super::{StageId, StageTable, Trace};
+use crate::{Id, write_str};
+use std::io::{self, Write};
+use std::ops::Range;
+
+// One stage an instruction spent on a lane, over `[start, end)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LaneInterval {
+    pub lane: u32,
+    pub id: Id,
+    pub stage: StageId,
+    pub start: i64,
+    pub end: i64,
+    pub flushed: bool,
+}
+
+// What each lane held, cycle by cycle, as the bars of a Gantt chart: one
+// row a lane, one bar an instruction's stage on it.
+#[derive(Clone, Debug, Default)]
+pub struct LaneOccupancy {
+    stages: StageTable,
+    // by lane, then start, then id
+    intervals: Vec<LaneInterval>,
+}
+
+impl LaneOccupancy {
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub fn intervals(&self) -> &[LaneInterval] {
+        &self.intervals
+    }
+
+    pub fn lanes(&self) -> Vec<u32> {
+        let mut lanes: Vec<u32> = self.intervals.iter().map(|i| i.lane).collect();
+        lanes.dedup();
+        lanes
+    }
+
+    pub fn lane(&self, lane: u32) -> &[LaneInterval] {
+        let from = self.intervals.partition_point(|i| i.lane < lane);
+        let to = self.intervals.partition_point(|i| i.lane <= lane);
+        &self.intervals[from..to]
+    }
+
+    // Those overlapping `cycles`, cut down to it.
+    pub fn clip(&self, cycles: Range<i64>) -> Self {
+        let intervals = self
+            .intervals
+            .iter()
+            .filter(|i| i.start < cycles.end && i.end > cycles.start)
+            .map(|&i| LaneInterval {
+                start: i.start.max(cycles.start),
+                end: i.end.min(cycles.end),
+                ..i
+            })
+            .collect();
+        Self {
+            stages: self.stages.clone(),
+            intervals,
+        }
+    }
+
+    // `{"lanes": [..], "intervals": [{"lane", "id", "stage", "start", "end",
+    // "flushed"}, ..]}`, with stages by name.
+    pub fn write_json<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        let lanes: Vec<String> = self.lanes().iter().map(u32::to_string).collect();
+        write!(out, "{{\"lanes\":[{}],\"intervals\":[", lanes.join(","))?;
+        for (n, i) in self.intervals.iter().enumerate() {
+            if n > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{{\"lane\":{},\"id\":{},\"stage\":", i.lane, i.id)?;
+            write_str(&mut out, self.stages.name(i.stage).as_bytes())?;
+            write!(
+                out,
+                ",\"start\":{},\"end\":{},\"flushed\":{}}}",
+                i.start, i.end, i.flushed
+            )?;
+        }
+        writeln!(out, "]}}")?;
+        out.flush()
+    }
+}
+
+impl Trace<'_> {
+    // Every finished stage of every instruction, by the lane it was on.
+    pub fn lane_occupancy(&self) -> LaneOccupancy {
+        let mut intervals: Vec<LaneInterval> = self
+            .instructions()
+            .iter()
+            .flat_map(|rec| {
+                let spans = rec.stages.iter().filter(|s| s.end >= s.start);
+                spans.map(|s| LaneInterval {
+                    lane: s.lane,
+                    id: rec.id,
+                    stage: s.stage,
+                    start: s.start,
+                    end: s.end,
+                    flushed: rec.is_flushed(),
+                })
+            })
+            .collect();
+        intervals.sort_by_key(|i| (i.lane, i.start, i.id, i.stage));
+        LaneOccupancy {
+            stages: self.stages().clone(),
+            intervals,
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    Command, CommandSource, Commands, Id, LogKind, ParseError, ParseErrorKind, ParseMetrics,
+    Parser, StrRef, Tolerance, WarningKind,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+mod bandwidth;
+mod bubble;
+mod deps;
+mod fingerprint;
+mod gantt;
+mod query;
+mod reconstruct;
+mod record;
+mod spill;
+mod stage;
+mod thread;
+mod wakeup;
+pub use bandwidth::*;
+pub use bubble::*;
+pub use deps::*;
+pub use gantt::*;
+pub use query::*;
+pub use reconstruct::*;
+pub use record::*;
+pub use stage::*;
+pub use thread::*;
+pub use wakeup::*;
+
+pub struct Trace<'a> {
+    input: Cow<'a, [u8]>,
+    version: Option<u32>,
+    stages: StageTable,
+    instructions: Vec<InstructionRecord>,
+    ids: HashMap<Id, usize>,
+    threads: ThreadTable,
+    end_cycle: i64,
+}
+
+struct Parts {
+    version: Option<u32>,
+    stages: StageTable,
+    instructions: Vec<InstructionRecord>,
+    end_cycle: i64,
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(input: &'a [u8]) -> Result<Self, ParseError> {
+        let parts = build_any(input)?;
+        Ok(Self::from_parts(Cow::Borrowed(input), parts))
+    }
+
+    // `new`, with parsing and reconstruction both held to `tolerance`.
+    pub fn with_tolerance(input: &'a [u8], tolerance: Tolerance) -> Result<Self, ParseError> {
+        let rec = Reconstructor::new(input).with_tolerance(tolerance);
+        let parts = build(Commands::with_tolerance(input, tolerance)?, rec, None)?;
+        Ok(Self::from_parts(Cow::Borrowed(input), parts))
+    }
+
+    pub fn from_vec(input: Vec<u8>) -> Result<Trace<'static>, ParseError> {
+        let parts = build_any(&input)?;
+        Ok(Trace::from_parts(Cow::Owned(input), parts))
+    }
+
+    // A trace from any backend. `new` reads text or binary input; this also
+    // takes commands built in memory or by an importer.
+    pub fn from_source<S: CommandSource<'a>>(source: S) -> Result<Self, ParseError> {
+        Self::from_source_at(source, 0, None)
+    }
+
+    pub(crate) fn window(
+        input: &'a [u8],
+        offset: usize,
+        cycle: i64,
+        until: i64,
+    ) -> Result<Self, ParseError> {
+        let parser = Parser::resume(input, offset).extensions().at_cycle(cycle);
+        Self::from_source_at(parser, cycle, Some(until))
+    }
+
+    pub(crate) fn from_source_at<S: CommandSource<'a>>(
+        source: S,
+        cycle: i64,
+        until: Option<i64>,
+    ) -> Result<Self, ParseError> {
+        let input = source.input();
+        let rec = Reconstructor::new(input).with_cycle(cycle);
+        let parts = build(source, rec, until)?;
+        Ok(Self::from_parts(Cow::Borrowed(input), parts))
+    }
+
+    fn from_parts(input: Cow<'a, [u8]>, parts: Parts) -> Self {
+        let ids = id_map(&parts.instructions);
+        let threads = ThreadTable::build(&parts.instructions);
+        Self {
+            input,
+            version: parts.version,
+            stages: parts.stages,
+            instructions: parts.instructions,
+            ids,
+            threads,
+            end_cycle: parts.end_cycle,
+        }
+    }
+
+    // Renumbers the stages as `map`, from `StageTable::absorb`, says, into
+    // `shared`, which holds them all.
+    pub(crate) fn restage(&mut self, shared: &StageTable, map: &[StageId]) {
+        for span in self.instructions.iter_mut().flat_map(|r| &mut r.stages) {
+            span.stage = map[span.stage.index()];
+        }
+        self.set_stages(shared);
+    }
+
+    // A table that orders the stages as this one does, with more after.
+    pub(crate) fn set_stages(&mut self, shared: &StageTable) {
+        self.stages.clone_from(shared);
+    }
+
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub fn instructions(&self) -> &[InstructionRecord] {
+        &self.instructions
+    }
+
+    pub fn threads(&self) -> &ThreadTable {
+        &self.threads
+    }
+
+    pub fn get(&self, id: Id) -> Option<&InstructionRecord> {
+        self.ids.get(&id).map(|&i| &self.instructions[i])
+    }
+
+    pub fn start_cycle(&self) -> i64 {
+        self.instructions
+            .first()
+            .map_or(self.end_cycle, |r| r.start)
+    }
+
+    pub fn end_cycle(&self) -> i64 {
+        self.end_cycle
+    }
+
+    pub fn text(&self, s: StrRef) -> &[u8] {
+        s.get(&self.input)
+    }
+
+    // The text of one of Konata's panes. Each `L` record of a kind appends
+    // to what the earlier ones wrote, so an instruction with several gets
+    // them joined in order; one with a single record borrows it.
+    pub fn pane(&self, rec: &InstructionRecord, kind: LogKind) -> Cow<'_, [u8]> {
+        rec.pane(&self.input, kind)
+    }
+
+    // The left pane, with the surrounding whitespace simulators tend to
+    // leave trimmed off.
+    pub fn label(&self, rec: &InstructionRecord) -> Cow<'_, [u8]> {
+        rec.label(&self.input)
+    }
+
+    // The mouse-over popup.
+    pub fn detail(&self, rec: &InstructionRecord) -> Cow<'_, [u8]> {
+        self.pane(rec, LogKind::MouseOver)
+    }
+
+    // Kind 2 logs, which Konata keeps but doesn't show in either pane.
+    pub fn extra(&self, rec: &InstructionRecord) -> Cow<'_, [u8]> {
+        self.pane(rec, LogKind::Other)
+    }
+
+    pub fn pc(&self, rec: &InstructionRecord) -> Option<u64> {
+        parse_pc(&self.label(rec))
+    }
+
+    // Heap bytes `new` would need for the instruction model of `input`, from
+    // a pass that only counts commands. It doesn't include the input itself.
+    pub fn estimate_model_memory(input: &[u8]) -> Result<usize, ParseError> {
+        let mut m = ParseMetrics::default();
+        for (_, cmd) in Commands::new(input)? {
+            m.count(&cmd?);
+        }
+        Ok(model_memory(&m))
+    }
+}
+
+fn model_memory(m: &ParseMetrics) -> usize {
+    const ALLOC_OVERHEAD: usize = 16;
+    let n = m.instructions as usize;
+    if n == 0 {
+        return 0;
+    }
+    // the per-instruction vectors grow by doubling from four
+    let per = |total: u64, size: usize| match (total as usize).div_ceil(n) {
+        0 => 0,
+        avg => avg.max(4).next_power_of_two() * size + ALLOC_OVERHEAD,
+    };
+    let vectors = per(m.stage_starts, size_of::<StageSpan>())
+        + per(m.logs, size_of::<LogRecord>())
+        + per(m.deps, size_of::<DepRecord>());
+    // a control byte per slot and a 7/8 load factor in the id map
+    let id_slot = (size_of::<(Id, usize)>() + 1) * 8 / 7;
+    // the record vector, the map and the (usually single) thread's index
+    // vector all round capacity up to a power of two
+    let slot = size_of::<InstructionRecord>() + id_slot + size_of::<u32>();
+    vectors * n + slot * n.next_power_of_two()
+}
+
+fn id_map(instructions: &[InstructionRecord]) -> HashMap<Id, usize> {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.id, i))
+        .collect()
+}
+
+fn build_any(input: &[u8]) -> Result<Parts, ParseError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("trace", bytes = input.len()).entered();
+    build(Commands::new(input)?, Reconstructor::new(input), None)
+}
+
+fn build<'a, S: CommandSource<'a>>(
+    mut source: S,
+    mut rec: Reconstructor,
+    until: Option<i64>,
+) -> Result<Parts, ParseError> {
+    let mut done = Vec::new();
+    let mut ids = HashMap::new();
+    while let Some(cmd) = source.next_command() {
+        if until.is_some_and(|u| rec.cycle() > u) {
+            break;
+        }
+        let cmd = cmd?;
+        match rec.feed(cmd.offset, cmd.command)? {
+            Step::Pending => {}
+            Step::Retired(r) | Step::Evicted(r) => {
+                ids.insert(r.id, done.len());
+                done.push(r);
+            }
+            Step::Orphan(c) => {
+                if !attach(&mut done, &ids, c, rec.cycle()) {
+                    rec.irregular(
+                        cmd.offset,
+                        ParseErrorKind::UnknownInstruction,
+                        WarningKind::UnknownInstruction,
+                    )?;
+                }
+            }
+        }
+    }
+    let version = rec.version();
+    let end_cycle = rec.cycle();
+    let (stages, rest) = rec.finish()?;
+    done.extend(rest);
+    done.sort_by_key(|r| r.offset);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(instructions = done.len(), end_cycle, "trace built");
+    Ok(Parts {
+        version,
+        stages,
+        instructions: done,
+        end_cycle,
+    })
+}
+
+// Logs and dependencies that came after their instruction retired. Whether
+// there was one to attach `cmd` to.
+fn attach(
+    done: &mut [InstructionRecord],
+    ids: &HashMap<Id, usize>,
+    cmd: Command,
+    cycle: i64,
+) -> bool {
+    match cmd {
+        Command::Log { id, kind, text } => {
+            let Some(&i) = ids.get(&id) else {
+                return false;
+            };
+            done[i].logs.push(LogRecord { kind, text });
+        }
+        Command::Dep {
+            consumer_id,
+            producer_id,
+            kind,
+            label,
+        } => {
+            let Some(&i) = ids.get(&consumer_id) else {
+                return false;
+            };
+            done[i].producers.push(DepRecord {
+                producer_id,
+                kind,
+                cycle,
+                label,
+            });
+        }
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,58 @@
+use super::{InstructionRecord, StageId, Trace};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    TotalLatency,
+    StageLatency(StageId),
+    WakeupDelay,
+}
+
+impl SortBy {
+    pub fn key(self, rec: &InstructionRecord) -> Option<u64> {
+        match self {
+            SortBy::TotalLatency => rec.latency(),
+            SortBy::StageLatency(stage) => Some(rec.stage_latency(stage)),
+            SortBy::WakeupDelay => rec.wakeup_delay(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ranked<'t> {
+    pub record: &'t InstructionRecord,
+    pub label: Cow<'t, [u8]>,
+    pub value: u64,
+}
+
+impl Trace<'_> {
+    pub fn top_n(&self, n: usize, by: SortBy) -> Vec<Ranked<'_>> {
+        let n = n.min(self.instructions().len());
+        if n == 0 {
+            return Vec::new();
+        }
+        // min-heap of the best n so far; ties favour the earlier instruction
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        for (i, rec) in self.instructions().iter().enumerate() {
+            if let Some(value) = by.key(rec) {
+                heap.push(Reverse((value, Reverse(i))));
+                if heap.len() > n {
+                    heap.pop();
+                }
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((value, Reverse(i)))| {
+                let record = &self.instructions()[i];
+                Ranked {
+                    record,
+                    label: self.label(record),
+                    value,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,374 @@
+use super::record::OPEN;
+use super::spill::Spill;
+use super::{DepRecord, InstructionRecord, LogRecord, StageSpan, StageTable};
+use crate::{Clock, Command, Id, ParseError, ParseErrorKind, Tolerance, Warning, WarningKind};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+pub enum Step {
+    Pending,
+    Retired(InstructionRecord),
+    // pushed out, unfinished, to make room under `Eviction::EvictOldest`
+    Evicted(InstructionRecord),
+    Orphan(Command),
+}
+
+// What to do when an `I` would go over the in-flight cap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+    #[default]
+    Error,
+    EvictOldest,
+    // move the oldest to this scratch file, reading it back when it's used
+    Spill(PathBuf),
+}
+
+pub struct Reconstructor<'a> {
+    input: &'a [u8],
+    clock: Clock,
+    version: Option<u32>,
+    stages: StageTable,
+    in_flight: HashMap<Id, InstructionRecord>,
+    max_in_flight: usize,
+    eviction: Eviction,
+    // ids by age for eviction; entries for ids since gone are skipped
+    order: VecDeque<(usize, Id)>,
+    spill: Option<Spill>,
+    warnings: Vec<Warning>,
+    tolerance: Tolerance,
+    // the highest `I` id so far, for spotting gaps
+    last_id: Option<Id>,
+}
+
+impl<'a> Reconstructor<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            clock: Clock::new(),
+            version: None,
+            stages: StageTable::new(),
+            in_flight: HashMap::new(),
+            max_in_flight: usize::MAX,
+            eviction: Eviction::Error,
+            order: VecDeque::new(),
+            spill: None,
+            warnings: Vec::new(),
+            tolerance: Tolerance::default(),
+            last_id: None,
+        }
+    }
+
+    // Strict fails on stage ends that match no start, records for
+    // instructions not in flight and `I` ids that skip ahead, where the
+    // others warn.
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn tolerance(&self) -> Tolerance {
+        self.tolerance
+    }
+
+    pub(super) fn irregular(
+        &mut self,
+        offset: usize,
+        kind: ParseErrorKind,
+        warning: WarningKind,
+    ) -> Result<(), ParseError> {
+        if self.tolerance == Tolerance::Strict {
+            return Err(ParseError { offset, kind });
+        }
+        self.warnings.push(Warning {
+            offset,
+            kind: warning,
+        });
+        Ok(())
+    }
+
+    pub fn with_eviction(mut self, eviction: Eviction) -> Self {
+        if let Eviction::Spill(path) = &eviction {
+            self.spill = Some(Spill::new(path.clone()));
+        }
+        self.eviction = eviction;
+        self
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = max;
+        self
+    }
+
+    pub fn with_cycle(mut self, cycle: i64) -> Self {
+        self.clock = Clock::at(cycle);
+        self
+    }
+
+    pub fn cycle(&self) -> i64 {
+        self.clock.cycle()
+    }
+
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, Spill::len)
+    }
+
+    fn spill_error(offset: usize) -> impl FnOnce(std::io::Error) -> ParseError {
+        move |_| ParseError {
+            offset,
+            kind: ParseErrorKind::SpillFailed,
+        }
+    }
+
+    fn oldest(&mut self) -> Option<InstructionRecord> {
+        while let Some((offset, id)) = self.order.pop_front() {
+            if self.in_flight.get(&id).is_some_and(|r| r.offset == offset) {
+                return self.in_flight.remove(&id);
+            }
+        }
+        None
+    }
+
+    fn track(&mut self, offset: usize, id: Id) {
+        if self.eviction == Eviction::Error {
+            return;
+        }
+        self.order.push_back((offset, id));
+        if self.order.len() > 2 * self.in_flight.len() + 1024 {
+            let live = &self.in_flight;
+            self.order
+                .retain(|(offset, id)| live.get(id).is_some_and(|r| r.offset == *offset));
+        }
+    }
+
+    // Makes room for one more in-flight instruction, if the policy allows.
+    fn evict(&mut self, offset: usize) -> Result<Option<InstructionRecord>, ParseError> {
+        let too_many = ParseError {
+            offset,
+            kind: ParseErrorKind::TooManyInFlight,
+        };
+        if self.eviction == Eviction::Error {
+            return Err(too_many);
+        }
+        let Some(old) = self.oldest() else {
+            return Err(too_many);
+        };
+        self.warnings.push(Warning {
+            offset: old.offset,
+            kind: WarningKind::Evicted,
+        });
+        match &mut self.spill {
+            Some(spill) => {
+                spill.put(&old).map_err(Self::spill_error(offset))?;
+                Ok(None)
+            }
+            None => Ok(Some(old)),
+        }
+    }
+
+    // Brings a spilled instruction back in for a command that refers to it,
+    // spilling the oldest in its place if need be.
+    fn unspill(&mut self, offset: usize, id: Id) -> Result<(), ParseError> {
+        let Some(spill) = self.spill.as_mut().filter(|s| s.contains(id)) else {
+            return Ok(());
+        };
+        let rec = spill.take(id).map_err(Self::spill_error(offset))?.unwrap();
+        if self.in_flight.len() >= self.max_in_flight {
+            self.evict(offset)?;
+        }
+        self.track(rec.offset, id);
+        self.in_flight.insert(id, rec);
+        Ok(())
+    }
+
+    pub fn feed(&mut self, offset: usize, cmd: Command) -> Result<Step, ParseError> {
+        if let Some(id) = cmd.id()
+            && self.spill.is_some()
+            && !matches!(cmd, Command::Instruction { .. })
+        {
+            self.unspill(offset, id)?;
+        }
+        let cycle = self.clock.cycle();
+        match cmd {
+            Command::Kanata { version } => self.version = Some(version),
+            Command::Cycle { .. } => self.clock.apply(&cmd),
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => {
+                let spilled = self.spill.as_ref().is_some_and(|s| s.contains(id_in_file));
+                if self.in_flight.contains_key(&id_in_file) || spilled {
+                    return Err(ParseError {
+                        offset,
+                        kind: ParseErrorKind::DuplicateInstruction,
+                    });
+                }
+                if self
+                    .last_id
+                    .is_some_and(|last| id_in_file > last.saturating_add(1))
+                {
+                    self.irregular(offset, ParseErrorKind::IdGap, WarningKind::IdGap)?;
+                }
+                self.last_id = Some(self.last_id.map_or(id_in_file, |l| l.max(id_in_file)));
+                let evicted = if self.in_flight.len() >= self.max_in_flight {
+                    self.evict(offset)?
+                } else {
+                    None
+                };
+                let rec = InstructionRecord::new(id_in_file, id_in_sim, thread_id, offset, cycle);
+                self.track(offset, id_in_file);
+                self.in_flight.insert(id_in_file, rec);
+                if let Some(rec) = evicted {
+                    return Ok(Step::Evicted(rec));
+                }
+            }
+            Command::Log { id, kind, text } => match self.in_flight.get_mut(&id) {
+                Some(rec) => rec.logs.push(LogRecord { kind, text }),
+                None => return Ok(Step::Orphan(cmd)),
+            },
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                let Some(rec) = self.in_flight.get_mut(&id) else {
+                    return Ok(Step::Orphan(cmd));
+                };
+                let stage = self
+                    .stages
+                    .intern(name.get(self.input))
+                    .map_err(|kind| ParseError { offset, kind })?;
+                if start {
+                    rec.close_lane(lane_id, cycle);
+                    rec.stages.push(StageSpan {
+                        stage,
+                        lane: lane_id,
+                        start: cycle,
+                        end: OPEN,
+                    });
+                } else if !rec.close_stage(stage, lane_id, cycle) {
+                    self.irregular(
+                        offset,
+                        ParseErrorKind::UnmatchedStage,
+                        WarningKind::UnmatchedStage,
+                    )?;
+                }
+            }
+            Command::Retire { id, retire, kind } => {
+                let Some(mut rec) = self.in_flight.remove(&id) else {
+                    return Ok(Step::Orphan(cmd));
+                };
+                rec.close_all(cycle);
+                rec.end = Some(cycle);
+                rec.retire_id = Some(retire);
+                rec.retire_kind = Some(kind);
+                return Ok(Step::Retired(rec));
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => match self.in_flight.get_mut(&consumer_id) {
+                Some(rec) => rec.producers.push(DepRecord {
+                    producer_id,
+                    kind,
+                    cycle,
+                    label,
+                }),
+                None => return Ok(Step::Orphan(cmd)),
+            },
+            Command::StageColor { name, color } => {
+                self.stages.set_color(name.get(self.input), color);
+            }
+        }
+        Ok(Step::Pending)
+    }
+
+    // A copy of the model state for replaying from this point; the copy
+    // fails on overflow rather than evicting.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            clock: self.clock,
+            version: self.version,
+            stages: self.stages.clone(),
+            in_flight: self.in_flight.clone(),
+            max_in_flight: self.max_in_flight,
+            tolerance: self.tolerance,
+            last_id: self.last_id,
+            ..Self::new(self.input)
+        }
+    }
+
+    // The same state over other input, for when the commands come from
+    // buffers that don't outlive them. Texts in the records in flight still
+    // point into the old input, so this is only for callers that don't keep
+    // any: commands without logs and dependency labels.
+    #[cfg(feature = "listen")]
+    pub(crate) fn continue_on<'b>(self, input: &'b [u8]) -> Reconstructor<'b> {
+        Reconstructor {
+            input,
+            clock: self.clock,
+            version: self.version,
+            stages: self.stages,
+            in_flight: self.in_flight,
+            max_in_flight: self.max_in_flight,
+            eviction: self.eviction,
+            order: self.order,
+            spill: self.spill,
+            warnings: self.warnings,
+            tolerance: self.tolerance,
+            last_id: self.last_id,
+        }
+    }
+
+    // Takes over the instructions in flight in `other`, a reconstructor over
+    // the same input that got as far as this one, with their stages renamed
+    // into this one's table.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn adopt(&mut self, other: Reconstructor<'a>) -> Result<(), ParseErrorKind> {
+        let map = self.stages.absorb(&other.stages)?;
+        for (id, mut rec) in other.in_flight {
+            for span in &mut rec.stages {
+                span.stage = map[span.stage.index()];
+            }
+            self.in_flight.insert(id, rec);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn in_flight_records(&self) -> impl Iterator<Item = &InstructionRecord> {
+        self.in_flight.values()
+    }
+
+    pub fn finish(self) -> Result<(StageTable, Vec<InstructionRecord>), ParseError> {
+        let cycle = self.clock.cycle();
+        let mut rest: Vec<_> = self.in_flight.into_values().collect();
+        if let Some(mut spill) = self.spill {
+            let spilled = spill.drain().map_err(Self::spill_error(self.input.len()))?;
+            rest.extend(spilled);
+        }
+        for rec in &mut rest {
+            rec.close_all(cycle);
+        }
+        rest.sort_by_key(|r| r.offset);
+        Ok((self.stages, rest))
+    }
+}
@@ -0,0 +1,185 @@
+use super::StageId;
+use crate::{DepKind, Id, LogKind, RetireKind, StrRef};
+use std::borrow::Cow;
+
+pub(super) const OPEN: i64 = i64::MIN;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StageSpan {
+    pub stage: StageId,
+    pub lane: u32,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl StageSpan {
+    pub fn cycles(&self) -> u64 {
+        (self.end - self.start).max(0) as u64
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    pub kind: LogKind,
+    pub text: StrRef,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DepRecord {
+    pub producer_id: Id,
+    pub kind: DepKind,
+    pub cycle: i64,
+    pub label: Option<StrRef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstructionRecord {
+    pub id: Id,
+    pub sim_id: Id,
+    pub thread_id: u32,
+    pub offset: usize,
+    pub start: i64,
+    pub end: Option<i64>,
+    pub retire_id: Option<Id>,
+    pub retire_kind: Option<RetireKind>,
+    pub stages: Vec<StageSpan>,
+    pub logs: Vec<LogRecord>,
+    pub producers: Vec<DepRecord>,
+}
+
+impl InstructionRecord {
+    pub fn new(id: Id, sim_id: Id, thread_id: u32, offset: usize, start: i64) -> Self {
+        Self {
+            id,
+            sim_id,
+            thread_id,
+            offset,
+            start,
+            end: None,
+            retire_id: None,
+            retire_kind: None,
+            stages: Vec::new(),
+            logs: Vec::new(),
+            producers: Vec::new(),
+        }
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retire_kind == Some(RetireKind::Retire)
+    }
+
+    pub fn is_flushed(&self) -> bool {
+        self.retire_kind == Some(RetireKind::Flush)
+    }
+
+    pub fn latency(&self) -> Option<u64> {
+        self.end.map(|end| (end - self.start).max(0) as u64)
+    }
+
+    pub fn stage_latency(&self, stage: StageId) -> u64 {
+        self.stages
+            .iter()
+            .filter(|s| s.stage == stage)
+            .map(StageSpan::cycles)
+            .sum()
+    }
+
+    pub fn wakeup_delay(&self) -> Option<u64> {
+        self.producers
+            .iter()
+            .map(|d| d.cycle)
+            .max()
+            .map(|c| (c - self.start).max(0) as u64)
+    }
+
+    pub fn first_log(&self, kind: LogKind) -> Option<StrRef> {
+        self.logs.iter().find(|l| l.kind == kind).map(|l| l.text)
+    }
+
+    // `Trace::pane` and `Trace::label` for a record read from `input`
+    // without a trace, as the streaming APIs give them.
+    pub fn pane<'a>(&self, input: &'a [u8], kind: LogKind) -> Cow<'a, [u8]> {
+        let mut texts = self
+            .logs
+            .iter()
+            .filter(|l| l.kind == kind)
+            .map(|l| l.text.get(input));
+        let Some(first) = texts.next() else {
+            return Cow::Borrowed(&[]);
+        };
+        match texts.next() {
+            None => Cow::Borrowed(first),
+            Some(second) => {
+                let mut out = [first, second].concat();
+                texts.for_each(|t| out.extend_from_slice(t));
+                Cow::Owned(out)
+            }
+        }
+    }
+
+    pub fn label<'a>(&self, input: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.pane(input, LogKind::LeftPane) {
+            Cow::Borrowed(s) => Cow::Borrowed(s.trim_ascii()),
+            Cow::Owned(s) => Cow::Owned(s.trim_ascii().to_vec()),
+        }
+    }
+
+    pub(super) fn close_lane(&mut self, lane: u32, cycle: i64) {
+        for span in self.stages.iter_mut().rev() {
+            if span.lane == lane && span.end == OPEN {
+                span.end = cycle;
+            }
+        }
+    }
+
+    pub(super) fn close_stage(&mut self, stage: StageId, lane: u32, cycle: i64) -> bool {
+        if let Some(span) = self
+            .stages
+            .iter_mut()
+            .rev()
+            .find(|s| s.stage == stage && s.lane == lane && s.end == OPEN)
+        {
+            span.end = cycle;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn close_all(&mut self, cycle: i64) {
+        for span in &mut self.stages {
+            if span.end == OPEN {
+                span.end = cycle;
+            }
+        }
+    }
+}
+
+pub fn parse_pc(label: &[u8]) -> Option<u64> {
+    split_pc(label).0
+}
+
+pub fn split_pc(label: &[u8]) -> (Option<u64>, &[u8]) {
+    let label = label.trim_ascii();
+    let end = label
+        .iter()
+        .position(|&b| b == b':' || b.is_ascii_whitespace())
+        .unwrap_or(label.len());
+    let tok = &label[..end];
+    let tok = tok
+        .strip_prefix(b"0x")
+        .or_else(|| tok.strip_prefix(b"0X"))
+        .unwrap_or(tok);
+    if tok.is_empty() || tok.len() > 16 || !tok.iter().all(u8::is_ascii_hexdigit) {
+        return (None, label);
+    }
+    let Some(pc) = std::str::from_utf8(tok)
+        .ok()
+        .and_then(|t| u64::from_str_radix(t, 16).ok())
+    else {
+        return (None, label);
+    };
+    let rest = &label[end..];
+    let rest = rest.strip_prefix(b":").unwrap_or(rest);
+    (Some(pc), rest.trim_ascii_start())
+}
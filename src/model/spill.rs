@@ -0,0 +1,193 @@
+use super::{DepRecord, InstructionRecord, LogRecord, StageId, StageSpan};
+use crate::{DepKind, Id, LogKind, RetireKind, StrRef};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+// Records evicted from the in-flight set, appended to a scratch file and
+// read back by id. A record spilled twice just leaves a dead copy behind.
+pub(super) struct Spill {
+    path: PathBuf,
+    file: Option<File>,
+    end: u64,
+    index: HashMap<Id, (u64, usize)>,
+}
+
+impl Spill {
+    pub(super) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            end: 0,
+            index: HashMap::new(),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(super) fn contains(&self, id: Id) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    pub(super) fn put(&mut self, rec: &InstructionRecord) -> io::Result<()> {
+        let buf = encode(rec);
+        let at = self.end;
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(at))?;
+        file.write_all(&buf)?;
+        self.end += buf.len() as u64;
+        self.index.insert(rec.id, (at, buf.len()));
+        Ok(())
+    }
+
+    pub(super) fn take(&mut self, id: Id) -> io::Result<Option<InstructionRecord>> {
+        let Some((at, len)) = self.index.remove(&id) else {
+            return Ok(None);
+        };
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(at))?;
+        let mut buf = vec![0; len];
+        file.read_exact(&mut buf)?;
+        decode(&buf).map(Some).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "corrupt spilled instruction")
+        })
+    }
+
+    pub(super) fn drain(&mut self) -> io::Result<Vec<InstructionRecord>> {
+        let ids: Vec<Id> = self.index.keys().copied().collect();
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            out.extend(self.take(id)?);
+        }
+        if self.file.take().is_some() {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(out)
+    }
+}
+
+fn encode(rec: &InstructionRecord) -> Vec<u8> {
+    let mut b = Vec::new();
+    b.extend(rec.id.to_le_bytes());
+    b.extend(rec.sim_id.to_le_bytes());
+    b.extend(rec.thread_id.to_le_bytes());
+    b.extend((rec.offset as u64).to_le_bytes());
+    b.extend(rec.start.to_le_bytes());
+    b.extend(rec.end.unwrap_or(i64::MIN).to_le_bytes());
+    b.push(rec.end.is_some() as u8);
+    b.extend(rec.retire_id.unwrap_or(0).to_le_bytes());
+    b.push(rec.retire_kind.map_or(0, |k| k as u8));
+    b.extend((rec.stages.len() as u32).to_le_bytes());
+    for s in &rec.stages {
+        b.extend((s.stage.index() as u16).to_le_bytes());
+        b.extend(s.lane.to_le_bytes());
+        b.extend(s.start.to_le_bytes());
+        b.extend(s.end.to_le_bytes());
+    }
+    b.extend((rec.logs.len() as u32).to_le_bytes());
+    for l in &rec.logs {
+        b.push(l.kind as u8);
+        b.extend(l.text.offset().to_le_bytes());
+        b.extend(l.text.len().to_le_bytes());
+    }
+    b.extend((rec.producers.len() as u32).to_le_bytes());
+    for d in &rec.producers {
+        b.extend(d.producer_id.to_le_bytes());
+        b.push(d.kind as u8);
+        b.extend(d.cycle.to_le_bytes());
+        let label = d.label.unwrap_or(StrRef::new(0, 0));
+        b.push(d.label.is_some() as u8);
+        b.extend(label.offset().to_le_bytes());
+        b.extend(label.len().to_le_bytes());
+    }
+    b
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn id(&mut self) -> Option<Id> {
+        self.bytes().map(Id::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.bytes().map(i64::from_le_bytes)
+    }
+}
+
+fn decode(buf: &[u8]) -> Option<InstructionRecord> {
+    let mut r = Reader(buf);
+    let (id, sim_id, thread_id) = (r.id()?, r.id()?, r.u32()?);
+    let mut rec = InstructionRecord::new(id, sim_id, thread_id, r.u64()? as usize, r.i64()?);
+    let end = r.i64()?;
+    rec.end = (r.u8()? != 0).then_some(end);
+    let retire_id = r.id()?;
+    rec.retire_kind = match r.u8()? {
+        0 => None,
+        k => Some(RetireKind::try_from(k).ok()?),
+    };
+    rec.retire_id = rec.retire_kind.map(|_| retire_id);
+    for _ in 0..r.u32()? {
+        rec.stages.push(StageSpan {
+            stage: StageId::from_raw(r.u16()?),
+            lane: r.u32()?,
+            start: r.i64()?,
+            end: r.i64()?,
+        });
+    }
+    for _ in 0..r.u32()? {
+        let kind = LogKind::try_from(r.u8()?).ok()?;
+        let text = StrRef::new(r.u64()?, r.u16()?);
+        rec.logs.push(LogRecord { kind, text });
+    }
+    for _ in 0..r.u32()? {
+        let (producer_id, kind, cycle) = (r.id()?, r.u8()?, r.i64()?);
+        let labelled = r.u8()? != 0;
+        let label = StrRef::new(r.u64()?, r.u16()?);
+        rec.producers.push(DepRecord {
+            producer_id,
+            kind: DepKind::try_from(kind).ok()?,
+            cycle,
+            label: labelled.then_some(label),
+        });
+    }
+    Some(rec)
+}
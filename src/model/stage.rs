@@ -0,0 +1,134 @@
+use crate::ParseErrorKind;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StageId(u16);
+
+impl StageId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub(super) fn from_raw(v: u16) -> Self {
+        Self(v)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "StoredStages", into = "StoredStages")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StageTable {
+    names: Vec<String>,
+    ids: HashMap<String, StageId>,
+    // display hints from `P` records, by name since they may come before
+    // the stage is first used
+    colors: HashMap<String, u32>,
+}
+
+impl StageTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `TooManyStages` for a new name once every 16-bit id is taken.
+    pub fn intern(&mut self, name: &[u8]) -> Result<StageId, ParseErrorKind> {
+        let name = String::from_utf8_lossy(name.trim_ascii());
+        if let Some(&id) = self.ids.get(name.as_ref()) {
+            return Ok(id);
+        }
+        let id = u16::try_from(self.names.len()).map_err(|_| ParseErrorKind::TooManyStages)?;
+        let id = StageId(id);
+        self.names.push(name.to_string());
+        self.ids.insert(name.into_owned(), id);
+        Ok(id)
+    }
+
+    pub fn get(&self, name: &str) -> Option<StageId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: StageId) -> &str {
+        &self.names[id.index()]
+    }
+
+    pub fn set_color(&mut self, name: &[u8], color: u32) {
+        let name = String::from_utf8_lossy(name.trim_ascii()).into_owned();
+        self.colors.insert(name, color & 0xff_ffff);
+    }
+
+    // The declared color as 0xRRGGBB, if the trace gave one.
+    pub fn color(&self, id: StageId) -> Option<u32> {
+        self.colors.get(self.name(id)).copied()
+    }
+
+    // Every declared color, by name, whether or not the stage was used.
+    pub(crate) fn colors(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.colors.iter().map(|(n, &c)| (n.as_str(), c))
+    }
+
+    // Interns `other`'s stages, and takes its colors for names with none
+    // here. The id here of each of its stages, by its own.
+    pub(crate) fn absorb(&mut self, other: &StageTable) -> Result<Vec<StageId>, ParseErrorKind> {
+        for (name, &color) in &other.colors {
+            self.colors.entry(name.clone()).or_insert(color);
+        }
+        other
+            .names
+            .iter()
+            .map(|n| self.intern(n.as_bytes()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (StageId, &str)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (StageId(i as u16), n.as_str()))
+    }
+}
+
+// A table as stored: the names in id order, and the colors by name, sorted
+// so the same table always writes the same.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct StoredStages {
+    names: Vec<String>,
+    colors: BTreeMap<String, u32>,
+}
+
+impl TryFrom<StoredStages> for StageTable {
+    type Error = ParseErrorKind;
+
+    fn try_from(stored: StoredStages) -> Result<Self, ParseErrorKind> {
+        let mut table = Self::new();
+        for name in stored.names {
+            table.intern(name.as_bytes())?;
+        }
+        table.colors = stored.colors.into_iter().collect();
+        Ok(table)
+    }
+}
+
+impl From<StageTable> for StoredStages {
+    fn from(table: StageTable) -> Self {
+        Self {
+            names: table.names,
+            colors: table.colors.into_iter().collect(),
+        }
+    }
+}
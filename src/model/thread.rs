@@ -0,0 +1,113 @@
+use super::{InstructionRecord, Trace};
+use crate::Id;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: u32,
+    // indices into the trace's instructions, in program order
+    pub instructions: Vec<u32>,
+    pub retired: u64,
+    pub flushed: u64,
+}
+
+// The hardware threads of a trace in ascending id order.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadTable {
+    threads: Vec<ThreadInfo>,
+    index: HashMap<u32, usize>,
+}
+
+impl ThreadTable {
+    pub fn build(instructions: &[InstructionRecord]) -> Self {
+        let mut index = HashMap::new();
+        let mut threads: Vec<ThreadInfo> = Vec::new();
+        for (i, rec) in instructions.iter().enumerate() {
+            let k = *index.entry(rec.thread_id).or_insert_with(|| {
+                threads.push(ThreadInfo {
+                    id: rec.thread_id,
+                    ..ThreadInfo::default()
+                });
+                threads.len() - 1
+            });
+            let t = &mut threads[k];
+            t.instructions.push(i as u32);
+            t.retired += rec.is_retired() as u64;
+            t.flushed += rec.is_flushed() as u64;
+        }
+        threads.sort_by_key(|t| t.id);
+        let index = threads.iter().enumerate().map(|(k, t)| (t.id, k)).collect();
+        Self { threads, index }
+    }
+
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    pub fn get(&self, thread: u32) -> Option<&ThreadInfo> {
+        self.index.get(&thread).map(|&k| &self.threads[k])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ThreadInfo> {
+        self.threads.iter()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Interleaving {
+    pub retirements: u64,
+    // consecutive retirements from different threads
+    pub switches: u64,
+    // the most retirements in a row from one thread
+    pub longest_run: u64,
+}
+
+impl Interleaving {
+    pub fn switch_rate(&self) -> f64 {
+        match self.retirements {
+            0 | 1 => 0.0,
+            n => self.switches as f64 / (n - 1) as f64,
+        }
+    }
+}
+
+impl Trace<'_> {
+    pub fn thread_instructions(&self, thread: u32) -> impl Iterator<Item = &InstructionRecord> {
+        let indices = self
+            .threads()
+            .get(thread)
+            .map_or(&[][..], |t| &t.instructions);
+        indices.iter().map(|&i| &self.instructions()[i as usize])
+    }
+
+    // How the retire slots are shared out, walking retirements in retire id
+    // order.
+    pub fn interleaving(&self) -> Interleaving {
+        let mut retired: Vec<(Id, usize, u32)> = self
+            .instructions()
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_retired())
+            .map(|(i, r)| (r.retire_id.unwrap_or(0), i, r.thread_id))
+            .collect();
+        retired.sort_unstable();
+        let mut m = Interleaving {
+            retirements: retired.len() as u64,
+            ..Interleaving::default()
+        };
+        let mut run = 0;
+        for (k, &(_, _, thread)) in retired.iter().enumerate() {
+            if k > 0 && retired[k - 1].2 != thread {
+                m.switches += 1;
+                run = 0;
+            }
+            run += 1;
+            m.longest_run = m.longest_run.max(run);
+        }
+        m
+    }
+}
@@ -0,0 +1,182 @@
+use super::{InstructionRecord, StageId, StageSpan, StageTable, Trace};
+use crate::report::csv_field;
+use crate::write_str;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+// The wakeups from producers in one stage to consumers in another, by how
+// many cycles the consumer took to move on from its stage once woken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WakeupCell {
+    pub producer: StageId,
+    pub consumer: StageId,
+    // count by latency
+    pub latencies: BTreeMap<u64, u64>,
+}
+
+impl WakeupCell {
+    pub fn count(&self) -> u64 {
+        self.latencies.values().sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            n => self.latencies.iter().map(|(&l, &c)| l * c).sum::<u64>() as f64 / n as f64,
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.latencies.keys().next_back().copied().unwrap_or(0)
+    }
+
+    // By nearest rank.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        let n = self.count();
+        let rank = ((q.clamp(0.0, 1.0) * n as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&l, &c) in &self.latencies {
+            seen += c;
+            if seen >= rank {
+                return Some(l);
+            }
+        }
+        None
+    }
+}
+
+// Wakeup latency for each pair of producer and consumer stage, for a heatmap
+// of which forwarding paths are slow.
+#[derive(Clone, Debug, Default)]
+pub struct WakeupHeatmap {
+    stages: StageTable,
+    // by producer stage, then consumer stage
+    cells: Vec<WakeupCell>,
+    // wakeups from producers the trace has no record of, or with a stage
+    // missing on either side
+    pub skipped: u64,
+}
+
+// The span `rec` was in at `cycle`, or else the next one it went into.
+fn waiting_in(rec: &InstructionRecord, cycle: i64) -> Option<&StageSpan> {
+    let spans = rec.stages.iter().filter(|s| s.end >= s.start);
+    spans.filter(|s| s.end > cycle).min_by_key(|s| s.start)
+}
+
+// The last span `rec` had started by `cycle`: the one it produced from.
+fn producing_in(rec: &InstructionRecord, cycle: i64) -> Option<&StageSpan> {
+    let spans = rec.stages.iter().filter(|s| s.start <= cycle);
+    spans.max_by_key(|s| s.start)
+}
+
+impl WakeupHeatmap {
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub fn cells(&self) -> &[WakeupCell] {
+        &self.cells
+    }
+
+    pub fn cell(&self, producer: StageId, consumer: StageId) -> Option<&WakeupCell> {
+        let i = self
+            .cells
+            .binary_search_by_key(&(producer, consumer), |c| (c.producer, c.consumer));
+        i.ok().map(|i| &self.cells[i])
+    }
+
+    pub fn wakeups(&self) -> u64 {
+        self.cells.iter().map(WakeupCell::count).sum()
+    }
+
+    // One row for each latency seen in each cell, ready to pivot.
+    pub fn write_csv<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        writeln!(out, "producer_stage,consumer_stage,latency,count")?;
+        for c in &self.cells {
+            let (p, q) = (self.stages.name(c.producer), self.stages.name(c.consumer));
+            for (l, n) in &c.latencies {
+                writeln!(out, "{},{},{},{}", csv_field(p), csv_field(q), l, n)?;
+            }
+        }
+        out.flush()
+    }
+
+    // `{"stages": [..], "skipped": n, "cells": [..]}`, each cell with its
+    // summary and its histogram as `[latency, count]` pairs.
+    pub fn write_json<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        write!(out, "{{\"stages\":[")?;
+        for (i, (_, name)) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write_str(&mut out, name.as_bytes())?;
+        }
+        write!(out, "],\"skipped\":{},\"cells\":[", self.skipped)?;
+        for (i, c) in self.cells.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{{\"producer\":")?;
+            write_str(&mut out, self.stages.name(c.producer).as_bytes())?;
+            write!(out, ",\"consumer\":")?;
+            write_str(&mut out, self.stages.name(c.consumer).as_bytes())?;
+            let q = |q| c.quantile(q).unwrap_or(0);
+            write!(
+                out,
+                ",\"count\":{},\"mean\":{:.3},\"p50\":{},\"p90\":{},\"max\":{},\"histogram\":[",
+                c.count(),
+                c.mean(),
+                q(0.5),
+                q(0.9),
+                c.max()
+            )?;
+            for (j, (l, n)) in c.latencies.iter().enumerate() {
+                if j > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "[{},{}]", l, n)?;
+            }
+            write!(out, "]}}")?;
+        }
+        writeln!(out, "]}}")?;
+        out.flush()
+    }
+}
+
+impl Trace<'_> {
+    // Each wakeup counted under the stage its producer was in at the
+    // dependency's cycle, or had last been in, and the stage the consumer was
+    // woken in, or went into next; its latency runs from the wakeup to the
+    // consumer leaving that stage.
+    pub fn wakeup_heatmap(&self) -> WakeupHeatmap {
+        let mut cells: BTreeMap<(StageId, StageId), BTreeMap<u64, u64>> = BTreeMap::new();
+        let mut skipped = 0;
+        for rec in self.instructions() {
+            for d in &rec.producers {
+                let producer = self.get(d.producer_id);
+                let p = producer.and_then(|p| producing_in(p, d.cycle));
+                let (Some(p), Some(c)) = (p, waiting_in(rec, d.cycle)) else {
+                    skipped += 1;
+                    continue;
+                };
+                let latency = (c.end - d.cycle).max(0) as u64;
+                let cell = cells.entry((p.stage, c.stage)).or_default();
+                *cell.entry(latency).or_default() += 1;
+            }
+        }
+        WakeupHeatmap {
+            stages: self.stages().clone(),
+            cells: cells
+                .into_iter()
+                .map(|((producer, consumer), latencies)| WakeupCell {
+                    producer,
+                    consumer,
+                    latencies,
+                })
+                .collect(),
+            skipped,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    Command, CommandSource, DepKind, Id, LogKind, ParseError, ParseErrorKind, RetireKind, StrRef,
+};
+use std::collections::HashMap;
+
+const TAG_HEADER: u64 = 0;
+const TAG_CYCLE: u64 = 1;
+const TAG_INSTRUCTION: u64 = 2;
+const TAG_LOG: u64 = 3;
+const TAG_START: u64 = 4;
+const TAG_END: u64 = 5;
+const TAG_RETIRE: u64 = 6;
+const TAG_DEP: u64 = 7;
+const TAG_STAGE_COLOR: u64 = 8;
+
+// the cycles since the previous event, as a signed 24-bit field
+const DELTA_BITS: u32 = 24;
+const MAX_DELTA: i64 = (1 << (DELTA_BITS - 1)) - 1;
+
+// One command in 16 bytes: a tag, a flag nibble, the cycles since the event
+// before and the instruction id in `head`, the rest in `body`. Stage names
+// and dependency labels are interned; log texts stay `StrRef`s into the
+// input, which has to be kept around to read them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PackedEvent {
+    head: u64,
+    body: u64,
+}
+
+const _: () = assert!(size_of::<PackedEvent>() == 16);
+
+// Ids are kept in 32 bits whatever their width.
+#[allow(clippy::useless_conversion)]
+fn narrow(id: Id) -> Option<u32> {
+    u32::try_from(id).ok()
+}
+
+#[allow(clippy::useless_conversion)]
+fn widen(id: u32) -> Id {
+    Id::from(id)
+}
+
+impl PackedEvent {
+    fn new(tag: u64, flags: u8, id: u32, body: u64) -> Self {
+        Self {
+            head: tag | (flags as u64) << 4 | (id as u64) << 32,
+            body,
+        }
+    }
+
+    fn tag(self) -> u64 {
+        self.head & 0xf
+    }
+
+    fn flags(self) -> u8 {
+        (self.head >> 4 & 0xf) as u8
+    }
+
+    fn id(self) -> Id {
+        widen((self.head >> 32) as u32)
+    }
+
+    fn with_delta(mut self, delta: i64) -> Self {
+        let field = (delta as u64) & ((1 << DELTA_BITS) - 1);
+        self.head |= field << 8;
+        self
+    }
+
+    // The cycles that pass before this event.
+    pub fn cycle_delta(self) -> i32 {
+        // sign-extend the 24-bit field
+        ((self.head as u32) as i32) >> 8
+    }
+}
+
+// A trace's commands as packed events, for holding far more of them in
+// memory than `Command` allows. Relative cycle commands fold into the next
+// event; the commands come back out in order, with those merged.
+#[derive(Clone, Debug, Default)]
+pub struct PackedTrace {
+    events: Vec<PackedEvent>,
+    texts: Vec<StrRef>,
+    index: HashMap<Vec<u8>, u32>,
+    // relative cycles not yet given to an event
+    pending: i64,
+}
+
+impl PackedTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_source<'a, S: CommandSource<'a>>(mut source: S) -> Result<Self, ParseError> {
+        let mut packed = Self::new();
+        let input = source.input();
+        while let Some(cmd) = source.next_command() {
+            let cmd = cmd?;
+            packed.push(cmd.offset, cmd.command, input)?;
+        }
+        Ok(packed)
+    }
+
+    pub fn events(&self) -> &[PackedEvent] {
+        &self.events
+    }
+
+    fn intern(&mut self, s: StrRef, input: &[u8]) -> u32 {
+        let next = self.texts.len() as u32;
+        *self.index.entry(s.get(input).to_vec()).or_insert_with(|| {
+            self.texts.push(s);
+            next
+        })
+    }
+
+    // Fails with `ValueTooBig` at `offset` for an id or id difference that
+    // doesn't fit in 32 bits.
+    pub fn push(&mut self, offset: usize, cmd: Command, input: &[u8]) -> Result<(), ParseError> {
+        let too_big = ParseError {
+            offset,
+            kind: ParseErrorKind::ValueTooBig,
+        };
+        let narrow = |id: Id| narrow(id).ok_or(too_big);
+        let event = match cmd {
+            Command::Cycle { abs: false, value } => {
+                self.pending += value as i64;
+                return Ok(());
+            }
+            Command::Cycle { abs: true, value } => {
+                self.pending = 0;
+                PackedEvent::new(TAG_CYCLE, 1, 0, value as u32 as u64)
+            }
+            Command::Kanata { version } => PackedEvent::new(TAG_HEADER, 0, 0, version as u64),
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => {
+                let sim =
+                    i32::try_from(id_in_sim as i64 - id_in_file as i64).map_err(|_| too_big)?;
+                let body = sim as u32 as u64 | (thread_id as u64) << 32;
+                PackedEvent::new(TAG_INSTRUCTION, 0, narrow(id_in_file)?, body)
+            }
+            Command::Log { id, kind, text } => {
+                let body = text.offset() << 16 | text.len() as u64;
+                PackedEvent::new(TAG_LOG, kind as u8 - b'0', narrow(id)?, body)
+            }
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                let tag = if start { TAG_START } else { TAG_END };
+                let body = lane_id as u64 | (self.intern(name, input) as u64) << 32;
+                PackedEvent::new(tag, 0, narrow(id)?, body)
+            }
+            Command::Retire { id, retire, kind } => {
+                let body = narrow(retire)? as u64;
+                PackedEvent::new(TAG_RETIRE, kind as u8 - b'0', narrow(id)?, body)
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => {
+                // 0 for none, else one past the interned text
+                let label = label.map_or(0, |l| self.intern(l, input) as u64 + 1);
+                let body = narrow(producer_id)? as u64 | label << 32;
+                PackedEvent::new(TAG_DEP, kind as u8 - b'0', narrow(consumer_id)?, body)
+            }
+            Command::StageColor { name, color } => {
+                let body = color as u64 | (self.intern(name, input) as u64) << 32;
+                PackedEvent::new(TAG_STAGE_COLOR, 0, 0, body)
+            }
+        };
+        // cycle deltas too big for the field go out as cycle events of their own
+        while self.pending.abs() > MAX_DELTA {
+            let step = self.pending.clamp(i32::MIN as i64, i32::MAX as i64);
+            let cycle = PackedEvent::new(TAG_CYCLE, 0, 0, step as i32 as u32 as u64);
+            self.events.push(cycle);
+            self.pending -= step;
+        }
+        self.events.push(event.with_delta(self.pending));
+        self.pending = 0;
+        Ok(())
+    }
+
+    pub fn unpack(&self, event: PackedEvent) -> Command {
+        let body = event.body;
+        let (low, high) = (body as u32, (body >> 32) as u32);
+        let id = event.id();
+        let text = |i: u32| self.texts[i as usize];
+        let digit = event.flags() + b'0';
+        match event.tag() {
+            TAG_HEADER => Command::Kanata { version: low },
+            TAG_CYCLE => Command::Cycle {
+                abs: event.flags() & 1 != 0,
+                value: low as i32,
+            },
+            TAG_INSTRUCTION => Command::Instruction {
+                id_in_file: id,
+                id_in_sim: (id as i64 + low as i32 as i64) as Id,
+                thread_id: high,
+            },
+            TAG_LOG => Command::Log {
+                id,
+                kind: LogKind::try_from(digit).unwrap_or(LogKind::LeftPane),
+                text: StrRef::new(body >> 16, body as u16),
+            },
+            tag @ (TAG_START | TAG_END) => Command::Pipeline {
+                start: tag == TAG_START,
+                id,
+                lane_id: low,
+                name: text(high),
+            },
+            TAG_RETIRE => Command::Retire {
+                id,
+                retire: widen(low),
+                kind: RetireKind::try_from(digit).unwrap_or(RetireKind::Retire),
+            },
+            TAG_DEP => Command::Dep {
+                consumer_id: id,
+                producer_id: widen(low),
+                kind: DepKind::try_from(digit).unwrap_or(DepKind::WakeUp),
+                label: high.checked_sub(1).map(text),
+            },
+            _ => Command::StageColor {
+                name: text(high),
+                color: low,
+            },
+        }
+    }
+
+    // The commands back, with a relative cycle command in front of each
+    // event that comes some cycles after the one before.
+    pub fn commands(&self) -> impl Iterator<Item = Command> + '_ {
+        let cycle = |value: i64| {
+            (value != 0).then_some(Command::Cycle {
+                abs: false,
+                value: value as i32,
+            })
+        };
+        self.events
+            .iter()
+            .flat_map(move |&e| {
+                let delta = cycle(e.cycle_delta() as i64);
+                delta.into_iter().chain(Some(self.unpack(e)))
+            })
+            .chain(cycle(self.pending))
+    }
+}
@@ -0,0 +1,77 @@
+use super::{ParseError, ParseErrorKind, Parser};
+use crate::Command;
+
+// Commands with their texts as slices of the input instead of `StrRef`s,
+// which can't be resolved against the wrong buffer.
+pub struct BorrowedCommands<'a> {
+    parser: Parser<'a>,
+}
+
+// Like `BorrowedCommands`, with texts checked to be UTF-8.
+pub struct Utf8Commands<'a> {
+    parser: Parser<'a>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn borrowed(self) -> BorrowedCommands<'a> {
+        BorrowedCommands { parser: self }
+    }
+
+    pub fn utf8(self) -> Utf8Commands<'a> {
+        Utf8Commands { parser: self }
+    }
+}
+
+impl<'a> BorrowedCommands<'a> {
+    pub fn parser(&self) -> &Parser<'a> {
+        &self.parser
+    }
+
+    pub fn parser_mut(&mut self) -> &mut Parser<'a> {
+        &mut self.parser
+    }
+}
+
+impl<'a> Utf8Commands<'a> {
+    pub fn parser(&self) -> &Parser<'a> {
+        &self.parser
+    }
+
+    pub fn parser_mut(&mut self) -> &mut Parser<'a> {
+        &mut self.parser
+    }
+}
+
+impl<'a> Iterator for BorrowedCommands<'a> {
+    type Item = (usize, Result<Command<&'a [u8]>, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.parser.input();
+        let (offset, cmd) = self.parser.next()?;
+        Some((offset, cmd.map(|c| c.resolve(input))))
+    }
+}
+
+impl<'a> Iterator for Utf8Commands<'a> {
+    type Item = (usize, Result<Command<&'a str>, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.parser.input();
+        let (offset, cmd) = self.parser.next()?;
+        let cmd = cmd.and_then(|c| {
+            let mut bad = None;
+            // a command has at most one text
+            let c = c.map_text(|s| {
+                std::str::from_utf8(s.get(input)).unwrap_or_else(|e| {
+                    bad = Some(ParseError {
+                        offset: s.offset() as usize + e.valid_up_to(),
+                        kind: ParseErrorKind::InvalidUtf8,
+                    });
+                    ""
+                })
+            });
+            bad.map_or(Ok(c), Err)
+        });
+        Some((offset, cmd))
+    }
+}
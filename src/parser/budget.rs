@@ -0,0 +1,76 @@
+use super::{ParseError, Parser};
+use crate::Command;
+use std::time::{Duration, Instant};
+
+// How much a `take_budget` call may parse before it stops. `Both` stops at
+// whichever limit comes first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Budget {
+    Bytes(usize),
+    Millis(u64),
+    Both { bytes: usize, millis: u64 },
+}
+
+// the clock is read once per this many lines
+const LINES_PER_CHECK: u32 = 256;
+
+// The commands a parser yields until its budget runs out. It always stops
+// between lines, so calling `take_budget` again carries on from there.
+pub struct Budgeted<'p, 'a> {
+    parser: &'p mut Parser<'a>,
+    budget: Budget,
+    start: usize,
+    started: Instant,
+    lines: u32,
+    exhausted: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn take_budget(&mut self, budget: Budget) -> Budgeted<'_, 'a> {
+        Budgeted {
+            start: self.get_offset(),
+            parser: self,
+            budget,
+            started: Instant::now(),
+            lines: 0,
+            exhausted: false,
+        }
+    }
+}
+
+impl Budgeted<'_, '_> {
+    // Whether it stopped for the budget rather than the end of the input.
+    pub fn exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    fn spent(&mut self) -> bool {
+        let (bytes, millis) = match self.budget {
+            Budget::Bytes(n) => (Some(n), None),
+            Budget::Millis(ms) => (None, Some(ms)),
+            Budget::Both { bytes, millis } => (Some(bytes), Some(millis)),
+        };
+        let read = self.parser.get_offset() - self.start;
+        let over_bytes = bytes.is_some_and(|n| read >= n);
+        let over_time = millis.is_some_and(|ms| {
+            self.lines += 1;
+            self.lines % LINES_PER_CHECK == 1 && self.started.elapsed() >= Duration::from_millis(ms)
+        });
+        over_bytes || over_time
+    }
+}
+
+impl Iterator for Budgeted<'_, '_> {
+    type Item = (usize, Result<Command, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        if self.spent() && self.parser.get_offset() < self.parser.input().len() {
+            self.exhausted = true;
+            return None;
+        }
+        self.parser.next()
+    }
+}
@@ -1,6 +1,13 @@
 use crate::Command;
+use crate::document::parse_line;
+use memchr::{memchr, memchr2};
+use std::collections::HashSet;
+use std::fmt;
 
-#[derive(Debug)]
+pub const KANATA_VERSION: u32 = 4;
+pub const MIN_KANATA_VERSION: u32 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ParseErrorKind {
     InvalidHeader,
     InvalidLogKind,
@@ -12,24 +19,248 @@ pub enum ParseErrorKind {
     ExpectedText,
     UnexpectedCharacter,
     UnexpectedEof,
+    DuplicateInstruction,
+    TooManyInFlight,
+    UnsupportedVersion,
+    SpillFailed,
+    TrailingGarbage,
+    InvalidUtf8,
+    UnmatchedStage,
+    UnknownInstruction,
+    IdGap,
+    TooManyStages,
+    SketchMismatch,
+}
+
+impl ParseErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ParseErrorKind::InvalidHeader => "invalid-header",
+            ParseErrorKind::InvalidLogKind => "invalid-log-kind",
+            ParseErrorKind::InvalidRetireKind => "invalid-retire-kind",
+            ParseErrorKind::InvalidDepKind => "invalid-dep-kind",
+            ParseErrorKind::TextTooLong => "text-too-long",
+            ParseErrorKind::ExpectedValue => "expected-value",
+            ParseErrorKind::ValueTooBig => "value-too-big",
+            ParseErrorKind::ExpectedText => "expected-text",
+            ParseErrorKind::UnexpectedCharacter => "unexpected-character",
+            ParseErrorKind::UnexpectedEof => "unexpected-eof",
+            ParseErrorKind::DuplicateInstruction => "duplicate-instruction",
+            ParseErrorKind::TooManyInFlight => "too-many-in-flight",
+            ParseErrorKind::UnsupportedVersion => "unsupported-version",
+            ParseErrorKind::SpillFailed => "spill-failed",
+            ParseErrorKind::TrailingGarbage => "trailing-garbage",
+            ParseErrorKind::InvalidUtf8 => "invalid-utf8",
+            ParseErrorKind::UnmatchedStage => "unmatched-stage",
+            ParseErrorKind::UnknownInstruction => "unknown-instruction",
+            ParseErrorKind::IdGap => "id-gap",
+            ParseErrorKind::TooManyStages => "too-many-stages",
+            ParseErrorKind::SketchMismatch => "sketch-mismatch",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ParseErrorKind::InvalidHeader => "expected a `Kanata` header",
+            ParseErrorKind::InvalidLogKind => "log kind must be 0, 1 or 2",
+            ParseErrorKind::InvalidRetireKind => "retire kind must be 0 or 1",
+            ParseErrorKind::InvalidDepKind => "dependency kind must be 0",
+            ParseErrorKind::TextTooLong => "text is longer than 65535 bytes",
+            ParseErrorKind::ExpectedValue => "expected a number",
+            ParseErrorKind::ValueTooBig => "number is out of range",
+            ParseErrorKind::ExpectedText => "expected text",
+            ParseErrorKind::UnexpectedCharacter => "unexpected character",
+            ParseErrorKind::UnexpectedEof => "unexpected end of input",
+            ParseErrorKind::DuplicateInstruction => "instruction id is already in flight",
+            ParseErrorKind::TooManyInFlight => "too many instructions in flight",
+            ParseErrorKind::UnsupportedVersion => "unsupported Kanata version",
+            ParseErrorKind::SpillFailed => "could not spill in-flight instructions to disk",
+            ParseErrorKind::TrailingGarbage => "unexpected text after the last field",
+            ParseErrorKind::InvalidUtf8 => "text is not valid UTF-8",
+            ParseErrorKind::UnmatchedStage => "stage end without a matching start",
+            ParseErrorKind::UnknownInstruction => "record for an instruction not in flight",
+            ParseErrorKind::IdGap => "instruction ids skip ahead",
+            ParseErrorKind::TooManyStages => "more than 65536 stage names",
+            ParseErrorKind::SketchMismatch => "latency sketches of different accuracy",
+        }
+    }
 }
 
-#[derive(Debug)]
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ParseError {
     pub offset: usize,
     pub kind: ParseErrorKind,
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(e: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+// How much irregularity the parser, the reconstructor and the importers
+// put up with, the same way in each:
+// - `Strict` rejects anything irregular: text after a line's last field,
+//   stage ends that match no start, records for instructions that aren't
+//   in flight (but for logs and dependencies after retiring), and `I` ids
+//   that skip ahead.
+// - `Lenient` rejects malformed records, but takes the rest with a warning.
+// - `Recover` gets past what it can: unknown kind digits take the default
+//   kind, overlong texts are cut short, and lines or rows that still don't
+//   parse are skipped, each with a warning.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Tolerance {
+    Strict,
+    #[default]
+    Lenient,
+    Recover,
+}
+
+// Which line endings the parser accepts. `Auto` takes `\n`, `\r\n` and a
+// lone `\r`, warning once when a file mixes them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Newline {
+    Lf,
+    CrLf,
+    #[default]
+    Auto,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WarningKind {
+    UnknownKind,
+    TruncatedText,
+    SkippedLine(ParseErrorKind),
+    CycleWentBack,
+    CycleJumped,
+    Evicted,
+    MixedLineEndings,
+    UnmatchedStage,
+    UnknownInstruction,
+    IdGap,
+}
+
+impl WarningKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningKind::UnknownKind => "unknown-kind",
+            WarningKind::TruncatedText => "truncated-text",
+            WarningKind::SkippedLine(_) => "skipped-line",
+            WarningKind::CycleWentBack => "cycle-went-back",
+            WarningKind::CycleJumped => "cycle-jumped",
+            WarningKind::Evicted => "evicted",
+            WarningKind::MixedLineEndings => "mixed-line-endings",
+            WarningKind::UnmatchedStage => "unmatched-stage",
+            WarningKind::UnknownInstruction => "unknown-instruction",
+            WarningKind::IdGap => "id-gap",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            WarningKind::UnknownKind => "unknown kind digit, using the default kind",
+            WarningKind::TruncatedText => "text truncated to 65535 bytes",
+            WarningKind::SkippedLine(kind) => kind.message(),
+            WarningKind::CycleWentBack => "cycle moved backwards",
+            WarningKind::CycleJumped => "cycle jumped further ahead than allowed",
+            WarningKind::Evicted => "too many instructions in flight, evicted the oldest",
+            WarningKind::MixedLineEndings => "line ending differs from the first line's",
+            WarningKind::UnmatchedStage => ParseErrorKind::UnmatchedStage.message(),
+            WarningKind::UnknownInstruction => ParseErrorKind::UnknownInstruction.message(),
+            WarningKind::IdGap => ParseErrorKind::IdGap.message(),
+        }
+    }
+}
+
+// Checks on `C` commands beyond the backwards warning every parse gives:
+// forward jumps of more than `max_jump` cycles warn too, and with `correct`
+// the command is changed to leave the cycle where it was, or `max_jump`
+// ahead of it, keeping its relative or absolute form.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CycleWatchdog {
+    pub max_jump: Option<i64>,
+    pub correct: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub offset: usize,
+    pub kind: WarningKind,
+}
+
+// Running totals for monitoring; lines counts every line the parser
+// looked at, whether or not it parsed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseMetrics {
+    pub bytes: u64,
+    pub lines: u64,
+    pub headers: u64,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub logs: u64,
+    pub stage_starts: u64,
+    pub stage_ends: u64,
+    pub retires: u64,
+    pub deps: u64,
+    pub extensions: u64,
+    pub unknown: u64,
+    pub skipped: u64,
+    pub errors: u64,
+    pub recovered: u64,
+}
+
+impl ParseMetrics {
+    pub(crate) fn count(&mut self, cmd: &Command) {
+        let n = match cmd {
+            Command::Kanata { .. } => &mut self.headers,
+            Command::Cycle { .. } => &mut self.cycles,
+            Command::Instruction { .. } => &mut self.instructions,
+            Command::Log { .. } => &mut self.logs,
+            Command::Pipeline { start: true, .. } => &mut self.stage_starts,
+            Command::Pipeline { start: false, .. } => &mut self.stage_ends,
+            Command::Retire { .. } => &mut self.retires,
+            Command::Dep { .. } => &mut self.deps,
+            Command::StageColor { .. } => &mut self.extensions,
+        };
+        *n += 1;
+    }
+}
+
+mod borrowed;
+mod budget;
 mod primitive;
+mod push;
+pub use borrowed::{BorrowedCommands, Utf8Commands};
+pub use budget::{Budget, Budgeted};
 pub use primitive::Parser;
+pub use push::PushParser;
 mod rules;
 
 impl<'a> Iterator for Parser<'a> {
     type Item = (usize, Result<Command, ParseError>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let offset = self.get_offset();
-        if let Some(b) = self.current() {
+        loop {
+            let offset = self.get_offset();
+            let b = self.current()?;
+            if matches!(b, b'\r' | b'\n') && self.blank_line() {
+                continue;
+            }
             let res = match b {
                 b'K' => self.parse_header(),
                 b'C' => self.parse_c(),
@@ -39,11 +270,137 @@ impl<'a> Iterator for Parser<'a> {
                 b'E' => self.parse_pipeline(false),
                 b'R' => self.parse_r(),
                 b'W' => self.parse_w(),
+                b'P' if self.has_extensions() => self.parse_p(),
                 _ => Err(self.error(ParseErrorKind::UnexpectedCharacter)),
             };
-            Some((offset, res))
+            let consumed = self.get_offset() - offset;
+            let m = self.metrics_mut();
+            m.bytes += consumed as u64;
+            m.lines += 1;
+            #[cfg(feature = "tracing")]
+            if m.lines.is_multiple_of(1_000_000) {
+                tracing::debug!(lines = m.lines, offset, "parse progress");
+            }
+
+            match res {
+                Ok(cmd) => {
+                    self.metrics_mut().count(&cmd);
+                    return Some((offset, Ok(self.watch_cycle(offset, cmd))));
+                }
+                Err(e) => {
+                    if e.kind == ParseErrorKind::UnexpectedCharacter && e.offset == offset {
+                        self.metrics_mut().unknown += 1;
+                    }
+                    if self.recovers() {
+                        let next = self.next_line(offset);
+                        let skipped = next.saturating_sub(self.get_offset());
+                        self.metrics_mut().bytes += skipped as u64;
+                        self.seek(next);
+                        self.warn(offset, WarningKind::SkippedLine(e.kind));
+                        self.skip_line(offset..next);
+                        continue;
+                    }
+                    self.metrics_mut().errors += 1;
+                }
+            }
+            return Some((offset, res));
+        }
+    }
+}
+
+impl Parser<'_> {
+    // Where the line from `offset` ends, past its break. A lone `\r` ends
+    // one too, unless the line endings were pinned to another.
+    fn next_line(&self, offset: usize) -> usize {
+        let input = self.input();
+        let rest = &input[offset..];
+        let found = match self.newline() {
+            Newline::Auto => memchr2(b'\n', b'\r', rest),
+            Newline::Lf | Newline::CrLf => memchr(b'\n', rest),
+        };
+        match found {
+            None => input.len(),
+            Some(i) if rest[i..].starts_with(b"\r\n") => offset + i + 2,
+            Some(i) => offset + i + 1,
+        }
+    }
+
+    // Moves the clock for a `C` command, returning it as corrected by the
+    // watchdog if that's on.
+    fn watch_cycle(&mut self, offset: usize, cmd: Command) -> Command {
+        let Command::Cycle { abs, value } = cmd else {
+            return cmd;
+        };
+        let before = self.clock().cycle();
+        let watchdog = self.watchdog();
+        let after = if abs {
+            value as i64
+        } else {
+            before + value as i64
+        };
+        let limit = watchdog
+            .max_jump
+            .map_or(i64::MAX, |j| before.saturating_add(j));
+        let fixed = if after < before {
+            self.warn(offset, WarningKind::CycleWentBack);
+            before
+        } else if after > limit {
+            self.warn(offset, WarningKind::CycleJumped);
+            limit
         } else {
-            None
+            after
+        };
+        let cmd = if watchdog.correct && fixed != after {
+            let value = if abs { fixed } else { fixed - before };
+            // out of range only for cycles a `C=` couldn't have set
+            Command::Cycle {
+                abs,
+                value: value.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            }
+        } else {
+            cmd
+        };
+        self.clock().apply(&cmd);
+        cmd
+    }
+}
+
+// The version in the header on the first line, if it has one.
+pub(crate) fn header_version(input: &[u8]) -> Option<u32> {
+    match Parser::new(input).next() {
+        Some((_, Ok(Command::Kanata { version }))) => Some(version),
+        _ => None,
+    }
+}
+
+// Every error in the file, resynchronizing at the next line after each one,
+// where the parser and `Trace` stop at the first. Lines are parsed strictly.
+pub fn check(input: &[u8]) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    let mut version = None;
+    let mut live = HashSet::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let Some((offset, cmd, next)) = parse_line(input, pos, version, Tolerance::Strict) else {
+            break;
+        };
+        match cmd {
+            Ok(Command::Kanata { version: v }) => {
+                version.get_or_insert(v);
+            }
+            Ok(Command::Instruction { id_in_file, .. }) if !live.insert(id_in_file) => {
+                errors.push(ParseError {
+                    offset,
+                    kind: ParseErrorKind::DuplicateInstruction,
+                });
+            }
+            Ok(Command::Retire { id, .. }) => {
+                live.remove(&id);
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e),
         }
+        pos = next;
     }
+    errors
 }
@@ -1,22 +1,241 @@
-use super::{ParseError, ParseErrorKind};
+use super::{
+    CycleWatchdog, Newline, ParseError, ParseErrorKind, ParseMetrics, Tolerance, Warning,
+    WarningKind,
+};
+use crate::Clock;
+use std::ops::Range;
 
 pub struct Parser<'a> {
     input: &'a [u8],
     pos: usize,
+    version: Option<u32>,
+    tolerance: Tolerance,
+    extensions: bool,
+    newline: Newline,
+    watchdog: CycleWatchdog,
+    // the first line ending seen, and whether a different one was reported
+    ending: Option<&'static [u8]>,
+    mixed: bool,
+    clock: Clock,
+    warnings: Vec<Warning>,
+    on_warning: Option<Box<dyn FnMut(Warning) + 'a>>,
+    metrics: ParseMetrics,
+    skipped: Vec<Range<usize>>,
 }
 impl<'a> Parser<'a> {
     pub fn new(input: &'a [u8]) -> Self {
-        Self { input, pos: 0 }
+        Self::with_offset(input, 0)
+    }
+
+    // `with_offset` for picking up partway through a trace, with the version
+    // from its header, which decides how `I` lines read.
+    pub fn resume(input: &'a [u8], pos: usize) -> Self {
+        let parser = Self::with_offset(input, pos);
+        match super::header_version(input) {
+            Some(v) => parser.with_version(v),
+            None => parser,
+        }
+    }
+
+    pub fn with_offset(input: &'a [u8], pos: usize) -> Self {
+        Self {
+            input,
+            pos,
+            version: None,
+            tolerance: Tolerance::default(),
+            extensions: false,
+            newline: Newline::Auto,
+            watchdog: CycleWatchdog::default(),
+            ending: None,
+            mixed: false,
+            clock: Clock::new(),
+            warnings: Vec::new(),
+            on_warning: None,
+            metrics: ParseMetrics::default(),
+            skipped: Vec::new(),
+        }
+    }
+
+    // Strict parsing fails with `TrailingGarbage` on anything but whitespace
+    // after a line's last field, rather than reading it as the next command.
+    // Recovering warns instead of failing on unknown kind digits and
+    // overlong texts, and skips lines that still don't parse.
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    // `tolerance(Tolerance::Recover)`, under its old name, which isn't what
+    // `Tolerance::Lenient` does.
+    #[deprecated(note = "selects `Tolerance::Recover`; use `tolerance` to pick one")]
+    pub fn lenient(self) -> Self {
+        self.tolerance(Tolerance::Recover)
+    }
+
+    pub fn strict(self) -> Self {
+        self.tolerance(Tolerance::Strict)
+    }
+
+    pub fn get_tolerance(&self) -> Tolerance {
+        self.tolerance
+    }
+
+    pub fn newlines(mut self, newline: Newline) -> Self {
+        self.newline = newline;
+        self
+    }
+
+    pub fn watch_cycles(mut self, watchdog: CycleWatchdog) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    // Accept records this crate adds on top of Kanata, like `P` stage
+    // colors. Without it they are unknown lines.
+    pub fn extensions(mut self) -> Self {
+        self.extensions = true;
+        self
+    }
+
+    // Without a callback, warnings are kept for `take_warnings`.
+    pub fn on_warning(mut self, f: impl FnMut(Warning) + 'a) -> Self {
+        self.on_warning = Some(Box::new(f));
+        self
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn metrics(&self) -> ParseMetrics {
+        self.metrics
+    }
+
+    // The byte ranges of the lines lenient parsing skipped, line break
+    // included, in input order.
+    pub fn skipped_ranges(&self) -> &[Range<usize>] {
+        &self.skipped
+    }
+
+    pub(super) fn skip_line(&mut self, range: Range<usize>) {
+        self.metrics.skipped += 1;
+        self.skipped.push(range);
+    }
+
+    pub(super) fn metrics_mut(&mut self) -> &mut ParseMetrics {
+        &mut self.metrics
+    }
+
+    pub(super) fn recovers(&self) -> bool {
+        self.tolerance == Tolerance::Recover
+    }
+
+    pub(super) fn is_strict(&self) -> bool {
+        self.tolerance == Tolerance::Strict
+    }
+
+    pub(super) fn newline(&self) -> Newline {
+        self.newline
+    }
+
+    pub(super) fn watchdog(&self) -> CycleWatchdog {
+        self.watchdog
+    }
+
+    pub(super) fn saw_ending(&mut self, offset: usize, ending: &'static [u8]) {
+        match self.ending {
+            None => self.ending = Some(ending),
+            Some(first) if first != ending && !self.mixed => {
+                self.mixed = true;
+                self.warn(offset, WarningKind::MixedLineEndings);
+            }
+            Some(_) => {}
+        }
+    }
+
+    pub(super) fn has_extensions(&self) -> bool {
+        self.extensions
+    }
+
+    // For parsing from an offset partway through, where the cycle is known.
+    pub fn at_cycle(mut self, cycle: i64) -> Self {
+        self.clock = Clock::at(cycle);
+        self
+    }
+
+    // The cycle as of the last command parsed.
+    // Not `cycle`, which would be shadowed by `Iterator::cycle` on `&mut`.
+    pub fn current_cycle(&self) -> i64 {
+        self.clock.cycle()
+    }
+
+    pub(super) fn clock(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
+    pub(super) fn warn(&mut self, offset: usize, kind: WarningKind) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(offset, code = kind.code(), "{}", kind.message());
+        if !matches!(
+            kind,
+            WarningKind::CycleWentBack | WarningKind::CycleJumped | WarningKind::MixedLineEndings
+        ) {
+            self.metrics.recovered += 1;
+        }
+        let w = Warning { offset, kind };
+        match &mut self.on_warning {
+            Some(f) => f(w),
+            None => self.warnings.push(w),
+        }
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    pub(super) fn set_version(&mut self, version: u32) {
+        self.version = Some(version);
+    }
+
+    pub(super) fn seek(&mut self, pos: usize) {
+        self.pos = pos;
     }
 
     pub(super) fn advance(&mut self, n: usize) {
         self.pos += n;
     }
 
-    pub(super) fn get_offset(&self) -> usize {
+    pub fn get_offset(&self) -> usize {
         self.pos
     }
 
+    pub fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    // The same settings and state over more input, starting at its start.
+    // Warnings stay behind, and so does the callback, which can't outlive
+    // this parser.
+    pub(super) fn continue_on<'b>(&self, input: &'b [u8]) -> Parser<'b> {
+        Parser {
+            clock: self.clock,
+            version: self.version,
+            tolerance: self.tolerance,
+            extensions: self.extensions,
+            newline: self.newline,
+            watchdog: self.watchdog,
+            ending: self.ending,
+            mixed: self.mixed,
+            metrics: self.metrics,
+            ..Parser::new(input)
+        }
+    }
+
     pub(super) fn rest(&self) -> &'a [u8] {
         &self.input[self.get_offset()..]
     }
@@ -0,0 +1,174 @@
+use super::{Newline, ParseError, ParseMetrics, Parser, Warning};
+use crate::Command;
+use memchr::{memchr, memchr2, memrchr, memrchr2};
+
+// A parser fed the input a buffer at a time, as it arrives. Only whole lines
+// are parsed; the unfinished line at the end of a buffer is carried over and
+// completed by the next, wherever the split falls, including between the
+// `\r` and `\n` of a line ending. Offsets count from the start of the stream.
+// Don't feed it more after an error.
+pub struct PushParser {
+    state: Parser<'static>,
+    carry: Vec<u8>,
+    // where the carry starts in the stream
+    offset: usize,
+    warnings: Vec<Warning>,
+}
+
+impl PushParser {
+    // Takes its settings from `parser`, which is best made over empty input:
+    // `Parser::new(b"").extensions()`.
+    pub fn new(parser: Parser<'_>) -> Self {
+        Self {
+            state: parser.continue_on(b""),
+            carry: Vec::new(),
+            offset: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    // The bytes held back waiting for the rest of their line.
+    pub fn carry_len(&self) -> usize {
+        self.carry.len()
+    }
+
+    pub fn metrics(&self) -> ParseMetrics {
+        self.state.metrics()
+    }
+
+    pub fn version(&self) -> Option<u32> {
+        self.state.version()
+    }
+
+    pub fn current_cycle(&self) -> i64 {
+        self.state.current_cycle()
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn cr_ends_lines(&self) -> bool {
+        self.state.newline() == Newline::Auto
+    }
+
+    // How many bytes of `buf` make up whole lines. A `\r` at the very end
+    // doesn't count until it's known not to start a `\r\n`.
+    fn whole_lines(&self, buf: &[u8]) -> usize {
+        let end = if self.cr_ends_lines() {
+            memrchr2(b'\n', b'\r', buf)
+        } else {
+            memrchr(b'\n', buf)
+        };
+        match end {
+            Some(i) if buf[i] == b'\r' && i + 1 == buf.len() => self.whole_lines(&buf[..i]),
+            Some(i) => i + 1,
+            None => 0,
+        }
+    }
+
+    // How much of `chunk` finishes the line in the carry, if it does.
+    fn rest_of_line(&self, chunk: &[u8]) -> Option<usize> {
+        if self.cr_ends_lines() && self.carry.last() == Some(&b'\r') {
+            return Some((chunk[0] == b'\n') as usize);
+        }
+        let i = if self.cr_ends_lines() {
+            memchr2(b'\n', b'\r', chunk)?
+        } else {
+            memchr(b'\n', chunk)?
+        };
+        if chunk[i] == b'\n' {
+            return Some(i + 1);
+        }
+        chunk.get(i + 1).map(|&b| i + 1 + (b == b'\n') as usize)
+    }
+
+    fn parse(
+        &mut self,
+        lines: &[u8],
+        f: &mut impl FnMut(&[u8], usize, Command) -> Result<(), ParseError>,
+    ) -> Result<(), ParseError> {
+        let mut parser = self.state.continue_on(lines);
+        let base = self.offset;
+        let mut result = Ok(());
+        for (offset, cmd) in parser.by_ref() {
+            let done = match cmd {
+                Ok(cmd) => f(lines, base + offset, cmd),
+                Err(e) => Err(ParseError {
+                    offset: base + e.offset,
+                    ..e
+                }),
+            };
+            if let Err(e) = done {
+                result = Err(e);
+                break;
+            }
+        }
+        self.warnings
+            .extend(parser.take_warnings().into_iter().map(|w| Warning {
+                offset: base + w.offset,
+                ..w
+            }));
+        self.state = parser.continue_on(b"");
+        self.offset += lines.len();
+        result
+    }
+
+    // Parses every line `chunk` completes, handing each command to `f`.
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+        mut f: impl FnMut(usize, Command<&[u8]>),
+    ) -> Result<(), ParseError> {
+        self.feed_refs(chunk, |lines, offset, cmd| {
+            f(offset, cmd.map_text(|s| s.get(lines)));
+            Ok(())
+        })
+    }
+
+    // `feed` with the commands' texts left as references into the lines
+    // passed along with them, stopping at the first error `f` returns.
+    pub(crate) fn feed_refs(
+        &mut self,
+        chunk: &[u8],
+        mut f: impl FnMut(&[u8], usize, Command) -> Result<(), ParseError>,
+    ) -> Result<(), ParseError> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        let mut rest = chunk;
+        if !self.carry.is_empty() {
+            let Some(n) = self.rest_of_line(chunk) else {
+                self.carry.extend_from_slice(chunk);
+                return Ok(());
+            };
+            let mut line = std::mem::take(&mut self.carry);
+            line.extend_from_slice(&chunk[..n]);
+            self.parse(&line, &mut f)?;
+            // keep the allocation for the next carry
+            line.clear();
+            self.carry = line;
+            rest = &chunk[n..];
+        }
+        let n = self.whole_lines(rest);
+        self.parse(&rest[..n], &mut f)?;
+        self.carry.extend_from_slice(&rest[n..]);
+        Ok(())
+    }
+
+    // Parses what's left in the carry as the last line of the input.
+    pub fn finish(&mut self, mut f: impl FnMut(usize, Command<&[u8]>)) -> Result<(), ParseError> {
+        self.finish_refs(|lines, offset, cmd| {
+            f(offset, cmd.map_text(|s| s.get(lines)));
+            Ok(())
+        })
+    }
+
+    pub(crate) fn finish_refs(
+        &mut self,
+        mut f: impl FnMut(&[u8], usize, Command) -> Result<(), ParseError>,
+    ) -> Result<(), ParseError> {
+        let line = std::mem::take(&mut self.carry);
+        self.parse(&line, &mut f)
+    }
+}
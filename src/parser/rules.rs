@@ -1,5 +1,7 @@
-use super::{ParseError, ParseErrorKind, Parser};
-use crate::{Command, DepKind, LogKind, RetireKind, StrRef};
+use super::{
+    KANATA_VERSION, MIN_KANATA_VERSION, Newline, ParseError, ParseErrorKind, Parser, WarningKind,
+};
+use crate::{Command, DepKind, Id, LogKind, RetireKind, StrRef};
 use memchr::memchr2;
 use std::convert::TryFrom;
 
@@ -10,13 +12,50 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn lineend(&mut self) {
-        if let Some(b'\r' | b'\n') = self.current() {
-            self.bump();
-            if let Some(b'\n') = self.current() {
-                self.bump();
-            }
+    // The rest of a line after its last field, which may only be whitespace
+    // when strict.
+    fn end_line(&mut self) -> Result<(), ParseError> {
+        self.spaces();
+        if self.is_strict() && !matches!(self.current(), None | Some(b'\r' | b'\n')) {
+            return Err(self.error(ParseErrorKind::TrailingGarbage));
+        }
+        self.lineend()?;
+        Ok(())
+    }
+
+    // One line ending, or none at the end of the input.
+    fn lineend(&mut self) -> Result<(), ParseError> {
+        let ending: &'static [u8] = match self.rest() {
+            [b'\r', b'\n', ..] => b"\r\n",
+            [b'\n', ..] => b"\n",
+            [b'\r', ..] => b"\r",
+            _ => return Ok(()),
+        };
+        let ok = match self.newline() {
+            Newline::Auto => true,
+            Newline::Lf => ending == b"\n",
+            Newline::CrLf => ending == b"\r\n",
+        };
+        if !ok {
+            return Err(self.error(ParseErrorKind::UnexpectedCharacter));
         }
+        let offset = self.get_offset();
+        self.advance(ending.len());
+        self.saw_ending(offset, ending);
+        Ok(())
+    }
+
+    // Skips an empty line, counting it like any other.
+    pub(super) fn blank_line(&mut self) -> bool {
+        let offset = self.get_offset();
+        if self.lineend().is_err() {
+            return false;
+        }
+        let consumed = self.get_offset() - offset;
+        let m = self.metrics_mut();
+        m.bytes += consumed as u64;
+        m.lines += 1;
+        true
     }
 
     fn expect(&mut self, expected: u8) -> Result<(), ParseError> {
@@ -56,23 +95,27 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // Errors with `ValueTooBig` at the start of the field, after taking all
+    // of its digits, rather than wrapping.
     fn parse_u64(&mut self) -> Result<u64, ParseError> {
-        let mut v = 0u64;
+        let start = self.get_offset();
         let r = self.rest();
-        let mut i = 0;
-        while i < r.len() && r[i].is_ascii_digit() {
-            v = v * 10 + (r[i] - b'0') as u64;
-            i += 1;
-        }
-        if i > 0 {
-            self.advance(i);
-            Ok(v)
-        } else {
-            Err(self.error(ParseErrorKind::ExpectedValue))
+        let len = r.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 {
+            return Err(self.error(ParseErrorKind::ExpectedValue));
         }
+        let v = r[..len].iter().try_fold(0u64, |v, &c| {
+            v.checked_mul(10)?.checked_add((c - b'0') as u64)
+        });
+        self.advance(len);
+        v.ok_or(ParseError {
+            offset: start,
+            kind: ParseErrorKind::ValueTooBig,
+        })
     }
 
     fn parse_i32(&mut self) -> Result<i32, ParseError> {
+        let start = self.get_offset();
         if let Some(c) = self.current() {
             let mut neg = false;
             if c == b'-' {
@@ -81,17 +124,37 @@ impl<'a> Parser<'a> {
             } else if c == b'+' {
                 self.bump();
             }
-            let num = i32::try_from(self.parse_u64()?)
-                .map_err(|_| self.error(ParseErrorKind::ValueTooBig))?;
-            if neg { Ok(-num) } else { Ok(num) }
+            let too_big = ParseError {
+                offset: start,
+                kind: ParseErrorKind::ValueTooBig,
+            };
+            let num = self.parse_u64().map_err(|e| match e.kind {
+                ParseErrorKind::ValueTooBig => too_big,
+                _ => e,
+            })?;
+            let v = i64::try_from(num).map_err(|_| too_big)?;
+            i32::try_from(if neg { -v } else { v }).map_err(|_| too_big)
         } else {
             Err(self.error(ParseErrorKind::UnexpectedEof))
         }
     }
 
     fn parse_u32(&mut self) -> Result<u32, ParseError> {
+        let start = self.get_offset();
         let v = self.parse_u64()?;
-        u32::try_from(v).map_err(|_| self.error(ParseErrorKind::ValueTooBig))
+        u32::try_from(v).map_err(|_| ParseError {
+            offset: start,
+            kind: ParseErrorKind::ValueTooBig,
+        })
+    }
+
+    fn parse_id(&mut self) -> Result<Id, ParseError> {
+        let start = self.get_offset();
+        let v = self.parse_u64()?;
+        Id::try_from(v).map_err(|_| ParseError {
+            offset: start,
+            kind: ParseErrorKind::ValueTooBig,
+        })
     }
 
     fn text(&mut self) -> Result<StrRef, ParseError> {
@@ -107,21 +170,54 @@ impl<'a> Parser<'a> {
             return Err(self.error(ParseErrorKind::ExpectedText));
         }
 
-        let text_len = u16::try_from(len).map_err(|_| self.error(ParseErrorKind::TextTooLong))?;
+        let text_len = match u16::try_from(len) {
+            Ok(n) => n,
+            Err(_) if self.recovers() => {
+                self.warn(start, WarningKind::TruncatedText);
+                u16::MAX
+            }
+            Err(_) => return Err(self.error(ParseErrorKind::TextTooLong)),
+        };
         self.advance(len);
 
         Ok(StrRef::new(start as u64, text_len))
     }
 
+    // Unknown kind digits fall back to `default` with a warning when
+    // recovering.
+    fn kind<K: TryFrom<u8, Error = ParseErrorKind>>(
+        &mut self,
+        default: K,
+    ) -> Result<K, ParseError> {
+        let offset = self.get_offset();
+        match K::try_from(self.single_digit()?) {
+            Ok(kind) => Ok(kind),
+            Err(_) if self.recovers() => {
+                self.warn(offset, WarningKind::UnknownKind);
+                Ok(default)
+            }
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
     pub(super) fn parse_header(&mut self) -> Result<Command, ParseError> {
         let kanata = b"Kanata\t";
         if !self.rest().starts_with(kanata) {
             return Err(self.error(ParseErrorKind::InvalidHeader));
         }
         self.advance(kanata.len());
+        let start = self.get_offset();
         let version = self.parse_u32()?; // version
-        self.spaces();
-        self.lineend();
+        if !(MIN_KANATA_VERSION..=KANATA_VERSION).contains(&version) {
+            return Err(ParseError {
+                offset: start,
+                kind: ParseErrorKind::UnsupportedVersion,
+            });
+        }
+        self.set_version(version);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(version, offset = start, "kanata header");
+        self.end_line()?;
         Ok(Command::Kanata { version })
     }
 
@@ -130,21 +226,26 @@ impl<'a> Parser<'a> {
         let abs = self.eat(b'=');
         self.tab()?;
         let value = self.parse_i32()?;
-        self.spaces();
-        self.lineend();
+        self.end_line()?;
         Ok(Command::Cycle { abs, value })
     }
 
     pub(super) fn parse_i(&mut self) -> Result<Command, ParseError> {
         self.bump(); // I
         self.tab()?;
-        let id_file = self.parse_u32()?;
+        let id_file = self.parse_id()?;
         self.tab()?;
-        let id_sim = self.parse_u32()?;
-        self.tab()?;
-        let thread = self.parse_u32()?;
-        self.spaces();
-        self.lineend();
+        let id_sim = self.parse_id()?;
+        // 0003 has no thread column; everything runs on thread 0
+        let thread = if self.version() != Some(3) {
+            self.tab()?;
+            self.parse_u32()?
+        } else if self.eat(b'\t') {
+            self.parse_u32()?
+        } else {
+            0
+        };
+        self.end_line()?;
         Ok(Command::Instruction {
             id_in_file: id_file,
             id_in_sim: id_sim,
@@ -155,24 +256,24 @@ impl<'a> Parser<'a> {
     pub(super) fn parse_l(&mut self) -> Result<Command, ParseError> {
         self.bump(); // L
         self.tab()?;
-        let id = self.parse_u32()?;
+        let id = self.parse_id()?;
         self.tab()?;
-        let kind = LogKind::try_from(self.single_digit()?).map_err(|e| self.error(e))?;
+        let kind = self.kind(LogKind::Other)?;
         self.tab()?;
         let text = self.text()?;
-        self.lineend();
+        self.lineend()?;
         Ok(Command::Log { id, kind, text })
     }
 
     pub(super) fn parse_pipeline(&mut self, start: bool) -> Result<Command, ParseError> {
         self.bump(); // S or E
         self.tab()?;
-        let id = self.parse_u32()?;
+        let id = self.parse_id()?;
         self.tab()?;
         let lane = self.parse_u32()?;
         self.tab()?;
         let name = self.text()?;
-        self.lineend();
+        self.lineend()?;
         Ok(Command::Pipeline {
             start,
             id,
@@ -184,30 +285,62 @@ impl<'a> Parser<'a> {
     pub(super) fn parse_r(&mut self) -> Result<Command, ParseError> {
         self.bump(); // R
         self.tab()?;
-        let id = self.parse_u32()?;
+        let id = self.parse_id()?;
         self.tab()?;
-        let retire = self.parse_u32()?;
+        let retire = self.parse_id()?;
         self.tab()?;
-        let kind = RetireKind::try_from(self.single_digit()?).map_err(|e| self.error(e))?;
-        self.spaces();
-        self.lineend();
+        let kind = self.kind(RetireKind::Retire)?;
+        self.end_line()?;
         Ok(Command::Retire { id, retire, kind })
     }
 
     pub(super) fn parse_w(&mut self) -> Result<Command, ParseError> {
         self.bump(); // W
         self.tab()?;
-        let c = self.parse_u32()?;
+        let c = self.parse_id()?;
         self.tab()?;
-        let p = self.parse_u32()?;
+        let p = self.parse_id()?;
         self.tab()?;
-        let kind = DepKind::try_from(self.single_digit()?).map_err(|e| self.error(e))?;
-        self.spaces();
-        self.lineend();
+        let kind = self.kind(DepKind::WakeUp)?;
+        // a label is an extension, after a tab of its own
+        let label = if self.has_extensions() && self.eat(b'\t') {
+            self.spaces();
+            match self.current() {
+                None | Some(b'\r' | b'\n') => None,
+                Some(_) => Some(self.text()?),
+            }
+        } else {
+            None
+        };
+        if label.is_some() {
+            self.lineend()?;
+        } else {
+            self.end_line()?;
+        }
         Ok(Command::Dep {
             consumer_id: c,
             producer_id: p,
             kind,
+            label,
         })
     }
+
+    // P <rrggbb> <stage name>, with an optional `#` before the color
+    pub(super) fn parse_p(&mut self) -> Result<Command, ParseError> {
+        self.bump(); // P
+        self.tab()?;
+        self.eat(b'#');
+        let r = self.rest();
+        let digits = r.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        if digits != 6 {
+            return Err(self.error(ParseErrorKind::ExpectedValue));
+        }
+        let hex = std::str::from_utf8(&r[..6]).unwrap();
+        let color = u32::from_str_radix(hex, 16).unwrap();
+        self.advance(6);
+        self.tab()?;
+        let name = self.text()?;
+        self.lineend()?;
+        Ok(Command::StageColor { name, color })
+    }
 }
@@ -0,0 +1,143 @@
+use crate::{CommandSource, ParseError, Stamped};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+
+// Parses on the calling thread while consumers analyze on their own, each
+// fed the same batches of commands through a bounded queue. Parsing waits
+// whenever a consumer falls `depth` batches behind.
+#[derive(Copy, Clone, Debug)]
+pub struct Pipelined {
+    batch: usize,
+    depth: usize,
+}
+
+impl Default for Pipelined {
+    fn default() -> Self {
+        // big enough that the queue isn't the cost, small enough to stay in cache
+        Self {
+            batch: 4096,
+            depth: 4,
+        }
+    }
+}
+
+impl Pipelined {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn batch(mut self, commands: usize) -> Self {
+        self.batch = commands.max(1);
+        self
+    }
+
+    pub fn depth(mut self, batches: usize) -> Self {
+        self.depth = batches.max(1);
+        self
+    }
+
+    // Runs each consumer on the commands of `source` and returns what they
+    // return, in order. A parse error ends every consumer's stream where it
+    // happened and is returned once they are done.
+    pub fn run<'a, S, F, R>(&self, mut source: S, consumers: Vec<F>) -> Result<Vec<R>, ParseError>
+    where
+        S: CommandSource<'a>,
+        F: FnOnce(Batches<'a>) -> R + Send,
+        R: Send,
+    {
+        let input = source.input();
+        std::thread::scope(|scope| {
+            let mut senders: Vec<Option<SyncSender<Arc<[Stamped]>>>> = Vec::new();
+            let mut handles = Vec::new();
+            for consumer in consumers {
+                let (tx, rx) = sync_channel(self.depth);
+                senders.push(Some(tx));
+                let batches = Batches {
+                    input,
+                    rx,
+                    batch: Arc::new([]),
+                    pos: 0,
+                };
+                handles.push(scope.spawn(move || consumer(batches)));
+            }
+
+            let mut send = |batch: Vec<Stamped>| {
+                let batch: Arc<[Stamped]> = batch.into();
+                for tx in &mut senders {
+                    // a consumer that stopped listening gets nothing more
+                    if tx.as_ref().is_some_and(|t| t.send(batch.clone()).is_err()) {
+                        *tx = None;
+                    }
+                }
+                senders.iter().any(Option::is_some)
+            };
+            let mut batch = Vec::with_capacity(self.batch);
+            let mut error = None;
+            while let Some(cmd) = source.next_command() {
+                match cmd {
+                    Ok(cmd) => batch.push(cmd),
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+                if batch.len() == self.batch
+                    && !send(std::mem::replace(
+                        &mut batch,
+                        Vec::with_capacity(self.batch),
+                    ))
+                {
+                    break;
+                }
+            }
+            if !batch.is_empty() {
+                send(batch);
+            }
+            drop(senders);
+
+            let results = handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|p| std::panic::resume_unwind(p)))
+                .collect();
+            error.map_or(Ok(results), Err)
+        })
+    }
+}
+
+// One consumer's end of the queue: the batches in order, or the commands in
+// them as a `CommandSource` over the same input.
+pub struct Batches<'a> {
+    input: &'a [u8],
+    rx: Receiver<Arc<[Stamped]>>,
+    batch: Arc<[Stamped]>,
+    pos: usize,
+}
+
+impl Iterator for Batches<'_> {
+    type Item = Arc<[Stamped]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos < self.batch.len() {
+            // the rest of one partly read as commands
+            let rest = self.batch[self.pos..].into();
+            self.pos = self.batch.len();
+            return Some(rest);
+        }
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a> CommandSource<'a> for Batches<'a> {
+    fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        while self.pos == self.batch.len() {
+            self.batch = self.rx.recv().ok()?;
+            self.pos = 0;
+        }
+        self.pos += 1;
+        Some(Ok(self.batch[self.pos - 1]))
+    }
+}
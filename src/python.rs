@@ -0,0 +1,400 @@
+use crate::{Command, Id, InstructionRecord, Parser, SortBy, Trace};
+use numpy::PyArray1;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Arc;
+
+fn value_error(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn lossy(s: &[u8]) -> String {
+    String::from_utf8_lossy(s).into_owned()
+}
+
+#[pyclass(name = "Header", get_all, frozen)]
+struct PyHeader {
+    offset: usize,
+    version: u32,
+}
+
+#[pyclass(name = "Cycle", get_all, frozen)]
+struct PyCycle {
+    offset: usize,
+    cycle: i64,
+    abs: bool,
+    value: i32,
+}
+
+#[pyclass(name = "Instruction", get_all, frozen)]
+struct PyInstruction {
+    offset: usize,
+    cycle: i64,
+    id: Id,
+    sim_id: Id,
+    thread_id: u32,
+}
+
+#[pyclass(name = "Log", get_all, frozen)]
+struct PyLog {
+    offset: usize,
+    cycle: i64,
+    id: Id,
+    kind: &'static str,
+    text: String,
+}
+
+#[pyclass(name = "Stage", get_all, frozen)]
+struct PyStage {
+    offset: usize,
+    cycle: i64,
+    start: bool,
+    id: Id,
+    lane: u32,
+    name: String,
+}
+
+#[pyclass(name = "Retire", get_all, frozen)]
+struct PyRetire {
+    offset: usize,
+    cycle: i64,
+    id: Id,
+    retire_id: Id,
+    kind: &'static str,
+}
+
+#[pyclass(name = "Dep", get_all, frozen)]
+struct PyDep {
+    offset: usize,
+    cycle: i64,
+    consumer_id: Id,
+    producer_id: Id,
+    kind: &'static str,
+    label: Option<String>,
+}
+
+#[pyclass(name = "StageColor", get_all, frozen)]
+struct PyStageColor {
+    offset: usize,
+    cycle: i64,
+    name: String,
+    color: u32,
+}
+
+fn command_object(
+    py: Python<'_>,
+    input: &[u8],
+    offset: usize,
+    cycle: i64,
+    cmd: Command,
+) -> PyResult<Py<PyAny>> {
+    let obj = match cmd {
+        Command::Kanata { version } => Py::new(py, PyHeader { offset, version })?.into_any(),
+        Command::Cycle { abs, value } => Py::new(
+            py,
+            PyCycle {
+                offset,
+                cycle,
+                abs,
+                value,
+            },
+        )?
+        .into_any(),
+        Command::Instruction {
+            id_in_file,
+            id_in_sim,
+            thread_id,
+        } => Py::new(
+            py,
+            PyInstruction {
+                offset,
+                cycle,
+                id: id_in_file,
+                sim_id: id_in_sim,
+                thread_id,
+            },
+        )?
+        .into_any(),
+        Command::Log { id, kind, text } => Py::new(
+            py,
+            PyLog {
+                offset,
+                cycle,
+                id,
+                kind: kind.name(),
+                text: lossy(text.get(input)),
+            },
+        )?
+        .into_any(),
+        Command::Pipeline {
+            start,
+            id,
+            lane_id,
+            name,
+        } => Py::new(
+            py,
+            PyStage {
+                offset,
+                cycle,
+                start,
+                id,
+                lane: lane_id,
+                name: lossy(name.get(input).trim_ascii()),
+            },
+        )?
+        .into_any(),
+        Command::Retire { id, retire, kind } => Py::new(
+            py,
+            PyRetire {
+                offset,
+                cycle,
+                id,
+                retire_id: retire,
+                kind: kind.name(),
+            },
+        )?
+        .into_any(),
+        Command::Dep {
+            consumer_id,
+            producer_id,
+            kind,
+            label,
+        } => Py::new(
+            py,
+            PyDep {
+                offset,
+                cycle,
+                consumer_id,
+                producer_id,
+                kind: kind.name(),
+                label: label.map(|l| lossy(l.get(input))),
+            },
+        )?
+        .into_any(),
+        Command::StageColor { name, color } => Py::new(
+            py,
+            PyStageColor {
+                offset,
+                cycle,
+                name: lossy(name.get(input).trim_ascii()),
+                color,
+            },
+        )?
+        .into_any(),
+    };
+    Ok(obj)
+}
+
+// One parser for the iterator's life, so the version and line endings
+// carry from one command to the next. It reads `input`, which it is
+// declared before so that it is dropped first. Unsendable, as a parser
+// may hold a warning callback that isn't `Send`.
+#[pyclass(name = "Commands", unsendable)]
+pub(crate) struct PyCommands {
+    parser: Parser<'static>,
+    input: Arc<[u8]>,
+}
+
+impl PyCommands {
+    pub(crate) fn new(input: Vec<u8>) -> Self {
+        let input: Arc<[u8]> = input.into();
+        // the bytes stay put for as long as the `Arc` lives
+        let bytes: &'static [u8] = unsafe { &*Arc::as_ptr(&input) };
+        Self {
+            parser: Parser::new(bytes).extensions(),
+            input,
+        }
+    }
+}
+
+#[pymethods]
+impl PyCommands {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Py<PyAny>>> {
+        let Some((offset, cmd)) = self.parser.next() else {
+            return Ok(None);
+        };
+        let cmd = cmd.map_err(value_error)?;
+        let cycle = self.parser.current_cycle();
+        command_object(py, &self.input, offset, cycle, cmd).map(Some)
+    }
+}
+
+#[pyclass(name = "InstructionRecord", get_all, frozen)]
+struct PyRecord {
+    id: Id,
+    sim_id: Id,
+    thread_id: u32,
+    start: i64,
+    end: Option<i64>,
+    retire_kind: Option<&'static str>,
+    label: String,
+    detail: String,
+    extra: String,
+    stages: Vec<(String, u32, i64, i64)>,
+}
+
+impl PyRecord {
+    fn new(trace: &Trace, rec: &InstructionRecord) -> Self {
+        Self {
+            id: rec.id,
+            sim_id: rec.sim_id,
+            thread_id: rec.thread_id,
+            start: rec.start,
+            end: rec.end,
+            retire_kind: rec.retire_kind.map(|k| k.name()),
+            label: lossy(&trace.label(rec)),
+            detail: lossy(&trace.detail(rec)),
+            extra: lossy(&trace.extra(rec)),
+            stages: rec
+                .stages
+                .iter()
+                .map(|s| {
+                    let name = trace.stages().name(s.stage).to_string();
+                    (name, s.lane, s.start, s.end)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[pyclass(name = "Trace", frozen)]
+struct PyTrace {
+    inner: Trace<'static>,
+}
+
+#[pymethods]
+impl PyTrace {
+    fn __len__(&self) -> usize {
+        self.inner.instructions().len()
+    }
+
+    fn stages(&self) -> Vec<String> {
+        self.inner
+            .stages()
+            .iter()
+            .map(|(_, n)| n.to_string())
+            .collect()
+    }
+
+    fn instructions(&self) -> Vec<PyRecord> {
+        self.inner
+            .instructions()
+            .iter()
+            .map(|r| PyRecord::new(&self.inner, r))
+            .collect()
+    }
+
+    fn get(&self, id: Id) -> Option<PyRecord> {
+        self.inner.get(id).map(|r| PyRecord::new(&self.inner, r))
+    }
+
+    #[pyo3(signature = (n, by = "total", stage = None))]
+    fn top_n(&self, n: usize, by: &str, stage: Option<&str>) -> PyResult<Vec<(PyRecord, u64)>> {
+        let by = match by {
+            "total" => SortBy::TotalLatency,
+            "wakeup" => SortBy::WakeupDelay,
+            "stage" => {
+                let name = stage.ok_or_else(|| value_error("stage name required"))?;
+                let id = self
+                    .inner
+                    .stages()
+                    .get(name)
+                    .ok_or_else(|| value_error(format!("unknown stage {}", name)))?;
+                SortBy::StageLatency(id)
+            }
+            other => return Err(value_error(format!("unknown sort key {}", other))),
+        };
+        Ok(self
+            .inner
+            .top_n(n, by)
+            .into_iter()
+            .map(|r| (PyRecord::new(&self.inner, r.record), r.value))
+            .collect())
+    }
+
+    fn columns<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let recs = self.inner.instructions();
+        let col =
+            |f: &dyn Fn(&InstructionRecord) -> i64| -> Vec<i64> { recs.iter().map(f).collect() };
+        let d = PyDict::new(py);
+        d.set_item("id", PyArray1::from_vec(py, col(&|r| r.id as i64)))?;
+        d.set_item("sim_id", PyArray1::from_vec(py, col(&|r| r.sim_id as i64)))?;
+        d.set_item(
+            "thread_id",
+            PyArray1::from_vec(py, col(&|r| r.thread_id as i64)),
+        )?;
+        d.set_item("start", PyArray1::from_vec(py, col(&|r| r.start)))?;
+        d.set_item(
+            "end",
+            PyArray1::from_vec(py, col(&|r| r.end.unwrap_or(self.inner.end_cycle()))),
+        )?;
+        let flags =
+            |f: &dyn Fn(&InstructionRecord) -> bool| -> Vec<bool> { recs.iter().map(f).collect() };
+        d.set_item(
+            "retired",
+            PyArray1::from_vec(py, flags(&|r| r.is_retired())),
+        )?;
+        d.set_item(
+            "flushed",
+            PyArray1::from_vec(py, flags(&|r| r.is_flushed())),
+        )?;
+        Ok(d)
+    }
+
+    fn stage_columns<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let (mut instr, mut stage, mut lane, mut start, mut end) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (i, rec) in self.inner.instructions().iter().enumerate() {
+            for s in &rec.stages {
+                instr.push(i as i64);
+                stage.push(s.stage.index() as i64);
+                lane.push(s.lane as i64);
+                start.push(s.start);
+                end.push(s.end);
+            }
+        }
+        let d = PyDict::new(py);
+        d.set_item("instruction", PyArray1::from_vec(py, instr))?;
+        d.set_item("stage", PyArray1::from_vec(py, stage))?;
+        d.set_item("lane", PyArray1::from_vec(py, lane))?;
+        d.set_item("start", PyArray1::from_vec(py, start))?;
+        d.set_item("end", PyArray1::from_vec(py, end))?;
+        Ok(d)
+    }
+}
+
+#[pyfunction]
+fn parse(path: &str) -> PyResult<PyCommands> {
+    let input = std::fs::read(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(PyCommands::new(input))
+}
+
+#[pyfunction]
+fn load(path: &str) -> PyResult<PyTrace> {
+    let input = std::fs::read(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    let inner = Trace::from_vec(input).map_err(value_error)?;
+    Ok(PyTrace { inner })
+}
+
+#[pymodule]
+fn kanata(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    m.add_class::<PyCommands>()?;
+    m.add_class::<PyTrace>()?;
+    m.add_class::<PyRecord>()?;
+    m.add_class::<PyHeader>()?;
+    m.add_class::<PyCycle>()?;
+    m.add_class::<PyInstruction>()?;
+    m.add_class::<PyLog>()?;
+    m.add_class::<PyStage>()?;
+    m.add_class::<PyRetire>()?;
+    m.add_class::<PyDep>()?;
+    m.add_class::<PyStageColor>()?;
+    Ok(())
+}
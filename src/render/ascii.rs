@@ -0,0 +1,81 @@
+use crate::{InstructionRecord, Trace};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsciiConfig {
+    pub abbreviations: HashMap<String, char>,
+    pub instructions: Range<usize>,
+    pub label_width: usize,
+}
+
+impl Default for AsciiConfig {
+    fn default() -> Self {
+        Self {
+            abbreviations: HashMap::new(),
+            instructions: 0..16,
+            label_width: 24,
+        }
+    }
+}
+
+pub fn render_ascii(trace: &Trace, config: &AsciiConfig) -> String {
+    let all = trace.instructions();
+    let rows: &[InstructionRecord] =
+        &all[config.instructions.start.min(all.len())..config.instructions.end.min(all.len())];
+    let letters: Vec<char> = trace
+        .stages()
+        .iter()
+        .map(|(_, name)| {
+            config
+                .abbreviations
+                .get(name)
+                .copied()
+                .unwrap_or_else(|| name.chars().next().unwrap_or('?'))
+        })
+        .collect();
+
+    let mut out = String::new();
+    let Some(first) = rows.iter().map(|r| r.start).min() else {
+        return out;
+    };
+    let last = rows
+        .iter()
+        .flat_map(|r| r.stages.iter().map(|s| s.end.max(s.start + 1)))
+        .max()
+        .unwrap_or(first + 1);
+    let cols = (last - first) as usize;
+    let w = config.label_width;
+
+    let mut header = format!("{:<w$} ", format!("cycle {}+", first));
+    for c in 0..cols {
+        let _ = write!(header, "{:<3}", c % 100);
+    }
+    out.push_str(header.trim_end());
+    out.push('\n');
+
+    for rec in rows {
+        let mut label = format!("{} {}", rec.id, String::from_utf8_lossy(&trace.label(rec)));
+        label.truncate(label.floor_char_boundary(w));
+        let mut cells = vec![' '; cols];
+        for s in &rec.stages {
+            let from = (s.start - first) as usize;
+            let to = ((s.end.max(s.start + 1)) - first) as usize;
+            for cell in &mut cells[from..to.min(cols)] {
+                *cell = letters[s.stage.index()];
+            }
+        }
+        let _ = write!(out, "{:<w$} ", label);
+        let mut line = String::with_capacity(cols * 3);
+        for c in cells {
+            let _ = write!(line, "{:<3}", c);
+        }
+        out.push_str(line.trim_end());
+        if rec.is_flushed() {
+            out.push_str("  (flushed)");
+        }
+        out.push('\n');
+    }
+    out
+}
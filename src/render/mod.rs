@@ -0,0 +1,7 @@
+mod ascii;
+pub use ascii::*;
+
+#[cfg(feature = "render")]
+mod svg;
+#[cfg(feature = "render")]
+pub use svg::*;
@@ -0,0 +1,158 @@
+use crate::{InstructionRecord, StageId, StageTable, Trace};
+use std::io::{self, Write};
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SvgConfig {
+    pub cycles: Option<Range<i64>>,
+    pub instructions: Option<Range<usize>>,
+    pub cell_width: u32,
+    pub row_height: u32,
+    pub label_width: u32,
+}
+
+impl Default for SvgConfig {
+    fn default() -> Self {
+        Self {
+            cycles: None,
+            instructions: None,
+            cell_width: 12,
+            row_height: 16,
+            label_width: 240,
+        }
+    }
+}
+
+pub(crate) fn stage_color(stages: &StageTable, stage: StageId) -> String {
+    if let Some(rgb) = stages.color(stage) {
+        return format!("#{:06x}", rgb);
+    }
+    // golden-angle hue spacing keeps neighbouring stage ids distinguishable
+    let hue = (stage.index() as u32 * 137) % 360;
+    format!("hsl({},60%,70%)", hue)
+}
+
+fn escape(s: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c if c.is_control() => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn visible(rec: &InstructionRecord, cycles: &Range<i64>, last: i64) -> bool {
+    rec.start < cycles.end && rec.end.unwrap_or(last) >= cycles.start
+}
+
+pub fn render_svg<W: Write>(trace: &Trace, config: &SvgConfig, out: W) -> io::Result<()> {
+    let all = trace.instructions();
+    let range = config.instructions.clone().unwrap_or(0..all.len());
+    let candidates = &all[range.start.min(all.len())..range.end.min(all.len())];
+    let cycles = config.cycles.clone().unwrap_or_else(|| {
+        let start = candidates.iter().map(|r| r.start).min().unwrap_or(0);
+        let end = candidates
+            .iter()
+            .map(|r| r.end.unwrap_or(trace.end_cycle()))
+            .max()
+            .unwrap_or(start);
+        start..end + 1
+    });
+    let rows: Vec<&InstructionRecord> = candidates
+        .iter()
+        .filter(|r| visible(r, &cycles, trace.end_cycle()))
+        .collect();
+
+    let (cw, rh, lw) = (config.cell_width, config.row_height, config.label_width);
+    let span = (cycles.end - cycles.start).max(0) as u32;
+    let width = lw + span * cw;
+    let height = (rows.len() as u32 + 1) * rh;
+    let x = |c: i64| lw as i64 + (c.clamp(cycles.start, cycles.end) - cycles.start) * cw as i64;
+
+    let mut out = io::BufWriter::new(out);
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"{}\">",
+        width,
+        height,
+        rh * 3 / 4
+    )?;
+    writeln!(
+        out,
+        "<defs><pattern id=\"flush\" width=\"6\" height=\"6\" patternUnits=\"userSpaceOnUse\" patternTransform=\"rotate(45)\"><line x1=\"0\" y1=\"0\" x2=\"0\" y2=\"6\" stroke=\"#444\" stroke-width=\"2\"/></pattern></defs>"
+    )?;
+    writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>")?;
+
+    let step = (50 / cw.max(1)).max(1) as i64;
+    let mut c = cycles.start;
+    while c < cycles.end {
+        writeln!(
+            out,
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"#ddd\"/><text x=\"{0}\" y=\"{3}\">{4}</text>",
+            x(c),
+            rh,
+            height,
+            rh - 4,
+            c
+        )?;
+        c += step;
+    }
+
+    for (row, rec) in rows.iter().enumerate() {
+        let y = (row as u32 + 1) * rh;
+        writeln!(
+            out,
+            "<text x=\"2\" y=\"{}\">{} {}</text>",
+            y + rh - 4,
+            rec.id,
+            escape(&trace.label(rec))
+        )?;
+        for s in &rec.stages {
+            if s.end < cycles.start || s.start >= cycles.end {
+                continue;
+            }
+            let x0 = x(s.start);
+            let w = (x(s.end.max(s.start + 1)) - x0).max(1);
+            let name = trace.stages().name(s.stage);
+            writeln!(
+                out,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"#666\"><title>{} [{}, {})</title></rect>",
+                x0,
+                y + 1,
+                w,
+                rh - 2,
+                stage_color(trace.stages(), s.stage),
+                escape(name.as_bytes()),
+                s.start,
+                s.end
+            )?;
+            if rec.is_flushed() {
+                writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"url(#flush)\" opacity=\"0.5\"/>",
+                    x0,
+                    y + 1,
+                    w,
+                    rh - 2
+                )?;
+            }
+            if w as usize >= name.len() * (rh as usize / 2) {
+                writeln!(
+                    out,
+                    "<text x=\"{}\" y=\"{}\">{}</text>",
+                    x0 + 2,
+                    y + rh - 4,
+                    escape(name.as_bytes())
+                )?;
+            }
+        }
+    }
+    writeln!(out, "</svg>")?;
+    out.flush()
+}
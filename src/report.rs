@@ -0,0 +1,570 @@
+use crate::{
+    Collector, CommandSource, Id, InstructionRecord, LogKind, ParseError, Reconstructor,
+    RunMetadata, StageTable, Stats, Summary, Trace, parse_pc, stream, stream_source,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReportConfig {
+    pub window: u64,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self { window: 1000 }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Window {
+    pub retired: u64,
+    pub flushed: u64,
+    pub stage_cycles: Vec<u64>,
+    // the longest chain of producers behind each instruction ending here
+    pub chain_depth: Summary,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PcStats {
+    pub count: u64,
+    pub retired: u64,
+    pub flushed: u64,
+    pub latency: Summary,
+}
+
+// The shape of the dependency graph. `fan_in` and `fan_out` count the
+// instructions with each number of producers and of consumers. A wakeup
+// is critical when its producer was the last of the consumer's to finish,
+// and finished after the consumer started, so that the consumer can only
+// have been kept waiting on that one. Only producers already recorded are
+// seen, so when records come finished rather than in order, as streaming
+// ones do, an edge to a producer that finishes after its consumer counts
+// towards `fan_in` and `wakeups` alone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DepSummary {
+    pub fan_in: BTreeMap<u32, u64>,
+    pub fan_out: BTreeMap<u32, u64>,
+    pub wakeups: u64,
+    pub critical_wakeups: u64,
+}
+
+impl DepSummary {
+    pub fn critical_fraction(&self) -> f64 {
+        match self.wakeups {
+            0 => 0.0,
+            n => self.critical_wakeups as f64 / n as f64,
+        }
+    }
+}
+
+// Producers ending further back than this behind the latest instruction are
+// taken to have all their consumers, and are counted; a consumer of one
+// after that sees a chain of its own that starts there.
+const DEP_HORIZON: i64 = 1 << 16;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Pending {
+    depth: u32,
+    consumers: u32,
+    end: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Report {
+    config: ReportConfig,
+    stats: Stats,
+    windows: BTreeMap<i64, Window>,
+    per_pc: BTreeMap<u64, PcStats>,
+    metadata: Option<RunMetadata>,
+    deps: DepSummary,
+    // instructions that may yet gain consumers, until they're counted
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pending: HashMap<Id, Pending>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    sweep_at: usize,
+}
+
+impl Report {
+    pub fn new(config: ReportConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn from_trace(trace: &Trace, config: ReportConfig) -> Self {
+        let mut report = Self::new(config);
+        for rec in trace.instructions() {
+            report.record(trace.input(), trace.stages(), rec);
+        }
+        report.stats.set_stages(trace.stages().clone());
+        report.settle(i64::MAX);
+        report
+    }
+
+    pub fn streaming(
+        input: &[u8],
+        max_in_flight: usize,
+        config: ReportConfig,
+    ) -> Result<Self, ParseError> {
+        let mut report = Self::new(config);
+        let stages = stream(input, max_in_flight, &mut report)?;
+        report.stats.set_stages(stages);
+        report.settle(i64::MAX);
+        Ok(report)
+    }
+
+    pub fn from_source<'a, S: CommandSource<'a>>(
+        source: S,
+        max_in_flight: usize,
+        config: ReportConfig,
+    ) -> Result<Self, ParseError> {
+        let mut report = Self::new(config);
+        let rec = Reconstructor::new(source.input()).with_max_in_flight(max_in_flight);
+        let stages = stream_source(source, rec, &mut report)?;
+        report.stats.set_stages(stages);
+        report.settle(i64::MAX);
+        Ok(report)
+    }
+
+    // The run the trace came from, shown with the figures.
+    pub fn with_metadata(mut self, metadata: RunMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn metadata(&self) -> Option<&RunMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn config(&self) -> ReportConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        self.stats.stages()
+    }
+
+    // Instructions recorded one by one may still have consumers to come,
+    // and are counted here as they stand.
+    pub fn deps(&self) -> DepSummary {
+        let mut deps = self.deps.clone();
+        for p in self.pending.values() {
+            *deps.fan_out.entry(p.consumers).or_default() += 1;
+        }
+        deps
+    }
+
+    // Counts the fan-out of the instructions that ended before `before`.
+    fn settle(&mut self, before: i64) {
+        let fan_out = &mut self.deps.fan_out;
+        self.pending.retain(|_, p| {
+            let keep = p.end >= before;
+            if !keep {
+                *fan_out.entry(p.consumers).or_default() += 1;
+            }
+            keep
+        });
+        self.sweep_at = (self.pending.len() * 2).max(1024);
+    }
+
+    pub fn windows(&self) -> impl Iterator<Item = (i64, &Window)> {
+        let w = self.window();
+        self.windows.iter().map(move |(&k, v)| (k * w, v))
+    }
+
+    pub fn per_pc(&self) -> impl Iterator<Item = (u64, &PcStats)> {
+        self.per_pc.iter().map(|(&pc, s)| (pc, s))
+    }
+
+    fn window(&self) -> i64 {
+        self.config.window.max(1) as i64
+    }
+
+    // Where the window from `start` ends: a window's length, but for the
+    // last, which stops after the trace's last cycle.
+    fn window_end(&self, start: i64) -> i64 {
+        let end = start + self.window();
+        self.stats
+            .last_cycle()
+            .map_or(end, |last| end.min(last + 1).max(start + 1))
+    }
+
+    fn bucket(&mut self, key: i64, stages: usize) -> &mut Window {
+        let w = self.windows.entry(key).or_default();
+        w.stage_cycles.resize(stages.max(w.stage_cycles.len()), 0);
+        w
+    }
+
+    pub fn write_csv(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        if let Some(m) = &self.metadata {
+            let mut out = BufWriter::new(File::create(dir.join("metadata.csv"))?);
+            writeln!(out, "key,value")?;
+            let hz = m.clock_hz.map(|hz| hz.to_string());
+            let known = m
+                .labels()
+                .into_iter()
+                .chain(hz.as_deref().map(|hz| ("clock_hz", hz)));
+            for (k, v) in known.chain(m.extra.iter().map(|(k, v)| (k.as_str(), v.as_str()))) {
+                writeln!(out, "{},{}", csv_field(k), csv_field(v))?;
+            }
+            out.flush()?;
+        }
+
+        let mut out = BufWriter::new(File::create(dir.join("stage_stats.csv"))?);
+        writeln!(out, "stage,count,total,mean,min,max,p50,p90,p99")?;
+        for (id, name, s) in self.stats.iter() {
+            let q = |q| self.stats.stage_quantile(id, q).unwrap_or(0);
+            writeln!(
+                out,
+                "{},{},{},{:.3},{},{},{},{},{}",
+                csv_field(name),
+                s.count,
+                s.total,
+                s.mean(),
+                s.min,
+                s.max,
+                q(0.5),
+                q(0.9),
+                q(0.99)
+            )?;
+        }
+        out.flush()?;
+
+        let mut out = BufWriter::new(File::create(dir.join("ipc.csv"))?);
+        writeln!(out, "window_start,window_end,retired,flushed,ipc")?;
+        for (start, w) in self.windows() {
+            let end = self.window_end(start);
+            writeln!(
+                out,
+                "{},{},{},{},{:.4}",
+                start,
+                end,
+                w.retired,
+                w.flushed,
+                w.retired as f64 / (end - start) as f64
+            )?;
+        }
+        out.flush()?;
+
+        let mut out = BufWriter::new(File::create(dir.join("occupancy.csv"))?);
+        writeln!(out, "window_start,stage,occupancy")?;
+        for (start, w) in self.windows() {
+            let span = self.window_end(start) - start;
+            for (id, name) in self.stages().iter() {
+                let cycles = w.stage_cycles.get(id.index()).copied().unwrap_or(0);
+                writeln!(
+                    out,
+                    "{},{},{:.4}",
+                    start,
+                    csv_field(name),
+                    cycles as f64 / span as f64
+                )?;
+            }
+        }
+        out.flush()?;
+
+        let mut out = BufWriter::new(File::create(dir.join("per_pc.csv"))?);
+        writeln!(out, "pc,count,retired,flushed,mean_latency,max_latency")?;
+        for (pc, s) in self.per_pc() {
+            writeln!(
+                out,
+                "0x{:x},{},{},{},{:.3},{}",
+                pc,
+                s.count,
+                s.retired,
+                s.flushed,
+                s.latency.mean(),
+                s.latency.max
+            )?;
+        }
+        out.flush()?;
+
+        let deps = self.deps();
+        let mut out = BufWriter::new(File::create(dir.join("fan.csv"))?);
+        writeln!(out, "direction,edges,instructions")?;
+        for (dir, hist) in [("in", &deps.fan_in), ("out", &deps.fan_out)] {
+            for (n, count) in hist {
+                writeln!(out, "{},{},{}", dir, n, count)?;
+            }
+        }
+        out.flush()?;
+
+        let mut out = BufWriter::new(File::create(dir.join("chain_depth.csv"))?);
+        writeln!(out, "window_start,window_end,mean_depth,max_depth")?;
+        for (start, w) in self.windows() {
+            writeln!(
+                out,
+                "{},{},{:.3},{}",
+                start,
+                self.window_end(start),
+                w.chain_depth.mean(),
+                w.chain_depth.max
+            )?;
+        }
+        out.flush()
+    }
+
+    // The headline figures in the Prometheus text format. `labels`, such as
+    // the trace or benchmark name, go on every sample, followed by the run's
+    // metadata; a label named twice takes its last value from `labels`, or
+    // else the metadata's. Occupancy is a stage's cycles per trace cycle.
+    pub fn to_prometheus_text(&self, labels: &[(&str, &str)]) -> String {
+        let stats = &self.stats;
+        let mut out = String::new();
+        let run = self
+            .metadata
+            .as_ref()
+            .map(|m| m.labels())
+            .unwrap_or_default();
+        let mut common: Vec<(&str, &str)> = Vec::new();
+        for &(k, v) in labels {
+            match common.iter_mut().find(|(name, _)| *name == k) {
+                Some(label) => label.1 = v,
+                None => common.push((k, v)),
+            }
+        }
+        for &(k, v) in &run {
+            if !common.iter().any(|(name, _)| *name == k) {
+                common.push((k, v));
+            }
+        }
+        // a sample's own labels, like `stage`, win over the common ones
+        let sample = |out: &mut String, name: &str, extra: &[(&str, &str)], value: f64| {
+            let all: Vec<String> = common
+                .iter()
+                .filter(|(k, _)| !extra.iter().any(|(name, _)| name == k))
+                .chain(extra)
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            let _ = if all.is_empty() {
+                writeln!(out, "{} {}", name, value)
+            } else {
+                writeln!(out, "{}{{{}}} {}", name, all.join(","), value)
+            };
+        };
+        let head = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        };
+        let gauges = [
+            ("kanata_ipc", "retired instructions per cycle", stats.ipc()),
+            (
+                "kanata_flush_rate",
+                "fraction of instructions flushed",
+                match stats.instructions() {
+                    0 => 0.0,
+                    n => stats.flushed() as f64 / n as f64,
+                },
+            ),
+            (
+                "kanata_instructions",
+                "instructions in the trace",
+                stats.instructions() as f64,
+            ),
+            (
+                "kanata_retired",
+                "instructions retired",
+                stats.retired() as f64,
+            ),
+            (
+                "kanata_flushed",
+                "instructions flushed",
+                stats.flushed() as f64,
+            ),
+            (
+                "kanata_cycles",
+                "cycles from the first instruction to the last",
+                stats.cycles() as f64,
+            ),
+            (
+                "kanata_critical_wakeup_fraction",
+                "fraction of wakeups from the producer a consumer waited on last",
+                self.deps().critical_fraction(),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            head(&mut out, name, "gauge", help);
+            sample(&mut out, name, &[], value);
+        }
+
+        let name = "kanata_stage_latency_cycles";
+        head(
+            &mut out,
+            name,
+            "summary",
+            "cycles an instruction spends in a stage",
+        );
+        for (id, stage, s) in stats.iter() {
+            for q in QUANTILES {
+                let v = stats.stage_quantile(id, q).unwrap_or(0);
+                sample(
+                    &mut out,
+                    name,
+                    &[("stage", stage), ("quantile", &q.to_string())],
+                    v as f64,
+                );
+            }
+            sample(
+                &mut out,
+                &format!("{}_sum", name),
+                &[("stage", stage)],
+                s.total as f64,
+            );
+            sample(
+                &mut out,
+                &format!("{}_count", name),
+                &[("stage", stage)],
+                s.count as f64,
+            );
+        }
+        let name = "kanata_latency_cycles";
+        head(
+            &mut out,
+            name,
+            "summary",
+            "cycles from an instruction's start to its retire",
+        );
+        for q in QUANTILES {
+            let v = stats.latency_quantile(q).unwrap_or(0);
+            sample(&mut out, name, &[("quantile", &q.to_string())], v as f64);
+        }
+        sample(
+            &mut out,
+            &format!("{}_sum", name),
+            &[],
+            stats.latency().total as f64,
+        );
+        sample(
+            &mut out,
+            &format!("{}_count", name),
+            &[],
+            stats.latency().count as f64,
+        );
+
+        let name = "kanata_stage_occupancy";
+        head(
+            &mut out,
+            name,
+            "gauge",
+            "instructions in a stage per cycle, on average",
+        );
+        for (_, stage, s) in stats.iter() {
+            let v = match stats.cycles() {
+                0 => 0.0,
+                c => s.total as f64 / c as f64,
+            };
+            sample(&mut out, name, &[("stage", stage)], v);
+        }
+        out
+    }
+
+    // `to_prometheus_text` as OpenMetrics, which ends with `# EOF`.
+    pub fn to_openmetrics_text(&self, labels: &[(&str, &str)]) -> String {
+        let mut out = self.to_prometheus_text(labels);
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Collector for Report {
+    fn record(&mut self, input: &[u8], stages: &StageTable, rec: &InstructionRecord) {
+        self.stats.record(input, stages, rec);
+        let window = self.window();
+
+        let (mut depth, mut last) = (0, None);
+        for d in &rec.producers {
+            if let Some(p) = self.pending.get_mut(&d.producer_id) {
+                p.consumers += 1;
+                depth = depth.max(p.depth + 1);
+                last = last.max(Some(p.end));
+            } else {
+                depth = depth.max(1);
+            }
+        }
+        let deps = &mut self.deps;
+        *deps.fan_in.entry(rec.producers.len() as u32).or_default() += 1;
+        deps.wakeups += rec.producers.len() as u64;
+        deps.critical_wakeups += last.is_some_and(|end| end > rec.start) as u64;
+        let end = rec.end.unwrap_or(rec.start);
+        self.pending.insert(
+            rec.id,
+            Pending {
+                depth,
+                consumers: 0,
+                end,
+            },
+        );
+        if self.pending.len() >= self.sweep_at {
+            self.settle(end.saturating_sub(DEP_HORIZON));
+        }
+
+        if let Some(end) = rec.end {
+            let w = self.bucket(end.div_euclid(window), stages.len());
+            w.retired += rec.is_retired() as u64;
+            w.flushed += rec.is_flushed() as u64;
+            w.chain_depth.add(depth as u64);
+        }
+
+        for span in &rec.stages {
+            let mut c = span.start;
+            while c < span.end {
+                let key = c.div_euclid(window);
+                let next = ((key + 1) * window).min(span.end);
+                self.bucket(key, stages.len()).stage_cycles[span.stage.index()] +=
+                    (next - c) as u64;
+                c = next;
+            }
+        }
+
+        let label = rec.first_log(LogKind::LeftPane).map(|s| s.get(input));
+        if let Some(pc) = label.and_then(parse_pc) {
+            let s = self.per_pc.entry(pc).or_default();
+            s.count += 1;
+            s.retired += rec.is_retired() as u64;
+            s.flushed += rec.is_flushed() as u64;
+            if let Some(lat) = rec.latency() {
+                s.latency.add(lat);
+            }
+        }
+    }
+}
+
+pub(crate) fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
@@ -0,0 +1,887 @@
+use crate::document::parse_line;
+use crate::generate::Rng;
+use crate::{
+    BINARY_MAGIC, Clock, Command, Commands, CycleCheck, DepKind, Filter, Id, KANATA_VERSION,
+    LogKind, ParseError, Parser, RetireKind, Tolerance, Trace, Writer,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+
+// Writes a well-formed trace from commands picked out of other traces,
+// re-deriving the cycle commands from the cycle each command happened at.
+pub struct CycleWriter<W: Write> {
+    w: Writer<W>,
+    cycle: Option<i64>,
+}
+
+impl<W: Write> CycleWriter<W> {
+    pub fn new(out: W) -> io::Result<Self> {
+        let mut w = Writer::new(out);
+        w.write(&Command::<&[u8]>::Kanata {
+            version: KANATA_VERSION,
+        })?;
+        Ok(Self { w, cycle: None })
+    }
+
+    pub fn cycle(&self) -> Option<i64> {
+        self.cycle
+    }
+
+    pub fn write(&mut self, cycle: i64, cmd: &Command<&[u8]>) -> io::Result<()> {
+        self.advance_to(cycle)?;
+        self.w.write(cmd)
+    }
+
+    // Moves the clock on with nothing happening, as when a trace ends
+    // after the last command written.
+    pub fn advance_to(&mut self, cycle: i64) -> io::Result<()> {
+        let clamp = |v: i64| v.clamp(i32::MIN as i64, i32::MAX as i64);
+        let mut at = match self.cycle {
+            Some(c) => c,
+            None => {
+                let value = clamp(cycle);
+                self.w.write(&Command::<&[u8]>::Cycle {
+                    abs: true,
+                    value: value as i32,
+                })?;
+                value
+            }
+        };
+        while at != cycle {
+            let step = clamp(cycle - at);
+            self.w.write(&Command::<&[u8]>::Cycle {
+                abs: false,
+                value: step as i32,
+            })?;
+            at += step;
+        }
+        self.cycle = Some(cycle);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w.into_inner())
+    }
+}
+
+// The commands of the kept instructions, each with the cycle it happened at.
+// Dependencies survive only when both ends are kept.
+pub struct Selected<'a> {
+    commands: Commands<'a>,
+    clock: Clock,
+    keep: HashSet<Id>,
+    failed: bool,
+}
+
+impl<'a> Selected<'a> {
+    pub fn new(input: &'a [u8], keep: HashSet<Id>) -> Result<Self, ParseError> {
+        Ok(Self {
+            commands: Commands::new(input)?,
+            clock: Clock::new(),
+            keep,
+            failed: false,
+        })
+    }
+
+    pub fn kept(&self) -> &HashSet<Id> {
+        &self.keep
+    }
+}
+
+impl Iterator for Selected<'_> {
+    type Item = Result<(i64, Command), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        for (_, cmd) in &mut self.commands {
+            let cmd = match cmd {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            };
+            self.clock.apply(&cmd);
+            if wanted(&self.keep, &cmd) {
+                return Some(Ok((self.clock.cycle(), cmd)));
+            }
+        }
+        None
+    }
+}
+
+fn wanted(keep: &HashSet<Id>, cmd: &Command) -> bool {
+    match *cmd {
+        Command::Dep {
+            consumer_id,
+            producer_id,
+            ..
+        } => keep.contains(&consumer_id) && keep.contains(&producer_id),
+        // the colors of stages that may not be kept cost nothing
+        Command::StageColor { .. } => true,
+        _ => cmd.id().is_some_and(|id| keep.contains(&id)),
+    }
+}
+
+impl Trace<'_> {
+    pub fn select_commands(&self, filter: &Filter) -> Result<Selected<'_>, ParseError> {
+        let keep = self.select(filter).map(|r| r.id).collect();
+        Selected::new(self.input(), keep)
+    }
+}
+
+pub fn write_selected<W: Write>(input: &[u8], keep: HashSet<Id>, out: W) -> io::Result<W> {
+    let mut w = CycleWriter::new(out)?;
+    for item in Selected::new(input, keep)? {
+        let (cycle, cmd) = item?;
+        w.write(cycle, &cmd.map_text(|s| s.get(input)))?;
+    }
+    w.finish()
+}
+
+pub type IdSet = HashSet<Id>;
+
+// Just the given instructions as a trace of their own, small enough to hand
+// someone as a reproducer: all their records and the dependencies among
+// them, renumbered densely and moved to start at cycle 0, with only the
+// cycle commands those need.
+pub fn slice<W: Write>(trace: &Trace, ids: &IdSet, out: W) -> io::Result<W> {
+    let input = trace.input();
+    let mut w = CycleWriter::new(out)?;
+    let mut renumber = Renumber::default();
+    let mut shift = None;
+    for item in Selected::new(input, ids.clone())? {
+        let (cycle, cmd) = item?;
+        let Some(cmd) = renumber.map(cmd) else {
+            continue;
+        };
+        let shift = *shift.get_or_insert(-cycle);
+        w.write(cycle + shift, &cmd.map_text(|s| s.get(input)))?;
+    }
+    w.finish()
+}
+
+pub fn write_filtered<W: Write>(trace: &Trace, filter: &Filter, out: W) -> io::Result<W> {
+    let keep = trace.select(filter).map(|r| r.id).collect();
+    write_selected(trace.input(), keep, out)
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterReport {
+    pub instructions: u64,
+    pub kept: u64,
+    // instructions put together to test the filter on: all of them, unless
+    // it had a label literal to look for
+    pub candidates: u64,
+}
+
+// The ids of instructions that may have `text` in their label, from their
+// left-pane `L` records alone: those holding it, and those ending in the
+// start of it, which a label written over several records can continue.
+// Only `L` lines are parsed. Every instruction whose label holds `text` is
+// among them, but not every one of them has it.
+fn label_candidates(input: &[u8], text: &[u8], version: Option<u32>) -> IdSet {
+    let finder = memchr::memmem::Finder::new(text);
+    let may_hold =
+        |t: &[u8]| finder.find(t).is_some() || (1..text.len()).any(|n| t.ends_with(&text[..n]));
+    let mut found = IdSet::new();
+    let mut start = 0;
+    while start < input.len() {
+        let end = memchr::memchr2(b'\n', b'\r', &input[start..]).map_or(input.len(), |i| start + i);
+        if input[start] == b'L'
+            && let Some((_, Ok(Command::Log { id, kind, text: t }), _)) =
+                parse_line(input, start, version, Tolerance::Lenient)
+            && kind == LogKind::LeftPane
+            && may_hold(t.get(input))
+        {
+            found.insert(id);
+        }
+        start = end + 1;
+    }
+    found
+}
+
+// `write_filtered` straight from the input. A filter with a
+// `Filter::label_literal` first has the `L` records narrow the instructions
+// down to those that may hold it, so only those are put together and
+// tested against their whole label, and the rest of the trace is parsed
+// but never reconstructed; other filters, and binary traces, put the whole
+// trace together. Either way the same instructions are kept.
+pub fn filter_input<W: Write>(
+    input: &[u8],
+    filter: &Filter,
+    out: W,
+) -> io::Result<(W, FilterReport)> {
+    let literal = filter.label_literal();
+    let Some(text) = literal.filter(|_| !input.starts_with(BINARY_MAGIC)) else {
+        let trace = Trace::new(input)?;
+        let kept = trace.select(filter).count() as u64;
+        let n = trace.instructions().len() as u64;
+        let report = FilterReport {
+            instructions: n,
+            kept,
+            candidates: n,
+        };
+        return Ok((write_filtered(&trace, filter, out)?, report));
+    };
+    let mut report = FilterReport::default();
+    let mut commands = Commands::new(input)?;
+    let version = match commands.next() {
+        Some((_, Ok(Command::Kanata { version }))) => Some(version),
+        _ => None,
+    };
+    let keep = label_candidates(input, text.as_bytes(), version);
+
+    // the candidates as a trace of their own, ending when the input does so
+    // those in flight are so until the same cycle
+    let mut w = CycleWriter::new(Vec::new())?;
+    let mut clock = Clock::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if let Command::Instruction { id_in_file, .. } = cmd {
+            report.instructions += 1;
+            report.candidates += keep.contains(&id_in_file) as u64;
+        }
+        // dependencies on any producer, for filters on wakeups
+        let dep = matches!(cmd, Command::Dep { consumer_id, .. } if keep.contains(&consumer_id));
+        if dep || wanted(&keep, &cmd) {
+            w.write(clock.cycle(), &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    w.advance_to(clock.cycle())?;
+    let picked = w.finish()?;
+    let trace = Trace::new(&picked)?;
+    report.kept = trace.select(filter).count() as u64;
+    Ok((write_filtered(&trace, filter, out)?, report))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampling {
+    EveryNth(u64),
+    Random { rate: f64, seed: u64 },
+}
+
+// Gives the instructions that are kept dense ids in order of appearance, and
+// the retirements dense retire ids; records of anything else are dropped.
+#[derive(Default)]
+struct Renumber {
+    ids: HashMap<Id, Id>,
+    next_id: Id,
+    next_retire: Id,
+}
+
+impl Renumber {
+    fn forget(&mut self, id: Id) {
+        self.ids.remove(&id);
+    }
+
+    fn map(&mut self, cmd: Command) -> Option<Command> {
+        let id = |id: Id| self.ids.get(&id).copied();
+        match cmd {
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => {
+                self.ids.insert(id_in_file, self.next_id);
+                self.next_id += 1;
+                Some(Command::Instruction {
+                    id_in_file: self.next_id - 1,
+                    id_in_sim,
+                    thread_id,
+                })
+            }
+            Command::Log { id: v, kind, text } => id(v).map(|id| Command::Log { id, kind, text }),
+            Command::Pipeline {
+                start,
+                id: v,
+                lane_id,
+                name,
+            } => id(v).map(|id| Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            }),
+            Command::Retire { id: v, kind, .. } => id(v).map(|id| {
+                let retire = self.next_retire;
+                if kind == RetireKind::Retire {
+                    self.next_retire += 1;
+                }
+                Command::Retire { id, retire, kind }
+            }),
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => id(consumer_id)
+                .zip(id(producer_id))
+                .map(|(c, p)| Command::Dep {
+                    consumer_id: c,
+                    producer_id: p,
+                    kind,
+                    label,
+                }),
+            Command::StageColor { .. } => Some(cmd),
+            Command::Kanata { .. } | Command::Cycle { .. } => None,
+        }
+    }
+}
+
+// Keeps a sample of the instructions along with all their records, at the
+// cycles they happened, renumbered so viewers don't show the gaps.
+pub fn write_sampled<W: Write>(input: &[u8], sampling: Sampling, out: W) -> io::Result<W> {
+    let mut rng = Rng::new(match sampling {
+        Sampling::Random { seed, .. } => seed,
+        Sampling::EveryNth(_) => 0,
+    });
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    let mut ids = Renumber::default();
+    let mut seen = 0u64;
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if let Command::Instruction { id_in_file, .. } = cmd {
+            let keep = match sampling {
+                Sampling::EveryNth(n) => seen.is_multiple_of(n.max(1)),
+                Sampling::Random { rate, .. } => rng.chance(rate),
+            };
+            seen += 1;
+            if !keep {
+                ids.forget(id_in_file);
+                continue;
+            }
+        }
+        if let Some(cmd) = ids.map(cmd) {
+            w.write(clock.cycle(), &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    w.finish()
+}
+
+// Appends traces end to end, each starting the cycle after the previous one
+// ends, with ids and retire ids carrying on where the previous left off.
+pub fn concat<W: Write>(inputs: &[&[u8]], out: W) -> io::Result<W> {
+    let mut w = CycleWriter::new(out)?;
+    let mut ids = Renumber::default();
+    for input in inputs {
+        ids.ids.clear();
+        let mut clock = Clock::new();
+        let mut shift = None;
+        for (_, cmd) in Commands::new(input)? {
+            let cmd = cmd?;
+            clock.apply(&cmd);
+            let Some(cmd) = ids.map(cmd) else {
+                continue;
+            };
+            let shift = *shift.get_or_insert_with(|| match w.cycle() {
+                Some(end) => end + 1 - clock.cycle(),
+                None => 0,
+            });
+            w.write(clock.cycle() + shift, &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    w.finish()
+}
+
+// Shifts time so the first command after the header happens at `epoch`,
+// dropping whatever idle stretch came before it.
+pub fn rebase<W: Write>(input: &[u8], epoch: i64, out: W) -> io::Result<W> {
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    let mut shift = None;
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if matches!(cmd, Command::Kanata { .. } | Command::Cycle { .. }) {
+            continue;
+        }
+        let shift = *shift.get_or_insert(epoch - clock.cycle());
+        w.write(clock.cycle() + shift, &cmd.map_text(|s| s.get(input)))?;
+    }
+    w.finish()
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReanchorReport {
+    // cycle records that broke the check and were held at the cycle reached
+    pub reanchored: u64,
+    // how far the end of the trace moved, in cycles
+    pub shift: i64,
+}
+
+// Fixes the cycle records `cycle_diagnostics` flags: each one that goes back
+// further than `check` allows leaves the clock where it was instead, and
+// everything after it, relative or absolute, moves along by the same amount,
+// so the records that followed keep their spacing.
+pub fn reanchor_cycles<W: Write>(
+    input: &[u8],
+    check: &CycleCheck,
+    out: W,
+) -> io::Result<(W, ReanchorReport)> {
+    let mut w = CycleWriter::new(out)?;
+    let mut report = ReanchorReport::default();
+    let mut clock = Clock::new();
+    // the cycle reached in the output, once there's been a cycle record
+    let mut at = None;
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        match cmd {
+            Command::Kanata { .. } => continue,
+            Command::Cycle { abs, value } => {
+                let before = at.map(|c: i64| c - report.shift);
+                let target = clock.cycle() + report.shift;
+                if check.breaks(abs, value, before) {
+                    let c = at.unwrap_or(0);
+                    report.reanchored += 1;
+                    report.shift += c - target;
+                    at = Some(c);
+                } else {
+                    at = Some(target);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let cycle = clock.cycle() + report.shift;
+        w.write(cycle, &cmd.map_text(|s| s.get(input)))?;
+    }
+    Ok((w.finish()?, report))
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortReport {
+    pub commands: u64,
+    // commands that had to move ahead of ones read before them
+    pub reordered: u64,
+    // the furthest back any command went, in cycles
+    pub max_lag: u64,
+    // commands further back than the window, or than an earlier record of
+    // their own instruction, which were moved up to the cycle they could go
+    pub clamped: u64,
+}
+
+// Puts the commands of a trace whose cycle markers go briefly backwards into
+// cycle order, holding on to `window` cycles of them to do so.
+pub fn sort_by_cycle<W: Write>(input: &[u8], window: u64, out: W) -> io::Result<(W, SortReport)> {
+    let window = window.min(i64::MAX as u64) as i64;
+    let mut w = CycleWriter::new(out)?;
+    let mut report = SortReport::default();
+    let mut clock = Clock::new();
+    let mut buffer: BTreeMap<(i64, u64), Command> = BTreeMap::new();
+    // the latest cycle seen, and the latest of each instruction's records
+    let mut head = i64::MIN;
+    let mut last = HashMap::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if matches!(cmd, Command::Kanata { .. } | Command::Cycle { .. }) {
+            continue;
+        }
+        let mut cycle = clock.cycle();
+        let floor = w.cycle().unwrap_or(i64::MIN);
+        let own = cmd.id().and_then(|id| last.get(&id).copied());
+        if cycle < floor || own.is_some_and(|c| cycle < c) {
+            cycle = cycle.max(floor).max(own.unwrap_or(i64::MIN));
+            report.clamped += 1;
+        }
+        if cycle < head {
+            report.max_lag = report.max_lag.max(head.abs_diff(cycle));
+            if buffer.range((cycle + 1, 0)..).next().is_some() {
+                report.reordered += 1;
+            }
+        }
+        head = head.max(cycle);
+        if let Some(id) = cmd.id() {
+            match cmd {
+                Command::Retire { .. } => last.remove(&id),
+                _ => last.insert(id, cycle),
+            };
+        }
+        buffer.insert((cycle, report.commands), cmd);
+        report.commands += 1;
+        while let Some(entry) = buffer.first_entry() {
+            if entry.key().0 > head.saturating_sub(window) {
+                break;
+            }
+            let ((at, _), cmd) = entry.remove_entry();
+            w.write(at, &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    for ((at, _), cmd) in buffer {
+        w.write(at, &cmd.map_text(|s| s.get(input)))?;
+    }
+    Ok((w.finish()?, report))
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    pub cycles: u64,
+    pub stages: u64,
+    pub logs: u64,
+    pub deps: u64,
+}
+
+impl OptimizeReport {
+    pub fn removed(&self) -> u64 {
+        self.cycles + self.stages + self.logs + self.deps
+    }
+}
+
+#[derive(Default)]
+struct Seen<'a> {
+    // the stage open on each lane
+    lanes: HashMap<u32, &'a [u8]>,
+    logs: HashSet<(LogKind, &'a [u8])>,
+    deps: HashSet<(Id, DepKind)>,
+}
+
+// Drops records that change nothing: cycle commands that don't move the
+// clock, a stage started again on a lane where it's already open, a log line
+// an instruction already has, and a dependency declared before.
+pub fn optimize<W: Write>(input: &[u8], out: W) -> io::Result<(W, OptimizeReport)> {
+    let mut w = CycleWriter::new(out)?;
+    let mut report = OptimizeReport::default();
+    let mut clock = Clock::new();
+    let mut seen: HashMap<Id, Seen> = HashMap::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        let before = clock.cycle();
+        clock.apply(&cmd);
+        let redundant = match cmd {
+            Command::Kanata { .. } => true,
+            Command::Cycle { .. } => {
+                report.cycles += (clock.cycle() == before && w.cycle().is_some()) as u64;
+                true
+            }
+            Command::Instruction { id_in_file, .. } => {
+                seen.insert(id_in_file, Seen::default());
+                false
+            }
+            Command::Retire { id, .. } => {
+                seen.remove(&id);
+                false
+            }
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                let lanes = &mut seen.entry(id).or_default().lanes;
+                let name = name.get(input);
+                if !start {
+                    lanes.remove(&lane_id);
+                    false
+                } else if lanes.insert(lane_id, name) == Some(name) {
+                    report.stages += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            Command::Log { id, kind, text } => {
+                let logs = &mut seen.entry(id).or_default().logs;
+                let dup = !logs.insert((kind, text.get(input)));
+                report.logs += dup as u64;
+                dup
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                ..
+            } => {
+                let deps = &mut seen.entry(consumer_id).or_default().deps;
+                let dup = !deps.insert((producer_id, kind));
+                report.deps += dup as u64;
+                dup
+            }
+            Command::StageColor { .. } => false,
+        };
+        if !redundant {
+            w.write(clock.cycle(), &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    Ok((w.finish()?, report))
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactConfig {
+    // bytes of left pane text to keep per instruction
+    pub label_budget: Option<usize>,
+}
+
+// Keeps only the left pane labels of the log records, cut to the budget on a
+// character boundary, which is all a viewer needs to draw the trace.
+pub fn compact<W: Write>(input: &[u8], config: &CompactConfig, out: W) -> io::Result<W> {
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    let mut used: HashMap<Id, usize> = HashMap::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        let cmd = cmd.map_text(|s| s.get(input));
+        match cmd {
+            Command::Kanata { .. } | Command::Cycle { .. } => continue,
+            Command::Instruction { id_in_file, .. } => {
+                used.remove(&id_in_file);
+            }
+            Command::Retire { id, .. } => {
+                used.remove(&id);
+            }
+            Command::Log { kind, .. } if kind != LogKind::LeftPane => continue,
+            Command::Log { id, kind, text } => {
+                if let Some(budget) = config.label_budget {
+                    let used = used.entry(id).or_default();
+                    let mut len = text.len().min(budget - *used);
+                    while len < text.len() && len > 0 && text[len] & 0xc0 == 0x80 {
+                        len -= 1;
+                    }
+                    // nor in the middle of an escape like `\n`
+                    let slashes = text[..len]
+                        .iter()
+                        .rev()
+                        .take_while(|&&b| b == b'\\')
+                        .count();
+                    if len < text.len() && slashes % 2 == 1 {
+                        len -= 1;
+                    }
+                    if len == 0 && !text.is_empty() {
+                        continue;
+                    }
+                    *used += len;
+                    let text = &text[..len];
+                    w.write(clock.cycle(), &Command::Log { id, kind, text })?;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        w.write(clock.cycle(), &cmd)?;
+    }
+    w.finish()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Until {
+    RetiredInstructions(u64),
+    // cycles from the first instruction on
+    Cycles(u64),
+}
+
+// Cuts a trace short. Instructions still in flight at the cut are dropped
+// rather than left open, since viewers draw those as running on forever.
+pub fn truncate<W: Write>(input: &[u8], until: Until, out: W) -> io::Result<W> {
+    let mut clock = Clock::new();
+    let mut done = HashSet::new();
+    let (mut start, mut retired, mut limit) = (None, 0, None);
+    for (i, (_, cmd)) in Commands::new(input)?.enumerate() {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if let Until::Cycles(n) = until
+            && start.is_some_and(|s: i64| (clock.cycle().saturating_sub(s).max(0) as u64) >= n)
+        {
+            limit = Some(i);
+            break;
+        }
+        match cmd {
+            Command::Instruction { .. } if start.is_none() => start = Some(clock.cycle()),
+            Command::Retire { id, kind, .. } => {
+                done.insert(id);
+                retired += (kind == RetireKind::Retire) as u64;
+            }
+            _ => {}
+        }
+        if until == Until::RetiredInstructions(retired) {
+            limit = Some(i + 1);
+            break;
+        }
+    }
+
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    for (_, cmd) in Commands::new(input)?.take(limit.unwrap_or(usize::MAX)) {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if wanted(&done, &cmd) {
+            w.write(clock.cycle(), &cmd.map_text(|s| s.get(input)))?;
+        }
+    }
+    w.finish()
+}
+
+// Rescales time so every `factor` cycles become one, counted from the first
+// cycle of the trace. A stage that starts and ends within the same coarse
+// cycle is dropped, unless it's the last one before the instruction retires.
+pub fn coarsen<W: Write>(input: &[u8], factor: u64, out: W) -> io::Result<W> {
+    let factor = factor.clamp(1, i64::MAX as u64) as i64;
+    let mut w = CycleWriter::new(out)?;
+    let mut clock = Clock::new();
+    let mut base = None;
+    let mut at = None;
+    // stage starts not yet known to outlast their coarse cycle
+    let mut pending: Vec<(Id, u32, Command)> = Vec::new();
+    for (_, cmd) in Commands::new(input)? {
+        let cmd = cmd?;
+        clock.apply(&cmd);
+        if matches!(cmd, Command::Kanata { .. } | Command::Cycle { .. }) {
+            continue;
+        }
+        let base = *base.get_or_insert(clock.cycle());
+        let cycle = base + (clock.cycle() - base).div_euclid(factor);
+        if at != Some(cycle) {
+            for (_, _, s) in pending.drain(..) {
+                w.write(at.unwrap(), &s.map_text(|s| s.get(input)))?;
+            }
+            at = Some(cycle);
+        }
+        match cmd {
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } if factor > 1 => {
+                let open = pending
+                    .iter()
+                    .position(|&(i, l, _)| (i, l) == (id, lane_id));
+                if let Some(k) = open {
+                    let same = matches!(pending[k].2, Command::Pipeline { name: n, .. }
+                        if n.get(input) == name.get(input));
+                    if start || same {
+                        pending.remove(k);
+                    }
+                    if !start && same {
+                        continue;
+                    }
+                }
+                if start {
+                    pending.push((id, lane_id, cmd));
+                    continue;
+                }
+            }
+            Command::Retire { id, .. } => {
+                let mut k = 0;
+                while k < pending.len() {
+                    if pending[k].0 == id {
+                        let (_, _, s) = pending.remove(k);
+                        w.write(cycle, &s.map_text(|s| s.get(input)))?;
+                    } else {
+                        k += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+        w.write(cycle, &cmd.map_text(|s| s.get(input)))?;
+    }
+    if let Some(at) = at {
+        for (_, _, s) in pending {
+            w.write(at, &s.map_text(|s| s.get(input)))?;
+        }
+    }
+    w.finish()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    // bytes cut off the end: the unfinished last line
+    pub dropped: usize,
+    // stages ended on behalf of the flushed instructions
+    pub ended: u64,
+    // instructions still in flight at the end, flushed in the order they began
+    pub flushed: Vec<Id>,
+}
+
+// Fixes up a trace cut short by a crash: drops the half-written last line,
+// then ends the open stages of every instruction still in flight and
+// flushes it, all at the last cycle reached. The rest is copied as it was.
+pub fn repair<W: Write>(input: &[u8], mut out: W) -> io::Result<(W, RepairReport)> {
+    let line_start = |end: usize| {
+        input[..end]
+            .iter()
+            .rposition(|&b| matches!(b, b'\n' | b'\r'))
+            .map_or(0, |i| i + 1)
+    };
+    let mut cut = line_start(input.len());
+    // the last whole line, which may have been written without all its fields
+    let breaks = input[..cut]
+        .iter()
+        .rev()
+        .take_while(|&&b| matches!(b, b'\n' | b'\r'))
+        .count();
+    let last = line_start(cut - breaks);
+    let mut open: HashMap<Id, (usize, BTreeMap<u32, &[u8]>)> = HashMap::new();
+    let mut next_retire = 0;
+    for (offset, cmd) in Parser::new(&input[..cut]).extensions() {
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            Err(e) if e.offset >= last && cut > 0 => {
+                cut = last;
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match cmd {
+            Command::Instruction { id_in_file, .. } => {
+                open.insert(id_in_file, (offset, BTreeMap::new()));
+            }
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                if let Some((_, lanes)) = open.get_mut(&id) {
+                    if start {
+                        lanes.insert(lane_id, name.get(input));
+                    } else {
+                        lanes.remove(&lane_id);
+                    }
+                }
+            }
+            Command::Retire { id, retire, .. } => {
+                open.remove(&id);
+                next_retire = next_retire.max(retire + 1);
+            }
+            _ => {}
+        }
+    }
+
+    out.write_all(&input[..cut])?;
+    let mut report = RepairReport {
+        dropped: input.len() - cut,
+        ..RepairReport::default()
+    };
+    let mut open: Vec<_> = open.into_iter().collect();
+    open.sort_by_key(|&(_, (offset, _))| offset);
+    let mut w = Writer::new(out);
+    for (id, (_, lanes)) in open {
+        for (lane_id, name) in lanes {
+            w.write(&Command::Pipeline {
+                start: false,
+                id,
+                lane_id,
+                name,
+            })?;
+            report.ended += 1;
+        }
+        w.write(&Command::<&[u8]>::Retire {
+            id,
+            retire: next_retire,
+            kind: RetireKind::Flush,
+        })?;
+        next_retire += 1;
+        report.flushed.push(id);
+    }
+    w.flush()?;
+    Ok((w.into_inner(), report))
+}
@@ -0,0 +1,75 @@
+use crate::{Diagnostic, Id, Report};
+use schemars::{JsonSchema, Schema, schema_for};
+
+// JSON Schemas for what the crate writes as JSON, for consumers outside
+// Rust to validate against and generate code from.
+
+// One line of `write_events_jsonl`. Which fields are there depends on `cmd`.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(title = "KanataEvent")]
+struct EventLine {
+    offset: usize,
+    cycle: i64,
+    cmd: EventCmd,
+    version: Option<u32>,
+    // the argument of a cycle command
+    value: Option<i32>,
+    id: Option<Id>,
+    sim_id: Option<Id>,
+    thread_id: Option<u32>,
+    lane: Option<u32>,
+    stage: Option<String>,
+    retire_id: Option<Id>,
+    consumer_id: Option<Id>,
+    producer_id: Option<Id>,
+    kind: Option<EventKind>,
+    text: Option<String>,
+    label: Option<String>,
+    // `#rrggbb`
+    color: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+enum EventCmd {
+    Kanata,
+    #[schemars(rename = "C=")]
+    CycleAbs,
+    #[schemars(rename = "C")]
+    Cycle,
+    I,
+    L,
+    S,
+    E,
+    R,
+    W,
+    P,
+}
+
+// `LogKind`, `RetireKind` and `DepKind` by name
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+enum EventKind {
+    Left,
+    Hover,
+    Other,
+    Retire,
+    Flush,
+    Wakeup,
+}
+
+pub fn events_schema() -> Schema {
+    schema_for!(EventLine)
+}
+
+// `Report` as serde writes it.
+pub fn report_schema() -> Schema {
+    schema_for!(Report)
+}
+
+// A list of `Diagnostic`s as serde writes it.
+pub fn diagnostics_schema() -> Schema {
+    schema_for!(Vec<Diagnostic>)
+}
@@ -0,0 +1,265 @@
+use crate::{Id, InstructionRecord, LogKind, Trace};
+use std::collections::HashMap;
+
+const PANES: [LogKind; 3] = [LogKind::LeftPane, LogKind::MouseOver, LogKind::Other];
+
+// The three-byte windows of `text`, ASCII case folded, as numbers.
+fn grams(text: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    text.windows(3).map(|w| {
+        let [a, b, c] = [w[0], w[1], w[2]].map(|b| b.to_ascii_lowercase() as u32);
+        a << 16 | b << 8 | c
+    })
+}
+
+// Which instructions' log texts hold each trigram, so that a substring
+// search only looks at the instructions that have every trigram of what it
+// is after. Trigrams are case folded, and within one pane: the texts of an
+// instruction's `L` records of a kind joined, as Konata shows them.
+// Instructions are counted by their place in `Trace::instructions`, so an
+// index only fits the trace it was built from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LabelIndex {
+    instructions: u32,
+    // the instructions with each trigram, in order
+    postings: HashMap<u32, Vec<u32>>,
+}
+
+impl LabelIndex {
+    pub fn build(trace: &Trace) -> Self {
+        let mut postings: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut seen = Vec::new();
+        for (i, rec) in trace.instructions().iter().enumerate() {
+            seen.clear();
+            for kind in PANES {
+                seen.extend(grams(&trace.pane(rec, kind)));
+            }
+            seen.sort_unstable();
+            seen.dedup();
+            for &g in &seen {
+                postings.entry(g).or_default().push(i as u32);
+            }
+        }
+        Self {
+            instructions: trace.instructions().len() as u32,
+            postings,
+        }
+    }
+
+    // Whether it was built from a trace with this many instructions, as a
+    // cheap check that it belongs to the one being searched.
+    pub fn fits(&self, trace: &Trace) -> bool {
+        self.instructions as usize == trace.instructions().len()
+    }
+
+    pub fn trigrams(&self) -> usize {
+        self.postings.len()
+    }
+
+    // The places in `Trace::instructions` of those that may have `needle`
+    // in a pane, ignoring ASCII case: all of them for a needle shorter than
+    // three bytes, which no trigram narrows down.
+    pub fn candidates(&self, needle: &[u8]) -> Vec<u32> {
+        let mut wanted: Vec<u32> = grams(needle).collect();
+        if wanted.is_empty() {
+            return (0..self.instructions).collect();
+        }
+        wanted.sort_unstable();
+        wanted.dedup();
+        let mut lists = Vec::with_capacity(wanted.len());
+        for g in wanted {
+            match self.postings.get(&g) {
+                Some(list) => lists.push(list.as_slice()),
+                None => return Vec::new(),
+            }
+        }
+        // rarest first, so the intersection shrinks fastest
+        lists.sort_by_key(|l| l.len());
+        let mut out = lists[0].to_vec();
+        for list in &lists[1..] {
+            let mut rest = *list;
+            out.retain(|i| {
+                let k = rest.partition_point(|j| j < i);
+                rest = &rest[k..];
+                rest.first() == Some(i)
+            });
+        }
+        out
+    }
+
+    // Those that may have every one of `words`.
+    fn candidates_all<'q>(&self, words: impl IntoIterator<Item = &'q str>) -> Vec<u32> {
+        let mut out: Option<Vec<u32>> = None;
+        for w in words {
+            let mine = self.candidates(w.as_bytes());
+            out = Some(match out {
+                None => mine,
+                Some(v) => {
+                    let mut rest = mine.as_slice();
+                    v.into_iter()
+                        .filter(|i| {
+                            let k = rest.partition_point(|j| j < i);
+                            rest = &rest[k..];
+                            rest.first() == Some(i)
+                        })
+                        .collect()
+                }
+            });
+        }
+        out.unwrap_or_else(|| (0..self.instructions).collect())
+    }
+
+    // The ids of the instructions whose label contains `text`, as
+    // `Filter::label_contains` would pick them, in trace order.
+    pub fn find_labels(&self, trace: &Trace, text: &str) -> Vec<Id> {
+        let all = trace.instructions();
+        self.candidates(text.as_bytes())
+            .into_iter()
+            .filter_map(|i| all.get(i as usize))
+            .filter(|rec| memchr::memmem::find(&trace.label(rec), text.as_bytes()).is_some())
+            .map(|rec| rec.id)
+            .collect()
+    }
+}
+
+// How well a hit matched, best first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchKind {
+    // the query as written
+    Exact,
+    // the query, ignoring ASCII case
+    IgnoreCase,
+    // each word of the query in one pane, in any order, ignoring ASCII case
+    Words,
+}
+
+impl MatchKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchKind::Exact => "exact",
+            MatchKind::IgnoreCase => "ignore-case",
+            MatchKind::Words => "words",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchHit {
+    pub id: Id,
+    // when the instruction was created
+    pub cycle: i64,
+    pub pane: LogKind,
+    pub kind: MatchKind,
+    // of the match in the pane's text
+    pub position: usize,
+    // the match with some of the text either side, on one line
+    pub snippet: String,
+}
+
+// bytes of context either side of a match in a snippet
+const CONTEXT: usize = 24;
+
+fn snippet(text: &[u8], at: usize, len: usize) -> String {
+    let boundary = |i: usize| i >= text.len() || text[i] & 0xc0 != 0x80;
+    let mut from = at.saturating_sub(CONTEXT);
+    while !boundary(from) {
+        from -= 1;
+    }
+    let mut to = (at + len + CONTEXT).min(text.len());
+    while !boundary(to) {
+        to += 1;
+    }
+    let mut out = String::new();
+    if from > 0 {
+        out.push_str("...");
+    }
+    let body = String::from_utf8_lossy(&text[from..to]);
+    out.extend(body.chars().map(|c| if c.is_control() { ' ' } else { c }));
+    if to < text.len() {
+        out.push_str("...");
+    }
+    out
+}
+
+fn best_match(
+    trace: &Trace,
+    rec: &InstructionRecord,
+    query: &str,
+    words: &[Vec<u8>],
+) -> Option<SearchHit> {
+    let folded = query.as_bytes().to_ascii_lowercase();
+    let mut best: Option<SearchHit> = None;
+    for pane in PANES {
+        let text = trace.pane(rec, pane);
+        let lower = text.to_ascii_lowercase();
+        let found = memchr::memmem::find(&text, query.as_bytes())
+            .map(|at| (MatchKind::Exact, at, query.len()))
+            .or_else(|| {
+                memchr::memmem::find(&lower, &folded)
+                    .map(|at| (MatchKind::IgnoreCase, at, query.len()))
+            })
+            .or_else(|| {
+                let at: Option<Vec<usize>> = words
+                    .iter()
+                    .map(|w| memchr::memmem::find(&lower, w))
+                    .collect();
+                let at = at?.into_iter().min()?;
+                Some((MatchKind::Words, at, 0))
+            });
+        let Some((kind, position, len)) = found else {
+            continue;
+        };
+        // an earlier pane wins a tie
+        if best.as_ref().is_some_and(|b| b.kind <= kind) {
+            continue;
+        }
+        best = Some(SearchHit {
+            id: rec.id,
+            cycle: rec.start,
+            pane,
+            kind,
+            position,
+            snippet: snippet(&text, position, len),
+        });
+    }
+    best
+}
+
+impl Trace<'_> {
+    // The instructions with `query` in one of their panes, best match
+    // first: the query as written, then ignoring ASCII case, then with
+    // every word of it in one pane, in any order. Among equals, the left pane comes before
+    // the others, a match nearer the start of its text first, then the
+    // earliest instruction. An empty query finds nothing.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        self.search_in(query, 0..self.instructions().len() as u32)
+    }
+
+    // `search`, looking only at the instructions `index` says may match.
+    pub fn search_indexed(&self, index: &LabelIndex, query: &str) -> Vec<SearchHit> {
+        if !index.fits(self) {
+            return self.search(query);
+        }
+        let words: Vec<&str> = query.split_whitespace().collect();
+        self.search_in(query, index.candidates_all(words))
+    }
+
+    fn search_in(&self, query: &str, candidates: impl IntoIterator<Item = u32>) -> Vec<SearchHit> {
+        let words: Vec<Vec<u8>> = query
+            .split_whitespace()
+            .map(|w| w.as_bytes().to_ascii_lowercase())
+            .collect();
+        if words.is_empty() {
+            return Vec::new();
+        }
+        let all = self.instructions();
+        let mut hits: Vec<SearchHit> = candidates
+            .into_iter()
+            .filter_map(|i| all.get(i as usize))
+            .filter_map(|rec| best_match(self, rec, query, &words))
+            .collect();
+        let pane = |p: LogKind| PANES.iter().position(|&k| k == p);
+        hits.sort_by_key(|h| (h.kind, pane(h.pane), h.position, h.cycle, h.id));
+        hits
+    }
+}
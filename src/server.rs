@@ -0,0 +1,457 @@
+use crate::export::write_str;
+use crate::{
+    BINARY_MAGIC, BinaryTrace, Checkpoint, Collector, DEFAULT_INDEX_INTERVAL, Id, Index,
+    InstructionRecord, LabelIndex, LogKind, ParseError, Stats, Trace, TraceIndex,
+};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+// Instructions from before a window are looked for this many cycles past
+// their checkpoint at first, further if they haven't retired by then.
+const LOOKUP_CYCLES: i64 = 1 << 12;
+const DEFAULT_LIMIT: usize = 10_000;
+
+struct Loaded {
+    name: String,
+    data: Vec<u8>,
+    index: Index,
+    // the whole trace and its label index, from the first search on; the
+    // trace holds the bytes from then on
+    searchable: Option<(Trace<'static>, LabelIndex)>,
+}
+
+impl Loaded {
+    // With the index from the trace's sidecar, if it has one that fits.
+    fn open(name: String, data: Vec<u8>, sidecar: Option<TraceIndex>) -> Result<Self, ParseError> {
+        let index = if let Some(t) = sidecar {
+            t.into_index()
+        } else if data.starts_with(BINARY_MAGIC) {
+            BinaryTrace::new(&data)?.index().clone()
+        } else {
+            Index::build_for_open(&data, DEFAULT_INDEX_INTERVAL)?
+        };
+        Ok(Self {
+            name,
+            data,
+            index,
+            searchable: None,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        match &self.searchable {
+            Some((trace, _)) => trace.input(),
+            None => &self.data,
+        }
+    }
+
+    // The whole trace and its labels, from the default cache when there is
+    // one, built the first time a search asks.
+    fn searchable(&mut self) -> Result<&(Trace<'static>, LabelIndex), ParseError> {
+        if self.searchable.is_none() {
+            let trace = Trace::from_vec(self.data.clone())?;
+            let labels = cached_labels(&trace).unwrap_or_else(|| LabelIndex::build(&trace));
+            self.data = Vec::new();
+            self.searchable = Some((trace, labels));
+        }
+        Ok(self.searchable.as_ref().unwrap())
+    }
+
+    // The part of the trace from `cp` until cycle `until`.
+    fn window(&self, cp: Checkpoint, until: i64) -> Result<Trace<'_>, ParseError> {
+        let data = self.data();
+        if data.starts_with(BINARY_MAGIC) {
+            let file = BinaryTrace::new(data)?;
+            Trace::from_source_at(file.commands_at(cp), cp.cycle, Some(until))
+        } else {
+            Trace::window(data, cp.offset, cp.cycle, until)
+        }
+    }
+
+    // An instruction with ids numbered in order, as simulators write them,
+    // is found from the checkpoint before it rather than the start.
+    #[allow(clippy::unnecessary_cast)] // already u64 with wide ids
+    fn find(&self, id: Id) -> Result<Option<(Trace<'_>, usize)>, ParseError> {
+        let cp = self.index.seek_instruction(id as u64);
+        let end = self.index.end().cycle;
+        let mut cycles = LOOKUP_CYCLES;
+        loop {
+            let trace = self.window(cp, cp.cycle.saturating_add(cycles))?;
+            let at = trace.instructions().iter().position(|r| r.id == id);
+            let done = at.is_some_and(|i| trace.instructions()[i].end.is_some());
+            if done || cp.cycle.saturating_add(cycles) >= end {
+                return Ok(at.map(|i| (trace, i)));
+            }
+            cycles = cycles.saturating_mul(4);
+        }
+    }
+}
+
+// A trace's label index from the default cache, if there is one that fits.
+#[cfg(feature = "cache")]
+fn cached_labels(trace: &Trace) -> Option<LabelIndex> {
+    let cache = crate::TraceCache::default_cache()?;
+    let labels = cache
+        .labels_with(trace.input(), || Ok(LabelIndex::build(trace)))
+        .ok()?;
+    labels.fits(trace).then_some(labels)
+}
+
+#[cfg(not(feature = "cache"))]
+fn cached_labels(_: &Trace) -> Option<LabelIndex> {
+    None
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+fn json(status: u16, body: Vec<u8>) -> Response {
+    Response {
+        status,
+        body: String::from_utf8(body).unwrap_or_default(),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    let mut out = b"{\"error\":".to_vec();
+    let _ = write_str(&mut out, message.as_bytes());
+    out.push(b'}');
+    json(status, out)
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                match std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                {
+                    Some(v) => out.push(v),
+                    None => out.extend_from_slice(b"%"),
+                }
+            }
+            b => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+struct Query<'u>(Vec<(&'u str, &'u str)>);
+
+impl<'u> Query<'u> {
+    fn parse(s: &'u str) -> Self {
+        Self(s.split('&').filter_map(|kv| kv.split_once('=')).collect())
+    }
+
+    fn get(&self, key: &str) -> Option<&'u str> {
+        self.0.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+
+    fn num<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>, Response> {
+        self.get(key)
+            .map(|v| v.parse())
+            .transpose()
+            .map_err(|_| error(400, &format!("bad value for {}", key)))
+    }
+}
+
+fn write_record<W: Write>(
+    out: &mut W,
+    trace: &Trace,
+    r: &InstructionRecord,
+    full: bool,
+) -> io::Result<()> {
+    let opt = |v: Option<i64>| v.map_or("null".to_string(), |v| v.to_string());
+    write!(
+        out,
+        "{{\"id\":{},\"sim_id\":{},\"thread_id\":{},\"start\":{},\"end\":{},\"retire\":",
+        r.id,
+        r.sim_id,
+        r.thread_id,
+        r.start,
+        opt(r.end)
+    )?;
+    match r.retire_kind {
+        Some(kind) => write!(out, "\"{}\"", kind.name())?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"label\":")?;
+    write_str(out, &trace.label(r))?;
+    write!(out, ",\"stages\":[")?;
+    for (i, s) in r.stages.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{{\"stage\":")?;
+        write_str(out, trace.stages().name(s.stage).as_bytes())?;
+        write!(
+            out,
+            ",\"lane\":{},\"start\":{},\"end\":{}}}",
+            s.lane, s.start, s.end
+        )?;
+    }
+    write!(out, "]")?;
+    if full {
+        write!(out, ",\"detail\":")?;
+        write_str(out, &trace.pane(r, LogKind::MouseOver))?;
+        write!(out, ",\"producers\":[")?;
+        for (i, d) in r.producers.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(
+                out,
+                "{{\"id\":{},\"kind\":\"{}\",\"cycle\":{}}}",
+                d.producer_id,
+                d.kind.name(),
+                d.cycle
+            )?;
+        }
+        write!(out, "]")?;
+    }
+    write!(out, "}}")
+}
+
+fn write_stats<W: Write>(out: &mut W, stats: &Stats) -> io::Result<()> {
+    write!(
+        out,
+        "{{\"instructions\":{},\"retired\":{},\"flushed\":{},\"cycles\":{},\"ipc\":{},\"stages\":[",
+        stats.instructions(),
+        stats.retired(),
+        stats.flushed(),
+        stats.cycles(),
+        stats.ipc()
+    )?;
+    for (i, (id, name, s)) in stats.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{{\"stage\":")?;
+        write_str(out, name.as_bytes())?;
+        let q = |q| {
+            stats
+                .stage_quantile(id, q)
+                .map_or("null".to_string(), |v| v.to_string())
+        };
+        write!(
+            out,
+            ",\"count\":{},\"mean\":{},\"p50\":{},\"p99\":{},\"max\":{}}}",
+            s.count,
+            s.mean(),
+            q(0.5),
+            q(0.99),
+            s.max
+        )?;
+    }
+    write!(out, "]}}")
+}
+
+// A small HTTP API for browsing traces too big to send whole to a browser.
+// Opening a trace indexes it; every query after that parses only the part
+// of it the answer needs. All requests are `GET`s answered with JSON:
+//
+//   /open?path=P                       open P under the root, giving its number
+//   /traces/N                          summary of trace N
+//   /traces/N/cycles?from=A&to=B       instructions started in cycles [A, B)
+//   /traces/N/instructions/ID          one instruction, with detail and producers
+//   /traces/N/stats?from=A&to=B        statistics over the instructions in [A, B)
+//   /traces/N/search?q=TEXT            instructions with TEXT in a pane, best first
+//
+// `cycles` and `search` take a `limit`, 10000 by default. The first search
+// of a trace reads all of it and indexes its labels, which later searches
+// go through. Only files under `root` can be opened.
+pub struct TraceServer {
+    root: PathBuf,
+    traces: Vec<Loaded>,
+}
+
+impl TraceServer {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            traces: Vec::new(),
+        }
+    }
+
+    // Answers one request for `url`, a path with its query string.
+    pub fn handle(&mut self, url: &str) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query = Query::parse(query);
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let result = match parts[..] {
+            ["open"] => self.open(&query),
+            ["traces", n, ref rest @ ..] => {
+                let Some(loaded) = n.parse().ok().and_then(|n: usize| self.traces.get_mut(n))
+                else {
+                    return error(404, "no such trace");
+                };
+                match rest {
+                    [] => summary(loaded, n),
+                    ["cycles"] => cycles(loaded, &query),
+                    ["instructions", id] => instruction(loaded, id),
+                    ["stats"] => window_stats(loaded, &query),
+                    ["search"] => search(loaded, &query),
+                    _ => Err(error(404, "not found")),
+                }
+            }
+            _ => Err(error(404, "not found")),
+        };
+        result.unwrap_or_else(|r| r)
+    }
+
+    fn open(&mut self, query: &Query) -> Result<Response, Response> {
+        let path = percent_decode(
+            query
+                .get("path")
+                .ok_or_else(|| error(400, "missing path"))?,
+        );
+        let rel = Path::new(&path);
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(error(403, "path must stay under the root"));
+        }
+        let full = self.root.join(rel);
+        let data = std::fs::read(&full).map_err(|e| error(404, &e.to_string()))?;
+        let sidecar = TraceIndex::for_trace(&full, &data).ok().flatten();
+        let loaded = Loaded::open(path, data, sidecar).map_err(|e| error(422, &e.to_string()))?;
+        self.traces.push(loaded);
+        let n = self.traces.len() - 1;
+        summary(&self.traces[n], &n.to_string())
+    }
+
+    // Serves requests on `addr` one at a time, until the listener fails.
+    pub fn serve(mut self, addr: impl std::net::ToSocketAddrs) -> io::Result<()> {
+        let server = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+        let content_type =
+            tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+        for request in server.incoming_requests() {
+            let response = if *request.method() == tiny_http::Method::Get {
+                self.handle(request.url())
+            } else {
+                error(405, "only GET is supported")
+            };
+            let reply = tiny_http::Response::from_string(response.body)
+                .with_status_code(response.status)
+                .with_header(content_type.clone());
+            // a client that went away doesn't stop the server
+            let _ = request.respond(reply);
+        }
+        Ok(())
+    }
+}
+
+fn parse_error(e: ParseError) -> Response {
+    error(422, &e.to_string())
+}
+
+fn summary(loaded: &Loaded, n: &str) -> Result<Response, Response> {
+    let end = loaded.index.end();
+    let mut out = Vec::new();
+    write!(out, "{{\"trace\":{},\"path\":", n).unwrap();
+    write_str(&mut out, loaded.name.as_bytes()).unwrap();
+    write!(
+        out,
+        ",\"bytes\":{},\"commands\":{},\"instructions\":{},\"start_cycle\":{},\"end_cycle\":{}}}",
+        loaded.data().len(),
+        end.commands,
+        end.instructions,
+        loaded.index.first_cycle(),
+        end.cycle
+    )
+    .unwrap();
+    Ok(json(200, out))
+}
+
+fn range(loaded: &Loaded, query: &Query) -> Result<(i64, i64), Response> {
+    let from = query.num("from")?.unwrap_or(loaded.index.first_cycle());
+    let to = query.num("to")?.unwrap_or(loaded.index.end().cycle + 1);
+    Ok((from, to))
+}
+
+// The instructions started in [from, to), with the trace they're in.
+fn started_in(loaded: &Loaded, from: i64, to: i64) -> Result<Trace<'_>, Response> {
+    // the checkpoint strictly before `from`, so none started at `from` are missed
+    let cp = loaded.index.seek_cycle(from.saturating_sub(1));
+    loaded.window(cp, to).map_err(parse_error)
+}
+
+fn cycles(loaded: &Loaded, query: &Query) -> Result<Response, Response> {
+    let (from, to) = range(loaded, query)?;
+    let limit = query.num("limit")?.unwrap_or(DEFAULT_LIMIT);
+    let trace = started_in(loaded, from, to)?;
+    let mut out = b"{\"instructions\":[".to_vec();
+    let mut n = 0;
+    let mut more = false;
+    for r in trace.instructions() {
+        if r.start < from || r.start >= to {
+            continue;
+        }
+        if n == limit {
+            more = true;
+            break;
+        }
+        if n > 0 {
+            out.push(b',');
+        }
+        write_record(&mut out, &trace, r, false).unwrap();
+        n += 1;
+    }
+    write!(out, "],\"truncated\":{}}}", more).unwrap();
+    Ok(json(200, out))
+}
+
+fn instruction(loaded: &Loaded, id: &str) -> Result<Response, Response> {
+    let id: Id = id.parse().map_err(|_| error(400, "bad instruction id"))?;
+    let Some((trace, i)) = loaded.find(id).map_err(parse_error)? else {
+        return Err(error(404, "no such instruction"));
+    };
+    let mut out = Vec::new();
+    write_record(&mut out, &trace, &trace.instructions()[i], true).unwrap();
+    Ok(json(200, out))
+}
+
+fn window_stats(loaded: &Loaded, query: &Query) -> Result<Response, Response> {
+    let (from, to) = range(loaded, query)?;
+    let trace = started_in(loaded, from, to)?;
+    let mut stats = Stats::new();
+    for r in trace.instructions() {
+        if r.start >= from && r.start < to {
+            stats.record(trace.input(), trace.stages(), r);
+        }
+    }
+    let mut out = Vec::new();
+    write_stats(&mut out, &stats).unwrap();
+    Ok(json(200, out))
+}
+
+fn search(loaded: &mut Loaded, query: &Query) -> Result<Response, Response> {
+    let text = percent_decode(query.get("q").ok_or_else(|| error(400, "missing q"))?);
+    let limit = query.num("limit")?.unwrap_or(DEFAULT_LIMIT);
+    let (trace, labels) = loaded.searchable().map_err(parse_error)?;
+    let hits = trace.search_indexed(labels, &text);
+    let mut out = b"{\"hits\":[".to_vec();
+    for (i, h) in hits.iter().take(limit).enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        write!(
+            out,
+            "{{\"id\":{},\"cycle\":{},\"pane\":\"{}\",\"match\":\"{}\",\"snippet\":",
+            h.id,
+            h.cycle,
+            h.pane.name(),
+            h.kind.name()
+        )
+        .unwrap();
+        write_str(&mut out, h.snippet.as_bytes()).unwrap();
+        out.push(b'}');
+    }
+    write!(out, "],\"truncated\":{}}}", hits.len() > limit).unwrap();
+    Ok(json(200, out))
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+// DDSketch: values land in logarithmic buckets of width gamma, so any
+// quantile is answered within `alpha` relative error and two sketches with
+// the same accuracy merge by adding bucket counts.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Sketch {
+    alpha: f64,
+    ln_gamma: f64,
+    bins: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SketchError {
+    // an accuracy that isn't strictly between 0 and 1
+    BadAlpha,
+    // a merge of two sketches of different accuracy
+    AccuracyMismatch,
+}
+
+impl fmt::Display for SketchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SketchError::BadAlpha => "sketch accuracy must be between 0 and 1",
+            SketchError::AccuracyMismatch => "cannot merge sketches with different accuracy",
+        })
+    }
+}
+
+impl std::error::Error for SketchError {}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Self::with_alpha(0.01)
+    }
+}
+
+impl Sketch {
+    pub fn new(alpha: f64) -> Result<Self, SketchError> {
+        if !(alpha > 0.0 && alpha < 1.0) {
+            return Err(SketchError::BadAlpha);
+        }
+        Ok(Self::with_alpha(alpha))
+    }
+
+    fn with_alpha(alpha: f64) -> Self {
+        let gamma = (1.0 + alpha) / (1.0 - alpha);
+        Self {
+            alpha,
+            ln_gamma: gamma.ln(),
+            bins: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn bins(&self) -> usize {
+        self.bins.len() + (self.zeros > 0) as usize
+    }
+
+    pub fn add(&mut self, v: u64) {
+        self.add_n(v, 1);
+    }
+
+    pub fn add_n(&mut self, v: u64, n: u64) {
+        if n == 0 {
+            return;
+        }
+        if v == 0 {
+            self.zeros += n;
+        } else {
+            let key = ((v as f64).ln() / self.ln_gamma).ceil() as i32;
+            *self.bins.entry(key).or_default() += n;
+        }
+        self.count += n;
+    }
+
+    // Leaves this one as it was when the accuracies differ.
+    pub fn merge(&mut self, other: &Sketch) -> Result<(), SketchError> {
+        if self.alpha != other.alpha || self.ln_gamma != other.ln_gamma {
+            return Err(SketchError::AccuracyMismatch);
+        }
+        for (&k, &n) in &other.bins {
+            *self.bins.entry(k).or_default() += n;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+        Ok(())
+    }
+
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64).round() as u64;
+        if rank < self.zeros {
+            return Some(0);
+        }
+        let mut seen = self.zeros;
+        for (&k, &n) in &self.bins {
+            seen += n;
+            if seen > rank {
+                let gamma = self.ln_gamma.exp();
+                let v = 2.0 * (k as f64 * self.ln_gamma).exp() / (gamma + 1.0);
+                return Some(v.round() as u64);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{BinaryReader, Clock, Command, Commands, ParseError, Parser, StrRef};
+
+// A command with the offset it was read from and the cycle it happens at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stamped {
+    pub offset: usize,
+    pub cycle: i64,
+    pub command: Command,
+}
+
+// The commands of a trace in order, from whichever backend holds them. Their
+// texts are `StrRef`s into `input`. Callers stop at the first error; what a
+// source yields after one is up to it.
+pub trait CommandSource<'a> {
+    fn input(&self) -> &'a [u8];
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>>;
+}
+
+fn stamp(
+    (offset, cmd): (usize, Result<Command, ParseError>),
+    cycle: impl FnOnce(&Command) -> i64,
+) -> Result<Stamped, ParseError> {
+    let command = cmd?;
+    Ok(Stamped {
+        offset,
+        cycle: cycle(&command),
+        command,
+    })
+}
+
+impl<'a> CommandSource<'a> for Parser<'a> {
+    fn input(&self) -> &'a [u8] {
+        Parser::input(self)
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        let next = self.next()?;
+        Some(stamp(next, |_| self.current_cycle()))
+    }
+}
+
+impl<'a> CommandSource<'a> for BinaryReader<'a> {
+    fn input(&self) -> &'a [u8] {
+        BinaryReader::input(self)
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        let next = self.next()?;
+        Some(stamp(next, |_| self.current_cycle()))
+    }
+}
+
+impl<'a> CommandSource<'a> for Commands<'a> {
+    fn input(&self) -> &'a [u8] {
+        match self {
+            Commands::Text(p) => CommandSource::input(p),
+            Commands::Binary(r) => CommandSource::input(r),
+        }
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        match self {
+            Commands::Text(p) => p.next_command(),
+            Commands::Binary(r) => r.next_command(),
+        }
+    }
+}
+
+// Any iterator of offsets and commands over `input`, stamped by following
+// its `C` commands from zero, or from a known starting cycle.
+pub struct Clocked<'a, I> {
+    input: &'a [u8],
+    commands: I,
+    clock: Clock,
+}
+
+impl<'a, I> Clocked<'a, I>
+where
+    I: Iterator<Item = (usize, Result<Command, ParseError>)>,
+{
+    pub fn new(input: &'a [u8], commands: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            input,
+            commands: commands.into_iter(),
+            clock: Clock::new(),
+        }
+    }
+
+    pub fn at_cycle(mut self, cycle: i64) -> Self {
+        self.clock = Clock::at(cycle);
+        self
+    }
+}
+
+impl<'a, I> CommandSource<'a> for Clocked<'a, I>
+where
+    I: Iterator<Item = (usize, Result<Command, ParseError>)>,
+{
+    fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        let next = self.commands.next()?;
+        let clock = &mut self.clock;
+        Some(stamp(next, |cmd| {
+            clock.apply(cmd);
+            clock.cycle()
+        }))
+    }
+}
+
+// Commands kept in memory with their texts copied into one buffer, for
+// building a trace without writing it out. A command's offset is its
+// position in the buffer.
+#[derive(Clone, Debug, Default)]
+pub struct CommandBuffer {
+    text: Vec<u8>,
+    commands: Vec<(i64, Command)>,
+    clock: Clock,
+}
+
+impl CommandBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Texts past 65535 bytes are cut short, as the lenient parser does.
+    pub fn push<T: AsRef<[u8]>>(&mut self, cmd: Command<T>) {
+        let text = &mut self.text;
+        let cmd = cmd.map_text(|t| {
+            let t = t.as_ref();
+            let t = &t[..t.len().min(u16::MAX as usize)];
+            let s = StrRef::new(text.len() as u64, t.len() as u16);
+            text.extend_from_slice(t);
+            s
+        });
+        self.clock.apply(&cmd);
+        self.commands.push((self.clock.cycle(), cmd));
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    pub fn source(&self) -> BufferSource<'_> {
+        BufferSource {
+            text: &self.text,
+            commands: self.commands.iter().enumerate(),
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> FromIterator<Command<T>> for CommandBuffer {
+    fn from_iter<I: IntoIterator<Item = Command<T>>>(iter: I) -> Self {
+        let mut buf = Self::new();
+        iter.into_iter().for_each(|cmd| buf.push(cmd));
+        buf
+    }
+}
+
+pub struct BufferSource<'a> {
+    text: &'a [u8],
+    commands: std::iter::Enumerate<std::slice::Iter<'a, (i64, Command)>>,
+}
+
+impl<'a> CommandSource<'a> for BufferSource<'a> {
+    fn input(&self) -> &'a [u8] {
+        self.text
+    }
+
+    fn next_command(&mut self) -> Option<Result<Stamped, ParseError>> {
+        let (offset, &(cycle, command)) = self.commands.next()?;
+        Some(Ok(Stamped {
+            offset,
+            cycle,
+            command,
+        }))
+    }
+}
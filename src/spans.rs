@@ -0,0 +1,163 @@
+use crate::{Command, CommandSource, Id, ParseError, StageId, StageSpan, StageTable};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpanEvent {
+    Span {
+        id: Id,
+        span: StageSpan,
+    },
+    // an `E` with no open `S` of the same stage on the same lane
+    UnmatchedEnd {
+        offset: usize,
+        id: Id,
+        stage: StageId,
+        lane: u32,
+        cycle: i64,
+    },
+}
+
+// The stage spans of a trace as they close, paired from its `S` and `E`
+// commands without building instructions. As in the model, a stage started
+// on a lane ends whatever was open there, retiring ends every open stage,
+// and stages still open at the end close at the last cycle.
+pub struct Spans<'a, S> {
+    source: S,
+    input: &'a [u8],
+    stages: StageTable,
+    open: HashMap<Id, Vec<StageSpan>>,
+    ready: VecDeque<SpanEvent>,
+    cycle: i64,
+    done: bool,
+}
+
+impl<'a, S: CommandSource<'a>> Spans<'a, S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            input: source.input(),
+            source,
+            stages: StageTable::new(),
+            open: HashMap::new(),
+            ready: VecDeque::new(),
+            cycle: 0,
+            done: false,
+        }
+    }
+
+    // The names of the stages in the spans seen so far.
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    fn close(&mut self, id: Id, keep: impl Fn(&StageSpan) -> bool, cycle: i64) {
+        let Some(spans) = self.open.get_mut(&id) else {
+            return;
+        };
+        let ready = &mut self.ready;
+        spans.retain(|&span| {
+            if keep(&span) {
+                return true;
+            }
+            ready.push_back(SpanEvent::Span {
+                id,
+                span: StageSpan { end: cycle, ..span },
+            });
+            false
+        });
+    }
+
+    fn apply(&mut self, offset: usize, cycle: i64, cmd: Command) -> Result<(), ParseError> {
+        match cmd {
+            Command::Pipeline {
+                start: true,
+                id,
+                lane_id,
+                name,
+            } => {
+                self.close(id, |s| s.lane != lane_id, cycle);
+                let stage = self
+                    .stages
+                    .intern(name.get(self.input))
+                    .map_err(|kind| ParseError { offset, kind })?;
+                self.open.entry(id).or_default().push(StageSpan {
+                    stage,
+                    lane: lane_id,
+                    start: cycle,
+                    end: cycle,
+                });
+            }
+            Command::Pipeline {
+                start: false,
+                id,
+                lane_id,
+                name,
+            } => {
+                let stage = self
+                    .stages
+                    .intern(name.get(self.input))
+                    .map_err(|kind| ParseError { offset, kind })?;
+                let is = |s: &StageSpan| s.stage == stage && s.lane == lane_id;
+                if self.open.get(&id).is_some_and(|v| v.iter().any(is)) {
+                    self.close(id, |s| !is(s), cycle);
+                } else {
+                    self.ready.push_back(SpanEvent::UnmatchedEnd {
+                        offset,
+                        id,
+                        stage,
+                        lane: lane_id,
+                        cycle,
+                    });
+                }
+            }
+            Command::Retire { id, .. } => {
+                self.close(id, |_| false, cycle);
+                self.open.remove(&id);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S: CommandSource<'a>> Iterator for Spans<'a, S> {
+    type Item = Result<SpanEvent, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            match self.source.next_command() {
+                Some(Ok(cmd)) => {
+                    self.cycle = cmd.cycle;
+                    if let Err(e) = self.apply(cmd.offset, cmd.cycle, cmd.command) {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                None => {
+                    self.done = true;
+                    // in the order the instructions first opened a stage
+                    let mut rest: Vec<_> = self.open.drain().collect();
+                    rest.sort_by_key(|(id, spans)| (spans.first().map(|s| s.start), *id));
+                    for (id, spans) in rest {
+                        for span in spans {
+                            let span = StageSpan {
+                                end: self.cycle,
+                                ..span
+                            };
+                            self.ready.push_back(SpanEvent::Span { id, span });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
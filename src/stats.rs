@@ -0,0 +1,407 @@
+#[cfg(feature = "parallel")]
+use crate::{Checkpoint, Command, Index};
+use crate::{
+    CommandSource, InstructionRecord, ParseError, ParseErrorKind, Parser, Reconstructor, Sketch,
+    StageId, StageTable, Step, Trace,
+};
+
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1 << 16;
+
+pub trait Collector {
+    fn record(&mut self, input: &[u8], stages: &StageTable, rec: &InstructionRecord);
+}
+
+pub fn stream<C: Collector>(
+    input: &[u8],
+    max_in_flight: usize,
+    collector: &mut C,
+) -> Result<StageTable, ParseError> {
+    let rec = Reconstructor::new(input).with_max_in_flight(max_in_flight);
+    stream_with(input, rec, collector)
+}
+
+struct Each<F>(F);
+
+impl<F: FnMut(&InstructionRecord)> Collector for Each<F> {
+    fn record(&mut self, _input: &[u8], _stages: &StageTable, rec: &InstructionRecord) {
+        (self.0)(rec)
+    }
+}
+
+// Calls `f` once for each instruction as it retires, at the end for those
+// that never do, with no more than the default number in flight. Its texts
+// are read from `input`, as with `rec.label(input)`; logs written after an
+// instruction retired aren't in its record. The stage names are in the table
+// returned.
+pub fn for_each_instruction(
+    input: &[u8],
+    f: impl FnMut(&InstructionRecord),
+) -> Result<StageTable, ParseError> {
+    stream(input, DEFAULT_MAX_IN_FLIGHT, &mut Each(f))
+}
+
+// Like `stream`, with the reconstructor's cap and eviction policy up to the
+// caller. Evicted instructions are recorded as they leave, unfinished.
+pub fn stream_with<C: Collector>(
+    input: &[u8],
+    rec: Reconstructor,
+    collector: &mut C,
+) -> Result<StageTable, ParseError> {
+    stream_source(Parser::new(input).extensions(), rec, collector)
+}
+
+// `stream_with` over any backend. The reconstructor should be made with
+// the source's input.
+pub fn stream_source<'a, S: CommandSource<'a>, C: Collector>(
+    mut source: S,
+    mut rec: Reconstructor,
+    collector: &mut C,
+) -> Result<StageTable, ParseError> {
+    let input = source.input();
+    while let Some(cmd) = source.next_command() {
+        let cmd = cmd?;
+        if let Step::Retired(r) | Step::Evicted(r) = rec.feed(cmd.offset, cmd.command)? {
+            collector.record(input, rec.stages(), &r);
+        }
+    }
+    let (stages, rest) = rec.finish()?;
+    for r in &rest {
+        collector.record(input, &stages, r);
+    }
+    Ok(stages)
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Summary {
+    pub count: u64,
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Summary {
+    pub fn add(&mut self, v: u64) {
+        if self.count == 0 || v < self.min {
+            self.min = v;
+        }
+        self.max = self.max.max(v);
+        self.count += 1;
+        self.total += v;
+    }
+
+    pub fn merge(&mut self, other: &Summary) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.total += other.total;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Stats {
+    stages: StageTable,
+    per_stage: Vec<Summary>,
+    per_stage_sketch: Vec<Sketch>,
+    latency: Summary,
+    latency_sketch: Sketch,
+    instructions: u64,
+    retired: u64,
+    flushed: u64,
+    first_cycle: Option<i64>,
+    last_cycle: Option<i64>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_trace(trace: &Trace) -> Self {
+        let mut stats = Self::new();
+        for rec in trace.instructions() {
+            stats.record(trace.input(), trace.stages(), rec);
+        }
+        stats.stages = trace.stages().clone();
+        stats
+    }
+
+    pub fn streaming(input: &[u8], max_in_flight: usize) -> Result<Self, ParseError> {
+        let mut stats = Self::new();
+        stats.stages = stream(input, max_in_flight, &mut stats)?;
+        Ok(stats)
+    }
+
+    pub fn from_source<'a, S: CommandSource<'a>>(
+        source: S,
+        max_in_flight: usize,
+    ) -> Result<Self, ParseError> {
+        let mut stats = Self::new();
+        let rec = Reconstructor::new(source.input()).with_max_in_flight(max_in_flight);
+        stats.stages = stream_source(source, rec, &mut stats)?;
+        Ok(stats)
+    }
+
+    // The stats of each stretch between checkpoints of `index`, gathered in
+    // parallel and merged. A stretch follows the instructions created in it
+    // through the next stretch, and hands those still in flight after that
+    // to a single pass that carries them on until they retire, so the totals
+    // match a single pass that evicts nothing, and no stretch is read more
+    // than three times.
+    #[cfg(feature = "parallel")]
+    pub fn parallel(input: &[u8], index: &Index, max_in_flight: usize) -> Result<Self, ParseError> {
+        use rayon::prelude::*;
+        let cps = index.checkpoints();
+        let ends: Vec<usize> = cps
+            .iter()
+            .skip(1)
+            .map(|c| c.offset)
+            .chain([input.len()])
+            .collect();
+        let chunks: Vec<Result<(Self, Option<Reconstructor>), ParseError>> = (0..cps.len())
+            .into_par_iter()
+            .map(|i| {
+                let until = ends.get(i + 1).copied().unwrap_or(input.len());
+                Self::chunk(input, cps[i], ends[i], until, max_in_flight)
+            })
+            .collect();
+        let mut stats = Self::new();
+        let mut handed = Vec::new();
+        for (chunk, cp) in chunks.into_iter().zip(cps) {
+            let (chunk, open) = chunk?;
+            stats.merge(&chunk).map_err(|kind| ParseError {
+                offset: cp.offset,
+                kind,
+            })?;
+            handed.push(open);
+        }
+
+        // what stretch `i` hands over is in flight as stretch `i + 2` starts
+        let mut carry: Option<(Reconstructor, Self)> = None;
+        for (j, &cp) in cps.iter().enumerate() {
+            if let Some(open) = j.checked_sub(2).and_then(|i| handed[i].take()) {
+                let (rec, _) = carry.get_or_insert_with(|| {
+                    (Reconstructor::new(input).with_cycle(cp.cycle), Self::new())
+                });
+                rec.adopt(open).map_err(|kind| ParseError {
+                    offset: cp.offset,
+                    kind,
+                })?;
+            }
+            let Some((rec, carried)) = &mut carry else {
+                continue;
+            };
+            let parser = Parser::resume(&input[..ends[j]], cp.offset)
+                .extensions()
+                .at_cycle(cp.cycle);
+            for (offset, cmd) in parser {
+                let cmd = cmd?;
+                if let Command::Instruction { .. } = cmd {
+                    continue;
+                }
+                if let Step::Retired(r) = rec.feed(offset, cmd)? {
+                    carried.record(input, rec.stages(), &r);
+                }
+                if rec.in_flight() == 0 {
+                    break;
+                }
+            }
+            if rec.in_flight() == 0 || j + 1 == cps.len() {
+                let (rec, mut carried) = carry.take().unwrap();
+                let (stages, rest) = rec.finish()?;
+                for r in &rest {
+                    carried.record(input, &stages, r);
+                }
+                carried.stages = stages;
+                stats.merge(&carried).map_err(|kind| ParseError {
+                    offset: cp.offset,
+                    kind,
+                })?;
+            }
+        }
+        Ok(stats)
+    }
+
+    // One stretch, from `from` to `end`, with the instructions created in
+    // it followed on to `until`. Those still in flight there come back in
+    // the reconstructor, unless that's the end of the input.
+    #[cfg(feature = "parallel")]
+    fn chunk<'a>(
+        input: &'a [u8],
+        from: Checkpoint,
+        end: usize,
+        until: usize,
+        max_in_flight: usize,
+    ) -> Result<(Self, Option<Reconstructor<'a>>), ParseError> {
+        let mut stats = Self::new();
+        let mut rec = Reconstructor::new(input)
+            .with_cycle(from.cycle)
+            .with_max_in_flight(max_in_flight);
+        let parser = Parser::resume(&input[..until], from.offset)
+            .extensions()
+            .at_cycle(from.cycle);
+        for (offset, cmd) in parser {
+            let cmd = cmd?;
+            if offset >= end {
+                if rec.in_flight() == 0 {
+                    break;
+                }
+                // instructions from here on are the next stretch's
+                if let Command::Instruction { .. } = cmd {
+                    continue;
+                }
+            }
+            if let Step::Retired(r) | Step::Evicted(r) = rec.feed(offset, cmd)? {
+                stats.record(input, rec.stages(), &r);
+            }
+        }
+        if until < input.len() && rec.in_flight() > 0 {
+            stats.stages = rec.stages().clone();
+            return Ok((stats, Some(rec)));
+        }
+        let (stages, rest) = rec.finish()?;
+        for r in &rest {
+            stats.record(input, &stages, r);
+        }
+        stats.stages = stages;
+        Ok((stats, None))
+    }
+
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    pub(crate) fn set_stages(&mut self, stages: StageTable) {
+        self.stages = stages;
+    }
+
+    pub fn stage(&self, stage: StageId) -> Summary {
+        self.per_stage
+            .get(stage.index())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (StageId, &str, Summary)> {
+        self.stages
+            .iter()
+            .map(|(id, name)| (id, name, self.stage(id)))
+    }
+
+    pub fn stage_quantile(&self, stage: StageId, q: f64) -> Option<u64> {
+        self.per_stage_sketch.get(stage.index())?.quantile(q)
+    }
+
+    pub fn latency(&self) -> Summary {
+        self.latency
+    }
+
+    pub fn latency_quantile(&self, q: f64) -> Option<u64> {
+        self.latency_sketch.quantile(q)
+    }
+
+    // `TooManyStages` when the two together name more stages than ids, and
+    // `SketchMismatch` when their sketches differ in accuracy.
+    pub fn merge(&mut self, other: &Stats) -> Result<(), ParseErrorKind> {
+        let mismatch = |_| ParseErrorKind::SketchMismatch;
+        // first, so sketches that don't match leave this as it was
+        self.latency_sketch
+            .merge(&other.latency_sketch)
+            .map_err(mismatch)?;
+        for (id, name, summary) in other.iter() {
+            let mine = self.stages.intern(name.as_bytes())?.index();
+            self.per_stage.resize(self.stages.len(), Summary::default());
+            self.per_stage_sketch
+                .resize(self.stages.len(), Sketch::default());
+            self.per_stage[mine].merge(&summary);
+            if let Some(sketch) = other.per_stage_sketch.get(id.index()) {
+                self.per_stage_sketch[mine]
+                    .merge(sketch)
+                    .map_err(mismatch)?;
+            }
+        }
+        self.latency.merge(&other.latency);
+        self.instructions += other.instructions;
+        self.retired += other.retired;
+        self.flushed += other.flushed;
+        self.first_cycle = min_opt(self.first_cycle, other.first_cycle);
+        self.last_cycle = self.last_cycle.max(other.last_cycle);
+        Ok(())
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    pub fn flushed(&self) -> u64 {
+        self.flushed
+    }
+
+    pub fn cycles(&self) -> u64 {
+        match (self.first_cycle, self.last_cycle) {
+            (Some(a), Some(b)) => (b - a).max(0) as u64,
+            _ => 0,
+        }
+    }
+
+    // The cycle the last instruction ended in, or was last seen in.
+    pub fn last_cycle(&self) -> Option<i64> {
+        self.last_cycle
+    }
+
+    pub fn ipc(&self) -> f64 {
+        match self.cycles() {
+            0 => 0.0,
+            c => self.retired as f64 / c as f64,
+        }
+    }
+}
+
+impl Collector for Stats {
+    fn record(&mut self, _input: &[u8], stages: &StageTable, rec: &InstructionRecord) {
+        self.per_stage.resize(stages.len(), Summary::default());
+        self.per_stage_sketch
+            .resize(stages.len(), Sketch::default());
+        for span in &rec.stages {
+            self.per_stage[span.stage.index()].add(span.cycles());
+            self.per_stage_sketch[span.stage.index()].add(span.cycles());
+        }
+        if let Some(lat) = rec.latency() {
+            self.latency.add(lat);
+            self.latency_sketch.add(lat);
+        }
+        self.instructions += 1;
+        self.retired += rec.is_retired() as u64;
+        self.flushed += rec.is_flushed() as u64;
+        let end = rec.end.unwrap_or(rec.start);
+        self.first_cycle = Some(self.first_cycle.map_or(rec.start, |c| c.min(rec.start)));
+        self.last_cycle = Some(self.last_cycle.map_or(end, |c| c.max(end)));
+    }
+}
+
+fn min_opt(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{OwnedCommand, ParseError, Parser, Writer};
+
+pub fn round_trip(commands: &[OwnedCommand]) -> Result<Vec<OwnedCommand>, ParseError> {
+    let mut w = Writer::new(Vec::new());
+    for cmd in commands {
+        w.write(cmd).expect("writing to a Vec cannot fail");
+    }
+    let out = w.into_inner();
+    Parser::new(&out)
+        .extensions()
+        .map(|(_, cmd)| cmd.map(|c| c.into_owned(&out)))
+        .collect()
+}
+
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+const STAGES: [&[u8]; 6] = [b"F", b"Dc", b"Rn", b"Is", b"X", b"Cm"];
+
+// Builds a well-formed trace out of a stream of choices, with `pick(n)`
+// returning something in 0..n, so `arbitrary` and `proptest` can share it.
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+pub(crate) fn sequence(steps: usize, mut pick: impl FnMut(u32) -> u32) -> Vec<OwnedCommand> {
+    use crate::{Command, DepKind, Id, KANATA_VERSION, LogKind, RetireKind};
+
+    let mut out = vec![
+        Command::Kanata {
+            version: KANATA_VERSION,
+        },
+        Command::Cycle {
+            abs: true,
+            value: pick(100) as i32,
+        },
+    ];
+    let mut live: Vec<(Id, Vec<(u32, usize)>)> = Vec::new();
+    let mut next_id = 0;
+    let mut retired = 0;
+    for _ in 0..steps {
+        let n = live.len() as u32;
+        let cmd = match pick(8) {
+            2 | 3 => {
+                let id = next_id;
+                next_id += 1;
+                live.push((id, Vec::new()));
+                out.push(Command::Instruction {
+                    id_in_file: id,
+                    id_in_sim: id,
+                    thread_id: pick(2),
+                });
+                Command::Log {
+                    id,
+                    kind: LogKind::LeftPane,
+                    text: format!("{:x}: op{}", 0x1000 + 4 * id, pick(16)).into_bytes(),
+                }
+            }
+            4 if n > 0 => {
+                let (id, open) = &mut live[pick(n) as usize];
+                let (lane, stage) = (pick(2), pick(STAGES.len() as u32) as usize);
+                open.retain(|&(l, _)| l != lane);
+                open.push((lane, stage));
+                Command::Pipeline {
+                    start: true,
+                    id: *id,
+                    lane_id: lane,
+                    name: STAGES[stage].to_vec(),
+                }
+            }
+            5 if live.iter().any(|(_, open)| !open.is_empty()) => {
+                let (id, open) = live.iter_mut().find(|(_, open)| !open.is_empty()).unwrap();
+                let (lane, stage) = open.remove(pick(open.len() as u32) as usize);
+                Command::Pipeline {
+                    start: false,
+                    id: *id,
+                    lane_id: lane,
+                    name: STAGES[stage].to_vec(),
+                }
+            }
+            6 if n > 0 => Command::Log {
+                id: live[pick(n) as usize].0,
+                kind: if pick(2) == 0 {
+                    LogKind::MouseOver
+                } else {
+                    LogKind::Other
+                },
+                text: format!("note {}", pick(1000)).into_bytes(),
+            },
+            7 if n >= 2 => {
+                let producer = pick(n - 1) as usize;
+                Command::Dep {
+                    consumer_id: live[n as usize - 1].0,
+                    producer_id: live[producer].0,
+                    kind: DepKind::WakeUp,
+                    label: None,
+                }
+            }
+            1 if n > 0 => {
+                let (id, _) = live.remove(pick(n) as usize);
+                retired += 1;
+                Command::Retire {
+                    id,
+                    retire: retired - 1,
+                    kind: if pick(8) == 0 {
+                        RetireKind::Flush
+                    } else {
+                        RetireKind::Retire
+                    },
+                }
+            }
+            _ => Command::Cycle {
+                abs: false,
+                value: 1 + pick(3) as i32,
+            },
+        };
+        out.push(cmd);
+    }
+    out
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    use super::sequence;
+    use crate::{Command, KANATA_VERSION, MIN_KANATA_VERSION, OwnedCommand};
+    use arbitrary::{Arbitrary, Result, Unstructured};
+
+    fn text(u: &mut Unstructured<'_>) -> Result<Vec<u8>> {
+        let mut t: Vec<u8> = u.arbitrary()?;
+        t.retain(|&b| b != b'\r' && b != b'\n');
+        t.truncate(u16::MAX as usize);
+        if t.is_empty() {
+            t.push(b'x');
+        }
+        Ok(t)
+    }
+
+    // the parser reads whitespace after the kind as a separator
+    fn label(mut t: Vec<u8>) -> Option<Vec<u8>> {
+        let n = t.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        t.drain(..n);
+        (!t.is_empty()).then_some(t)
+    }
+
+    impl<'a> Arbitrary<'a> for Command<Vec<u8>> {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(match u.int_in_range(0..=7)? {
+                0 => Command::Kanata {
+                    version: u.int_in_range(MIN_KANATA_VERSION..=KANATA_VERSION)?,
+                },
+                1 => Command::Cycle {
+                    abs: u.arbitrary()?,
+                    value: u.arbitrary()?,
+                },
+                2 => Command::Instruction {
+                    id_in_file: u.arbitrary()?,
+                    id_in_sim: u.arbitrary()?,
+                    thread_id: u.arbitrary()?,
+                },
+                3 => Command::Log {
+                    id: u.arbitrary()?,
+                    kind: u.arbitrary()?,
+                    text: text(u)?,
+                },
+                4 => Command::Pipeline {
+                    start: u.arbitrary()?,
+                    id: u.arbitrary()?,
+                    lane_id: u.arbitrary()?,
+                    name: text(u)?,
+                },
+                5 => Command::Retire {
+                    id: u.arbitrary()?,
+                    retire: u.arbitrary()?,
+                    kind: u.arbitrary()?,
+                },
+                6 => Command::StageColor {
+                    name: text(u)?,
+                    color: u.int_in_range(0..=0xff_ffff)?,
+                },
+                _ => Command::Dep {
+                    consumer_id: u.arbitrary()?,
+                    producer_id: u.arbitrary()?,
+                    kind: u.arbitrary()?,
+                    label: match u.arbitrary()? {
+                        true => label(text(u)?),
+                        false => None,
+                    },
+                },
+            })
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ValidTrace(pub Vec<OwnedCommand>);
+
+    impl<'a> Arbitrary<'a> for ValidTrace {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let steps = u.arbitrary_len::<u16>()?;
+            Ok(ValidTrace(sequence(steps, |n| {
+                u.int_in_range(0..=n - 1).unwrap_or(0)
+            })))
+        }
+    }
+}
+#[cfg(feature = "arbitrary")]
+pub use arbitrary_impls::*;
+
+#[cfg(feature = "proptest")]
+mod strategies {
+    use super::sequence;
+    use crate::{
+        Command, DepKind, Id, KANATA_VERSION, LogKind, MIN_KANATA_VERSION, OwnedCommand, RetireKind,
+    };
+    use proptest::prelude::*;
+
+    fn text() -> impl Strategy<Value = Vec<u8>> {
+        let byte = any::<u8>().prop_filter("line break", |&b| b != b'\r' && b != b'\n');
+        proptest::collection::vec(byte, 1..64)
+    }
+
+    // the parser reads whitespace after the kind as a separator
+    fn label() -> impl Strategy<Value = Vec<u8>> {
+        text().prop_filter("leading whitespace", |t| t[0] != b' ' && t[0] != b'\t')
+    }
+
+    pub fn any_command() -> impl Strategy<Value = OwnedCommand> {
+        let log_kind = prop_oneof![
+            Just(LogKind::LeftPane),
+            Just(LogKind::MouseOver),
+            Just(LogKind::Other)
+        ];
+        let retire_kind = prop_oneof![Just(RetireKind::Retire), Just(RetireKind::Flush)];
+        prop_oneof![
+            (MIN_KANATA_VERSION..=KANATA_VERSION).prop_map(|version| Command::Kanata { version }),
+            (any::<bool>(), any::<i32>()).prop_map(|(abs, value)| Command::Cycle { abs, value }),
+            any::<(Id, Id, u32)>().prop_map(|(id_in_file, id_in_sim, thread_id)| {
+                Command::Instruction {
+                    id_in_file,
+                    id_in_sim,
+                    thread_id,
+                }
+            }),
+            (any::<Id>(), log_kind, text()).prop_map(|(id, kind, text)| Command::Log {
+                id,
+                kind,
+                text
+            }),
+            (any::<(bool, Id, u32)>(), text()).prop_map(|((start, id, lane_id), name)| {
+                Command::Pipeline {
+                    start,
+                    id,
+                    lane_id,
+                    name,
+                }
+            }),
+            (any::<(Id, Id)>(), retire_kind)
+                .prop_map(|((id, retire), kind)| { Command::Retire { id, retire, kind } }),
+            (any::<(Id, Id)>(), proptest::option::of(label())).prop_map(
+                |((consumer_id, producer_id), label)| Command::Dep {
+                    consumer_id,
+                    producer_id,
+                    kind: DepKind::WakeUp,
+                    label,
+                }
+            ),
+            (text(), 0..=0xff_ffffu32)
+                .prop_map(|(name, color)| Command::StageColor { name, color }),
+        ]
+    }
+
+    pub fn valid_trace(max_steps: usize) -> impl Strategy<Value = Vec<OwnedCommand>> {
+        // each step takes a handful of choices; shrinking the list shrinks the trace
+        proptest::collection::vec(any::<u32>(), 0..max_steps * 3).prop_map(|choices| {
+            let mut it = choices.iter().copied();
+            sequence(choices.len() / 3, |n| it.next().map_or(0, |c| c % n))
+        })
+    }
+}
+#[cfg(feature = "proptest")]
+pub use strategies::*;
@@ -97,23 +97,34 @@ impl<'a> PrettyPrinter<'a> {
                 consumer_id,
                 producer_id,
                 kind,
+                label,
             } => {
                 let kind = match kind {
                     DepKind::WakeUp => "wakeup",
                 };
 
-                let _ = writeln!(
+                let _ = write!(
                     self.out,
                     "Dep {} <- {} ({})",
                     consumer_id, producer_id, kind
                 );
+                if let Some(label) = label {
+                    let label = String::from_utf8_lossy(self.strref(label));
+                    let _ = write!(self.out, " label=\"{}\"", label);
+                }
+                let _ = writeln!(self.out);
+            }
+
+            Command::StageColor { name, color } => {
+                let name = String::from_utf8_lossy(self.strref(name));
+                let _ = writeln!(self.out, "StageColor name={} color=#{:06x}", name, color);
             }
         }
     }
 }
 
 fn parse_and_pretty_print(input: &[u8]) -> Result<String, ParseError> {
-    let parser = Parser::new(input);
+    let parser = Parser::new(input).extensions();
     let mut pp = PrettyPrinter::new(input);
     for (_, cmd) in parser {
         let cmd = cmd?;
@@ -132,3 +143,4007 @@ fn kanata_logs() {
         assert_snapshot!(out);
     }
 }
+
+#[test]
+fn top_n_latency() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let x = trace.stages().get("X").unwrap();
+    let mut out = String::new();
+    for by in [SortBy::TotalLatency, SortBy::StageLatency(x)] {
+        for r in trace.top_n(5, by) {
+            let label = String::from_utf8_lossy(&r.label);
+            let _ = writeln!(
+                out,
+                "{:?} id={} value={} {}",
+                by, r.record.id, r.value, label
+            );
+        }
+    }
+    assert_snapshot!(out);
+
+    // more than there are is all of them
+    let all = trace.top_n(usize::MAX, SortBy::TotalLatency);
+    let with_latency = trace
+        .instructions()
+        .iter()
+        .filter(|r| r.latency().is_some());
+    assert_eq!(all.len(), with_latency.count());
+    assert!(all.windows(2).all(|w| w[0].value >= w[1].value));
+    assert!(
+        Trace::new(b"Kanata\t0004\n")
+            .unwrap()
+            .top_n(usize::MAX, SortBy::WakeupDelay)
+            .is_empty()
+    );
+}
+
+#[test]
+fn streaming_stats_match_trace() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let full = Stats::from_trace(&trace);
+    let streamed = Stats::streaming(&input, DEFAULT_MAX_IN_FLIGHT).unwrap();
+    assert_eq!(full.latency(), streamed.latency());
+    assert_eq!(full.retired(), streamed.retired());
+    for (id, name, summary) in full.iter() {
+        let other = streamed.stages().get(name).unwrap();
+        assert_eq!(summary, streamed.stage(other), "stage {:?}", id);
+    }
+}
+
+#[test]
+fn sketch_quantiles_merge() {
+    let mut a = Sketch::default();
+    let mut b = Sketch::default();
+    for v in 1..=1000u64 {
+        if v % 2 == 0 { a.add(v) } else { b.add(v) }
+    }
+    a.merge(&b).unwrap();
+    assert_eq!(a.count(), 1000);
+    for (q, exact) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+        let got = a.quantile(q).unwrap() as f64;
+        assert!((got - exact).abs() <= exact * 0.02, "q={} got={}", q, got);
+    }
+
+    for alpha in [0.0, -0.5, 1.0, 2.0, f64::NAN, f64::INFINITY] {
+        assert_eq!(Sketch::new(alpha).err(), Some(SketchError::BadAlpha));
+    }
+    let mut coarse = Sketch::new(0.05).unwrap();
+    coarse.add(7);
+    let before = a.clone();
+    assert_eq!(a.merge(&coarse), Err(SketchError::AccuracyMismatch));
+    assert_eq!(a, before);
+}
+
+#[test]
+fn writer_round_trip() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let mut w = Writer::new(Vec::new());
+    let mut expected = Vec::new();
+    for (_, cmd) in Parser::new(&input) {
+        let cmd = cmd.unwrap();
+        w.write_ref(&cmd, &input).unwrap();
+        expected.push(cmd.into_owned(&input));
+    }
+    let output = w.into_inner();
+    let actual: Vec<_> = Parser::new(&output)
+        .map(|(_, cmd)| cmd.unwrap().into_owned(&output))
+        .collect();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn rtl_import() {
+    let config =
+        RtlConfig::parse("delimiter = comma\npc = pc\nstage = F f\nstage = X x\n").unwrap();
+    let rows = "cycle,pc,f,x\n10,40,1,0\n11,40,0,1\n12,44,1,0\n5000000000,48,1,0\n";
+    let mut out = Vec::new();
+    import_rtl(rows.as_bytes(), &config, &mut out).unwrap();
+    let trace = Trace::new(&out).unwrap();
+    let summary: Vec<_> = trace
+        .instructions()
+        .iter()
+        .map(|r| (r.start, r.end, r.retire_kind, trace.label(r).into_owned()))
+        .collect();
+    // the last two are still in the pipeline when the rows run out
+    assert_eq!(
+        summary,
+        [
+            (10, Some(12), Some(RetireKind::Retire), b"40".to_vec()),
+            (
+                12,
+                Some(5000000000),
+                Some(RetireKind::Flush),
+                b"44".to_vec()
+            ),
+            (
+                5000000000,
+                Some(5000000000),
+                Some(RetireKind::Flush),
+                b"48".to_vec()
+            ),
+        ]
+    );
+    let steps = Parser::new(&out)
+        .filter(|(_, c)| matches!(c, Ok(Command::Cycle { abs: false, .. })))
+        .count();
+    // three for the gap that no one `C` can cover
+    assert_eq!(steps, 5);
+
+    let early = "cycle,pc,f,x\n-3000000000,40,1,0\n";
+    let err = import_rtl_commands(early.as_bytes(), &config).unwrap_err();
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<ImportError>());
+    assert_eq!(
+        err,
+        Some(&ImportError {
+            line: 2,
+            kind: ImportErrorKind::ValueTooBig
+        })
+    );
+}
+
+#[test]
+fn detect_formats() {
+    for path in glob("testinput/*.log").unwrap() {
+        let input = std::fs::read(path.unwrap()).unwrap();
+        assert_eq!(detect_format(&input), Format::Kanata);
+    }
+    assert_eq!(detect_format(b"I\t0\t0\t0\nS\t0\t0\tF\n"), Format::Kanata);
+    assert_eq!(
+        detect_format(b"O3PipeView:fetch:1000:0x400:0:1:nop\n"),
+        Format::O3PipeView
+    );
+    assert_eq!(detect_format(&[0x1f, 0x8b, 8, 0]), Format::Gzip);
+    assert_eq!(detect_format(&[0x28, 0xb5, 0x2f, 0xfd]), Format::Zstd);
+    assert_eq!(detect_format(b"cycle,pc\n1,0x40\n"), Format::Unknown);
+    assert_eq!(detect_format(b""), Format::Unknown);
+}
+
+#[test]
+fn migrate_0003() {
+    let old = b"Kanata\t0003\nC=\t0\nI\t0\t0\nL\t0\t0\t0x40: nop\nS\t0\t0\tF\nC\t2\nI\t1\t1\t1\nR\t0\t0\t0\n";
+    let mut out = Vec::new();
+    migrate(old, &mut out).unwrap();
+    assert_snapshot!(String::from_utf8(out).unwrap(), @r"
+    Kanata	0004
+    C=	0
+    I	0	0	0
+    L	0	0	0x40: nop
+    S	0	0	F
+    C	2
+    I	1	1	1
+    R	0	0	0
+    ");
+
+    let trace = Trace::new(old).unwrap();
+    assert_eq!(trace.version(), Some(3));
+    let threads: Vec<_> = trace.instructions().iter().map(|r| r.thread_id).collect();
+    assert_eq!(threads, [0, 1]);
+    assert_eq!(
+        Parser::new(b"Kanata\t0009\n")
+            .next()
+            .unwrap()
+            .1
+            .unwrap_err()
+            .kind,
+        ParseErrorKind::UnsupportedVersion
+    );
+}
+
+// The second sample as a 0003 trace, with no thread column.
+fn sample_0003() -> Vec<u8> {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let text = String::from_utf8(input).unwrap();
+    let mut out = String::new();
+    for line in text.lines() {
+        match line.strip_suffix("\t0") {
+            Some(i) if line.starts_with("I\t") => out.push_str(i),
+            _ if line.starts_with("Kanata") => out.push_str("Kanata\t0003"),
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+#[test]
+fn resume_0003() {
+    let input = sample_0003();
+    let trace = Trace::new(&input).unwrap();
+    assert_eq!(trace.version(), Some(3));
+    let index = Index::build(&input, 1 << 10).unwrap();
+    assert!(index.checkpoints().len() > 2);
+
+    let mid = index.checkpoints()[index.checkpoints().len() / 2].cycle;
+    let window = index.window(&input, mid..trace.end_cycle()).unwrap();
+    assert!(!window.instructions().is_empty());
+    for r in window.instructions() {
+        let f = trace.get(r.id).unwrap();
+        assert_eq!((r.start, r.end), (f.start, f.end));
+    }
+
+    let late = &trace.instructions()[trace.instructions().len() - 3];
+    let mut marks = Bookmarks::new();
+    marks.set("late", Bookmark::Instruction(late.id)).unwrap();
+    assert_eq!(marks.resolve("late", &input, &index), Ok(Some(late.start)));
+
+    #[cfg(feature = "ffi")]
+    unsafe {
+        use crate::ffi::*;
+        let p = kanata_parser_new(input.as_ptr(), input.len());
+        let mut cmd = KanataCommand::default();
+        let mut tags = Vec::new();
+        while kanata_parser_next(p, &mut cmd) == 1 {
+            tags.push(cmd.tag);
+        }
+        assert_eq!(kanata_parser_error(p, std::ptr::null_mut()), -1);
+        kanata_parser_free(p);
+        assert_eq!(tags.len(), Parser::new(&input).count());
+        let instructions = tags.iter().filter(|&&t| t == b'I').count();
+        assert_eq!(instructions, trace.instructions().len());
+    }
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_round_trip() {
+    use crate::ffi::*;
+    let sample = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let extra = b"Kanata\t0004\nP\t#4e79a7\tF\nC=\t0\nI\t0\t0\t0\nI\t1\t1\t0\nW\t1\t0\t0\tbypass r3\nS\t0\t0\tF\nC\t1\nR\t0\t0\t0\nR\t1\t1\t1\n";
+    let path = std::env::temp_dir().join(format!("kanata-ffi-{}.log", std::process::id()));
+    let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+    for input in [&sample[..], &extra[..]] {
+        unsafe {
+            let p = kanata_parser_new(input.as_ptr(), input.len());
+            let w = kanata_writer_new(c_path.as_ptr());
+            assert!(!w.is_null());
+            let mut cmd = KanataCommand::default();
+            while kanata_parser_next(p, &mut cmd) == 1 {
+                assert_eq!(kanata_writer_write(w, &cmd), 0);
+            }
+            assert_eq!(kanata_parser_next(p, &mut cmd), 0);
+            assert_eq!(kanata_parser_error(p, std::ptr::null_mut()), -1);
+            kanata_parser_free(p);
+            assert_eq!(kanata_writer_free(w), 0);
+        }
+        let output = std::fs::read(&path).unwrap();
+        let commands = |data: &[u8]| -> Vec<_> {
+            Parser::new(data)
+                .extensions()
+                .map(|(_, c)| c.unwrap().into_owned(data))
+                .collect()
+        };
+        assert_eq!(commands(&output), commands(input));
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    // errors stick, with their offset and kind
+    let bad = b"Kanata\t0004\nQ\n";
+    unsafe {
+        let p = kanata_parser_new(bad.as_ptr(), bad.len());
+        let mut cmd = KanataCommand::default();
+        assert_eq!(kanata_parser_next(p, &mut cmd), 1);
+        assert_eq!(cmd.tag, b'K');
+        assert_eq!(kanata_parser_next(p, &mut cmd), -1);
+        assert_eq!(kanata_parser_next(p, &mut cmd), -1);
+        let mut kind = 0;
+        assert_eq!(kanata_parser_error(p, &mut kind), 12);
+        assert_eq!(kind, ParseErrorKind::UnexpectedCharacter as i32);
+        kanata_parser_free(p);
+        assert_eq!(kanata_parser_next(std::ptr::null_mut(), &mut cmd), -1);
+    }
+}
+
+#[test]
+fn binary_round_trip() {
+    for path in glob("testinput/*.log").unwrap() {
+        let input = std::fs::read(path.unwrap()).unwrap();
+        let data = convert_to_binary(&input, Vec::new()).unwrap();
+        assert_eq!(detect_format(&data), Format::Binary);
+
+        let expected: Vec<_> = Parser::new(&input)
+            .map(|(_, cmd)| cmd.unwrap().into_owned(&input))
+            .collect();
+        let actual: Vec<_> = BinaryReader::new(&data)
+            .unwrap()
+            .map(|(_, cmd)| cmd.unwrap().into_owned(&data))
+            .collect();
+        assert_eq!(expected, actual);
+
+        let text = Trace::new(&input).unwrap();
+        let binary = Trace::new(&data).unwrap();
+        let summary = |t: &Trace| {
+            t.instructions()
+                .iter()
+                .map(|r| (r.id, r.start, r.end, t.label(r).to_vec(), r.stages.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(&text), summary(&binary));
+    }
+
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let mut w = BinaryWriter::new(Vec::new()).with_interval(64);
+    for (_, cmd) in Parser::new(&input) {
+        w.write_ref(&cmd.unwrap(), &input).unwrap();
+    }
+    let data = w.finish().unwrap();
+    let file = BinaryTrace::new(&data).unwrap();
+    assert!(file.index().checkpoints().len() > 1);
+    let full = file.trace().unwrap();
+    let mid = file.index().checkpoints()[1].cycle;
+    let window = file.window(mid..full.end_cycle()).unwrap();
+    assert!(!window.instructions().is_empty());
+    for r in window.instructions() {
+        let f = full.get(r.id).unwrap();
+        assert_eq!((r.start, r.end), (f.start, f.end));
+    }
+}
+
+#[cfg(all(feature = "msgpack", feature = "bincode"))]
+#[test]
+fn serde_round_trip() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let commands: Vec<OwnedCommand> = Parser::new(&input)
+        .map(|(_, cmd)| cmd.unwrap().into_owned(&input))
+        .collect();
+    let trace = Trace::new(&input).unwrap();
+    let report = Report::from_trace(&trace, ReportConfig::default());
+
+    type Codec = (
+        fn(&Report) -> std::io::Result<Vec<u8>>,
+        fn(&[u8]) -> std::io::Result<Report>,
+    );
+    let codecs: [Codec; 2] = [(to_msgpack, from_msgpack), (to_bincode, from_bincode)];
+    for (encode, decode) in codecs {
+        let data = encode(&report).unwrap();
+        let back = decode(&data).unwrap();
+        assert_eq!(encode(&back).unwrap(), data);
+        assert_eq!(back.stats().retired(), report.stats().retired());
+        assert_eq!(back.stages().len(), report.stages().len());
+        assert!(back.windows().eq(report.windows()));
+        assert!(back.per_pc().eq(report.per_pc()));
+    }
+
+    let back: Vec<OwnedCommand> = from_msgpack(&to_msgpack(&commands).unwrap()).unwrap();
+    assert_eq!(back, commands);
+    let back: Vec<OwnedCommand> = from_bincode(&to_bincode(&commands).unwrap()).unwrap();
+    assert_eq!(back, commands);
+}
+
+#[test]
+fn document_incremental_edits() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let mut doc = Document::new(input[..20_000].to_vec());
+    let snippets: [&[u8]; 6] = [b"", b"\n", b"\t", b"7", b"C\t3\n", b"S\t5\t0\tX\n"];
+    let mut seed = 0x2545_f491_u64;
+    let mut rand = |n: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % n.max(1)
+    };
+    for _ in 0..300 {
+        let len = doc.text().len();
+        let start = rand(len + 1);
+        let end = (start + rand(8)).min(len);
+        doc.edit(start..end, snippets[rand(snippets.len())]);
+        let fresh = Document::new(doc.text().to_vec());
+        assert_eq!(doc.lines(), fresh.lines());
+    }
+
+    let mut doc = Document::new(b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nS\t0\t0\tF\n".to_vec());
+    assert_eq!(doc.edit(17..17, b"C\t2\n"), 1..4);
+    assert_eq!(doc.lines()[3].cycle, 7);
+    assert_eq!(doc.trace().unwrap().instructions()[0].start, 7);
+}
+
+#[test]
+fn highlight_tokens() {
+    let input = b"Kanata\t0004\nC=\t-1\nI\t0\t0\t0\nL\t0\t0\t12: add\tr1\nS\t0\t0\tF\nR\t0\t0\t7\nX\t1\nW\t1\tz\t0\t9\n";
+    let mut out = String::new();
+    for (r, kind) in highlight(input) {
+        writeln!(out, "{:?} {:?}", kind, String::from_utf8_lossy(&input[r])).unwrap();
+    }
+    assert_snapshot!(out);
+}
+
+#[test]
+fn diagnostics_lsp() {
+    let input = "Kanata\t0003\nI\t0\t0\nS\t0\t0\tF\nE\t0\t0\tD\nL\t5\t0\tx\nC\tz\nW\t0\t9\t0\nR\t0\t0\t0\nS\t0\t0\tF\nI\t1\t1\nC\t\u{1f600}\nL\t1\t7\tx\n";
+    let mut out = String::new();
+    for d in diagnostics(input.as_bytes()) {
+        writeln!(
+            out,
+            "{}:{}-{}:{} {:?} {} {}",
+            d.start.line,
+            d.start.character,
+            d.end.line,
+            d.end.character,
+            d.severity,
+            d.code,
+            d.message
+        )
+        .unwrap();
+    }
+    assert_snapshot!(out);
+}
+
+#[test]
+fn round_trip_extremes() {
+    let commands = vec![
+        Command::Kanata {
+            version: KANATA_VERSION,
+        },
+        Command::Cycle {
+            abs: true,
+            value: i32::MIN,
+        },
+        Command::Cycle {
+            abs: false,
+            value: i32::MAX,
+        },
+        Command::Instruction {
+            id_in_file: Id::MAX,
+            id_in_sim: 0,
+            thread_id: u32::MAX,
+        },
+    ];
+    assert_eq!(round_trip(&commands).unwrap(), commands);
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_round_trip() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let bytes: Vec<u8> = (0..1 << 16)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let mut u = Unstructured::new(&bytes);
+    while !u.is_empty() {
+        let cmd = OwnedCommand::arbitrary(&mut u).unwrap();
+        assert_eq!(round_trip(std::slice::from_ref(&cmd)).unwrap(), [cmd]);
+    }
+
+    let mut u = Unstructured::new(&bytes);
+    let ValidTrace(commands) = ValidTrace::arbitrary(&mut u).unwrap();
+    assert_eq!(round_trip(&commands).unwrap(), commands);
+    let mut text = Writer::new(Vec::new());
+    for cmd in &commands {
+        text.write(cmd).unwrap();
+    }
+    assert!(Trace::from_vec(text.into_inner()).is_ok());
+}
+
+#[cfg(feature = "proptest")]
+proptest::proptest! {
+    #[test]
+    fn proptest_round_trip(cmd in any_command()) {
+        proptest::prop_assert_eq!(round_trip(std::slice::from_ref(&cmd)).unwrap(), [cmd]);
+    }
+
+    #[test]
+    fn proptest_valid_trace(commands in valid_trace(200)) {
+        proptest::prop_assert_eq!(round_trip(&commands).unwrap(), commands.clone());
+        let mut text = Writer::new(Vec::new());
+        for cmd in &commands {
+            text.write(cmd).unwrap();
+        }
+        proptest::prop_assert!(Trace::from_vec(text.into_inner()).is_ok());
+    }
+}
+
+#[test]
+fn generate_synthetic() {
+    let config = GenConfig {
+        seed: 7,
+        instructions: 2000,
+        ..GenConfig::default()
+    };
+    let data = generate(&config, Vec::new()).unwrap();
+    assert_eq!(data, generate(&config, Vec::new()).unwrap());
+    assert_ne!(
+        data,
+        generate(
+            &GenConfig {
+                seed: 8,
+                ..config.clone()
+            },
+            Vec::new()
+        )
+        .unwrap()
+    );
+
+    let trace = Trace::new(&data).unwrap();
+    let stats = Stats::from_trace(&trace);
+    assert_eq!(stats.retired(), 2000);
+    assert!(stats.flushed() > 0);
+    assert!(stats.ipc() > 0.5 && stats.ipc() <= 4.0, "{}", stats.ipc());
+    assert!(
+        diagnostics(&data)
+            .iter()
+            .all(|d| d.severity == Severity::Hint)
+    );
+
+    let names: Vec<_> = trace.stages().iter().map(|(_, n)| n).collect();
+    assert_eq!(names, ["F", "Dc", "Rn", "Ds", "Is", "X", "Wb", "Cm"]);
+}
+
+#[test]
+fn check_accumulates() {
+    let input = b"Kanata\t0004\nI\t0\t0\t0\nL\t0\t7\tx\nC\tz\nI\t0\t0\t0\nR\t0\t0\t0\nI\t0\t0\t0\nR\t0\t0\t9";
+    let errors: Vec<_> = check(input).iter().map(|e| (e.offset, e.kind)).collect();
+    assert_eq!(
+        errors,
+        [
+            (25, ParseErrorKind::InvalidLogKind),
+            (30, ParseErrorKind::ExpectedValue),
+            (32, ParseErrorKind::DuplicateInstruction),
+            (63, ParseErrorKind::InvalidRetireKind),
+        ]
+    );
+    assert!(check(&generate(&GenConfig::default(), Vec::new()).unwrap()).is_empty());
+}
+
+#[test]
+fn lenient_warnings() {
+    let long = "x".repeat(70_000);
+    let input = format!(
+        "Kanata\t0004\nC=\t10\nI\t0\t0\t0\nL\t0\t7\tx\nS\t0\t0\t{}\nC=\t4\nbogus\nR\t0\t0\t5\nW\t1\tz\t0\n",
+        long
+    );
+    let input = input.as_bytes();
+
+    let mut strict = Parser::new(input);
+    assert_eq!(
+        strict.nth(3).unwrap().1.unwrap_err().kind,
+        ParseErrorKind::InvalidLogKind
+    );
+
+    let mut parser = Parser::new(input).tolerance(Tolerance::Recover);
+    let commands: Vec<_> = parser.by_ref().map(|(_, c)| c.unwrap()).collect();
+    assert_eq!(commands.len(), 7);
+    assert!(matches!(
+        commands[3],
+        Command::Log {
+            kind: LogKind::Other,
+            ..
+        }
+    ));
+    assert!(matches!(commands[4], Command::Pipeline { name, .. } if name.len() == u16::MAX));
+    assert!(matches!(
+        commands[6],
+        Command::Retire {
+            kind: RetireKind::Retire,
+            ..
+        }
+    ));
+    let warnings: Vec<_> = parser.take_warnings().iter().map(|w| w.kind).collect();
+    assert_eq!(
+        warnings,
+        [
+            WarningKind::UnknownKind,
+            WarningKind::TruncatedText,
+            WarningKind::CycleWentBack,
+            WarningKind::SkippedLine(ParseErrorKind::UnexpectedCharacter),
+            WarningKind::UnknownKind,
+            WarningKind::SkippedLine(ParseErrorKind::ExpectedValue),
+        ]
+    );
+
+    let mut seen = Vec::new();
+    let n = Parser::new(input)
+        .tolerance(Tolerance::Recover)
+        .on_warning(|w| seen.push(w.offset))
+        .count();
+    assert_eq!((n, seen.len()), (7, 6));
+}
+
+#[test]
+fn parse_metrics() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t9\tx\nS\t0\t0\tF\nE\t0\t0\tF\n?\nS\t0\t0\nR\t0\t0\t0\n";
+    let mut parser = Parser::new(input).tolerance(Tolerance::Recover);
+    parser.by_ref().for_each(drop);
+    assert_eq!(
+        parser.metrics(),
+        ParseMetrics {
+            bytes: input.len() as u64,
+            lines: 9,
+            headers: 1,
+            cycles: 1,
+            instructions: 1,
+            logs: 1,
+            stage_starts: 1,
+            stage_ends: 1,
+            retires: 1,
+            unknown: 1,
+            skipped: 2,
+            recovered: 3,
+            ..ParseMetrics::default()
+        }
+    );
+    assert_eq!(parser.skipped_ranges(), [49..51, 51..57]);
+
+    let mut parser = Parser::new(input);
+    assert!(parser.find(|(_, c)| c.is_err()).is_some());
+    assert_eq!(parser.metrics().errors, 1);
+}
+
+#[test]
+fn estimate_memory() {
+    assert_eq!(Trace::estimate_model_memory(b"Kanata\t0004\n").unwrap(), 0);
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let estimate = Trace::estimate_model_memory(&input).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let records = std::mem::size_of_val(trace.instructions());
+    assert!(estimate > records && estimate < 8 * records, "{}", estimate);
+}
+
+#[test]
+fn in_flight_eviction() {
+    struct Collect(Vec<InstructionRecord>);
+    impl Collector for Collect {
+        fn record(&mut self, _: &[u8], _: &StageTable, rec: &InstructionRecord) {
+            self.0.push(rec.clone());
+        }
+    }
+    let input = generate(
+        &GenConfig {
+            instructions: 3000,
+            ..GenConfig::default()
+        },
+        Vec::new(),
+    )
+    .unwrap();
+    let run = |eviction| {
+        let rec = Reconstructor::new(&input)
+            .with_max_in_flight(16)
+            .with_eviction(eviction);
+        let mut out = Collect(Vec::new());
+        stream_with(&input, rec, &mut out).map(|_| out.0)
+    };
+
+    let err = run(Eviction::Error).unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::TooManyInFlight);
+
+    let evicted = run(Eviction::EvictOldest).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    assert_eq!(evicted.len(), trace.instructions().len());
+    assert!(evicted.iter().any(|r| r.retire_kind.is_none()));
+
+    let path = std::env::temp_dir().join(format!("kanata-spill-{}", std::process::id()));
+    let mut spilled = run(Eviction::Spill(path.clone())).unwrap();
+    spilled.sort_by_key(|r| r.offset);
+    assert_eq!(spilled, trace.instructions());
+    assert!(!path.exists());
+}
+
+#[test]
+fn cursor_replay() {
+    let input = generate(
+        &GenConfig {
+            instructions: 5000,
+            ..GenConfig::default()
+        },
+        Vec::new(),
+    )
+    .unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let index = Index::build(&input, 1 << 12).unwrap();
+    let mut cursor = Cursor::new(&input, &index).unwrap();
+    assert_eq!(cursor.cycle(), 0);
+
+    let expected = |c: i64| {
+        let (mut live, mut retired) = (Vec::new(), Vec::new());
+        for r in trace.instructions() {
+            match r.end {
+                _ if r.start > c => {}
+                Some(end) if end < c => {}
+                Some(end) if end == c => retired.push(r.id),
+                _ => {
+                    let stage = r.stages.iter().rfind(|s| s.start <= c).map(|s| s.stage);
+                    live.push((r.id, stage));
+                }
+            }
+        }
+        (live, retired)
+    };
+    let mut seed = 99u64;
+    for _ in 0..200 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        let n = (seed >> 33) % 300;
+        match (seed >> 20) % 3 {
+            0 => cursor.step_forward(n).unwrap(),
+            1 => cursor.step_back(n).unwrap(),
+            _ => cursor.seek((n * 5) as i64).unwrap(),
+        }
+        let c = cursor.cycle();
+        let live: Vec<_> = cursor
+            .in_flight()
+            .iter()
+            .map(|r| (r.id, r.stages.last().map(|s| s.stage)))
+            .collect();
+        let retired: Vec<_> = cursor.retired().iter().map(|r| r.id).collect();
+        assert_eq!((live, retired), expected(c), "cycle {}", c);
+    }
+}
+
+#[test]
+fn filter_expressions() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let count = |src: &str| {
+        let expr = FilterExpr::parse(src).unwrap();
+        trace
+            .instructions()
+            .iter()
+            .filter(|r| expr.matches(&trace, r))
+            .count()
+    };
+    let all = trace.instructions().len();
+    assert_eq!(count("true"), all);
+    assert_eq!(count("retired || !retired"), all);
+    assert_eq!(
+        count("flushed"),
+        trace
+            .instructions()
+            .iter()
+            .filter(|r| r.is_flushed())
+            .count()
+    );
+    assert_eq!(count("id >= 10 && id < 20"), 10);
+    assert_eq!(count("(id < 5 || id == 0x10) && label =~ \"\""), 6);
+    assert_eq!(count("stage(\"nope\") > 0"), 0);
+    assert_eq!(count("label =~ \"add\"") + count("label !~ \"add\""), all);
+
+    let err = |src: &str| FilterExpr::parse(src).unwrap_err();
+    assert_eq!(err("id == \"x\"").kind, FilterErrorKind::TypeMismatch);
+    assert_eq!(err("id && retired").kind, FilterErrorKind::TypeMismatch);
+    assert_eq!(err("latency").kind, FilterErrorKind::TypeMismatch);
+    assert_eq!(err("bogus == 1").offset, 0);
+    assert_eq!(
+        err("label =~ \"x").kind,
+        FilterErrorKind::UnterminatedString
+    );
+    assert_eq!(err("(id == 1").kind, FilterErrorKind::ExpectedParen);
+    assert_eq!(err("id == 1 2").kind, FilterErrorKind::TrailingInput);
+
+    // nesting past the limit is an error, not a stack overflow
+    let deep =
+        |open: &str, close: &str, n: usize| format!("{}retired{}", open.repeat(n), close.repeat(n));
+    assert!(FilterExpr::parse(&deep("(", ")", 200)).is_ok());
+    assert!(FilterExpr::parse(&deep("!", "", 200)).is_ok());
+    for src in [
+        deep("(", ")", 300_000),
+        deep("!", "", 300_000),
+        deep("(!", ")", 300_000),
+        vec!["retired"; 300_000].join(" && "),
+        vec!["retired"; 300_000].join(" || "),
+    ] {
+        assert_eq!(err(&src).kind, FilterErrorKind::TooDeep);
+    }
+    let chain = vec!["retired"; 200].join(" && ");
+    assert!(FilterExpr::parse(&format!("({}) || ({})", chain, chain)).is_ok());
+}
+
+#[test]
+fn filter_builder() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let same = |f: Filter, src: &str| {
+        let expr = FilterExpr::parse(src).unwrap();
+        let a: Vec<Id> = trace.select(&f).map(|r| r.id).collect();
+        let b: Vec<Id> = trace.select(&expr.into()).map(|r| r.id).collect();
+        assert_eq!(a, b, "{}", src);
+        a.len()
+    };
+    assert_eq!(same(Filter::all(), "true"), trace.instructions().len());
+    assert_eq!(same(Filter::id_range(10..20), "id >= 10 && id < 20"), 10);
+    same(
+        Filter::thread(0)
+            .and(Filter::retired())
+            .and(!Filter::id_range(0..100)),
+        "thread == 0 && retired && !(id < 100)",
+    );
+    same(
+        Filter::flushed().or(Filter::stage_at_least("Is", 3)),
+        "flushed || stage(\"Is\") >= 3",
+    );
+    same(Filter::cycle_range(100..200), "start < 200 && end >= 100");
+
+    let f = Filter::id_range(5..8);
+    let out = write_filtered(&trace, &f, Vec::new()).unwrap();
+    let kept = Trace::new(&out).unwrap();
+    let ids: Vec<Id> = kept.instructions().iter().map(|r| r.sim_id).collect();
+    let want: Vec<Id> = trace.select(&f).map(|r| r.sim_id).collect();
+    assert_eq!(ids, want);
+    let commands: Vec<_> = trace
+        .select_commands(&f)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert!(commands.is_sorted_by_key(|&(cycle, _)| cycle));
+    let starts = commands
+        .iter()
+        .filter(|(_, c)| matches!(c, Command::Instruction { .. }))
+        .count();
+    assert_eq!(starts, 3);
+}
+
+#[test]
+fn sampling() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let out = write_sampled(&input, Sampling::EveryNth(10), Vec::new()).unwrap();
+    let sampled = Trace::new(&out).unwrap();
+    assert_eq!(sampled.instructions().len(), 405);
+    let spans = |t: &Trace, r: &InstructionRecord| {
+        let s: Vec<_> = r
+            .stages
+            .iter()
+            .map(|s| (t.stages().name(s.stage).to_string(), s.start, s.end))
+            .collect();
+        (r.sim_id, r.start, r.end, r.retire_kind, s)
+    };
+    for (i, r) in sampled.instructions().iter().enumerate() {
+        assert_eq!(r.id, i as Id);
+        let orig = &trace.instructions()[i * 10];
+        assert_eq!(spans(&sampled, r), spans(&trace, orig));
+    }
+    let retired: Vec<Id> = sampled
+        .instructions()
+        .iter()
+        .filter(|r| r.is_retired())
+        .filter_map(|r| r.retire_id)
+        .collect();
+    assert!(retired.iter().enumerate().all(|(i, &r)| r == i as Id));
+
+    let random = |seed| {
+        let sampling = Sampling::Random { rate: 0.25, seed };
+        write_sampled(&input, sampling, Vec::new()).unwrap()
+    };
+    assert_eq!(random(3), random(3));
+    let n = Trace::new(&random(3)).unwrap().instructions().len();
+    assert!((800..1200).contains(&n), "{}", n);
+}
+
+#[test]
+fn truncation() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let cut = |until| Trace::from_vec(truncate(&input, until, Vec::new()).unwrap()).unwrap();
+
+    let t = cut(Until::RetiredInstructions(1000));
+    assert_eq!(
+        t.instructions().iter().filter(|r| r.is_retired()).count(),
+        1000
+    );
+    for r in t.instructions() {
+        assert!(r.end.is_some());
+        let orig = trace.get(r.id).unwrap();
+        assert_eq!(
+            (r.start, r.end, r.stages.len()),
+            (orig.start, orig.end, orig.stages.len())
+        );
+    }
+
+    let t = cut(Until::Cycles(500));
+    let start = trace.start_cycle();
+    assert!(!t.instructions().is_empty());
+    assert!(t.instructions().iter().all(|r| r.end < Some(start + 500)));
+
+    assert!(cut(Until::RetiredInstructions(0)).instructions().is_empty());
+    let all = cut(Until::Cycles(u64::MAX));
+    let ended = trace.instructions().iter().filter(|r| r.end.is_some());
+    assert_eq!(all.instructions().len(), ended.count());
+}
+
+#[test]
+fn coarsening() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let coarse = |k| Trace::from_vec(coarsen(&input, k, Vec::new()).unwrap()).unwrap();
+
+    let same = coarse(1);
+    for (a, b) in trace.instructions().iter().zip(same.instructions()) {
+        assert_eq!(
+            (a.start, a.end, a.stages.len()),
+            (b.start, b.end, b.stages.len())
+        );
+    }
+
+    let base = trace.start_cycle();
+    let scale = |c: i64| base + (c - base).div_euclid(10);
+    let t = coarse(10);
+    assert_eq!(t.instructions().len(), trace.instructions().len());
+    assert_eq!(t.end_cycle(), scale(trace.end_cycle()));
+    let (mut before, mut after) = (0, 0);
+    for (a, b) in trace.instructions().iter().zip(t.instructions()) {
+        assert_eq!((scale(a.start), a.end.map(scale)), (b.start, b.end));
+        assert!(
+            b.stages
+                .iter()
+                .all(|s| s.end > s.start || Some(s.end) == b.end || s.end == t.end_cycle())
+        );
+        before += a.stages.len();
+        after += b.stages.len();
+    }
+    assert!(after < before / 2, "{} of {}", after, before);
+}
+
+#[test]
+fn concatenation() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let out = concat(&[&input, &input], Vec::new()).unwrap();
+    let both = Trace::new(&out).unwrap();
+    let n = trace.instructions().len();
+    assert_eq!(both.instructions().len(), 2 * n);
+
+    let shift = trace.end_cycle() + 1 - trace.start_cycle();
+    let retired = trace
+        .instructions()
+        .iter()
+        .filter(|r| r.is_retired())
+        .count() as Id;
+    for (i, r) in trace.instructions().iter().enumerate() {
+        let (a, b) = (&both.instructions()[i], &both.instructions()[n + i]);
+        assert_eq!((a.start, a.end), (r.start, r.end));
+        assert_eq!(b.id, a.id + n as Id);
+        assert_eq!(b.start, a.start + shift);
+        assert_eq!(b.stages.len(), a.stages.len());
+        if r.is_retired() {
+            assert_eq!(b.retire_id, a.retire_id.map(|v| v + retired));
+        }
+    }
+}
+
+#[test]
+fn rebasing() {
+    let input =
+        b"Kanata\t0004\nC=\t1000000\nC\t50\nI\t0\t0\t0\nS\t0\t0\tF\nC\t3\nE\t0\t0\tF\nR\t0\t0\t0\n";
+    let out = rebase(input, 0, Vec::new()).unwrap();
+    assert_snapshot!(String::from_utf8(out).unwrap(), @r"
+    Kanata	0004
+    C=	0
+    I	0	0	0
+    S	0	0	F
+    C	3
+    E	0	0	F
+    R	0	0	0
+    ");
+    let trace = Trace::new(input).unwrap();
+    let out = rebase(input, 100, Vec::new()).unwrap();
+    let moved = Trace::new(&out).unwrap();
+    assert_eq!(moved.start_cycle(), 100);
+    assert_eq!(
+        moved.end_cycle() - 100,
+        trace.end_cycle() - trace.start_cycle()
+    );
+}
+
+#[test]
+fn sorting_by_cycle() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t5\nI\t1\t1\t0\nC\t-3\nE\t0\t0\tF\nS\t0\t0\tX\nS\t1\t0\tF\nC\t9\nR\t0\t0\t0\nC\t-20\nR\t1\t1\t0\n";
+    let (out, report) = sort_by_cycle(input, 8, Vec::new()).unwrap();
+    assert_snapshot!(String::from_utf8(out).unwrap(), @r"
+    Kanata	0004
+    C=	0
+    I	0	0	0
+    S	0	0	F
+    C	2
+    E	0	0	F
+    S	0	0	X
+    C	3
+    I	1	1	0
+    S	1	0	F
+    R	1	1	0
+    C	6
+    R	0	0	0
+    ");
+    assert_eq!(
+        report,
+        SortReport {
+            commands: 8,
+            reordered: 3,
+            max_lag: 6,
+            clamped: 2,
+        }
+    );
+}
+
+#[test]
+fn redundancy_elimination() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\tadd\nS\t0\t0\tF\nC\t0\nS\t0\t0\tF\nL\t0\t0\tadd\nL\t0\t1\tadd\nC\t1\nI\t1\t1\t0\nW\t1\t0\t0\nW\t1\t0\t0\nE\t0\t0\tF\nS\t0\t0\tF\nR\t0\t0\t0\n";
+    let (out, report) = optimize(input, Vec::new()).unwrap();
+    assert_snapshot!(String::from_utf8(out).unwrap(), @r"
+    Kanata	0004
+    C=	0
+    I	0	0	0
+    L	0	0	add
+    S	0	0	F
+    L	0	1	add
+    C	1
+    I	1	1	0
+    W	1	0	0
+    E	0	0	F
+    S	0	0	F
+    R	0	0	0
+    ");
+    assert_eq!(
+        report,
+        OptimizeReport {
+            cycles: 1,
+            stages: 1,
+            logs: 1,
+            deps: 1,
+        }
+    );
+}
+
+#[test]
+fn compact_profile() {
+    let input = "Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\tld r1\nL\t0\t1\tmiss\nL\t0\t0\t, [r2]\nL\t0\t2\tx\nI\t1\t1\t0\nL\t1\t0\tabcdef\\n\nI\t2\t2\t0\nL\t2\t0\tλλλλ\nC\t1\nR\t0\t0\t0\n";
+    let config = CompactConfig {
+        label_budget: Some(7),
+    };
+    let out = compact(input.as_bytes(), &config, Vec::new()).unwrap();
+    assert_snapshot!(String::from_utf8(out).unwrap(), @r"
+    Kanata	0004
+    C=	0
+    I	0	0	0
+    L	0	0	ld r1
+    L	0	0	, 
+    I	1	1	0
+    L	1	0	abcdef
+    I	2	2	0
+    L	2	0	λλλ
+    C	1
+    R	0	0	0
+    ");
+    let out = compact(input.as_bytes(), &CompactConfig::default(), Vec::new()).unwrap();
+    let trace = Trace::new(&out).unwrap();
+    let logs = &trace.instructions()[0].logs;
+    assert_eq!(logs.len(), 2);
+    assert_eq!(trace.text(logs[1].text), b", [r2]");
+}
+
+#[test]
+fn thread_table() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t1\nI\t1\t1\t0\nI\t2\t2\t1\nI\t3\t3\t1\nI\t4\t4\t0\nC\t1\nR\t0\t0\t0\nR\t2\t1\t0\nR\t1\t2\t0\nR\t3\t3\t0\nR\t4\t0\t1\n";
+    let trace = Trace::new(input).unwrap();
+    let threads: Vec<_> = trace
+        .threads()
+        .iter()
+        .map(|t| (t.id, t.instructions.clone(), t.retired, t.flushed))
+        .collect();
+    assert_eq!(threads, [(0, vec![1, 4], 1, 1), (1, vec![0, 2, 3], 3, 0)]);
+    let ids: Vec<Id> = trace.thread_instructions(1).map(|r| r.id).collect();
+    assert_eq!(ids, [0, 2, 3]);
+    assert_eq!(trace.thread_instructions(7).count(), 0);
+    let m = trace.interleaving();
+    assert_eq!(
+        m,
+        Interleaving {
+            retirements: 4,
+            switches: 2,
+            longest_run: 2,
+        }
+    );
+    assert!((m.switch_rate() - 2.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn retire_bandwidth() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nI\t1\t1\t0\nS\t1\t0\tF\nC\t1\nS\t0\t0\tX\nS\t1\t0\tX\nC\t3\nR\t0\t0\t0\nR\t1\t1\t0\nI\t2\t2\t0\nS\t2\t0\tF\nC\t1\nR\t2\t2\t0\nC\t2\n";
+    let trace = Trace::new(input).unwrap();
+    let bw = trace.retire_bandwidth(None);
+    let stalls: Vec<_> = bw
+        .stalls
+        .iter()
+        .map(|&(s, n)| (trace.stages().name(s), n))
+        .collect();
+    assert_eq!((bw.width, bw.cycles, bw.retired), (2, 8, 3));
+    assert_eq!(bw.slots, [6, 1, 1]);
+    assert_eq!(stalls, [("X", 3), ("F", 1)]);
+    assert_eq!(bw.unattributed, 2);
+    assert_eq!(bw.zero_cycles(), 6);
+    assert!((trace.retire_bandwidth(Some(4)).utilization() - 3.0 / 32.0).abs() < 1e-9);
+}
+
+#[test]
+fn dep_labels() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nI\t1\t1\t0\nW\t1\t0\t0\tbypass r3\nW\t1\t0\t0\t \nC\t1\nR\t0\t0\t0\nR\t1\t1\t0\n";
+    assert_snapshot!(parse_and_pretty_print(input).unwrap(), @r#"
+    Kanata version=4
+    Cycle =0
+    Instr file=0 sim=0 thread=0
+    Instr file=1 sim=1 thread=0
+    Dep 1 <- 0 (wakeup) label="bypass r3"
+    Dep 1 <- 0 (wakeup)
+    Cycle +1
+    Retire id=0 rid=0 kind=retire
+    Retire id=1 rid=1 kind=retire
+    "#);
+
+    let trace = Trace::new(input).unwrap();
+    let deps = &trace.get(1).unwrap().producers;
+    let labels: Vec<_> = deps
+        .iter()
+        .map(|d| d.label.map(|l| trace.text(l)))
+        .collect();
+    assert_eq!(labels, [Some(&b"bypass r3"[..]), None]);
+
+    let mut text = Vec::new();
+    migrate(input, &mut text).unwrap();
+    let binary = convert_to_binary(input, Vec::new()).unwrap();
+    for data in [&text, &binary] {
+        let labels: Vec<_> = Commands::new(data)
+            .unwrap()
+            .filter_map(|(_, c)| match c.unwrap() {
+                Command::Dep { label, .. } => Some(label.map(|l| l.get(data).to_vec())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, [Some(b"bypass r3".to_vec()), None]);
+    }
+}
+
+#[test]
+fn dep_labels_strict() {
+    let dep = |line: &[u8], extensions: bool| {
+        let mut input = b"Kanata\t0004\n".to_vec();
+        input.extend_from_slice(line);
+        let mut parser = Parser::new(&input).strict();
+        if extensions {
+            parser = parser.extensions();
+        }
+        let (_, cmd) = parser.nth(1).unwrap();
+        cmd.map(|c| match c {
+            Command::Dep { kind, label, .. } => (kind, label.map(|l| l.get(&input).to_vec())),
+            c => panic!("{c:?}"),
+        })
+        .map_err(|e| (e.offset, e.kind))
+    };
+    let garbage = Err((19, ParseErrorKind::TrailingGarbage));
+    // a label needs a tab before it, and the extension
+    assert_eq!(dep(b"W\t1\t0\t01\n", true), garbage);
+    assert_eq!(
+        dep(b"W\t1\t0\t0 junk\n", true),
+        Err((20, ParseErrorKind::TrailingGarbage))
+    );
+    assert_eq!(
+        dep(b"W\t1\t0\t0\tr3\n", false),
+        Err((20, ParseErrorKind::TrailingGarbage))
+    );
+    assert_eq!(
+        dep(b"W\t1\t0\t0\tr3\n", true),
+        Ok((DepKind::WakeUp, Some(b"r3".to_vec())))
+    );
+    assert_eq!(dep(b"W\t1\t0\t0\t\n", true), Ok((DepKind::WakeUp, None)));
+}
+
+#[test]
+fn log_panes() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\t 0x40: add\nL\t0\t1\tsrc=r1\nL\t0\t0\t r2, r3 \nL\t0\t1\t\\ndst=r2\nL\t0\t2\tx\nI\t1\t1\t0\nL\t1\t1\tonly\nC\t1\nR\t0\t0\t0\nR\t1\t1\t0\n";
+    let trace = Trace::new(input).unwrap();
+    let (a, b) = (trace.get(0).unwrap(), trace.get(1).unwrap());
+    assert_eq!(&*trace.label(a), b"0x40: add r2, r3");
+    assert_eq!(&*trace.detail(a), b"src=r1\\ndst=r2");
+    assert_eq!(&*trace.extra(a), b"x");
+    assert_eq!(trace.pc(a), Some(0x40));
+    assert!(trace.label(b).is_empty());
+    assert!(matches!(
+        trace.detail(b),
+        std::borrow::Cow::Borrowed(b"only")
+    ));
+}
+
+#[test]
+fn stage_colors() {
+    let input = b"Kanata\t0004\nP\t#4e79a7\tF\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nS\t0\t0\tX\nR\t0\t0\t0\n";
+    let mut strict = Parser::new(input);
+    assert!(strict.find_map(|(_, c)| c.err()).is_some());
+    let parsed: Vec<_> = Parser::new(input)
+        .extensions()
+        .map(|(_, c)| c.unwrap().into_owned(input))
+        .collect();
+    assert_eq!(
+        parsed[1],
+        Command::StageColor {
+            name: b"F".to_vec(),
+            color: 0x4e79a7
+        }
+    );
+    assert_eq!(round_trip(&parsed).unwrap(), parsed);
+
+    let binary = convert_to_binary(input, Vec::new()).unwrap();
+    for data in [&input[..], &binary] {
+        let trace = Trace::new(data).unwrap();
+        let stages = trace.stages();
+        assert_eq!(stages.color(stages.get("F").unwrap()), Some(0x4e79a7));
+        assert_eq!(stages.color(stages.get("X").unwrap()), None);
+    }
+
+    #[cfg(feature = "render")]
+    {
+        let trace = Trace::new(input).unwrap();
+        let mut svg = Vec::new();
+        render_svg(&trace, &SvgConfig::default(), &mut svg).unwrap();
+        assert!(String::from_utf8(svg).unwrap().contains("fill=\"#4e79a7\""));
+    }
+}
+
+#[test]
+fn stage_ids_run_out() {
+    use std::io::Write as _;
+
+    let mut stages = StageTable::new();
+    for i in 0..=u16::MAX {
+        stages.intern(format!("s{}", i).as_bytes()).unwrap();
+    }
+    assert_eq!(
+        stages.intern(b"one more"),
+        Err(ParseErrorKind::TooManyStages)
+    );
+    assert_eq!(stages.intern(b"s7").unwrap(), stages.get("s7").unwrap());
+    assert_eq!(stages.len(), 1 << 16);
+
+    // a trace naming one too many fails at the first line past the last id
+    let mut input = b"Kanata\t0004\nC=\t0\n".to_vec();
+    for i in 0..=u16::MAX {
+        writeln!(input, "I\t{0}\t{0}\t0\nS\t{0}\t0\ts{0}\nR\t{0}\t{0}\t0", i).unwrap();
+    }
+    let full = input.clone();
+    input.extend_from_slice(b"I\t65536\t65536\t0\n");
+    let at = input.len();
+    input.extend_from_slice(b"S\t65536\t0\tone more\n");
+    let err = Trace::new(&input).err().unwrap();
+    assert_eq!((err.kind, err.offset), (ParseErrorKind::TooManyStages, at));
+
+    // and a workspace refuses a trace whose stages don't fit with the rest
+    let mut ws = Workspace::new();
+    ws.add("full", Trace::from_vec(full).unwrap()).unwrap();
+    let other = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tone more\n".to_vec();
+    let other = Trace::from_vec(other).unwrap();
+    assert_eq!(
+        ws.add("other", other).err(),
+        Some(ParseErrorKind::TooManyStages)
+    );
+    assert_eq!(ws.stages().len(), 1 << 16);
+    assert!(ws.get("other").is_none());
+}
+
+#[test]
+fn strict_trailing_content() {
+    let input = b"Kanata\t0004 \nC=\t0\t\nI\t0\t0\t0\t5\nR\t0\t0\t0\n";
+    // the parser doesn't move past an error, so only look that far
+    let kinds = |p: Parser| {
+        p.take(4)
+            .map(|(o, c)| (o, c.err().map(|e| (e.offset, e.kind))))
+            .collect::<Vec<_>>()
+    };
+    let garbage = Some((27, ParseErrorKind::TrailingGarbage));
+    assert_eq!(
+        kinds(Parser::new(input).strict())[..3],
+        [(0, None), (13, None), (19, garbage)]
+    );
+    // without it the extra field is read as the start of another command
+    assert_eq!(
+        kinds(Parser::new(input))[3],
+        (27, Some((27, ParseErrorKind::UnexpectedCharacter)))
+    );
+    let errors: Vec<_> = check(input).iter().map(|e| (e.offset, e.kind)).collect();
+    assert_eq!(errors, [(27, ParseErrorKind::TrailingGarbage)]);
+}
+
+#[test]
+fn line_trivia() {
+    let doc = Document::new(
+        b"Kanata\t0004  \r\nC=\t0\nI\t0\t0\t0\nL\t0\t0\t 0x40: add \nW\t0\t0\t0\t \t r3 \nW\t0\t0\t0\t\nR\t0\t0\t0 x\n"
+            .to_vec(),
+    );
+    let spans = |i: usize| {
+        let t = doc.trivia(i);
+        let text = |r: std::ops::Range<usize>| String::from_utf8_lossy(&doc.text()[r]).into_owned();
+        let seps: Vec<_> = t.separators.into_iter().map(text).collect();
+        format!("{:?} {:?} {:?}", seps, text(t.trailing), text(t.line_end))
+    };
+    let all: Vec<_> = (0..doc.lines().len()).map(spans).collect();
+    // the `x` after the retire is a line of its own to the document
+    assert_snapshot!(all.join("\n"), @r#"
+    ["\t"] "  " "\r\n"
+    ["\t"] "" "\n"
+    ["\t", "\t", "\t"] "" "\n"
+    ["\t", "\t", "\t"] "" "\n"
+    ["\t", "\t", "\t", "\t \t "] "" "\n"
+    ["\t", "\t", "\t"] "\t" "\n"
+    ["\t", "\t", "\t"] " " ""
+    [] "" "\n"
+    "#);
+}
+
+#[test]
+fn newline_policies() {
+    let input = b"Kanata\t0004\n\nC=\t0\r\nI\t0\t0\t0\r\rR\t0\t0\t0";
+    let mut parser = Parser::new(input);
+    let commands: Vec<_> = parser.by_ref().map(|(o, c)| (o, c.is_ok())).collect();
+    assert_eq!(commands, [(0, true), (13, true), (19, true), (28, true)]);
+    assert_eq!(parser.metrics().lines, 6);
+    assert_eq!(parser.metrics().bytes, input.len() as u64);
+    let warnings: Vec<_> = parser
+        .take_warnings()
+        .iter()
+        .map(|w| (w.offset, w.kind))
+        .collect();
+    assert_eq!(warnings, [(17, WarningKind::MixedLineEndings)]);
+
+    let first_error = |newline| {
+        Parser::new(input)
+            .newlines(newline)
+            .find_map(|(_, c)| c.err())
+            .map(|e| e.offset)
+    };
+    assert_eq!(first_error(Newline::Auto), None);
+    assert_eq!(first_error(Newline::Lf), Some(17));
+    assert_eq!(first_error(Newline::CrLf), Some(11));
+
+    let doc = Document::new(b"Kanata\t0004\n\n\nC=\t0\n\n".to_vec());
+    let offsets: Vec<_> = doc.lines().iter().map(|l| l.offset).collect();
+    assert_eq!(offsets, [0, 14]);
+    assert_eq!(doc.errors().count(), 0);
+}
+
+#[test]
+fn line_index() {
+    let input = b"Kanata\t0004\r\nC=\t0\rI\t0\t0\t0\n\nR";
+    let lines = LineIndex::new(input);
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines.line_col(0), (0, 0));
+    assert_eq!(lines.line_col(12), (0, 12));
+    assert_eq!(lines.line_col(13), (1, 0));
+    assert_eq!(lines.line_col(20), (2, 2));
+    assert_eq!(lines.line_col(input.len() + 5), (4, 1));
+    let texts: Vec<_> = (0..lines.len()).map(|l| lines.line_text(l)).collect();
+    assert_eq!(
+        texts,
+        [&b"Kanata\t0004"[..], b"C=\t0", b"I\t0\t0\t0", b"", b"R"]
+    );
+}
+
+#[test]
+fn textual_diff() {
+    let a = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nI\t1\t1\t0\nL\t0\t0\tadd\nL\t1\t0\tsub\nS\t0\t0\tF\nS\t1\t0\tF\nC\t1\nE\t0\t0\tF\nR\t0\t0\t0\nC\t1\nR\t1\t1\t0\n";
+    // same-cycle records in another order, with one label changed
+    let b = b"Kanata\t0004\nC=\t0\nI\t1\t1\t0\nI\t0\t0\t0\nS\t1\t0\tF\nL\t1\t0\tsub\nL\t0\t0\tmul\nS\t0\t0\tF\nC\t1\nE\t0\t0\tF\nR\t0\t0\t0\nC\t1\nR\t1\t1\t0\n";
+    let reordered = b"Kanata\t0004\nC=\t0\nI\t1\t1\t0\nI\t0\t0\t0\nL\t1\t0\tsub\nL\t0\t0\tadd\nS\t1\t0\tF\nS\t0\t0\tF\nC\t1\nR\t0\t0\t0\nE\t0\t0\tF\nC\t1\nR\t1\t1\t0\n";
+    assert_eq!(crate::textual_diff(a, reordered).unwrap(), "");
+    assert_snapshot!(crate::textual_diff(a, b).unwrap(), @r"
+    --- a
+    +++ b
+    @@ -2,7 +2,7 @@ C=	0
+     C=	0
+     I	0	0	0
+     I	1	1	0
+    -L	0	0	add
+    +L	0	0	mul
+     L	1	0	sub
+     S	0	0	F
+     S	1	0	F
+    ");
+}
+
+#[test]
+fn command_sources() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let data = convert_to_binary(&input, Vec::new()).unwrap();
+    let buffer: CommandBuffer = Parser::new(&input)
+        .extensions()
+        .map(|(_, cmd)| cmd.unwrap().into_owned(&input))
+        .collect();
+
+    let mut stamps = Vec::new();
+    let mut parser = Parser::new(&input);
+    while let Some(cmd) = parser.next_command().filter(|_| stamps.len() < 4) {
+        let cmd = cmd.unwrap();
+        stamps.push((cmd.offset, cmd.cycle));
+    }
+    let mut binary = BinaryReader::new(&data).unwrap();
+    let mut cycles = Vec::new();
+    while let Some(cmd) = binary.next_command() {
+        cycles.push(cmd.unwrap().cycle);
+    }
+    let mut from_buffer = buffer.source();
+    assert_eq!(from_buffer.next_command().unwrap().unwrap().offset, 0);
+    assert_eq!(cycles.len(), buffer.len());
+    assert_eq!(
+        cycles.last(),
+        Some(&Trace::new(&input).unwrap().end_cycle())
+    );
+
+    let summary = |t: &Trace| {
+        t.instructions()
+            .iter()
+            .map(|r| (r.id, r.start, r.end, t.label(r).to_vec(), r.stages.len()))
+            .collect::<Vec<_>>()
+    };
+    let text = Trace::new(&input).unwrap();
+    let traces = [
+        Trace::from_source(Parser::new(&input).extensions()).unwrap(),
+        Trace::from_source(BinaryReader::new(&data).unwrap()).unwrap(),
+        Trace::from_source(buffer.source()).unwrap(),
+    ];
+    for t in &traces {
+        assert_eq!(summary(t), summary(&text));
+    }
+    let stats = Stats::from_source(buffer.source(), DEFAULT_MAX_IN_FLIGHT).unwrap();
+    let expected = Stats::from_trace(&text);
+    assert_eq!(
+        (stats.instructions(), stats.retired(), stats.cycles()),
+        (
+            expected.instructions(),
+            expected.retired(),
+            expected.cycles()
+        )
+    );
+    assert_snapshot!(format!("{:?}", stamps), @"[(0, 0), (12, -1), (18, 0), (22, 0)]");
+}
+
+#[test]
+fn id_width() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t4294967296\t4294967297\t0\nR\t4294967296\t0\t0\n";
+    let cmd = Parser::new(input).nth(2).unwrap().1;
+    #[cfg(feature = "wide-ids")]
+    {
+        assert!(cmd.is_ok());
+        let trace = Trace::new(input).unwrap();
+        assert_eq!(trace.instructions()[0].sim_id, 4294967297);
+        let data = convert_to_binary(input, Vec::new()).unwrap();
+        let binary = Trace::new(&data).unwrap();
+        assert_eq!(binary.instructions()[0].id, 4294967296);
+    }
+    #[cfg(not(feature = "wide-ids"))]
+    assert_eq!(cmd.unwrap_err().kind, ParseErrorKind::ValueTooBig);
+}
+
+#[test]
+fn borrowed_commands() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\tadd\nW\t0\t0\t0\tr\xff\n";
+    let cmds: Vec<_> = Parser::new(input)
+        .extensions()
+        .borrowed()
+        .take(5)
+        .map(|(_, c)| c.unwrap())
+        .collect();
+    assert_eq!(
+        cmds[3],
+        Command::Log {
+            id: 0,
+            kind: LogKind::LeftPane,
+            text: &b"add"[..]
+        }
+    );
+    let expected: Vec<_> = Parser::new(input)
+        .extensions()
+        .take(5)
+        .map(|(_, c)| c.unwrap().resolve(input))
+        .collect();
+    assert_eq!(cmds, expected);
+
+    let mut utf8 = Parser::new(input).extensions().utf8();
+    let text: Vec<_> = utf8
+        .by_ref()
+        .take(4)
+        .filter_map(|(_, c)| c.unwrap().text().copied())
+        .collect();
+    assert_eq!(text, ["add"]);
+    let err = utf8.next().unwrap().1.unwrap_err();
+    assert_eq!(
+        (err.offset, err.kind),
+        (input.len() - 2, ParseErrorKind::InvalidUtf8)
+    );
+    assert_eq!(utf8.parser().metrics().deps, 1);
+}
+
+#[test]
+fn packed_events() {
+    assert_eq!(std::mem::size_of::<PackedEvent>(), 16);
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let packed = PackedTrace::from_source(Parser::new(&input).extensions()).unwrap();
+    let commands: Vec<Command> = Parser::new(&input)
+        .extensions()
+        .map(|(_, cmd)| cmd.unwrap())
+        .collect();
+    assert!(packed.events().len() < commands.len());
+
+    // the same commands come back, short of how the cycle commands are split
+    let unpacked: Vec<Command> = packed.commands().collect();
+    let plain = |cmds: &[Command]| {
+        let mut clock = Clock::new();
+        cmds.iter()
+            .filter_map(|c| {
+                clock.apply(c);
+                let c = c.into_owned(&input);
+                (!matches!(c, OwnedCommand::Cycle { .. })).then_some((clock.cycle(), c))
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(plain(&unpacked), plain(&commands));
+    let trace =
+        Trace::from_source(Clocked::new(&input, unpacked.iter().map(|&c| (0, Ok(c))))).unwrap();
+    assert_eq!(trace.end_cycle(), Trace::new(&input).unwrap().end_cycle());
+
+    let mut big = PackedTrace::new();
+    let far = Command::Cycle {
+        abs: false,
+        value: 1 << 30,
+    };
+    big.push(0, far, b"").unwrap();
+    big.push(0, Command::Kanata { version: 4 }, b"").unwrap();
+    assert_eq!(big.events().len(), 2);
+    assert_eq!(big.commands().next(), Some(far));
+}
+
+#[test]
+fn pipelined_analysis() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let stats = |b: Batches| Stats::from_source(b, 1 << 16).unwrap().instructions() as usize;
+    let trace = |b: Batches| Trace::from_source(b).unwrap().instructions().len();
+    let consumers: Vec<fn(Batches) -> usize> = vec![stats, trace, |b| b.map(|b| b.len()).sum()];
+    let counts = Pipelined::new()
+        .batch(7)
+        .depth(1)
+        .run(Parser::new(&input).extensions(), consumers)
+        .unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let commands = Parser::new(&input).extensions().count();
+    assert_eq!(
+        counts,
+        [
+            trace.instructions().len(),
+            trace.instructions().len(),
+            commands
+        ]
+    );
+
+    // consumers see what came before the error, then the error is returned
+    let input = b"Kanata\t0004\nI\t0\t0\t0\nI\t1\t1\t0\nX\n";
+    let seen = |b: Batches| b.map(|b| b.len()).sum::<usize>();
+    let err = Pipelined::new()
+        .batch(1)
+        .run(Parser::new(input), vec![seen, seen])
+        .unwrap_err();
+    assert_eq!(err.offset, input.len() - 2);
+    let seen = std::sync::Mutex::new(0);
+    let _ = Pipelined::new().run(
+        Parser::new(input),
+        vec![|b: Batches| {
+            *seen.lock().unwrap() = b.map(|b| b.len()).sum::<usize>();
+        }],
+    );
+    assert_eq!(*seen.lock().unwrap(), 3);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_index() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let every = Index::build(&input, 1).unwrap();
+    for interval in [64, 4096, 1 << 20] {
+        let index = Index::build_parallel(&input, interval).unwrap();
+        assert_eq!(index.end(), every.end());
+        assert_eq!(index.interval(), interval);
+        assert_eq!(index.checkpoints()[0], every.checkpoints()[0]);
+        for cp in index.checkpoints() {
+            let i = every
+                .checkpoints()
+                .partition_point(|c| c.offset < cp.offset);
+            assert_eq!(every.checkpoints()[i], *cp);
+        }
+        assert!(index.checkpoints().len() >= input.len() / interval / 2);
+    }
+    let small = std::fs::read("testinput/kanata-sample-1.log").unwrap();
+    let serial = Index::build(&small, 64).unwrap();
+    assert_eq!(Index::build_parallel(&small, 64).unwrap(), serial);
+
+    let mut bad = input.clone();
+    bad.extend_from_slice(b"Q\n");
+    let err = Index::build_parallel(&bad, 64).unwrap_err();
+    assert_eq!(err, Index::build(&bad, 64).unwrap_err());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_stats() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let whole = Stats::streaming(&input, DEFAULT_MAX_IN_FLIGHT).unwrap();
+    let summary = |s: &Stats| {
+        let mut stages: Vec<_> = s.iter().map(|(_, n, sum)| (n.to_string(), sum)).collect();
+        stages.sort_by(|a, b| a.0.cmp(&b.0));
+        let counts = (s.instructions(), s.retired(), s.flushed(), s.cycles());
+        (counts, s.latency(), stages)
+    };
+    for interval in [64, 4096, 1 << 20] {
+        let index = Index::build(&input, interval).unwrap();
+        let stats = Stats::parallel(&input, &index, DEFAULT_MAX_IN_FLIGHT).unwrap();
+        assert_eq!(summary(&stats), summary(&whole));
+    }
+    assert!(Index::build(&input, 64).unwrap().checkpoints().len() > 10);
+
+    // an instruction near the start that never retires, and one in a stage
+    // all the way from the middle, both handed on from their stretches
+    let mut stuck = input.clone();
+    let at = memchr::memmem::find(
+        &stuck, b"
+C	1
+",
+    )
+    .unwrap()
+        + 1;
+    stuck.splice(
+        at..at,
+        b"I	900000	0	0
+S	900000	0	stuck
+"
+        .iter()
+        .copied(),
+    );
+    let mid = stuck.len() / 2;
+    let at = mid
+        + memchr::memmem::find(
+            &stuck[mid..],
+            b"
+C	1
+",
+        )
+        .unwrap()
+        + 1;
+    stuck.splice(
+        at..at,
+        b"I	900001	0	0
+S	900001	0	long
+"
+        .iter()
+        .copied(),
+    );
+    stuck.extend_from_slice(
+        b"R	900001	0	0
+",
+    );
+    for data in [stuck, sample_0003()] {
+        let whole = Stats::streaming(&data, DEFAULT_MAX_IN_FLIGHT).unwrap();
+        for interval in [64, 4096] {
+            let index = Index::build(&data, interval).unwrap();
+            let stats = Stats::parallel(&data, &index, DEFAULT_MAX_IN_FLIGHT).unwrap();
+            assert_eq!(summary(&stats), summary(&whole));
+        }
+    }
+}
+
+#[test]
+fn cycle_watchdog() {
+    let input = b"Kanata\t0004\nC=\t100\nC\t5\nC=\t100\nC\t1000\nC=\t2000\nC\t1\n";
+    let run = |watchdog| {
+        let mut parser = Parser::new(input).watch_cycles(watchdog);
+        let mut cycles = Vec::new();
+        while let Some((_, cmd)) = parser.next() {
+            cmd.unwrap();
+            cycles.push(parser.current_cycle());
+        }
+        let warnings: Vec<_> = parser
+            .take_warnings()
+            .iter()
+            .map(|w| (w.offset, w.kind.code()))
+            .collect();
+        (cycles, warnings)
+    };
+    let (cycles, warnings) = run(CycleWatchdog::default());
+    assert_eq!(cycles, [0, 100, 105, 100, 1100, 2000, 2001]);
+    assert_eq!(warnings, [(23, "cycle-went-back")]);
+
+    let watchdog = CycleWatchdog {
+        max_jump: Some(500),
+        correct: false,
+    };
+    let (_, warnings) = run(watchdog);
+    assert_eq!(
+        warnings,
+        [
+            (23, "cycle-went-back"),
+            (30, "cycle-jumped"),
+            (37, "cycle-jumped")
+        ]
+    );
+    let (cycles, _) = run(CycleWatchdog {
+        correct: true,
+        ..watchdog
+    });
+    assert_eq!(cycles, [0, 100, 105, 105, 605, 1105, 1106]);
+    let commands: Vec<_> = Parser::new(input)
+        .watch_cycles(CycleWatchdog {
+            correct: true,
+            ..watchdog
+        })
+        .map(|(_, cmd)| cmd.unwrap())
+        .collect();
+    assert_eq!(
+        commands[3],
+        Command::Cycle {
+            abs: true,
+            value: 105
+        }
+    );
+    assert_eq!(
+        commands[4],
+        Command::Cycle {
+            abs: false,
+            value: 500
+        }
+    );
+}
+
+#[test]
+fn repair_truncated() {
+    let input = b"Kanata\t0004\nC=\t10\nI\t0\t0\t0\nS\t0\t0\tF\nI\t1\t1\t0\nS\t1\t0\tF\nS\t1\t1\tX\nE\t1\t0\tF\nR\t0\t7\t0\nC\t3\nL\t1\t0\tha";
+    let (out, report) = repair(input, Vec::new()).unwrap();
+    assert_eq!(report.dropped, 8);
+    assert_eq!((report.ended, report.flushed), (1, vec![1]));
+    assert_snapshot!(String::from_utf8(out[input.len() - 8..].to_vec()).unwrap(), @"
+    E	1	1	X
+    R	1	8	1
+    ");
+    let trace = Trace::new(&out).unwrap();
+    let ends: Vec<_> = trace.instructions().iter().map(|r| r.end).collect();
+    assert_eq!(ends, [Some(10), Some(13)]);
+
+    // a last line with its line break but not all its fields goes too
+    let input = b"Kanata\t0004\nI\t0\t0\t0\nR\t0\n";
+    let (out, report) = repair(input, Vec::new()).unwrap();
+    assert_eq!(report.dropped, 4);
+    assert_eq!(out, b"Kanata\t0004\nI\t0\t0\t0\nR\t0\t0\t1\n");
+    assert_eq!(
+        repair(b"Kanata\t0004\nX\nI\t0\t0\t0\n", Vec::new())
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn budgeted_parsing() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let whole: Vec<_> = Parser::new(&input)
+        .map(|(o, cmd)| (o, cmd.unwrap()))
+        .collect();
+    let mut parser = Parser::new(&input);
+    let mut parts = Vec::new();
+    let mut rounds = 0;
+    loop {
+        let mut part = parser.take_budget(Budget::Bytes(4096));
+        parts.extend(part.by_ref().map(|(o, cmd)| (o, cmd.unwrap())));
+        rounds += 1;
+        if !part.exhausted() {
+            break;
+        }
+        let at = parser.get_offset();
+        assert!(input[at - 1] == b'\n', "stopped mid-line at {}", at);
+    }
+    assert_eq!(parts, whole);
+    // each round ends on the first line boundary past the budget
+    assert!(rounds > 1 && rounds <= input.len().div_ceil(4096));
+
+    let mut parser = Parser::new(&input);
+    let mut none = parser.take_budget(Budget::Millis(0));
+    assert_eq!((none.next(), none.exhausted()), (None, true));
+    let mut all = parser.take_budget(Budget::Millis(60_000));
+    assert_eq!(all.by_ref().count(), whole.len());
+    assert!(!all.exhausted());
+
+    // the bytes or the time, whichever runs out first
+    let mut parser = Parser::new(&input);
+    let both = |bytes, millis| Budget::Both { bytes, millis };
+    let mut part = parser.take_budget(both(4096, 60_000));
+    let n = part.by_ref().count();
+    assert!(part.exhausted());
+    assert!((4096..4096 + 256).contains(&parser.get_offset()));
+    let mut none = parser.take_budget(both(usize::MAX, 0));
+    assert_eq!((none.next(), none.exhausted()), (None, true));
+    let mut rest = parser.take_budget(both(usize::MAX, 60_000));
+    assert_eq!(n + rest.by_ref().count(), whole.len());
+    assert!(!rest.exhausted());
+}
+
+#[test]
+fn push_parser_carry() {
+    let parse_all = |input: &[u8], splits: &mut dyn Iterator<Item = usize>| {
+        let mut push = PushParser::new(Parser::new(b"").extensions());
+        let mut out = Vec::new();
+        let mut at = 0;
+        let mut carried = 0;
+        for end in splits.chain([input.len()]) {
+            let end = end.clamp(at, input.len());
+            push.feed(&input[at..end], |o, c| {
+                out.push((o, c.map_text(<[u8]>::to_vec)))
+            })
+            .unwrap();
+            carried = carried.max(push.carry_len());
+            at = end;
+        }
+        push.finish(|o, c| out.push((o, c.map_text(<[u8]>::to_vec))))
+            .unwrap();
+        (out, push.take_warnings(), carried)
+    };
+    let whole = |input: &[u8]| {
+        let mut parser = Parser::new(input).extensions();
+        let cmds: Vec<_> = parser
+            .by_ref()
+            .map(|(o, c)| (o, c.unwrap().map_text(|s| s.get(input).to_vec())))
+            .collect();
+        (cmds, parser.take_warnings())
+    };
+
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let (cmds, warnings) = whole(&input);
+    for size in [7, 64, 4096] {
+        let (out, w, carried) = parse_all(&input, &mut (size..input.len()).step_by(size));
+        assert_eq!((out, w), (cmds.clone(), warnings.clone()), "size {}", size);
+        assert!(carried < 4096);
+    }
+
+    // splits at every point of a line ending in `\r\n`, a number and a text,
+    // and a last line without a line break
+    let input = b"Kanata\t0004\r\nC=\t1234\r\nI\t0\t0\t0\r\nL\t0\t0\tsome text\r\nC\t1\rE\t0\t0\tX";
+    let (cmds, warnings) = whole(input);
+    assert_eq!(warnings.len(), 1);
+    for a in 0..input.len() {
+        for b in a..input.len() {
+            let (out, w, _) = parse_all(input, &mut [a, b].into_iter());
+            assert_eq!((&out, &w), (&cmds, &warnings), "split at {} and {}", a, b);
+        }
+    }
+    let mut push = PushParser::new(Parser::new(b""));
+    push.feed(b"Kanata\t0004\nC=\t12", |_, _| {}).unwrap();
+    assert_eq!(push.carry_len(), 5);
+    let mut cycle = None;
+    push.feed(b"34\nI\t0", |o, c| {
+        cycle = Some((o, c.map_text(<[u8]>::to_vec)))
+    })
+    .unwrap();
+    assert_eq!(
+        cycle,
+        Some((
+            12,
+            Command::Cycle {
+                abs: true,
+                value: 1234
+            }
+        ))
+    );
+    assert_eq!(push.carry_len(), 3);
+    let err = push.feed(b"\tx\n", |_, _| {}).unwrap_err();
+    assert_eq!(err.offset, 24);
+}
+
+#[test]
+fn stage_spans() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let mut expected: Vec<_> = trace
+        .instructions()
+        .iter()
+        .flat_map(|r| {
+            let stages = trace.stages();
+            r.stages.iter().map(move |s| {
+                (
+                    r.id,
+                    stages.name(s.stage).to_string(),
+                    s.lane,
+                    s.start,
+                    s.end,
+                )
+            })
+        })
+        .collect();
+    let mut spans = Spans::new(Parser::new(&input));
+    let mut seen = Vec::new();
+    for event in spans.by_ref() {
+        if let SpanEvent::Span { id, span } = event.unwrap() {
+            seen.push((id, span));
+        }
+    }
+    let mut seen: Vec<_> = seen
+        .into_iter()
+        .map(|(id, s)| {
+            (
+                id,
+                spans.stages().name(s.stage).to_string(),
+                s.lane,
+                s.start,
+                s.end,
+            )
+        })
+        .collect();
+    expected.sort();
+    seen.sort();
+    assert_eq!(seen, expected);
+
+    let input =
+        b"Kanata\t0004\nI\t0\t0\t0\nS\t0\t0\tF\nC\t2\nS\t0\t0\tD\nE\t0\t0\tF\nC\t1\nR\t0\t0\t0\n";
+    let mut spans = Spans::new(Parser::new(input));
+    let events: Vec<_> = spans.by_ref().map(|e| e.unwrap()).collect();
+    let f = spans.stages().get("F").unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0],
+        SpanEvent::Span {
+            id: 0,
+            span: StageSpan {
+                stage: f,
+                lane: 0,
+                start: 0,
+                end: 2
+            }
+        }
+    );
+    assert!(matches!(events[1], SpanEvent::UnmatchedEnd { offset: 40, stage, .. } if stage == f));
+    assert!(matches!(events[2], SpanEvent::Span { span, .. } if (span.start, span.end) == (2, 3)));
+}
+
+#[test]
+fn event_bus() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let (mut created, mut retired, mut flushed, mut deps, mut spans) = (0, 0, 0, 0, 0);
+    let mut labeled = 0;
+    let mut first = None;
+    EventBus::new()
+        .on_instruction(|_| created += 1)
+        .on_instruction(|e| {
+            first.get_or_insert(e.id);
+        })
+        .on_retire(|e| match e.kind {
+            RetireKind::Retire => retired += 1,
+            RetireKind::Flush => flushed += 1,
+        })
+        .on_dep(|e| {
+            deps += 1;
+            labeled += e.label.is_some() as usize;
+        })
+        .on_stage_span(|e, _| spans += matches!(e, SpanEvent::Span { .. }) as usize)
+        .run(Parser::new(&input))
+        .unwrap();
+    let records = trace.instructions();
+    assert_eq!(created, records.len());
+    assert_eq!(first, Some(records[0].id));
+    assert_eq!(retired, records.iter().filter(|r| r.is_retired()).count());
+    assert_eq!(flushed, records.iter().filter(|r| r.is_flushed()).count());
+    assert_eq!(
+        deps,
+        records.iter().map(|r| r.producers.len()).sum::<usize>()
+    );
+    assert_eq!(spans, records.iter().map(|r| r.stages.len()).sum::<usize>());
+    let with_label = records.iter().flat_map(|r| &r.producers);
+    assert_eq!(labeled, with_label.filter(|d| d.label.is_some()).count());
+
+    let err = EventBus::new()
+        .on_instruction(|_| {})
+        .run(Parser::new(b"Kanata\t0004\nI\t0\t0\t0\nX\n"))
+        .unwrap_err();
+    assert_eq!(err.offset, 20);
+}
+
+#[test]
+fn instruction_callbacks() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let mut seen = Vec::new();
+    let mut borrowed = 0;
+    let stages = for_each_instruction(&input, |rec| {
+        let label = rec.label(&input);
+        borrowed += matches!(label, std::borrow::Cow::Borrowed(_)) as usize;
+        seen.push((rec.id, rec.latency(), label.into_owned()));
+    })
+    .unwrap();
+    seen.sort_by_key(|&(id, ..)| id);
+    let expected: Vec<_> = trace
+        .instructions()
+        .iter()
+        .map(|r| (r.id, r.latency(), trace.label(r).into_owned()))
+        .collect();
+    // logs written after the retire come too late for the streamed record
+    assert_eq!(seen.len(), expected.len());
+    for (a, b) in seen.iter().zip(&expected) {
+        assert_eq!((a.0, a.1), (b.0, b.1));
+        assert!(a.2.is_empty() || a.2 == b.2);
+    }
+    assert!(borrowed > 0);
+    assert_eq!(stages.len(), trace.stages().len());
+}
+
+#[test]
+fn prometheus_text() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t2\nS\t0\t0\tX\nC\t1\nR\t0\t0\t0\nI\t1\t1\t0\nS\t1\t0\tF\nC\t3\nR\t1\t1\t1\n";
+    let trace = Trace::new(input).unwrap();
+    let report = Report::from_trace(&trace, ReportConfig::default());
+    let text = report.to_prometheus_text(&[("trace", "a \"b\"\\c")]);
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines.contains(&"kanata_flush_rate{trace=\"a \\\"b\\\"\\\\c\"} 0.5"));
+    assert!(lines.contains(&"# TYPE kanata_stage_latency_cycles summary"));
+    assert!(lines.contains(
+        &"kanata_stage_latency_cycles{trace=\"a \\\"b\\\"\\\\c\",stage=\"X\",quantile=\"0.99\"} 1"
+    ));
+    assert!(
+        lines.contains(
+            &"kanata_stage_latency_cycles_count{trace=\"a \\\"b\\\"\\\\c\",stage=\"F\"} 2"
+        )
+    );
+    assert!(!text.contains("# EOF"));
+    let open = report.to_openmetrics_text(&[("trace", "a \"b\"\\c")]);
+    assert_eq!(open.strip_suffix("# EOF\n"), Some(text.as_str()));
+
+    let text = report.to_prometheus_text(&[]);
+    assert!(text.contains("\nkanata_retired 1\n"));
+
+    // a label named twice goes on once, with its last value
+    let text = report.to_prometheus_text(&[("trace", "a"), ("stage", "b"), ("trace", "c")]);
+    assert!(text.contains("\nkanata_retired{trace=\"c\",stage=\"b\"} 1\n"));
+    assert!(text.contains("\nkanata_stage_occupancy{trace=\"c\",stage=\"X\"} "));
+}
+
+#[cfg(feature = "schema")]
+#[test]
+fn json_schemas() {
+    let mut out = Vec::new();
+    let input = std::fs::read("testinput/kanata-sample-1.log").unwrap();
+    write_events_jsonl(&input, &mut out).unwrap();
+    let events = events_schema();
+    let properties = events.as_value()["properties"].as_object().unwrap();
+    // every key the export writes is described
+    for line in String::from_utf8(out).unwrap().lines() {
+        for key in line.split("\"").skip(1).step_by(2) {
+            if line.contains(&format!("\"{}\":", key)) {
+                assert!(properties.contains_key(key), "{}", key);
+            }
+        }
+    }
+
+    let report = report_schema();
+    let defs = report.as_value()["$defs"].as_object().unwrap();
+    assert_eq!(defs["StageTable"]["type"], "object");
+    assert!(defs.contains_key("Stats"));
+    let diagnostics = diagnostics_schema();
+    assert_eq!(diagnostics.as_value()["type"], "array");
+}
+
+#[test]
+fn trace_fingerprint() {
+    let input = b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nL\t0\t0\tadd\nS\t0\t0\tF\nC\t3\nE\t0\t0\tF\nR\t0\t0\t0\n";
+    let fingerprint = |input: &[u8]| Trace::new(input).unwrap().fingerprint();
+    let base = fingerprint(input);
+    let same: [&[u8]; 2] = [
+        b"Kanata\t0004\r\nC=\t5\r\nI\t0\t0\t0\r\nL\t0\t0\tadd\r\nS\t0\t0\t F \r\nC\t1\r\nC\t2\r\nE\t0\t0\tF\r\nR\t0\t0\t0\r\n",
+        &convert_to_binary(input, Vec::new()).unwrap(),
+    ];
+    for input in same {
+        assert_eq!(fingerprint(input), base);
+    }
+    let different: [&[u8]; 2] = [
+        b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nL\t0\t0\tsub\nS\t0\t0\tF\nC\t3\nE\t0\t0\tF\nR\t0\t0\t0\n",
+        b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nL\t0\t0\tadd\nS\t0\t0\tF\nC\t4\nE\t0\t0\tF\nR\t0\t0\t0\n",
+    ];
+    for input in different {
+        assert_ne!(fingerprint(input), base);
+    }
+
+    let sample = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    // pinned, since cache keys outlive a build
+    assert_eq!(fingerprint(&sample), 8430290320092040971);
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn trace_cache() {
+    let dir = std::env::temp_dir().join(format!("kanata-cache-{}", std::process::id()));
+    let cache = TraceCache::new(&dir);
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let config = ReportConfig::default();
+    let report = cache.report(&input, config).unwrap();
+    let index = cache.index(&input, 1 << 16).unwrap();
+    assert_eq!(index, Index::build(&input, 1 << 16).unwrap());
+
+    // the same trace in binary shares the report but not the index
+    let binary = convert_to_binary(&input, Vec::new()).unwrap();
+    assert_eq!(
+        cache.fingerprint(&binary).unwrap(),
+        cache.fingerprint(&input).unwrap()
+    );
+    let from_binary = cache.report(&binary, config).unwrap();
+    assert_eq!(from_binary.stats().retired(), report.stats().retired());
+    assert_ne!(cache.index(&binary, 1 << 16).unwrap(), index);
+    assert_eq!(cache.stages(&input).unwrap().len(), report.stages().len());
+
+    // a damaged entry is rebuilt
+    let mut damaged = 0;
+    for entry in std::fs::read_dir(dir.join("v2/files")).unwrap() {
+        std::fs::write(entry.unwrap().path(), b"x").unwrap();
+        damaged += 1;
+    }
+    assert_eq!(damaged, 4);
+    assert_eq!(cache.index(&input, 1 << 16).unwrap(), index);
+    let labels = cache.labels(&input).unwrap();
+    assert_eq!(labels, LabelIndex::build(&Trace::new(&input).unwrap()));
+    assert_eq!(cache.labels(&input).unwrap(), labels);
+
+    // colors come back with the names
+    let colored =
+        b"Kanata\t0004\nP\t#4e79a7\tF\nP\t112233\tW\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nR\t0\t0\t0\n";
+    for _ in 0..2 {
+        let stages = cache.stages(colored).unwrap();
+        let f = stages.get("F").unwrap();
+        assert_eq!(stages.color(f), Some(0x4e79a7));
+        assert_eq!(stages.colors().count(), 2);
+    }
+
+    // opening a trace notes it in the default cache
+    cache.clear().unwrap();
+    let path = dir.join("run.log");
+    std::fs::write(&path, &input).unwrap();
+    TraceCache::set_default(Some(cache.clone()));
+    let opened = Trace::open(&path).unwrap();
+    TraceCache::set_default(None);
+    let key = format!("v2/files/{:016x}", xxhash_rust::xxh3::xxh3_64(&input));
+    let noted = std::fs::read(dir.join(key)).unwrap();
+    assert_eq!(from_bincode::<u64>(&noted).unwrap(), opened.fingerprint());
+    cache.clear().unwrap();
+    assert!(!dir.join("v2").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn trace_watcher() {
+    use std::io::Write as _;
+    use std::time::Duration;
+    let dir = std::env::temp_dir().join(format!("kanata-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("live.log");
+    std::fs::write(&path, b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF").unwrap();
+    let mut watcher = TraceWatcher::new(&path).unwrap();
+    let mut ids = Vec::new();
+    let mut collect = |_: usize, cmd: Command<&[u8]>| {
+        if let Command::Instruction { id_in_file, .. } = cmd {
+            ids.push(id_in_file);
+        }
+    };
+    assert_eq!(
+        watcher.update(&mut collect).unwrap(),
+        Some(WatchEvent::Appended(32))
+    );
+    assert_eq!(watcher.update(&mut collect).unwrap(), None);
+    // finishes the line cut off before
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"\nC\t2\nI\t1\t1\t0\n").unwrap();
+    drop(file);
+    let event = watcher.poll(Duration::from_secs(5), &mut collect).unwrap();
+    assert_eq!(event, Some(WatchEvent::Appended(13)));
+    assert_eq!(watcher.metrics().stage_starts, 1);
+    assert_eq!(watcher.current_cycle(), 2);
+
+    let tmp = dir.join("next.log");
+    std::fs::write(&tmp, b"Kanata\t0004\nI\t7\t7\t0\n").unwrap();
+    std::fs::rename(&tmp, &path).unwrap();
+    let event = watcher.poll(Duration::from_secs(5), &mut collect).unwrap();
+    assert_eq!(event, Some(WatchEvent::Replaced(20)));
+    assert_eq!(ids, [0, 1, 7]);
+    assert_eq!(watcher.metrics().instructions, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "listen")]
+#[test]
+fn live_listener() {
+    use std::io::Write as _;
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let listener = LiveListener::tcp("127.0.0.1:0").unwrap();
+    let mut stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    for part in input.chunks(10_007) {
+        stream.write_all(part).unwrap();
+    }
+    drop(stream);
+    let mut bad = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    bad.write_all(b"Kanata\t0004\nI\t0\t0\t0\nI\t0\t0\t0\n")
+        .unwrap();
+    drop(bad);
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+    let connections = loop {
+        let c = listener.connections();
+        if c.len() == 2 && c.iter().all(|c| c.closed) {
+            break c;
+        }
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    let whole = Stats::streaming(&input, DEFAULT_MAX_IN_FLIGHT).unwrap();
+    let live = connections.iter().find(|c| c.error.is_none()).unwrap();
+    assert_eq!(live.stats.instructions(), whole.instructions());
+    assert_eq!(live.stats.retired(), whole.retired());
+    assert_eq!(live.stats.latency(), whole.latency());
+    assert_eq!(live.in_flight, 0);
+    let failed = connections.iter().find(|c| c.error.is_some()).unwrap();
+    assert_eq!(
+        failed.error.unwrap().kind,
+        ParseErrorKind::DuplicateInstruction
+    );
+    assert_eq!(failed.error.unwrap().offset, 20);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn trace_server() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let mut server = TraceServer::new("testinput");
+    assert_eq!(server.handle("/open?path=../Cargo.toml").status, 403);
+    assert_eq!(server.handle("/traces/0").status, 404);
+    let open = server.handle("/open?path=kanata%2Dsample-2.log");
+    assert_eq!(open.status, 200);
+    assert!(
+        open.body
+            .starts_with("{\"trace\":0,\"path\":\"kanata-sample-2.log\"")
+    );
+
+    let whole = Stats::from_trace(&trace);
+    let stats = server.handle("/traces/0/stats").body;
+    assert!(stats.starts_with(&format!(
+        "{{\"instructions\":{},\"retired\":{},",
+        whole.instructions(),
+        whole.retired()
+    )));
+
+    let r = &trace.instructions()[trace.instructions().len() / 2];
+    let one = server.handle(&format!("/traces/0/instructions/{}", r.id));
+    assert_eq!(one.status, 200);
+    let expect = format!(
+        "{{\"id\":{},\"sim_id\":{},\"thread_id\":{},\"start\":{},\"end\":{},",
+        r.id,
+        r.sim_id,
+        r.thread_id,
+        r.start,
+        r.end.unwrap()
+    );
+    assert!(one.body.starts_with(&expect), "{}", one.body);
+
+    let (from, to) = (r.start, r.start + 10);
+    let expected = trace
+        .instructions()
+        .iter()
+        .filter(|r| (from..to).contains(&r.start))
+        .count();
+    let cycles = server
+        .handle(&format!("/traces/0/cycles?from={}&to={}", from, to))
+        .body;
+    assert_eq!(cycles.matches("\"sim_id\"").count(), expected);
+    let limited = server
+        .handle(&format!("/traces/0/cycles?from={}&to={}&limit=1", from, to))
+        .body;
+    assert!(limited.ends_with("],\"truncated\":true}"));
+    assert_eq!(server.handle("/traces/0/cycles?from=x").status, 400);
+
+    let hits = server.handle("/traces/0/search?q=addi+a0&limit=2").body;
+    let first = &trace.search("addi a0")[0];
+    assert!(hits.starts_with(&format!(
+        "{{\"hits\":[{{\"id\":{},\"cycle\":{},\"pane\":\"left\",\"match\":\"exact\",",
+        first.id, first.cycle
+    )));
+    assert!(hits.ends_with("],\"truncated\":true}"), "{}", hits);
+    assert_eq!(server.handle("/traces/0/search").status, 400);
+
+    // later searches go through the label index the first one built, and
+    // the rest still reads the same bytes
+    assert_eq!(
+        server.handle("/traces/0/search?q=addi+a0&limit=2").body,
+        hits
+    );
+    assert_eq!(
+        server
+            .handle(&format!("/traces/0/cycles?from={}&to={}", from, to))
+            .body,
+        cycles
+    );
+    assert_eq!(
+        server
+            .handle(&format!("/traces/0/instructions/{}", r.id))
+            .body,
+        one.body
+    );
+    assert_eq!(server.handle("/traces/0/stats").body, stats);
+}
+
+#[test]
+fn annotations_inlined() {
+    let input = std::fs::read("testinput/kanata-sample-1.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let second = &trace.instructions()[1];
+    let mut notes = Annotations::new();
+    notes.add(Annotation::instruction(second.id, "slow\nload").tag("mem"));
+    notes.add(Annotation::cycles(second.start..second.start + 5, "the burst").tag("tlb-storm"));
+    notes.add(Annotation::cycles(-10..-5, "before anything"));
+    assert_eq!(notes.for_instruction(second).count(), 2);
+    assert_eq!(notes.tagged("mem").count(), 1);
+
+    let out = write_annotated(&input, &notes, Vec::new()).unwrap();
+    let annotated = Trace::new(&out).unwrap();
+    assert_eq!(annotated.instructions().len(), trace.instructions().len());
+    let back = Annotations::from_trace(&annotated);
+    assert_eq!(back.annotations.len(), 2);
+    assert_eq!(back.annotations[0].note, "slow load");
+    assert_eq!(back.annotations[0].tags, ["mem"]);
+    assert_eq!(back.annotations[1], notes.annotations[1]);
+    // the rest of the trace is as it was
+    assert_eq!(
+        Stats::from_trace(&annotated).latency(),
+        Stats::from_trace(&trace).latency()
+    );
+
+    #[cfg(feature = "json")]
+    {
+        let path = std::env::temp_dir().join(format!("kanata-notes-{}.json", std::process::id()));
+        notes.save(&path).unwrap();
+        assert_eq!(Annotations::load(&path).unwrap(), notes);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn bookmarks_seek() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let index = Index::build(&input, 1 << 10).unwrap();
+    let late = &trace.instructions()[trace.instructions().len() - 3];
+    let mut marks = Bookmarks::new();
+    marks.set("start", Bookmark::Cycle(20)).unwrap();
+    marks.set("late", Bookmark::Instruction(late.id)).unwrap();
+    assert!(marks.set("bad\tname", Bookmark::Cycle(0)).is_err());
+
+    let path = std::env::temp_dir().join(format!("kanata-marks-{}.txt", std::process::id()));
+    marks.save(&path).unwrap();
+    let loaded = Bookmarks::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, marks);
+    assert!(Bookmarks::parse("x\tcycle\tnope\n").is_err());
+
+    let mut cursor = Cursor::new(&input, &index).unwrap();
+    assert!(cursor.seek_bookmark(&loaded, "late").unwrap());
+    assert_eq!(cursor.cycle(), late.start);
+    assert!(cursor.in_flight().iter().any(|r| r.id == late.id));
+    assert!(cursor.seek_bookmark(&loaded, "start").unwrap());
+    assert_eq!(cursor.cycle(), 20);
+    assert!(!cursor.seek_bookmark(&loaded, "missing").unwrap());
+    assert_eq!(cursor.cycle(), 20);
+}
+
+#[test]
+fn run_metadata_sidecar() {
+    let dir = std::env::temp_dir().join(format!("kanata-meta-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let trace_path = dir.join("run.log");
+    std::fs::copy("testinput/kanata-sample-1.log", &trace_path).unwrap();
+    let (_, none) = Trace::open_with_metadata(&trace_path).unwrap();
+    assert_eq!(none, None);
+
+    let mut meta = RunMetadata {
+        simulator: Some("gem5 \"23.1\"".into()),
+        benchmark: Some("mcf".into()),
+        config_hash: Some("9f2c".into()),
+        clock_hz: Some(3_000_000_000),
+        ..RunMetadata::default()
+    };
+    meta.extra.insert("seed".into(), "42".into());
+    meta.save(RunMetadata::sidecar_path(&trace_path)).unwrap();
+    assert!(dir.join("run.log.meta.toml").exists());
+    let (trace, found) = Trace::open_with_metadata(&trace_path).unwrap();
+    assert_eq!(found.as_ref(), Some(&meta));
+    assert_eq!(
+        meta.describe().unwrap(),
+        "mcf (gem5 \"23.1\", cfg 9f2c, 3000 MHz)"
+    );
+    let parsed = RunMetadata::parse("# run\nbenchmark = \"x\"\nthreads = 4\n").unwrap();
+    assert_eq!(parsed.extra["threads"], "4");
+    assert!(RunMetadata::parse("clock_hz = fast").is_err());
+
+    let report = Report::from_trace(&trace, ReportConfig::default()).with_metadata(meta);
+    let text = report.to_prometheus_text(&[("trace", "run.log")]);
+    assert!(text.contains(
+        "kanata_ipc{trace=\"run.log\",simulator=\"gem5 \\\"23.1\\\"\",benchmark=\"mcf\",config_hash=\"9f2c\"}"
+    ));
+    // the caller's labels win over the metadata's
+    let text = report.to_prometheus_text(&[("benchmark", "mcf-ref")]);
+    assert!(text.contains(
+        "kanata_ipc{benchmark=\"mcf-ref\",simulator=\"gem5 \\\"23.1\\\"\",config_hash=\"9f2c\"}"
+    ));
+    report.write_csv(&dir).unwrap();
+    let csv = std::fs::read_to_string(dir.join("metadata.csv")).unwrap();
+    assert!(csv.contains("clock_hz,3000000000\nseed,42\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn workspace_shared_stages() {
+    let one = std::fs::read("testinput/kanata-sample-1.log").unwrap();
+    let two = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let own = Stats::from_trace(&Trace::new(&two).unwrap());
+    let mut ws = Workspace::new();
+    ws.add("small", Trace::from_vec(one).unwrap()).unwrap();
+    ws.open_as("big", "testinput/kanata-sample-2.log").unwrap();
+    assert_eq!(ws.names().collect::<Vec<_>>(), ["small", "big"]);
+
+    // every trace sees the whole table, and ids mean the same stage in each
+    for (_, trace) in ws.iter() {
+        assert_eq!(trace.stages().len(), ws.stages().len());
+    }
+    let big = ws.stats("big").unwrap();
+    for (_, name, s) in own.iter() {
+        assert_eq!(big.stage(ws.stages().get(name).unwrap()), s, "{}", name);
+    }
+    let cmp = ws.compare("small", "big").unwrap();
+    assert!((cmp.ipc_delta() - (own.ipc() - ws.stats("small").unwrap().ipc())).abs() < 1e-9);
+    let stl = ws.stages().get("stl").unwrap();
+    let (_, _, a, b) = cmp.stage_means().find(|m| m.0 == stl).unwrap();
+    assert_eq!(
+        (a, b),
+        (0.0, own.stage(own.stages().get("stl").unwrap()).mean())
+    );
+    assert!(ws.compare("small", "missing").is_none());
+    assert!(ws.remove("small").is_some());
+    assert_eq!(ws.len(), 1);
+}
+
+#[test]
+fn instruction_matching() {
+    let one = std::fs::read("testinput/kanata-sample-1.log").unwrap();
+    let two = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    // the same run after another, so with every id moved on
+    let joined = concat(&[&one, &two], Vec::new()).unwrap();
+    let trace = Trace::new(&two).unwrap();
+    let retired_only = write_filtered(&trace, &Filter::retired(), Vec::new()).unwrap();
+    let mut ws = Workspace::new();
+    ws.add("two", Trace::from_vec(two.clone()).unwrap())
+        .unwrap();
+    ws.add("joined", Trace::from_vec(joined).unwrap()).unwrap();
+    ws.add("retired", Trace::from_vec(retired_only).unwrap())
+        .unwrap();
+
+    let same = ws.match_instructions("two", "two").unwrap();
+    assert_eq!(same.len(), trace.instructions().len());
+    assert!(same.pairs().iter().all(|&(a, b)| a == b));
+
+    let moved = ws.match_instructions("two", "joined").unwrap();
+    let shift = ws.get("joined").unwrap().instructions().len() - trace.instructions().len();
+    let id = |i: usize| trace.instructions()[i].id;
+    assert_eq!(moved.len(), trace.instructions().len());
+    assert_eq!(moved.get(id(100)), Some(id(100) + shift as Id));
+    assert_eq!(moved.reverse(id(100) + shift as Id), Some(id(100)));
+
+    // without the wrong paths, only the retired ones have counterparts
+    let pruned = ws.match_instructions("two", "retired").unwrap();
+    let retired: Vec<_> = trace
+        .instructions()
+        .iter()
+        .filter(|r| r.is_retired())
+        .collect();
+    assert_eq!(pruned.len(), retired.len());
+    assert!(retired.iter().all(|r| pruned.get(r.id) == Some(r.id)));
+    let (a, b) = (ws.get("two").unwrap(), ws.get("retired").unwrap());
+    assert!(pruned.latency_deltas(a, b).all(|(_, _, d)| d == 0));
+    assert!(ws.match_instructions("two", "nope").is_none());
+}
+
+#[test]
+fn stage_delta_significance() {
+    let run = |miss: u32| {
+        let config = GenConfig {
+            instructions: 3000,
+            miss_latency: vec![(miss, 1.0)],
+            ..GenConfig::default()
+        };
+        Trace::from_vec(generate(&config, Vec::new()).unwrap()).unwrap()
+    };
+    let mut ws = Workspace::new();
+    ws.add("base", run(12)).unwrap();
+    ws.add("slow", run(40)).unwrap();
+    ws.add("again", run(12)).unwrap();
+
+    let stages = ws.stages().clone();
+    let same = ws.stage_deltas("base", "again", 0.95).unwrap();
+    assert!(same.iter().all(|d| d.delta == 0.0 && !d.is_significant()));
+    let slow = ws.stage_deltas("base", "slow", 0.95).unwrap();
+    let find = |name: &str| slow.iter().find(|d| stages.name(d.stage) == name).unwrap();
+    // loads that miss wait longer before they can commit
+    let cm = find("Cm");
+    assert!(cm.is_regression() && cm.delta > 20.0);
+    assert!(cm.low < cm.delta && cm.delta < cm.high);
+    assert!(!find("F").is_significant());
+    // a wider interval at a higher level
+    let strict = ws.stage_deltas("base", "slow", 0.999).unwrap();
+    let x = strict.iter().find(|d| d.stage == find("X").stage).unwrap();
+    assert!(x.high - x.low > find("X").high - find("X").low);
+}
+
+#[test]
+fn slice_reproducer() {
+    let config = GenConfig {
+        instructions: 4000,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    // one with a producer, the producer, and one far off
+    let consumer = trace
+        .instructions()
+        .iter()
+        .find(|r| r.start > 1000 && !r.producers.is_empty())
+        .unwrap();
+    let producer = consumer.producers[0].producer_id;
+    let far = trace.instructions()[3000].id;
+    let ids: IdSet = [consumer.id, producer, far, 1 << 30].into_iter().collect();
+    let out = slice(&trace, &ids, Vec::new()).unwrap();
+    let small = Trace::new(&out).unwrap();
+    assert_eq!(small.instructions().len(), 3);
+    assert_eq!(small.start_cycle(), 0);
+    assert!(out.len() < 2000);
+    let shift = trace.get(producer).unwrap().start;
+    for (rec, id) in small
+        .instructions()
+        .iter()
+        .zip([producer, consumer.id, far])
+    {
+        let orig = trace.get(id).unwrap();
+        assert_eq!(rec.sim_id, orig.sim_id);
+        assert_eq!(rec.start, orig.start - shift);
+        assert_eq!(rec.latency(), orig.latency());
+        assert_eq!(small.label(rec), trace.label(orig));
+    }
+    // the dependency between the two kept ends survives
+    assert_eq!(small.instructions()[1].producers.len(), 1);
+    assert_eq!(small.instructions()[1].producers[0].producer_id, 0);
+}
+
+#[test]
+fn backward_dependency_slice() {
+    let config = GenConfig {
+        instructions: 4000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let graph = DepGraph::new(&trace);
+    // one whose producers waited on others in turn
+    let target = trace.instructions()[3000..]
+        .iter()
+        .find(|r| {
+            r.producers
+                .iter()
+                .any(|d| !graph.producers(d.producer_id).is_empty())
+        })
+        .unwrap()
+        .id;
+    let all = graph.backward_slice(target, SliceBound::default());
+    // closed under producers
+    for &id in &all {
+        for d in graph.producers(id) {
+            assert!(trace.get(d.producer_id).is_none() || all.contains(&d.producer_id));
+        }
+    }
+    let direct = graph.backward_slice(
+        target,
+        SliceBound {
+            depth: Some(1),
+            ..SliceBound::default()
+        },
+    );
+    assert_eq!(direct.len(), 1 + graph.producers(target).len());
+    assert!(all.len() > direct.len());
+    for d in graph.producers(target) {
+        assert!(graph.consumers(d.producer_id).contains(&target));
+    }
+    let recent = graph.backward_slice(
+        target,
+        SliceBound {
+            cycles: Some(20),
+            ..SliceBound::default()
+        },
+    );
+    let start = trace.get(target).unwrap().start;
+    assert!(
+        recent
+            .iter()
+            .all(|&id| trace.get(id).unwrap().start >= start - 20)
+    );
+    assert!(recent.is_subset(&all));
+    assert!(
+        graph
+            .backward_slice(1 << 30, SliceBound::default())
+            .is_empty()
+    );
+
+    let out = slice(&trace, &direct, Vec::new()).unwrap();
+    assert_eq!(Trace::new(&out).unwrap().instructions().len(), direct.len());
+}
+
+#[test]
+fn forward_dependency_slice() {
+    let config = GenConfig {
+        instructions: 4000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let graph = DepGraph::new(&trace);
+    let source = trace.instructions()[1000..]
+        .iter()
+        .find(|r| !graph.consumers(r.id).is_empty())
+        .unwrap()
+        .id;
+    let held_up = graph.forward_slice(source, SliceBound::default());
+    assert!(held_up.len() > graph.consumers(source).len());
+    // each one reached waited on one already in
+    for &id in held_up.iter().filter(|&&id| id != source) {
+        assert!(
+            graph
+                .producers(id)
+                .iter()
+                .any(|d| held_up.contains(&d.producer_id))
+        );
+    }
+    // the two directions agree
+    for &id in &held_up {
+        assert!(
+            graph
+                .backward_slice(id, SliceBound::default())
+                .contains(&source)
+        );
+    }
+    let near = graph.forward_slice(
+        source,
+        SliceBound {
+            depth: Some(2),
+            cycles: Some(50),
+        },
+    );
+    assert!(near.is_subset(&held_up) && near.contains(&source));
+}
+
+#[test]
+fn dependency_paths() {
+    let config = GenConfig {
+        instructions: 4000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let graph = DepGraph::new(&trace);
+    let source = trace.instructions()[1000..]
+        .iter()
+        .find(|r| !graph.consumers(r.id).is_empty())
+        .unwrap()
+        .id;
+    let far = graph
+        .forward_slice(source, SliceBound::default())
+        .into_iter()
+        .max()
+        .unwrap();
+    assert!(graph.reachable(source, far) && !graph.reachable(far, source));
+    assert!(graph.reachable(source, source));
+
+    let fewest = graph.shortest_path(source, far).unwrap();
+    let cheapest = graph.shortest_path_by_cycles(source, far).unwrap();
+    for path in [&fewest, &cheapest] {
+        assert_eq!((path.ids[0], *path.ids.last().unwrap()), (source, far));
+        for w in path.ids.windows(2) {
+            assert!(graph.producers(w[1]).iter().any(|d| d.producer_id == w[0]));
+        }
+    }
+    assert!(fewest.edges() <= cheapest.edges());
+    assert!(cheapest.cycles <= fewest.cycles);
+    let depth = fewest.edges();
+    let within = |d| SliceBound {
+        depth: Some(d),
+        ..SliceBound::default()
+    };
+    assert!(graph.forward_slice(source, within(depth)).contains(&far));
+    assert!(
+        !graph
+            .forward_slice(source, within(depth - 1))
+            .contains(&far)
+    );
+    assert_eq!(graph.shortest_path(far, source), None);
+}
+
+#[test]
+fn dependency_cycles() {
+    let config = GenConfig {
+        instructions: 2000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    assert!(
+        DepGraph::new(&Trace::new(&input).unwrap())
+            .cycles()
+            .is_empty()
+    );
+
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\t100: add\nI\t1\t1\t0\n\
+        L\t1\t0\t104: sub\nI\t2\t2\t0\nL\t2\t0\t108: mul\nI\t3\t3\t0\nW\t1\t0\t0\n\
+        W\t0\t1\t0\nW\t2\t2\t0\nW\t3\t0\t0\nC\t1\nR\t0\t0\t0\nR\t1\t1\t0\nR\t2\t2\t0\nR\t3\t3\t0\n";
+    let trace = Trace::new(input).unwrap();
+    let cycles = DepGraph::new(&trace).cycles();
+    assert_eq!(cycles.len(), 2);
+    let ids = |c: &DepCycle| c.members.iter().map(|m| m.id).collect::<Vec<_>>();
+    assert_eq!(ids(&cycles[0]), [0, 1]);
+    assert_eq!(ids(&cycles[1]), [2]);
+    assert_eq!(cycles[0].members[1].label, "104: sub");
+    let offset = cycles[0].members[1].offset;
+    assert!(input[offset..].starts_with(b"I\t1\t"));
+}
+
+#[test]
+fn graphml_export() {
+    let config = GenConfig {
+        instructions: 500,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let mut out = Vec::new();
+    write_graphml(&trace, &mut out).unwrap();
+    let xml = String::from_utf8(out).unwrap();
+    let edges: usize = trace.instructions().iter().map(|r| r.producers.len()).sum();
+    assert_eq!(xml.matches("<node ").count(), trace.instructions().len());
+    assert_eq!(xml.matches("<edge ").count(), edges);
+    assert!(xml.contains(r#"<key id="wait" for="edge" attr.name="wait" attr.type="long"/>"#));
+    let flushed = trace
+        .instructions()
+        .iter()
+        .find(|r| r.is_flushed())
+        .unwrap();
+    let node = &xml[xml.find(&format!("<node id=\"n{}\">", flushed.id)).unwrap()..];
+    let node = &node[..node.find("</node>").unwrap()];
+    assert!(node.contains("<data key=\"status\">flushed</data>"));
+    assert!(node.contains("<data key=\"stages\">F&gt;"));
+    assert!(xml.ends_with("</graph>\n</graphml>\n"));
+}
+
+#[test]
+fn dot_export() {
+    let config = GenConfig {
+        instructions: 2000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let mut out = Vec::new();
+    write_dot(&trace, None, &mut out).unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    let edges: usize = trace.instructions().iter().map(|r| r.producers.len()).sum();
+    assert!(dot.starts_with("digraph deps {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(
+        dot.matches(" [label=").count(),
+        trace.instructions().len() + edges
+    );
+    let flushed = trace
+        .instructions()
+        .iter()
+        .find(|r| r.is_flushed())
+        .unwrap();
+    let node = &dot[dot
+        .find(&format!("  n{} [label=\"{}: ", flushed.id, flushed.id))
+        .unwrap()..];
+    assert!(node[..node.find('\n').unwrap()].ends_with(", style=dashed];"));
+
+    // a slice restricts both the nodes and the edges
+    let graph = DepGraph::new(&trace);
+    let target = trace.instructions()[1000..]
+        .iter()
+        .find(|r| {
+            r.producers
+                .iter()
+                .any(|d| !graph.producers(d.producer_id).is_empty())
+        })
+        .unwrap()
+        .id;
+    let slice = graph.backward_slice(target, SliceBound::default());
+    let mut out = Vec::new();
+    write_dot(&trace, Some(&slice), &mut out).unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    let edges: usize = slice
+        .iter()
+        .map(|&id| {
+            graph
+                .producers(id)
+                .iter()
+                .filter(|d| trace.get(d.producer_id).is_some() && slice.contains(&d.producer_id))
+                .count()
+        })
+        .sum();
+    assert_eq!(dot.matches(" -> ").count(), edges);
+    assert_eq!(dot.matches(" [label=").count(), slice.len() + edges);
+    assert!(dot.contains(&format!(" -> n{} [label=", target)));
+}
+
+#[test]
+fn report_dependency_metrics() {
+    let config = GenConfig {
+        instructions: 3000,
+        dep_rate: 0.6,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let report = Report::from_trace(&trace, ReportConfig { window: 500 });
+    let deps = report.deps();
+    let n = trace.instructions().len() as u64;
+    let edges: u64 = trace
+        .instructions()
+        .iter()
+        .map(|r| r.producers.len() as u64)
+        .sum();
+    let weighted = |h: &std::collections::BTreeMap<u32, u64>| {
+        h.iter().map(|(&k, &v)| k as u64 * v).sum::<u64>()
+    };
+    assert_eq!(deps.fan_in.values().sum::<u64>(), n);
+    assert_eq!(deps.fan_out.values().sum::<u64>(), n);
+    assert_eq!(
+        (weighted(&deps.fan_in), weighted(&deps.fan_out)),
+        (edges, edges)
+    );
+    assert_eq!(deps.wakeups, edges);
+    // at most one per consumer
+    let consumers = n - deps.fan_in.get(&0).copied().unwrap_or(0);
+    assert!(deps.critical_wakeups > 0 && deps.critical_wakeups <= consumers);
+
+    // the longest chain behind each, worked out directly
+    let mut depth = std::collections::HashMap::new();
+    let mut deepest = 0;
+    for r in trace.instructions() {
+        let d = r
+            .producers
+            .iter()
+            .map(|p| depth[&p.producer_id] + 1)
+            .max()
+            .unwrap_or(0);
+        depth.insert(r.id, d);
+        deepest = deepest.max(d);
+    }
+    assert!(deepest > 2);
+    let max = report
+        .windows()
+        .map(|(_, w)| w.chain_depth.max)
+        .max()
+        .unwrap();
+    assert_eq!(max, deepest);
+    let streamed =
+        Report::streaming(&input, DEFAULT_MAX_IN_FLIGHT, ReportConfig { window: 500 }).unwrap();
+    let sd = streamed.deps();
+    assert_eq!((&sd.fan_in, sd.wakeups), (&deps.fan_in, deps.wakeups));
+    assert!(sd.critical_wakeups <= deps.critical_wakeups);
+    assert!(
+        report
+            .to_prometheus_text(&[])
+            .contains("kanata_critical_wakeup_fraction ")
+    );
+}
+
+#[test]
+fn wakeup_heatmap() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nE\t0\t0\tF\nS\t0\t0\tX\nI\t1\t1\t0\nS\t1\t0\tF\nC\t1\nE\t1\t0\tF\nS\t1\t0\tIs\nW\t1\t0\t0\nW\t1\t9\t0\nC\t3\nE\t1\t0\tIs\nS\t1\t0\tX\nE\t0\t0\tX\nR\t0\t0\t0\nC\t1\nE\t1\t0\tX\nR\t1\t1\t0\n";
+    let trace = Trace::new(input).unwrap();
+    let heatmap = trace.wakeup_heatmap();
+    let id = |name| trace.stages().get(name).unwrap();
+    let cell = heatmap.cell(id("X"), id("Is")).unwrap();
+    assert_eq!(cell.latencies, std::collections::BTreeMap::from([(3, 1)]));
+    assert_eq!((heatmap.wakeups(), heatmap.skipped), (1, 1));
+    let mut csv = Vec::new();
+    heatmap.write_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "producer_stage,consumer_stage,latency,count\nX,Is,3,1\n"
+    );
+    let mut json = Vec::new();
+    heatmap.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with(r#"{"stages":["F","X","Is"],"skipped":1,"#));
+    assert!(json.contains(r#""producer":"X","consumer":"Is","count":1,"mean":3.000,"p50":3,"p90":3,"max":3,"histogram":[[3,1]]"#));
+
+    let config = GenConfig {
+        instructions: 2000,
+        dep_rate: 0.5,
+        ..GenConfig::default()
+    };
+    let input = generate(&config, Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let heatmap = trace.wakeup_heatmap();
+    let edges: usize = trace.instructions().iter().map(|r| r.producers.len()).sum();
+    assert_eq!(heatmap.wakeups() + heatmap.skipped, edges as u64);
+    assert!(heatmap.cells().len() > 1);
+}
+
+#[test]
+fn lane_bubbles() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nE\t0\t0\tF\nS\t0\t0\tX\nI\t1\t1\t0\nS\t1\t0\tF\nC\t1\nE\t0\t0\tX\nR\t0\t0\t0\nC\t3\nE\t1\t0\tF\nS\t1\t0\tX\nC\t1\nE\t1\t0\tX\nR\t1\t1\t0\n";
+    let trace = Trace::new(input).unwrap();
+    let id = |name| trace.stages().get(name).unwrap();
+    let report = trace.bubbles();
+    assert_eq!(
+        report.bubbles,
+        [Bubble {
+            stage: id("X"),
+            lane: 0,
+            start: 2,
+            end: 5,
+            cause: id("F"),
+            blocker: 1,
+        }]
+    );
+    assert_eq!(report.lanes.len(), 1);
+    assert_eq!(report.lanes[0].causes, [(id("F"), 3)]);
+    let mut marks = Bookmarks::new();
+    report.bookmark_worst(&trace, 5, &mut marks).unwrap();
+    assert_eq!(marks.get("bubble-1-X-0"), Some(Bookmark::Cycle(2)));
+
+    let input = generate(&GenConfig::default(), Vec::new()).unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let report = trace.bubbles();
+    assert!(!report.bubbles.is_empty());
+    for lane in &report.lanes {
+        let cycles: u64 = lane.causes.iter().map(|&(_, n)| n).sum();
+        assert_eq!(cycles, lane.lengths.total);
+        assert!(lane.causes.iter().all(|&(s, _)| s < lane.stage));
+    }
+    let worst = report.worst(3);
+    assert!(worst.windows(2).all(|w| w[0].cycles() >= w[1].cycles()));
+}
+
+#[test]
+fn lane_occupancy_gantt() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nE\t0\t0\tF\nS\t0\t1\tX\nI\t1\t1\t0\nS\t1\t0\tF\nC\t2\nE\t0\t1\tX\nR\t0\t0\t0\nE\t1\t0\tF\nR\t1\t1\t1\n";
+    let trace = Trace::new(input).unwrap();
+    let gantt = trace.lane_occupancy();
+    assert_eq!(gantt.lanes(), [0, 1]);
+    let bars: Vec<(Id, i64, i64)> = gantt
+        .lane(0)
+        .iter()
+        .map(|i| (i.id, i.start, i.end))
+        .collect();
+    assert_eq!(bars, [(0, 0, 1), (1, 1, 3)]);
+    assert_eq!(gantt.lane(1)[0].stage, trace.stages().get("X").unwrap());
+    assert!(gantt.lane(0)[1].flushed);
+    assert!(gantt.lane(7).is_empty());
+    let clipped = gantt.clip(2..10);
+    assert_eq!(clipped.intervals().len(), 2);
+    assert!(clipped.intervals().iter().all(|i| i.start == 2));
+
+    let mut out = Vec::new();
+    clipped.write_json(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"lanes\":[0,1],\"intervals\":[{\"lane\":0,\"id\":1,\"stage\":\"F\",\"start\":2,\"end\":3,\"flushed\":true},{\"lane\":1,\"id\":0,\"stage\":\"X\",\"start\":2,\"end\":3,\"flushed\":false}]}\n"
+    );
+}
+
+#[test]
+fn overlap_conflicts() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nI\t1\t1\t0\nS\t1\t0\tF\nC\t1\nI\t2\t2\t0\nS\t2\t0\tF\nE\t0\t0\tF\nS\t1\t0\tX\nS\t0\t0\tDc\nS\t0\t1\tDc\nR\t0\t0\t0\nR\t1\t1\t0\nR\t2\t2\t0\n";
+    let lines = LineIndex::new(input);
+    let found = |config| {
+        overlap_diagnostics(input, &config)
+            .into_iter()
+            .map(|d| {
+                let related: Vec<usize> = d.related.iter().map(|r| lines.line(r.start)).collect();
+                (d.code, d.start.line, related)
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        found(OverlapConfig::default()),
+        [("reentered-stage", 12, vec![11])]
+    );
+    // the hand-off on line 8 settles within its cycle
+    assert_eq!(
+        found(OverlapConfig {
+            exclusive_lanes: true
+        }),
+        [
+            ("lane-conflict", 5, vec![3]),
+            ("reentered-stage", 12, vec![11])
+        ]
+    );
+    let generated = generate(&GenConfig::default(), Vec::new()).unwrap();
+    assert!(overlap_diagnostics(&generated, &OverlapConfig::default()).is_empty());
+}
+
+#[test]
+fn tolerance_levels() {
+    let bad_kind = b"Kanata\t0004\nI\t0\t0\t0\nL\t0\t7\tx\nR\t0\t0\t0\n";
+    for (tolerance, ok) in [
+        (Tolerance::Strict, false),
+        (Tolerance::Lenient, false),
+        (Tolerance::Recover, true),
+    ] {
+        let mut parser = Parser::new(bad_kind).tolerance(tolerance);
+        assert_eq!(parser.all(|(_, c)| c.is_ok()), ok);
+    }
+
+    // each fixed in turn, to reach the next
+    let unmatched = "Kanata\t0004\nI\t0\t0\t0\nS\t0\t0\tF\nE\t0\t0\tX\nR\t0\t0\t0\n";
+    let gap = "Kanata\t0004\nI\t0\t0\t0\nR\t0\t0\t0\nI\t2\t2\t0\nR\t2\t2\t0\n";
+    let unknown = "Kanata\t0004\nI\t0\t0\t0\nR\t0\t0\t0\nS\t5\t0\tF\n";
+    let late = "Kanata\t0004\nI\t0\t0\t0\nR\t0\t0\t0\nL\t0\t0\tlate\n";
+    let kind = |input: &str, tolerance| {
+        Trace::with_tolerance(input.as_bytes(), tolerance)
+            .err()
+            .map(|e| (e.kind, input.as_bytes()[e.offset] as char))
+    };
+    assert_eq!(
+        kind(unmatched, Tolerance::Strict),
+        Some((ParseErrorKind::UnmatchedStage, 'E'))
+    );
+    assert_eq!(
+        kind(gap, Tolerance::Strict),
+        Some((ParseErrorKind::IdGap, 'I'))
+    );
+    assert_eq!(
+        kind(unknown, Tolerance::Strict),
+        Some((ParseErrorKind::UnknownInstruction, 'S'))
+    );
+    assert_eq!(kind(late, Tolerance::Strict), None);
+    for input in [unmatched, gap, unknown, late] {
+        assert_eq!(kind(input, Tolerance::Lenient), None);
+    }
+    let generated = generate(&GenConfig::default(), Vec::new()).unwrap();
+    assert!(Trace::with_tolerance(&generated, Tolerance::Strict).is_ok());
+    let mut rec = Reconstructor::new(gap.as_bytes());
+    for (offset, cmd) in Parser::new(gap.as_bytes()) {
+        rec.feed(offset, cmd.unwrap()).unwrap();
+    }
+    let warnings: Vec<WarningKind> = rec.take_warnings().iter().map(|w| w.kind).collect();
+    assert_eq!(warnings, [WarningKind::IdGap]);
+
+    let rows = "cycle fv fpc xv xpc\n0 1 10 0 0\n1 0 0 1 10 extra\nbad 1 1 1 1\n2 0 0 0 0\n";
+    let import = |tolerance: &str| {
+        let config = format!(
+            "stage = F fv fpc\nstage = X xv xpc\ntolerance = {}\n",
+            tolerance
+        );
+        let config = RtlConfig::parse(&config).unwrap();
+        import_rtl_commands(rows.as_bytes(), &config).map_err(|e| {
+            let e = e.get_ref().and_then(|e| e.downcast_ref::<ImportError>());
+            *e.unwrap()
+        })
+    };
+    let err = |line, kind| Err(ImportError { line, kind });
+    assert_eq!(
+        import("strict").map(drop),
+        err(3, ImportErrorKind::RowLength)
+    );
+    assert_eq!(
+        import("lenient").map(drop),
+        err(4, ImportErrorKind::InvalidNumber)
+    );
+    let buffer = import("recover").unwrap();
+    let trace = Trace::from_source(buffer.source()).unwrap();
+    assert_eq!(trace.instructions().len(), 1);
+    assert!(trace.instructions()[0].is_retired());
+}
+
+#[test]
+fn numeric_overflow() {
+    let parse = |line: &str| {
+        let input = format!("Kanata\t0004\n{}\n", line);
+        let cmd = Parser::new(input.as_bytes()).nth(1).unwrap().1;
+        cmd.map(|_| ()).map_err(|e| (e.kind, e.offset - 12))
+    };
+    let too_big = ParseErrorKind::ValueTooBig;
+
+    assert_eq!(parse("C\t-2147483648"), Ok(()));
+    assert_eq!(parse("C\t2147483647"), Ok(()));
+    assert_eq!(parse("C\t-2147483649"), Err((too_big, 2)));
+    assert_eq!(parse("C\t2147483648"), Err((too_big, 2)));
+    assert_eq!(parse("C\t-9223372036854775808"), Err((too_big, 2)));
+    assert_eq!(parse("C\t18446744073709551615"), Err((too_big, 2)));
+    assert_eq!(parse("C\t-18446744073709551616"), Err((too_big, 2)));
+    assert_eq!(parse("I\t0\t0\t18446744073709551616"), Err((too_big, 6)));
+    assert_eq!(parse("I\t0\t0\t4294967296"), Err((too_big, 6)));
+    assert_eq!(
+        parse(&format!("S\t0\t{}\tF", "9".repeat(4096))),
+        Err((too_big, 4))
+    );
+    #[cfg(feature = "wide-ids")]
+    assert_eq!(parse("I\t18446744073709551615\t0\t0"), Ok(()));
+    assert_eq!(parse("I\t18446744073709551616\t0\t0"), Err((too_big, 2)));
+
+    // the diagnostic covers just the number
+    let input = b"Kanata\t0004\nC=\t0\nC\t-99999999999999999999999\nI\t0\t0\t0\n";
+    let diags = diagnostics(input);
+    let d = diags.iter().find(|d| d.code == "value-too-big").unwrap();
+    assert_eq!(&input[d.span.clone()], b"-99999999999999999999999");
+}
+
+#[test]
+fn relative_cycle_checks() {
+    let input = b"Kanata\t0004\nC=\t100\nI\t0\t0\t0\nS\t0\t0\tF\nC\t5\nC=\t102\nS\t0\t0\tX\n\
+C\t-2\nE\t0\t0\tX\nC=\t110\nR\t0\t0\t0\n";
+    let codes = |check: &CycleCheck| {
+        let diags = cycle_diagnostics(input, check);
+        let lines = LineIndex::new(input);
+        diags
+            .iter()
+            .map(|d| {
+                (
+                    d.start.line,
+                    d.code,
+                    d.related.first().map(|r| lines.line(r.start)),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        codes(&CycleCheck::default()),
+        [(5, "cycle-went-back", Some(4)), (7, "negative-delta", None)]
+    );
+    assert_eq!(
+        codes(&CycleCheck { max_back: 2 }),
+        [(5, "cycle-went-back", Some(4))]
+    );
+
+    let (out, report) = reanchor_cycles(input, &CycleCheck::default(), Vec::new()).unwrap();
+    assert_eq!(report.reanchored, 2);
+    assert_eq!(report.shift, 5);
+    assert!(cycle_diagnostics(&out, &CycleCheck::default()).is_empty());
+    let trace = Trace::new(&out).unwrap();
+    let rec = &trace.instructions()[0];
+    // X starts where the clock stood, and the records after keep their
+    // spacing from it
+    let spans: Vec<_> = rec.stages.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(spans, [(100, 105), (105, 105)]);
+    assert_eq!(rec.end, Some(115));
+
+    let (_, report) = reanchor_cycles(input, &CycleCheck { max_back: 2 }, Vec::new()).unwrap();
+    assert_eq!((report.reanchored, report.shift), (1, 3));
+}
+
+#[test]
+fn label_index() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let index = LabelIndex::build(&trace);
+    assert!(index.fits(&trace));
+    assert!(index.trigrams() > 100);
+    for needle in ["auipc a0", "addi", "a0", "ADDI", "0x305, a0", "zzz", ""] {
+        let filter = Filter::label_contains(needle);
+        let scanned: Vec<Id> = trace.select(&filter).map(|r| r.id).collect();
+        assert_eq!(index.find_labels(&trace, needle), scanned, "{:?}", needle);
+    }
+    assert!(!index.find_labels(&trace, "auipc").is_empty());
+    // trigrams ignore case, so candidates cover either
+    let lower = index.candidates(b"auipc");
+    assert_eq!(index.candidates(b"AUIPC"), lower);
+    assert!(lower.len() < trace.instructions().len());
+    assert!(index.candidates(b"i-cache-miss").len() > 1);
+
+    // a pane joined from two records matches across the join
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\tlw \nL\t0\t0\ta0\nR\t0\t0\t0\n";
+    let trace = Trace::new(input).unwrap();
+    assert_eq!(LabelIndex::build(&trace).find_labels(&trace, "lw a0"), [0]);
+}
+
+#[test]
+fn ranked_search() {
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\tLW A0, 0(sp)\nI\t1\t1\t0\n\
+L\t1\t0\ta0 <- lw 4(sp)\nC\t1\nI\t2\t2\t0\nL\t2\t0\tlw a0, 8(sp)\n\
+I\t3\t3\t0\nL\t3\t0\tsw a1\nL\t3\t1\tlw a0 missed\nC\t1\nI\t4\t4\t0\nL\t4\t0\t  lw a0, 16(sp)\n";
+    let trace = Trace::new(input).unwrap();
+    let hits = trace.search("lw a0");
+    let ranked: Vec<_> = hits.iter().map(|h| (h.id, h.kind, h.pane)).collect();
+    assert_eq!(
+        ranked,
+        [
+            (2, MatchKind::Exact, LogKind::LeftPane),
+            (4, MatchKind::Exact, LogKind::LeftPane),
+            (3, MatchKind::Exact, LogKind::MouseOver),
+            (0, MatchKind::IgnoreCase, LogKind::LeftPane),
+            (1, MatchKind::Words, LogKind::LeftPane),
+        ]
+    );
+    assert_eq!(hits[0].cycle, 1);
+    assert_eq!(hits[0].snippet, "lw a0, 8(sp)");
+    assert!(trace.search("").is_empty());
+    assert!(trace.search("lw a2").is_empty());
+
+    let long = format!("{}lw a0{}", "x".repeat(40), "é".repeat(20));
+    let text = format!("Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\t{}\n", long);
+    let trace = Trace::new(text.as_bytes()).unwrap();
+    let hit = &trace.search("lw a0")[0];
+    assert_eq!(hit.position, 40);
+    assert_eq!(
+        hit.snippet,
+        format!("...{}lw a0{}...", "x".repeat(24), "é".repeat(12))
+    );
+
+    // the label index narrows the search without changing what it finds
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let index = LabelIndex::build(&trace);
+    for query in ["addi a0", "A0 csrrw", "miss", "jal zero, 0x10"] {
+        assert_eq!(
+            trace.search_indexed(&index, query),
+            trace.search(query),
+            "{:?}",
+            query
+        );
+    }
+}
+
+#[test]
+fn literal_filter() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let trace = Trace::new(&input).unwrap();
+    let filters = [
+        Filter::label_contains("addi a0"),
+        Filter::label_contains("csrrw").and(Filter::thread(0)),
+        Filter::label_contains("jal").and(Filter::cycle_range(0..2000)),
+        FilterExpr::parse("label =~ \"lw\" && wakeup >= 1")
+            .unwrap()
+            .into(),
+        Filter::label_contains("nothing like this"),
+        Filter::label_contains("add").or(Filter::flushed()),
+    ];
+    for filter in &filters {
+        let whole = write_filtered(&trace, filter, Vec::new()).unwrap();
+        let (fast, report) = filter_input(&input, filter, Vec::new()).unwrap();
+        assert_eq!(fast, whole, "{:?}", filter);
+        assert_eq!(report.kept, trace.select(filter).count() as u64);
+        assert_eq!(report.instructions, trace.instructions().len() as u64);
+        if filter.label_literal().is_some() {
+            assert!(report.candidates < report.instructions);
+        }
+    }
+    // labels over several records, or with the literal only in what's
+    // trimmed off, are kept or dropped as for the whole trace
+    let input = b"Kanata\t0004\nC=\t0\nI\t0\t0\t0\nL\t0\t0\t0x400: va\nL\t0\t0\tdd v1\n\
+I\t1\t1\t0\nL\t1\t0\t0x404: v\nL\t1\t0\ta\nL\t1\t0\tdd.vv v2\n\
+I\t2\t2\t0\nL\t2\t0\t0x408: vadd  \nI\t3\t3\t0\nL\t3\t0\t0x40c: lw\nC\t1\n\
+R\t0\t0\t0\nR\t1\t1\t0\nR\t2\t2\t0\nR\t3\t3\t0\n";
+    let trace = Trace::new(input).unwrap();
+    for filter in [
+        Filter::label_contains("vadd"),
+        Filter::label_contains("vadd "),
+    ] {
+        let whole = write_filtered(&trace, &filter, Vec::new()).unwrap();
+        let (fast, report) = filter_input(input, &filter, Vec::new()).unwrap();
+        assert_eq!(fast, whole, "{:?}", filter);
+        assert_eq!(report.kept, trace.select(&filter).count() as u64);
+        assert_eq!(report.candidates, 3);
+    }
+    assert_eq!(trace.select(&Filter::label_contains("vadd")).count(), 3);
+    assert_eq!(trace.select(&Filter::label_contains("vadd ")).count(), 1);
+
+    assert_eq!(filters[0].label_literal(), Some("addi a0"));
+    assert_eq!(filters[3].label_literal(), Some("lw"));
+    assert_eq!(filters[5].label_literal(), None);
+    assert_eq!(Filter::label_contains("").label_literal(), None);
+}
+
+#[test]
+fn trace_index_sidecar() {
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let names = |s: &StageTable| s.iter().map(|(_, n)| n.to_string()).collect::<Vec<_>>();
+    let full = Trace::new(&input).unwrap();
+    let built = TraceIndex::build(&input, 1 << 12).unwrap();
+    assert_eq!(built.index(), &Index::build(&input, 1 << 12).unwrap());
+    assert_eq!(names(built.stages()), names(full.stages()));
+
+    let bytes = built.write(Vec::new()).unwrap();
+    assert!(bytes.starts_with(INDEX_MAGIC));
+    let read = TraceIndex::read(&bytes).unwrap();
+    assert_eq!(read.index(), built.index());
+    assert_eq!(names(read.stages()), names(built.stages()));
+    assert!(read.fits(&input));
+    assert!(!read.fits(&input[..input.len() - 1]));
+    assert!(TraceIndex::read(&bytes[..bytes.len() - 1]).is_err());
+    let mut newer = bytes.clone();
+    newer[INDEX_MAGIC.len()] += 1;
+    assert!(TraceIndex::read(&newer).is_err());
+
+    // windows number stages as the whole trace does
+    let mid = read.index().checkpoints()[3].cycle;
+    let window = read.window(&input, mid..mid + 200).unwrap();
+    let plain = read.index().window(&input, mid..mid + 200).unwrap();
+    assert_eq!(window.instructions().len(), plain.instructions().len());
+    for (a, b) in window.instructions().iter().zip(plain.instructions()) {
+        let stage = |t: &Trace, r: &InstructionRecord| {
+            r.stages
+                .iter()
+                .map(|s| t.stages().name(s.stage).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stage(&window, a), stage(&plain, b));
+        for s in &a.stages {
+            assert_eq!(
+                full.stages().get(window.stages().name(s.stage)),
+                Some(s.stage)
+            );
+        }
+    }
+
+    let colored =
+        b"Kanata\t0004\nP\t#4e79a7\tX\nC=\t0\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nS\t0\t0\tX\nR\t0\t0\t0\n";
+    let t = TraceIndex::build(colored, 64).unwrap();
+    let t = TraceIndex::read(&t.write(Vec::new()).unwrap()).unwrap();
+    assert_eq!(names(t.stages()), ["F", "X"]);
+    assert_eq!(
+        t.stages().color(t.stages().get("X").unwrap()),
+        Some(0x4e79a7)
+    );
+
+    let binary = convert_to_binary(&input, Vec::new()).unwrap();
+    let t = TraceIndex::build(&binary, 1 << 12).unwrap();
+    assert_eq!(t.index(), BinaryTrace::new(&binary).unwrap().index());
+    assert_eq!(names(t.stages()), names(full.stages()));
+    let window = t.window(&binary, mid..mid + 200).unwrap();
+    assert!(!window.instructions().is_empty());
+
+    // absent, stale or damaged sidecars fall back to building one
+    let dir = std::env::temp_dir().join(format!("kanata-kidx-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("run.log");
+    std::fs::write(&path, &input).unwrap();
+    assert!(TraceIndex::for_trace(&path, &input).unwrap().is_none());
+    built.save(&path).unwrap();
+    assert!(dir.join("run.log.kidx").exists());
+    let found = TraceIndex::for_trace(&path, &input).unwrap().unwrap();
+    assert_eq!(found.index(), built.index());
+    let mut longer = input.clone();
+    longer.extend_from_slice(b"C\t1\n");
+    assert!(TraceIndex::for_trace(&path, &longer).unwrap().is_none());
+    let rebuilt = TraceIndex::open(&path, &longer, 1 << 12).unwrap();
+    assert_eq!(rebuilt.index().end().offset, longer.len());
+    std::fs::write(TraceIndex::sidecar_path(&path), b"KANATAX\0junk").unwrap();
+    assert!(TraceIndex::for_trace(&path, &input).unwrap().is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "tui")]
+#[test]
+fn tui_navigation() {
+    use ratatui::crossterm::event::KeyCode;
+
+    // shorter than the screen: End stays on the first cycle
+    let short = b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nS\t0\t0\tF\nC\t3\nE\t0\t0\tF\nR\t0\t0\t0\n";
+    let index = Index::build(short, 1 << 10).unwrap();
+    let first = index.first_cycle();
+    let mut app = App::new(short, index).unwrap();
+    app.press(KeyCode::End);
+    assert_eq!(app.view, first);
+    app.scroll(-100);
+    assert_eq!(app.view, first);
+    app.scroll(100);
+    assert_eq!(app.view, 8);
+
+    let input = std::fs::read("testinput/kanata-sample-2.log").unwrap();
+    let index = Index::build(&input, 1 << 12).unwrap();
+    let (first, end) = (index.first_cycle(), index.end().cycle);
+    let mut app = App::new(&input, index).unwrap();
+    app.width = 20;
+    app.scroll(-1000);
+    assert_eq!(app.view, first);
+    app.scroll(i64::MAX / 4);
+    assert_eq!(app.view, end);
+    app.press(KeyCode::End);
+    assert_eq!(app.visible(), end - 20..end);
+    app.press(KeyCode::Char('-'));
+    app.press(KeyCode::End);
+    assert_eq!(app.visible(), end - 40..end);
+    app.press(KeyCode::Home);
+    assert_eq!(app.view, first);
+
+    app.ensure_loaded().unwrap();
+    let v = app.visible();
+    let rows = app.rows();
+    assert!(!rows.is_empty());
+    assert!(
+        rows.iter()
+            .all(|r| r.start < v.end && r.end.is_none_or(|e| e >= v.start))
+    );
+
+    for c in "/cache-miss".chars() {
+        app.press(KeyCode::Char(c));
+    }
+    assert!(!app.press(KeyCode::Enter));
+    assert_eq!(app.error, None);
+    assert!(app.hits.len() > 1);
+    let hit = &app.hits[0];
+    assert_eq!(app.view, (hit.cycle - 40 * 2 / 4).max(first));
+    assert_eq!(app.jump, Some(hit.id));
+    let id = hit.id;
+    app.ensure_loaded().unwrap();
+    assert!(app.rows().iter().any(|r| r.id == id));
+
+    app.press(KeyCode::Char('N'));
+    assert_eq!(app.hit, app.hits.len() - 1);
+    app.hit = 1;
+    app.goto_hit();
+    assert_eq!(app.jump, Some(app.hits[1].id));
+    assert!(app.press(KeyCode::Char('q')));
+}
+
+// Two instructions from cycle 0, the first retired through F, Dc and Cm, the
+// second flushed out of F, for checking what the exporters write.
+const SMALL: &[u8] = b"Kanata\t0004\nC=\t0\n\
+I\t0\t0\t0\nL\t0\t0\t0x400: addi x1, x1, 1\nS\t0\t0\tF\nC\t1\n\
+E\t0\t0\tF\nS\t0\t0\tDc\nC\t1\nE\t0\t0\tDc\nS\t0\t0\tCm\nC\t1\nE\t0\t0\tCm\nR\t0\t0\t0\n\
+I\t1\t1\t0\nL\t1\t0\t0x404: beq x1, x0, 8\nS\t1\t0\tF\nC\t2\nR\t1\t1\t1\n";
+
+#[test]
+fn o3_pipeview() {
+    let trace = Trace::new(SMALL).unwrap();
+    let mut out = Vec::new();
+    write_o3pipeview(&trace, &O3Config::default(), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "O3PipeView:fetch:1000:0x00000400:0:0:addi x1, x1, 1\n\
+         O3PipeView:decode:2000\n\
+         O3PipeView:rename:0\n\
+         O3PipeView:dispatch:0\n\
+         O3PipeView:issue:0\n\
+         O3PipeView:complete:3000\n\
+         O3PipeView:retire:4000:store:0\n\
+         O3PipeView:fetch:4000:0x00000404:0:1:beq x1, x0, 8\n\
+         O3PipeView:decode:0\n\
+         O3PipeView:rename:0\n\
+         O3PipeView:dispatch:0\n\
+         O3PipeView:issue:0\n\
+         O3PipeView:complete:0\n\
+         O3PipeView:retire:0:store:0\n"
+    );
+}
+
+#[test]
+fn chrome_trace() {
+    let trace = Trace::new(SMALL).unwrap();
+    let mut out = Vec::new();
+    let config = ChromeConfig { us_per_cycle: 0.5 };
+    write_chrome_trace(&trace, &config, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    // every span opened is closed
+    assert_eq!(text.matches("\"ph\":\"b\"").count(), 6);
+    assert_eq!(text.matches("\"ph\":\"e\"").count(), 6);
+    assert_snapshot!(text);
+
+    #[cfg(feature = "json")]
+    {
+        let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let events = v["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 13);
+        assert_eq!(events[1]["name"], "0x400: addi x1, x1, 1");
+        assert_eq!(events[9]["ts"], 1.5);
+        assert_eq!(events[9]["args"]["flushed"], true);
+    }
+}
+
+#[test]
+fn speedscope() {
+    let trace = Trace::new(SMALL).unwrap();
+    let mut by_pc = Vec::new();
+    write_speedscope(&trace, SpeedscopeGroup::Pc, &mut by_pc).unwrap();
+    let by_pc = String::from_utf8(by_pc).unwrap();
+    assert_snapshot!(by_pc);
+    let mut by_path = Vec::new();
+    write_speedscope(&trace, SpeedscopeGroup::StagePath, &mut by_path).unwrap();
+    let by_path = String::from_utf8(by_path).unwrap();
+    assert_snapshot!(by_path);
+
+    #[cfg(feature = "json")]
+    for text in [&by_pc, &by_path] {
+        let v: serde_json::Value = serde_json::from_str(text).unwrap();
+        let profile = &v["profiles"][0];
+        let weights: u64 = profile["weights"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w.as_u64().unwrap())
+            .sum();
+        assert_eq!(weights, 5);
+        assert_eq!(profile["endValue"], 5);
+        let frames = v["shared"]["frames"].as_array().unwrap().len() as u64;
+        for s in profile["samples"].as_array().unwrap() {
+            assert!(
+                s.as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|f| f.as_u64().unwrap() < frames)
+            );
+        }
+    }
+}
+
+#[test]
+fn vcd() {
+    let trace = Trace::new(SMALL).unwrap();
+    let config = VcdConfig {
+        lane_ids: true,
+        ..VcdConfig::default()
+    };
+    let mut out = Vec::new();
+    write_vcd(&trace, &config, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let width = format!("$var wire {} % lane0_id $end", Id::BITS);
+    assert!(text.contains(&width), "{}", text);
+    assert_snapshot!(text.replace(&width, "$var wire ID_BITS % lane0_id $end"));
+
+    // an id past 32 bits keeps all its bits
+    #[cfg(feature = "wide-ids")]
+    {
+        let wide =
+            b"Kanata\t0004\nI\t4294967296\t0\t0\nS\t4294967296\t0\tF\nC\t1\nR\t4294967296\t0\t0\n";
+        let mut out = Vec::new();
+        write_vcd(&Trace::new(wide).unwrap(), &config, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.contains(&format!("b1{} #\n", "0".repeat(32))),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn event_stream() {
+    let input = b"Kanata\t0004\nC=\t2\nP\t#ff8000\tF\n\
+I\t0\t7\t1\nL\t0\t1\tsay \"hi\", then\nS\t0\t0\tF\nC\t1\nE\t0\t0\tF\n\
+I\t1\t8\t1\nW\t1\t0\t0\tfwd\nR\t0\t3\t0\nR\t1\t4\t1\n";
+    let mut csv = Vec::new();
+    write_events_csv(input, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 13);
+    for line in csv.lines().filter(|l| !l.contains('"')) {
+        assert_eq!(line.split(',').count(), 10, "{}", line);
+    }
+    assert_snapshot!(csv);
+
+    let mut jsonl = Vec::new();
+    write_events_jsonl(input, &mut jsonl).unwrap();
+    let jsonl = String::from_utf8(jsonl).unwrap();
+    assert_eq!(jsonl.lines().count(), 12);
+    assert_snapshot!(jsonl);
+    #[cfg(feature = "json")]
+    for line in jsonl.lines() {
+        let v: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(v["offset"].is_u64() && v["cycle"].is_i64());
+    }
+
+    // the error stops the stream where it is
+    let mut out = Vec::new();
+    let bad = b"Kanata\t0004\nI\t0\t0\t0\nX\n";
+    let err = write_events_csv(bad, &mut out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_export() {
+    let input = [SMALL, b"W\t1\t0\t0\nW\t1\t0\t0\tbypass r3\n"].concat();
+    let trace = Trace::new(&input).unwrap();
+    let path = std::env::temp_dir().join(format!("kanata-sqlite-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    write_sqlite(&trace, &path).unwrap();
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    // id, start, end, retire kind, pc and label
+    type Row = (i64, i64, Option<i64>, Option<String>, Option<i64>, String);
+    let rows: Vec<Row> = conn
+        .prepare(
+            "SELECT id, start_cycle, end_cycle, retire_kind, pc, label \
+             FROM instructions ORDER BY id",
+        )
+        .unwrap()
+        .query_map([], |r| {
+            Ok((
+                r.get(0)?,
+                r.get(1)?,
+                r.get(2)?,
+                r.get(3)?,
+                r.get(4)?,
+                r.get(5)?,
+            ))
+        })
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        rows,
+        [
+            (
+                0,
+                0,
+                Some(3),
+                Some("retire".into()),
+                Some(0x400),
+                "0x400: addi x1, x1, 1".into()
+            ),
+            (
+                1,
+                3,
+                Some(5),
+                Some("flush".into()),
+                Some(0x404),
+                "0x404: beq x1, x0, 8".into()
+            ),
+        ]
+    );
+    let spans: Vec<(i64, String, i64, i64)> = conn
+        .prepare(
+            "SELECT instr_id, name, start_cycle, end_cycle FROM stage_spans \
+             JOIN stages ON stages.id = stage_id ORDER BY instr_id, start_cycle",
+        )
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        spans,
+        [
+            (0, "F".into(), 0, 1),
+            (0, "Dc".into(), 1, 2),
+            (0, "Cm".into(), 2, 3),
+            (1, "F".into(), 3, 5),
+        ]
+    );
+    let count = |table: &str| -> i64 {
+        conn.query_row(&format!("SELECT count(*) FROM {}", table), [], |r| r.get(0))
+            .unwrap()
+    };
+    assert_eq!((count("deps"), count("logs")), (2, 2));
+    let labels: Vec<Option<String>> = conn
+        .prepare("SELECT label FROM deps ORDER BY rowid")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(labels, [None, Some("bypass r3".into())]);
+    let indices: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'index'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(indices, 9);
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
+
+    // an id past i64::MAX fails the export instead of going in negative
+    #[cfg(feature = "wide-ids")]
+    {
+        let input = b"Kanata\t0004\nC=\t0\nI\t9223372036854775808\t0\t0\n";
+        let trace = Trace::new(input).unwrap();
+        assert!(matches!(
+            write_sqlite(&trace, &path),
+            Err(rusqlite::Error::ToSqlConversionFailure(_))
+        ));
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let tables: i64 = conn
+            .query_row("SELECT count(*) FROM sqlite_master", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(tables, 0);
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn collapsed_stacks() {
+    let input = [
+        SMALL,
+        b"I\t2\t2\t0\nS\t2\t0\tF\nC\t1\nE\t2\t0\tF\nS\t2\t0\tex;1\nR\t2\t2\t0\n\
+I\t3\t3\t0\nL\t3\t0\tnop;x\nS\t3\t0\tF\nC\t4\nR\t3\t3\t0\n",
+    ]
+    .concat();
+    let trace = Trace::new(&input).unwrap();
+    let mut out = Vec::new();
+    write_collapsed(&trace, &mut out).unwrap();
+    // zero-cycle stages are left out, `;` only ever separates frames
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "0x400;F 1\n0x400;Dc 1\n0x400;Cm 1\n0x404;F 2\n[unknown];F 1\nnop:x;F 4\n"
+    );
+}
+
+#[test]
+fn ascii_diagram() {
+    let trace = Trace::new(SMALL).unwrap();
+    let config = AsciiConfig {
+        abbreviations: [("Cm".to_string(), 'C')].into_iter().collect(),
+        label_width: 12,
+        ..AsciiConfig::default()
+    };
+    assert_snapshot!(render_ascii(&trace, &config));
+
+    let second = AsciiConfig {
+        instructions: 1..9,
+        ..config
+    };
+    let text = render_ascii(&trace, &second);
+    assert!(text.starts_with("cycle 3+"), "{}", text);
+    assert_eq!(text.lines().count(), 2);
+    let none = AsciiConfig {
+        instructions: 5..9,
+        ..second
+    };
+    assert_eq!(render_ascii(&trace, &none), "");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn python_commands() {
+    use crate::python::PyCommands;
+    use pyo3::prelude::*;
+
+    pyo3::Python::initialize();
+    Python::attach(|py| -> PyResult<()> {
+        let input = b"Kanata\t0004\nC=\t2\nP\t#ff8000\tF\nI\t0\t7\t1\nL\t0\t1\thi\n\
+S\t0\t0\tF\nC\t1\nE\t0\t0\tF\nI\t1\t8\t1\nW\t1\t0\t0\tfwd\nR\t0\t3\t0\n";
+        // the cycle after each command, by its offset
+        let mut clock = Clock::new();
+        let cycles: std::collections::HashMap<usize, i64> = Parser::new(input)
+            .extensions()
+            .map(|(o, cmd)| {
+                clock.apply(&cmd.unwrap());
+                (o, clock.cycle())
+            })
+            .collect();
+        let cmds = Bound::new(py, PyCommands::new(input.to_vec()))?;
+        let mut seen = Vec::new();
+        for obj in cmds.as_any().try_iter()? {
+            let obj = obj?;
+            let name: String = obj.get_type().name()?.extract()?;
+            let offset: usize = obj.getattr("offset")?.extract()?;
+            seen.push((name, offset));
+            if let Ok(cycle) = obj.getattr("cycle") {
+                assert_eq!(cycle.extract::<i64>()?, cycles[&offset]);
+            }
+            match seen.last().unwrap().0.as_str() {
+                "Dep" => assert_eq!(obj.getattr("label")?.extract::<String>()?, "fwd"),
+                "StageColor" => assert_eq!(obj.getattr("color")?.extract::<u32>()?, 0xff8000),
+                "Log" => assert_eq!(obj.getattr("kind")?.extract::<String>()?, "hover"),
+                _ => {}
+            }
+        }
+        let names: Vec<&str> = seen.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Header",
+                "Cycle",
+                "StageColor",
+                "Instruction",
+                "Log",
+                "Stage",
+                "Cycle",
+                "Stage",
+                "Instruction",
+                "Dep",
+                "Retire"
+            ]
+        );
+        // exhausted, it stays so
+        assert!(cmds.as_any().call_method0("__next__").is_err());
+
+        // the header's version holds for the rest
+        let old = Bound::new(py, PyCommands::new(sample_0003()))?;
+        let mut instructions = 0;
+        for o in old.as_any().try_iter()? {
+            instructions += (o?.get_type().name()? == "Instruction") as usize;
+        }
+        assert_eq!(
+            instructions,
+            Trace::new(&sample_0003()).unwrap().instructions().len()
+        );
+
+        let bad = Bound::new(py, PyCommands::new(b"Kanata\t0004\nX\n".to_vec()))?;
+        let mut it = bad.as_any().try_iter()?;
+        assert!(it.next().unwrap().is_ok());
+        let err = it.next().unwrap().unwrap_err();
+        assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id as SpanId};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    // Each span as it opens and each event with its span, as
+    // `span: name field=value ...`.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &SpanId, _: Context<'_, S>) {
+            let mut f = Fields(format!("open {}", attrs.metadata().name()));
+            attrs.record(&mut f);
+            self.0.lock().unwrap().push(f.0);
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let span = ctx.event_span(event).map_or("-", |s| s.name());
+            let mut f = Fields(format!("{} {}:", span, event.metadata().level()));
+            event.record(&mut f);
+            self.0.lock().unwrap().push(f.0);
+        }
+    }
+
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::registry().with(capture.clone());
+    let input = b"Kanata\t0004\nC=\t5\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nR\t0\t0\t0\nC=\t2\n";
+    tracing::subscriber::with_default(subscriber, || {
+        Index::build(input, 16).unwrap();
+        Trace::new(input).unwrap();
+    });
+    assert_eq!(
+        *capture.0.lock().unwrap(),
+        [
+            "open index bytes=50 interval=16",
+            "index DEBUG: message=kanata header version=4 offset=7",
+            "index WARN: message=cycle moved backwards offset=45 code=\"cycle-went-back\"",
+            "index DEBUG: message=index built checkpoints=3",
+            "open trace bytes=50",
+            "trace DEBUG: message=kanata header version=4 offset=7",
+            "trace WARN: message=cycle moved backwards offset=45 code=\"cycle-went-back\"",
+            "trace DEBUG: message=trace built instructions=1 end_cycle=2",
+        ]
+    );
+}
+
+#[test]
+fn report_csv() {
+    let input = [
+        SMALL,
+        b"I\t2\t2\t0\nW\t2\t0\t0\nS\t2\t0\tF\nC\t1\nR\t2\t2\t0\n",
+    ]
+    .concat();
+    let trace = Trace::new(&input).unwrap();
+    let report = Report::from_trace(&trace, ReportConfig { window: 2 });
+    let dir = std::env::temp_dir().join(format!("kanata-report-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    report.write_csv(&dir).unwrap();
+    // no metadata, no metadata table
+    assert!(!dir.join("metadata.csv").exists());
+
+    let mut all = String::new();
+    for name in [
+        "stage_stats",
+        "ipc",
+        "occupancy",
+        "per_pc",
+        "fan",
+        "chain_depth",
+    ] {
+        let text = std::fs::read_to_string(dir.join(format!("{}.csv", name))).unwrap();
+        let columns = text.lines().next().unwrap().split(',').count();
+        assert!(
+            text.lines().all(|l| l.split(',').count() == columns),
+            "{}",
+            name
+        );
+        let _ = write!(all, "== {}.csv\n{}", name, text);
+    }
+    assert_snapshot!(all);
+
+    // a window the trace ends early in counts only the cycles it has
+    let report = Report::from_trace(&trace, ReportConfig { window: 1000 });
+    report.write_csv(&dir).unwrap();
+    let ipc = std::fs::read_to_string(dir.join("ipc.csv")).unwrap();
+    assert_eq!(ipc.lines().nth(1), Some("0,7,2,1,0.2857"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "render")]
+#[test]
+fn svg_timeline() {
+    let input = [
+        b"Kanata\t0004\nP\t#ff8000\tF\n".as_slice(),
+        &SMALL[b"Kanata\t0004\n".len()..],
+        b"I\t2\t2\t0\nL\t2\t0\ta<b & \"c\"\nS\t2\t0\tDc\nC\t1\nR\t2\t2\t0\n",
+    ]
+    .concat();
+    let trace = Trace::new(&input).unwrap();
+    let config = SvgConfig {
+        cell_width: 10,
+        row_height: 10,
+        label_width: 100,
+        ..SvgConfig::default()
+    };
+    let mut out = Vec::new();
+    render_svg(&trace, &config, &mut out).unwrap();
+    let svg = String::from_utf8(out).unwrap();
+    assert!(svg.contains("fill=\"#ff8000\""));
+    assert!(svg.contains("a&lt;b &amp; &quot;c&quot;"));
+    assert_snapshot!(svg);
+
+    // only the rows in the cycles asked for
+    let late = SvgConfig {
+        cycles: Some(4..7),
+        ..config
+    };
+    let mut out = Vec::new();
+    render_svg(&trace, &late, &mut out).unwrap();
+    let svg = String::from_utf8(out).unwrap();
+    assert!(!svg.contains("addi") && svg.contains("beq") && svg.contains("a&lt;b"));
+    assert!(
+        svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"130\" height=\"30\"")
+    );
+}
+
+#[test]
+fn skipped_line_ranges() {
+    fn skipped(input: &[u8]) -> (usize, Vec<&[u8]>) {
+        let mut parser = Parser::new(input).tolerance(Tolerance::Recover);
+        let kept = parser.by_ref().filter(|(_, c)| c.is_ok()).count();
+        let lines = parser
+            .skipped_ranges()
+            .iter()
+            .map(|r| &input[r.clone()])
+            .collect();
+        (kept, lines)
+    }
+    // each range is its whole line and break, whatever the break, and the
+    // lines recovered in place aren't in them
+    let lf = b"Kanata\t0004\n?\nI\t0\t0\t0\nL\t0\t9\tx\nS\t0\nR\t0\t0\t0\nbad";
+    assert_eq!(skipped(lf), (4, vec![&b"?\n"[..], b"S\t0\n", b"bad"]));
+    let crlf = b"Kanata\t0004\r\n?\r\nI\t0\t0\t0\r\nS\t0\r\nR\t0\t0\t0\r\n";
+    assert_eq!(skipped(crlf), (3, vec![&b"?\r\n"[..], b"S\t0\r\n"]));
+    let cr = b"Kanata\t0004\r?\rI\t0\t0\t0\rS\t0\rR\t0\t0\t0\r";
+    assert_eq!(skipped(cr), (3, vec![&b"?\r"[..], b"S\t0\r"]));
+
+    // offsets are into the whole input when parsing starts part way in
+    let at = lf.iter().position(|&b| b == b'I').unwrap();
+    let mut parser = Parser::resume(lf, at).tolerance(Tolerance::Recover);
+    parser.by_ref().for_each(drop);
+    let ranges = parser.skipped_ranges().to_vec();
+    assert_eq!(ranges.len(), 2);
+    assert_eq!(&lf[ranges[0].clone()], b"S\t0\n");
+    assert_eq!(ranges[1].end, lf.len());
+}
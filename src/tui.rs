@@ -0,0 +1,338 @@
+use crate::{
+    BINARY_MAGIC, DEFAULT_INDEX_INTERVAL, Id, Index, InstructionRecord, LabelIndex, LogKind,
+    SearchHit, Trace, TraceIndex,
+};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+const LABEL_WIDTH: usize = 32;
+const PALETTE: [Color; 6] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+pub(crate) struct App<'a> {
+    input: &'a [u8],
+    index: Index,
+    trace: Trace<'a>,
+    loaded: Range<i64>,
+    pub(crate) view: i64,
+    pub(crate) zoom: i64,
+    pub(crate) selected: usize,
+    pub(crate) width: i64,
+    // the search being typed after `/`
+    prompt: Option<String>,
+    // the whole trace, parsed and indexed for the first search
+    searchable: Option<(Trace<'a>, LabelIndex)>,
+    // why the last search failed, shown in the title
+    pub(crate) error: Option<String>,
+    pub(crate) hits: Vec<SearchHit>,
+    pub(crate) hit: usize,
+    // the instruction to select once its window is loaded
+    pub(crate) jump: Option<Id>,
+}
+
+impl<'a> App<'a> {
+    pub(crate) fn new(input: &'a [u8], index: Index) -> io::Result<Self> {
+        let view = index.first_cycle();
+        let trace = index.window(input, view..view)?;
+        Ok(Self {
+            input,
+            index,
+            trace,
+            loaded: view..view,
+            view,
+            zoom: 1,
+            selected: 0,
+            width: 80,
+            prompt: None,
+            searchable: None,
+            error: None,
+            hits: Vec::new(),
+            hit: 0,
+            jump: None,
+        })
+    }
+
+    pub(crate) fn search(&mut self, query: &str) {
+        self.error = None;
+        if self.searchable.is_none() {
+            match self.load_searchable() {
+                Ok(s) => self.searchable = Some(s),
+                Err(e) => {
+                    self.hits.clear();
+                    self.error = Some(e.to_string());
+                    return;
+                }
+            }
+        }
+        if let Some((trace, labels)) = &self.searchable {
+            self.hits = trace.search_indexed(labels, query);
+        }
+        self.hit = 0;
+        self.goto_hit();
+    }
+
+    // The whole trace as one window off the index, and its labels from the
+    // default cache when there is one.
+    fn load_searchable(&self) -> io::Result<(Trace<'a>, LabelIndex)> {
+        let first = self.index.first_cycle();
+        let trace = self
+            .index
+            .window(self.input, first..self.index.end().cycle)?;
+        #[cfg(feature = "cache")]
+        if let Some(cache) = crate::TraceCache::default_cache() {
+            let labels = cache.labels_with(self.input, || Ok(LabelIndex::build(&trace)))?;
+            if labels.fits(&trace) {
+                return Ok((trace, labels));
+            }
+        }
+        let labels = LabelIndex::build(&trace);
+        Ok((trace, labels))
+    }
+
+    // Scrolls so the current hit starts a quarter of the way in.
+    pub(crate) fn goto_hit(&mut self) {
+        if let Some(h) = self.hits.get(self.hit) {
+            self.view = (h.cycle - self.width * self.zoom / 4).max(self.index.first_cycle());
+            self.jump = Some(h.id);
+        }
+    }
+
+    fn next_hit(&mut self, back: bool) {
+        let n = self.hits.len().max(1);
+        let step = if back { n - 1 } else { 1 };
+        self.hit = (self.hit + step) % n;
+        self.goto_hit();
+    }
+
+    pub(crate) fn visible(&self) -> Range<i64> {
+        self.view..self.view + self.width * self.zoom
+    }
+
+    pub(crate) fn ensure_loaded(&mut self) -> io::Result<()> {
+        let want = self.visible();
+        if want.start >= self.loaded.start && want.end <= self.loaded.end {
+            return Ok(());
+        }
+        let span = want.end - want.start;
+        let load = want.start - span..want.end + 2 * span;
+        self.trace = self.index.window(self.input, load.clone())?;
+        self.loaded = load;
+        Ok(())
+    }
+
+    pub(crate) fn rows(&self) -> Vec<&InstructionRecord> {
+        let v = self.visible();
+        let last = self.trace.end_cycle();
+        self.trace
+            .instructions()
+            .iter()
+            .filter(|r| r.start < v.end && r.end.unwrap_or(last) >= v.start)
+            .collect()
+    }
+
+    pub(crate) fn scroll(&mut self, cols: i64) {
+        let end = self.index.end().cycle;
+        self.view = (self.view + cols * self.zoom).clamp(self.index.first_cycle(), end.max(0));
+    }
+
+    fn timeline(&self, rec: &InstructionRecord) -> Line<'static> {
+        let mut spans = Vec::with_capacity(self.width as usize);
+        let end = rec.end.unwrap_or(self.trace.end_cycle());
+        for col in 0..self.width {
+            let c = self.view + col * self.zoom;
+            let stage = rec
+                .stages
+                .iter()
+                .rev()
+                .find(|s| s.start <= c && (c < s.end || s.start == s.end && c == s.start));
+            let span = match stage {
+                Some(s) => {
+                    let name = self.trace.stages().name(s.stage);
+                    let ch = name.chars().next().unwrap_or('?').to_string();
+                    let color = match self.trace.stages().color(s.stage) {
+                        Some(rgb) => Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8),
+                        None => PALETTE[s.stage.index() % PALETTE.len()],
+                    };
+                    let mut style = Style::new().fg(color);
+                    if rec.is_flushed() {
+                        style = style.add_modifier(Modifier::DIM | Modifier::CROSSED_OUT);
+                    }
+                    Span::styled(ch, style)
+                }
+                None if rec.start <= c && c < end => Span::raw("-"),
+                None => Span::raw(" "),
+            };
+            spans.push(span);
+        }
+        Line::from(spans)
+    }
+
+    fn details(&self, rec: &InstructionRecord) -> Vec<Line<'static>> {
+        let mut lines = vec![Line::from(format!(
+            "id={} sim={} thread={} cycles={}..{} {}",
+            rec.id,
+            rec.sim_id,
+            rec.thread_id,
+            rec.start,
+            rec.end.map_or("?".to_string(), |e| e.to_string()),
+            rec.retire_kind.map_or("in flight", |k| k.name()),
+        ))];
+        let mut text = String::new();
+        for log in rec.logs.iter().filter(|l| l.kind == LogKind::MouseOver) {
+            text.push_str(&String::from_utf8_lossy(self.trace.text(log.text)));
+        }
+        for l in text.replace("\\n", "\n").lines() {
+            lines.push(Line::from(l.to_string()));
+        }
+        lines
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, detail] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(8)]).areas(frame.area());
+        self.width = (main.width as i64 - LABEL_WIDTH as i64 - 3).max(1);
+        let _ = self.ensure_loaded();
+        if let Some(id) = self.jump.take()
+            && let Some(i) = self.rows().iter().position(|r| r.id == id)
+        {
+            self.selected = i;
+        }
+
+        let count = self.rows().len();
+        self.selected = self.selected.min(count.saturating_sub(1));
+        let rows = self.rows();
+        let height = main.height.saturating_sub(2) as usize;
+        let top = self.selected.saturating_sub(height.saturating_sub(1));
+        let mut lines = Vec::with_capacity(height);
+        for (i, rec) in rows.iter().enumerate().skip(top).take(height) {
+            let label = self.trace.label(rec);
+            let label = String::from_utf8_lossy(&label);
+            let mut head = format!("{:>6} {}", rec.id, label);
+            head.truncate(head.floor_char_boundary(LABEL_WIDTH));
+            let mut line = self.timeline(rec);
+            line.spans
+                .insert(0, Span::raw(format!("{:<w$} ", head, w = LABEL_WIDTH)));
+            if i == self.selected {
+                line = line.style(Style::new().add_modifier(Modifier::REVERSED));
+            }
+            lines.push(line);
+        }
+        let mut title = format!(
+            " cycles {}..{} (x{}) ",
+            self.visible().start,
+            self.visible().end,
+            self.zoom
+        );
+        match &self.prompt {
+            Some(q) => title.push_str(&format!("/{} ", q)),
+            None if let Some(e) = &self.error => title.push_str(&format!("search failed: {} ", e)),
+            None if !self.hits.is_empty() => {
+                title.push_str(&format!("hit {}/{} ", self.hit + 1, self.hits.len()))
+            }
+            None => {}
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            main,
+        );
+
+        let details = rows
+            .get(self.selected)
+            .map(|r| self.details(r))
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(details)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" detail ")),
+            detail,
+        );
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Press && self.press(key.code) {
+                return Ok(());
+            }
+        }
+    }
+
+    // Acts on a key; true when it asks to quit.
+    pub(crate) fn press(&mut self, code: KeyCode) -> bool {
+        if let Some(q) = &mut self.prompt {
+            match code {
+                KeyCode::Char(c) => q.push(c),
+                KeyCode::Backspace => {
+                    q.pop();
+                }
+                KeyCode::Enter => {
+                    let q = self.prompt.take().unwrap_or_default();
+                    self.search(&q);
+                }
+                KeyCode::Esc => self.prompt = None,
+                _ => {}
+            }
+            return false;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Left | KeyCode::Char('h') => self.scroll(-self.width / 4),
+            KeyCode::Right | KeyCode::Char('l') => self.scroll(self.width / 4),
+            KeyCode::PageUp => self.scroll(-self.width),
+            KeyCode::PageDown => self.scroll(self.width),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected += 1,
+            KeyCode::Char('+') | KeyCode::Char('=') => self.zoom = (self.zoom / 2).max(1),
+            KeyCode::Char('-') => self.zoom = (self.zoom * 2).min(1 << 20),
+            KeyCode::Home => self.view = self.index.first_cycle(),
+            // a trace shorter than the screen starts at its first cycle
+            KeyCode::End => {
+                self.view =
+                    (self.index.end().cycle - self.width * self.zoom).max(self.index.first_cycle())
+            }
+            KeyCode::Char('/') => self.prompt = Some(String::new()),
+            KeyCode::Char('n') => self.next_hit(false),
+            KeyCode::Char('N') => self.next_hit(true),
+            _ => {}
+        }
+        false
+    }
+}
+
+pub fn run_tui(input: &[u8]) -> io::Result<()> {
+    run_indexed(input, Index::build_for_open(input, DEFAULT_INDEX_INTERVAL)?)
+}
+
+fn run_indexed(input: &[u8], index: Index) -> io::Result<()> {
+    let mut app = App::new(input, index)?;
+    let mut terminal = ratatui::init();
+    let res = app.run(&mut terminal);
+    ratatui::restore();
+    res
+}
+
+// Seeks with the trace's sidecar index, if it has one that fits.
+pub fn open_tui(path: impl AsRef<Path>) -> io::Result<()> {
+    let input = std::fs::read(&path)?;
+    match TraceIndex::for_trace(path, &input)? {
+        // a binary trace's checkpoints are no use to the text windows here
+        Some(t) if !input.starts_with(BINARY_MAGIC) => run_indexed(&input, t.into_index()),
+        _ => run_tui(&input),
+    }
+}
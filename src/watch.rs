@@ -0,0 +1,135 @@
+use crate::{Command, ParseMetrics, Parser, PushParser};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, channel};
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    // new bytes at the end of the file, parsed as far as the last whole line
+    Appended(usize),
+    // the file was truncated or swapped for another, and parsed again from
+    // the start
+    Replaced(usize),
+}
+
+// Follows a trace a simulation is still writing. Each change parses only
+// the bytes added since the last, carrying a line cut off at the end over
+// to the next; a file that shrinks or is replaced starts over. The
+// directory is watched rather than the file, so replacing it by a rename
+// is seen too.
+pub struct TraceWatcher {
+    path: PathBuf,
+    rx: Receiver<notify::Result<notify::Event>>,
+    _watcher: RecommendedWatcher,
+    parser: PushParser,
+    read: u64,
+}
+
+fn watch_error(e: notify::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn fresh() -> PushParser {
+    PushParser::new(Parser::new(b"").extensions())
+}
+
+impl TraceWatcher {
+    // Nothing is parsed until the first `update` or `poll`.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = std::path::absolute(path)?;
+        let (tx, rx) = channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+        Ok(Self {
+            path,
+            rx,
+            _watcher: watcher,
+            parser: fresh(),
+            read: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Counts over everything parsed since the file was last replaced.
+    pub fn metrics(&self) -> ParseMetrics {
+        self.parser.metrics()
+    }
+
+    pub fn current_cycle(&self) -> i64 {
+        self.parser.current_cycle()
+    }
+
+    // Parses what's been written since the last call, handing each command
+    // to `f` with its offset in the file. `None` when nothing changed.
+    pub fn update(
+        &mut self,
+        f: impl FnMut(usize, Command<&[u8]>),
+    ) -> io::Result<Option<WatchEvent>> {
+        self.read_new(false, f)
+    }
+
+    // Waits up to `timeout` for the file to change, then updates. The file
+    // is checked at the end either way, in case an event was missed.
+    pub fn poll(
+        &mut self,
+        timeout: Duration,
+        f: impl FnMut(usize, Command<&[u8]>),
+    ) -> io::Result<Option<WatchEvent>> {
+        let mut replaced = false;
+        let mut event = self.rx.recv_timeout(timeout).ok();
+        while let Some(e) = event {
+            let e = e.map_err(watch_error)?;
+            if e.paths.iter().any(|p| p == &self.path) {
+                replaced |= matches!(
+                    e.kind,
+                    EventKind::Create(_)
+                        | EventKind::Remove(_)
+                        | EventKind::Modify(notify::event::ModifyKind::Name(_))
+                );
+            }
+            event = self.rx.try_recv().ok();
+        }
+        self.read_new(replaced, f)
+    }
+
+    fn read_new(
+        &mut self,
+        mut replaced: bool,
+        mut f: impl FnMut(usize, Command<&[u8]>),
+    ) -> io::Result<Option<WatchEvent>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            // between the old file going and the new one arriving
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        if replaced || len < self.read {
+            replaced = true;
+            self.parser = fresh();
+            self.read = 0;
+        }
+        if len == self.read && !replaced {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(self.read))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        self.read += buf.len() as u64;
+        self.parser.feed(&buf, &mut f)?;
+        Ok(Some(if replaced {
+            WatchEvent::Replaced(buf.len())
+        } else {
+            WatchEvent::Appended(buf.len())
+        }))
+    }
+}
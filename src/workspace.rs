@@ -0,0 +1,161 @@
+use crate::{ParseErrorKind, Report, ReportConfig, RunMetadata, StageId, StageTable, Stats, Trace};
+use std::io;
+use std::path::Path;
+
+struct Entry {
+    name: String,
+    trace: Trace<'static>,
+    metadata: Option<RunMetadata>,
+}
+
+// Several traces open together under names of their own, with one stage
+// table between them: each trace's stages are renumbered into it as the
+// trace is added, so a stage id means the same stage in all of them and
+// figures from different runs line up without matching names by hand.
+#[derive(Default)]
+pub struct Workspace {
+    stages: StageTable,
+    entries: Vec<Entry>,
+}
+
+// Two traces' figures over the workspace's stages.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub a: Stats,
+    pub b: Stats,
+}
+
+impl Comparison {
+    pub fn stages(&self) -> &StageTable {
+        self.a.stages()
+    }
+
+    pub fn ipc_delta(&self) -> f64 {
+        self.b.ipc() - self.a.ipc()
+    }
+
+    // The mean cycles spent in each stage, in `a` and then in `b`. A stage
+    // only one of them has counts zero in the other.
+    pub fn stage_means(&self) -> impl Iterator<Item = (StageId, &str, f64, f64)> {
+        self.stages()
+            .iter()
+            .map(|(id, name)| (id, name, self.a.stage(id).mean(), self.b.stage(id).mean()))
+    }
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The stages of every trace added so far.
+    pub fn stages(&self) -> &StageTable {
+        &self.stages
+    }
+
+    // One already under `name` is replaced; its stages stay in the table.
+    // `TooManyStages`, adding nothing, when the table can't take its stages.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        trace: Trace<'static>,
+    ) -> Result<&Trace<'static>, ParseErrorKind> {
+        self.insert(name.into(), trace, None)
+    }
+
+    // Opens the trace at `path` under its file name, with the metadata next
+    // to it if there is any. The name it went in under.
+    pub fn open(&mut self, path: impl AsRef<Path>) -> io::Result<String> {
+        let path = path.as_ref();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.open_as(name.into_owned(), path)
+    }
+
+    pub fn open_as(
+        &mut self,
+        name: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> io::Result<String> {
+        let name = name.into();
+        let (trace, metadata) = Trace::open_with_metadata(path)?;
+        self.insert(name.clone(), trace, metadata)
+            .map_err(|kind| io::Error::new(io::ErrorKind::InvalidData, kind.message()))?;
+        Ok(name)
+    }
+
+    fn insert(
+        &mut self,
+        name: String,
+        mut trace: Trace<'static>,
+        metadata: Option<RunMetadata>,
+    ) -> Result<&Trace<'static>, ParseErrorKind> {
+        let mut stages = self.stages.clone();
+        let map = stages.absorb(trace.stages())?;
+        self.stages = stages;
+        trace.restage(&self.stages, &map);
+        self.entries.retain(|e| e.name != name);
+        for e in &mut self.entries {
+            e.trace.set_stages(&self.stages);
+        }
+        self.entries.push(Entry {
+            name,
+            trace,
+            metadata,
+        });
+        Ok(&self.entries[self.entries.len() - 1].trace)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Trace<'static>> {
+        let i = self.entries.iter().position(|e| e.name == name)?;
+        Some(self.entries.remove(i).trace)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Trace<'static>> {
+        self.entry(name).map(|e| &e.trace)
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<&RunMetadata> {
+        self.entry(name)?.metadata.as_ref()
+    }
+
+    // In the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Trace<'static>)> {
+        self.entries.iter().map(|e| (e.name.as_str(), &e.trace))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn stats(&self, name: &str) -> Option<Stats> {
+        self.get(name).map(Stats::from_trace)
+    }
+
+    pub fn report(&self, name: &str, config: ReportConfig) -> Option<Report> {
+        let e = self.entry(name)?;
+        let report = Report::from_trace(&e.trace, config);
+        Some(match &e.metadata {
+            Some(m) => report.with_metadata(m.clone()),
+            None => report,
+        })
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Option<Comparison> {
+        Some(Comparison {
+            a: self.stats(a)?,
+            b: self.stats(b)?,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{Command, StrRef};
+use std::io::{self, Write};
+
+pub struct Writer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn write<T: AsRef<[u8]>>(&mut self, cmd: &Command<T>) -> io::Result<()> {
+        let out = &mut self.out;
+        match cmd {
+            Command::Kanata { version } => writeln!(out, "Kanata\t{:04}", version),
+            Command::Cycle { abs: true, value } => writeln!(out, "C=\t{}", value),
+            Command::Cycle { abs: false, value } => writeln!(out, "C\t{}", value),
+            Command::Instruction {
+                id_in_file,
+                id_in_sim,
+                thread_id,
+            } => writeln!(out, "I\t{}\t{}\t{}", id_in_file, id_in_sim, thread_id),
+            Command::Log { id, kind, text } => {
+                write!(out, "L\t{}\t{}\t", id, *kind as u8 as char)?;
+                out.write_all(text.as_ref())?;
+                writeln!(out)
+            }
+            Command::Pipeline {
+                start,
+                id,
+                lane_id,
+                name,
+            } => {
+                let c = if *start { 'S' } else { 'E' };
+                write!(out, "{}\t{}\t{}\t", c, id, lane_id)?;
+                out.write_all(name.as_ref())?;
+                writeln!(out)
+            }
+            Command::Retire { id, retire, kind } => {
+                writeln!(out, "R\t{}\t{}\t{}", id, retire, *kind as u8 as char)
+            }
+            Command::Dep {
+                consumer_id,
+                producer_id,
+                kind,
+                label,
+            } => {
+                write!(
+                    out,
+                    "W\t{}\t{}\t{}",
+                    consumer_id, producer_id, *kind as u8 as char
+                )?;
+                if let Some(label) = label {
+                    out.write_all(b"\t")?;
+                    out.write_all(label.as_ref())?;
+                }
+                writeln!(out)
+            }
+            Command::StageColor { name, color } => {
+                write!(out, "P\t{:06x}\t", color)?;
+                out.write_all(name.as_ref())?;
+                writeln!(out)
+            }
+        }
+    }
+
+    pub fn write_ref(&mut self, cmd: &Command<StrRef>, input: &[u8]) -> io::Result<()> {
+        self.write(&cmd.map_text(|s| s.get(input)))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
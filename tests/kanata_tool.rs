@@ -0,0 +1,194 @@
+// Runs the built `kanata-tool` binary, for what only shows in its exit
+// status and the files it writes.
+#![cfg(feature = "cli")]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const SAMPLE: &str = "testinput/kanata-sample-2.log";
+
+fn tool(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kanata-tool"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn code(out: &Output) -> i32 {
+    out.status.code().unwrap()
+}
+
+// A fresh directory for one test's files.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kanata-tool-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn p(s: &str) -> &Path {
+    Path::new(s)
+}
+
+#[test]
+fn validate_exit_codes() {
+    let dir = scratch("validate");
+    let out = tool(&[p("validate"), p(SAMPLE)]);
+    assert_eq!(code(&out), 0, "{}", String::from_utf8_lossy(&out.stdout));
+    assert!(String::from_utf8_lossy(&out.stdout).contains(": ok, "));
+
+    let bad = dir.join("bad.log");
+    std::fs::write(&bad, "Kanata\t0004\nI\t0\t0\t0\nS\t0\t0\tF\nX\t1\n").unwrap();
+    let out = tool(&[p("validate"), &bad]);
+    assert_eq!(code(&out), 1);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("bad.log:4:1: "));
+
+    let out = tool(&[p("validate"), &dir.join("missing.log")]);
+    assert_eq!(code(&out), 2);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn convert_infers_the_format() {
+    let dir = scratch("convert");
+    let cases: [(&str, &[u8]); 10] = [
+        ("out.json", b"{\"traceEvents\":["),
+        ("out.speedscope.json", b"{\"$schema\":"),
+        ("out.vcd", b"$timescale "),
+        ("out.o3", b"O3PipeView:fetch:"),
+        ("out.folded", b"0x"),
+        ("out.dot", b"digraph deps {\n"),
+        ("out.csv", b"offset,cycle,cmd,"),
+        ("out.jsonl", b"{\"offset\":0,"),
+        ("out.log", b"Kanata\t0004\n"),
+        ("out.kanatab", kanata::BINARY_MAGIC),
+    ];
+    for (name, head) in cases {
+        let path = dir.join(name);
+        let out = tool(&[p("convert"), p(SAMPLE), &path]);
+        assert_eq!(
+            code(&out),
+            0,
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let written = std::fs::read(&path).unwrap();
+        assert!(written.starts_with(head), "{}", name);
+    }
+
+    let odd = dir.join("out.txt");
+    let out = tool(&[p("convert"), p(SAMPLE), &odd]);
+    assert_eq!(code(&out), 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("pass --to"));
+    let out = tool(&[p("convert"), p(SAMPLE), &odd, p("--to"), p("vcd")]);
+    assert_eq!(code(&out), 0);
+    assert!(std::fs::read(&odd).unwrap().starts_with(b"$timescale "));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn diff_exit_status() {
+    let dir = scratch("diff");
+    let binary = dir.join("same.kanatab");
+    assert_eq!(code(&tool(&[p("convert"), p(SAMPLE), &binary])), 0);
+    // the same trace in another encoding is no different
+    assert_eq!(code(&tool(&[p("diff"), p(SAMPLE), &binary])), 0);
+    assert_eq!(
+        code(&tool(&[p("diff"), p(SAMPLE), &binary, p("--text")])),
+        0
+    );
+
+    let text = std::fs::read_to_string(SAMPLE).unwrap();
+    let changed = dir.join("changed.log");
+    std::fs::write(&changed, text.replacen("C\t12\n", "C\t13\n", 1)).unwrap();
+    let out = tool(&[p("diff"), p(SAMPLE), &changed]);
+    assert_eq!(code(&out), 1);
+    assert!(String::from_utf8_lossy(&out.stdout).contains(" instructions differ, 0 only in a"));
+    assert_eq!(
+        code(&tool(&[p("diff"), p(SAMPLE), &changed, p("--text")])),
+        1
+    );
+
+    assert_eq!(
+        code(&tool(&[p("diff"), p(SAMPLE), &dir.join("missing")])),
+        2
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn repair_input() {
+    let dir = scratch("repair");
+    let cut = dir.join("cut.log");
+    std::fs::write(&cut, "Kanata\t0004\nI\t0\t0\t0\nS\t0\t0\tF\nC\t1\nE\t0\t0").unwrap();
+    let fixed = dir.join("fixed.log");
+    let out = tool(&[p("repair"), &cut, &fixed]);
+    assert_eq!(code(&out), 0, "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(code(&tool(&[p("validate"), &fixed])), 0);
+
+    // read as any other input is, through its compression
+    #[cfg(feature = "gzip")]
+    {
+        use std::io::Write;
+        let gz = dir.join("cut.log.gz");
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        enc.write_all(&std::fs::read(&cut).unwrap()).unwrap();
+        std::fs::write(&gz, enc.finish().unwrap()).unwrap();
+        let unzipped = dir.join("unzipped.log");
+        assert_eq!(code(&tool(&[p("repair"), &gz, &unzipped])), 0);
+        assert_eq!(
+            std::fs::read(&unzipped).unwrap(),
+            std::fs::read(&fixed).unwrap()
+        );
+    }
+
+    let binary = dir.join("trace.kanatab");
+    assert_eq!(code(&tool(&[p("convert"), p(SAMPLE), &binary])), 0);
+    let out = tool(&[p("repair"), &binary, &dir.join("out.log")]);
+    assert_eq!(code(&out), 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("repair takes a text trace"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn merge_retire_ids() {
+    let dir = scratch("merge");
+    let input = "Kanata\t0004\nC=\t0\nI\t0\t0\t0\nI\t1\t1\t0\nI\t2\t2\t0\nC\t1\n\
+R\t0\t0\t0\nR\t1\t0\t1\nR\t2\t1\t0\n";
+    let (a, empty) = (dir.join("a.log"), dir.join("empty.log"));
+    std::fs::write(&a, input).unwrap();
+    std::fs::write(&empty, "Kanata\t0004\n").unwrap();
+    let merged = dir.join("merged.log");
+    let out = tool(&[p("merge"), &merged, &a, &empty]);
+    assert_eq!(code(&out), 0, "{}", String::from_utf8_lossy(&out.stderr));
+
+    // the flushed instruction doesn't use up a retire id, as with the library
+    let trace = kanata::Trace::new(input.as_bytes()).unwrap();
+    let all = trace.instructions().iter().map(|r| r.id).collect();
+    let sliced = kanata::slice(&trace, &all, Vec::new()).unwrap();
+    assert_eq!(std::fs::read(&merged).unwrap(), sliced);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn search_through_the_cache() {
+    let dir = scratch("search");
+    let plain = tool(&[p("search"), p(SAMPLE), p("addi a0")]);
+    assert_eq!(code(&plain), 0);
+    assert!(!plain.stdout.is_empty());
+    for _ in 0..2 {
+        let cached = tool(&[p("search"), p(SAMPLE), p("addi a0"), p("--cache"), &dir]);
+        assert_eq!(
+            code(&cached),
+            0,
+            "{}",
+            String::from_utf8_lossy(&cached.stderr)
+        );
+        assert_eq!(cached.stdout, plain.stdout);
+    }
+    let labels = glob::glob(&format!("{}/**/*.labels", dir.display())).unwrap();
+    assert_eq!(labels.count(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}